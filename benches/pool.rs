@@ -0,0 +1,34 @@
+//! Thread pool throughput: how many tiny jobs per second a pool gets
+//! through, for a few pool sizes, with one thread submitting.
+//!
+//! Run with `cargo bench --bench pool`.
+use custom_http::thread_pool::ThreadPool;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const JOBS: usize = 1_000_000;
+
+fn main() {
+    for size in [1, 4, 8] {
+        let pool = ThreadPool::builder().size(size).build().unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+
+        let start = Instant::now();
+        for _ in 0..JOBS {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        // Dropping the pool waits for the queue to drain.
+        drop(pool);
+        let elapsed = start.elapsed();
+
+        assert_eq!(done.load(Ordering::Relaxed), JOBS);
+        eprintln!(
+            "{size} workers: {:.0} jobs/s",
+            JOBS as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Build script.
+//!
+//! With the `embed` feature, copies the site into the binary: every file
+//! under `CUSTOM_HTTP_EMBED_DIR` (default `public`) becomes an entry of
+//! `EMBEDDED`, a sorted `&[(path, bytes, hash)]` table written to
+//! `$OUT_DIR/embedded_assets.rs` and included by `io::assets`. The hash is
+//! the FNV-1a hash `io::file::hash_file` computes, so ETags match those of
+//! the same files served from disk.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBED").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=CUSTOM_HTTP_EMBED_DIR");
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let dir = env::var("CUSTOM_HTTP_EMBED_DIR").unwrap_or_else(|_| String::from("public"));
+    let root = manifest_dir.join(dir);
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut files = Vec::new();
+    collect(&root, &root, &mut files);
+    files.sort();
+
+    let mut out = String::from("pub static EMBEDDED: &[(&str, &[u8], u64)] = &[\n");
+    for (path, file) in &files {
+        let bytes =
+            fs::read(file).unwrap_or_else(|e| panic!("can't embed {}: {e}", file.display()));
+        out.push_str(&format!(
+            "    ({path:?}, include_bytes!({:?}), {:#018x}),\n",
+            file.display(),
+            fnv1a(&bytes)
+        ));
+    }
+    out.push_str("];\n");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("embedded_assets.rs"), out).unwrap();
+}
+
+/// Collects `(root-relative path, absolute path)` for every file under `dir`.
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|e| panic!("can't embed {}: {e}", dir.display()));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        if path.is_dir() {
+            collect(root, &path, files);
+        } else {
+            let relative = path
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.push((format!("/{relative}"), path.canonicalize().unwrap()));
+        }
+    }
+}
+
+/// 64-bit FNV-1a, as in `io::file::hash_file`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,105 @@
+//! The command line of the `custom_http` binary.
+//!
+//! Settings come from three places, each overriding the one before: the
+//! defaults, a configuration file (`--config`, or the `CUSTOM_HTTP_CONFIG`
+//! environment variable), and the other flags. Where a flag appears on the
+//! line doesn't matter: flags always win over the file.
+use crate::error::ServerError;
+use crate::server::ServerConfig;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// What the command line asks for.
+///
+/// Variants:
+/// - `Serve(Box<ServerConfig>)`: Run a server with this configuration.
+/// - `Help`: Print `HELP` and exit.
+/// - `Version`: Print `VERSION` and exit.
+#[derive(Debug)]
+pub enum Command {
+    Serve(Box<ServerConfig>),
+    Help,
+    Version,
+}
+
+/// The environment variable naming a configuration file when `--config` doesn't.
+pub const CONFIG_ENV: &str = "CUSTOM_HTTP_CONFIG";
+
+/// The output of `--version`.
+pub const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// The output of `--help`.
+pub const HELP: &str = "\
+Usage: custom_http [options]
+
+Serves the files under a directory over HTTP/1.1.
+
+Options:
+  -c, --config <file>       Read settings from a TOML file (default: $CUSTOM_HTTP_CONFIG).
+                            The other options override it.
+  -a, --address <address>   Listen on an IP address and port (127.0.0.1:8080), an IP
+                            address alone, or a Unix socket path (unix:/run/site.sock).
+                            Repeat for more. Also accepted as --bind.
+  -p, --port <port>         Listen on this port on every IP address (default: 8080).
+  -r, --root <dir>          Serve files from this directory (default: public).
+      --mount <prefix>=<dir>
+                            Serve this directory under a URL prefix; repeat for more.
+      --proxy <prefix>=<url>
+                            Forward requests under a path prefix to an upstream
+                            (http://127.0.0.1:3000); repeat for more.
+      --workers <n>         Worker threads per event loop (default: one per CPU, 2 to 16).
+      --reactors <n>        Event loops, each with its own listener (default: one per CPU).
+      --log-level <level>   error, warn, info or debug (default: info).
+      --log-format <fmt>    text, or json for JSON lines with request IDs (default: text).
+      --access-log <file>   Log every response to this file (- for standard output).
+      --ipv6-only           Don't take IPv4 connections on IPv6 wildcard addresses.
+      --dual-stack          Do take them.
+      --allow <cidr>        Only accept clients in this range; repeat for more.
+      --deny <cidr>         Refuse clients in this range; repeat for more.
+      --deny-with-403       Answer refused clients with 403 instead of closing.
+      --trusted-proxy <cidr>
+                            Believe forwarding headers from this range; repeat for more.
+      --health              Answer /healthz and /readyz probes.
+      --tls-cert <file>     Serve HTTPS with this certificate chain (tls builds only).
+      --tls-key <file>      ... and this private key.
+  -h, --help                Print this help.
+  -V, --version             Print the version.
+";
+
+/// Turns the arguments (without the program name) into a `Command`.
+/// `config_env` is the value of `CONFIG_ENV`, used when there's no `--config`.
+///
+/// # Errors
+/// `ServerError::Config` for a bad or repeated `--config`, anything
+/// `ServerConfig::apply_args` rejects, and whatever reading the
+/// configuration file fails with (see `ServerConfig::apply_toml_path`).
+pub fn parse(
+    args: impl IntoIterator<Item = String>,
+    config_env: Option<OsString>,
+) -> Result<Command, ServerError> {
+    let mut config_path = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-c" | "--config" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| ServerError::Config(format!("{arg} needs a value")))?;
+                if config_path.replace(PathBuf::from(path)).is_some() {
+                    return Err(ServerError::Config(format!("{arg} given twice")));
+                }
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    let mut config = ServerConfig::default();
+    if let Some(path) = config_path.or(config_env.map(PathBuf::from)) {
+        config.apply_toml_path(path)?;
+    }
+    config.apply_args(rest)?;
+    Ok(Command::Serve(Box::new(config)))
+}
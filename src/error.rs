@@ -0,0 +1,66 @@
+//! The errors that stop the server from starting or keep it from running.
+//!
+//! Requests that go wrong are answered with an error page and never get
+//! here; `ServerError` is for the server itself: a bad address, a port
+//! someone else holds, a configuration that can't work, or an I/O failure
+//! in the event loop.
+use crate::thread_pool::PoolBuildError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a `Server` failed to start or stopped serving.
+///
+/// Variants:
+/// - `Bind { address, source }`: `address` parsed but couldn't be bound, e.g.
+///   because another process is listening on it.
+/// - `InvalidAddress { address, reason }`: `address` is neither an IP address and
+///   port nor a socket path.
+/// - `Config(String)`: A setting (or command-line flag) that can't work, described.
+/// - `Io(io::Error)`: Anything else, e.g. a missing document root or a failure
+///   of the poll itself.
+#[derive(Debug)]
+pub enum ServerError {
+    Bind { address: String, source: io::Error },
+    InvalidAddress { address: String, reason: String },
+    Config(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Bind { address, source } => write!(f, "cannot bind {address}: {source}"),
+            ServerError::InvalidAddress { address, reason } => {
+                write!(f, "invalid address {address}: {reason}")
+            }
+            ServerError::Config(message) => write!(f, "invalid configuration: {message}"),
+            ServerError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Bind { source, .. } => Some(source),
+            ServerError::Io(e) => Some(e),
+            ServerError::InvalidAddress { .. } | ServerError::Config(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+impl From<PoolBuildError> for ServerError {
+    fn from(e: PoolBuildError) -> ServerError {
+        match e {
+            PoolBuildError::ZeroSize => ServerError::Config(e.to_string()),
+            PoolBuildError::Spawn(e) => ServerError::Io(e),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! HTTP authentication middleware.
+//!
+//! `BasicAuth` asks for a user name and password (RFC 7617) on the paths it
+//! protects and checks them against `Credentials`, the users and password
+//! hashes of an `htpasswd` file. `BearerAuth` asks machine clients for a
+//! token (RFC 6750) instead. Wrap a router with either (see `Router::wrap`);
+//! requests they let through carry who they are in `HttpRequest::user`.
+//!
+//! Secrets stay out of the logs: the headers carrying them are never
+//! logged, and `redact_query` blanks tokens sent in query strings before the
+//! access log or `RequestLog` write a target.
+use crate::error::ServerError;
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::HttpResponse;
+use crate::http::status::StatusCode;
+use crate::server::{Middleware, Next};
+use crate::util;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+mod bcrypt;
+
+/// A password hash, in one of the formats `htpasswd` writes.
+///
+/// Variants:
+/// - `Bcrypt(String)`: `$2y$...` (`htpasswd -B`), and the `$2a$` and `$2b$`
+///   spellings. Slow on purpose; the cost is in the hash.
+/// - `Sha1([u8; 20])`: `{SHA}` and the base64 of the SHA-1 of the password
+///   (`htpasswd -s`). Unsalted, so only as good as the password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordHash {
+    Bcrypt(String),
+    Sha1([u8; 20]),
+}
+
+impl PasswordHash {
+    /// Parses a hash as it appears after the `:` of an `htpasswd` line.
+    ///
+    /// # Errors
+    /// A hash of another format: MD5 (`$apr1$`), `crypt` and plain text
+    /// passwords are refused rather than silently never matching.
+    pub fn parse(hash: &str) -> Result<PasswordHash, String> {
+        if bcrypt::is_hash(hash) {
+            return Ok(PasswordHash::Bcrypt(hash.to_owned()));
+        }
+        if let Some(encoded) = hash.strip_prefix("{SHA}") {
+            return util::base64_decode(encoded)
+                .and_then(|digest| digest.try_into().ok())
+                .map(PasswordHash::Sha1)
+                .ok_or_else(|| String::from("malformed {SHA} hash"));
+        }
+        Err(String::from(
+            "unsupported password hash; use bcrypt (htpasswd -B) or {SHA} (htpasswd -s)",
+        ))
+    }
+
+    /// Returns `true` if `password` hashes to this.
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Bcrypt(hash) => bcrypt::verify(password.as_bytes(), hash),
+            PasswordHash::Sha1(digest) => {
+                constant_time_eq(&util::sha1(password.as_bytes()), digest)
+            }
+        }
+    }
+}
+
+/// Users and their password hashes.
+///
+/// # Example
+/// ```
+/// let credentials = Credentials::parse_htpasswd(
+///     "alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n",
+/// )?;
+/// assert!(credentials.verify("alice", "secret"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    users: HashMap<String, PasswordHash>,
+    // The costliest bcrypt hash among `users`, checked (and the answer
+    // thrown away) when the user isn't known.
+    decoy: Option<String>,
+}
+
+impl Credentials {
+    /// Creates an empty store, which lets nobody in.
+    pub fn new() -> Credentials {
+        Credentials::default()
+    }
+
+    /// Parses `htpasswd` lines (`user:hash`). Blank lines and lines starting
+    /// with `#` are skipped.
+    ///
+    /// # Errors
+    /// The first line without a `:` or with a hash `PasswordHash::parse`
+    /// refuses, by line number.
+    pub fn parse_htpasswd(text: &str) -> Result<Credentials, String> {
+        let mut credentials = Credentials::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected user:hash", i + 1))?;
+            credentials
+                .insert(user, hash)
+                .map_err(|e| format!("line {}: {e}", i + 1))?;
+        }
+        Ok(credentials)
+    }
+
+    /// Reads an `htpasswd` file; see `parse_htpasswd`.
+    ///
+    /// # Errors
+    /// `ServerError::Io` if the file can't be read, and `ServerError::Config`
+    /// if it doesn't parse.
+    pub fn from_htpasswd_file(path: impl AsRef<Path>) -> Result<Credentials, ServerError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("htpasswd file {}: {e}", path.display()))
+        })?;
+        Credentials::parse_htpasswd(&text)
+            .map_err(|e| ServerError::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Adds `user` with the password hash `hash` (see `PasswordHash::parse`),
+    /// replacing any earlier entry for them.
+    ///
+    /// # Errors
+    /// As `PasswordHash::parse`.
+    pub fn insert(&mut self, user: &str, hash: &str) -> Result<(), String> {
+        let hash = PasswordHash::parse(hash)?;
+        if let PasswordHash::Bcrypt(bcrypt) = &hash
+            && bcrypt::cost(bcrypt) > self.decoy.as_deref().and_then(bcrypt::cost)
+        {
+            self.decoy = Some(bcrypt.clone());
+        }
+        self.users.insert(user.to_owned(), hash);
+        Ok(())
+    }
+
+    /// Returns `true` if `user` exists and `password` is theirs.
+    ///
+    /// An unknown user takes as long as a wrong password for the costliest
+    /// bcrypt entry (or a `{SHA}` one, if there is no bcrypt entry), so the
+    /// time taken doesn't tell whether the user exists. In a store mixing
+    /// costs, or bcrypt and `{SHA}`, it still tells the cheaper entries apart.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match (self.users.get(user), &self.decoy) {
+            (Some(hash), _) => hash.verify(password),
+            (None, Some(decoy)) => {
+                let _ = bcrypt::verify(password.as_bytes(), decoy);
+                false
+            }
+            (None, None) => {
+                let _ = constant_time_eq(&util::sha1(password.as_bytes()), &[0; 20]);
+                false
+            }
+        }
+    }
+}
+
+/// Middleware asking for a user name and password on the paths under
+/// `prefixes`, and letting through only those `credentials` know.
+///
+/// Anything else under those paths gets `401 Unauthorized` with
+/// `WWW-Authenticate: Basic realm="<realm>"`, so browsers prompt for a login.
+/// Other paths are let through untouched.
+///
+/// Basic credentials travel in the clear, base64 aside: serve protected paths
+/// over TLS (see `io::tls`).
+///
+/// # Fields
+/// - `realm` (*String*): Shown by browsers in the login prompt.
+/// - `prefixes` (*Vec<String>*): The protected paths. `/admin/` protects
+///   `/admin` and everything under `/admin/`; `/status` only that path.
+/// - `credentials` (*Credentials*): Who may come in.
+///
+/// # Example
+/// ```
+/// router.wrap(BasicAuth {
+///     realm: String::from("admin"),
+///     prefixes: vec![String::from("/admin/")],
+///     credentials: Credentials::from_htpasswd_file("/etc/custom_http/htpasswd")?,
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    pub realm: String,
+    pub prefixes: Vec<String>,
+    pub credentials: Credentials,
+}
+
+impl BasicAuth {
+    /// Returns the user `request` logs in as, if the password is right.
+    fn user(&self, request: &HttpRequest) -> Option<String> {
+        let (scheme, credentials) = request.authorization()?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(util::base64_decode(credentials)?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        self.credentials
+            .verify(user, password)
+            .then(|| user.to_owned())
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        match protects(&self.prefixes, &request.path) {
+            Some(true) => {}
+            Some(false) => return next(request),
+            None => return HttpResponse::text(StatusCode::BAD_REQUEST, "bad request"),
+        }
+        match self.user(request) {
+            Some(user) => {
+                request.user = Some(user);
+                next(request)
+            }
+            None => {
+                let realm = self.realm.replace(['"', '\\', '\r', '\n', '\0'], "");
+                let challenge = format!("Basic realm=\"{realm}\", charset=\"UTF-8\"");
+                HttpResponse::text(StatusCode::UNAUTHORIZED, "unauthorized").with_header(
+                    headers::WWW_AUTHENTICATE,
+                    HeaderValue::try_from(challenge).unwrap_or(HeaderValue::from_static("Basic")),
+                )
+            }
+        }
+    }
+}
+
+/// The tokens `BearerAuth` accepts, each with the identity it stands for.
+///
+/// Only a digest of each token is kept, and a token is looked up by
+/// comparing its digest with every one in constant time, so neither the
+/// answer's timing nor the token's length gives a guess away.
+#[derive(Clone, Default)]
+pub struct TokenSet {
+    tokens: Vec<([u8; 20], String)>,
+}
+
+impl TokenSet {
+    /// Creates an empty set, which accepts nothing.
+    pub fn new() -> TokenSet {
+        TokenSet::default()
+    }
+
+    /// Accepts `token` as `identity` (e.g. the name of the client it was
+    /// issued to), which is what ends up in `HttpRequest::user`.
+    pub fn insert(&mut self, token: &str, identity: &str) -> &mut TokenSet {
+        self.tokens
+            .push((util::sha1(token.as_bytes()), identity.to_owned()));
+        self
+    }
+
+    /// Returns the identity of `token`, if it is one of the set.
+    pub fn identify(&self, token: &str) -> Option<&str> {
+        let digest = util::sha1(token.as_bytes());
+        // Every entry is compared, whatever matched before.
+        self.tokens.iter().fold(None, |found, (known, identity)| {
+            let matches = constant_time_eq(&digest, known);
+            found.or(matches.then_some(identity.as_str()))
+        })
+    }
+}
+
+impl fmt::Debug for TokenSet {
+    // The digests are left out: they are as good as the tokens to a guesser.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSet")
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+/// How `BearerAuth` decides whether a token is good.
+///
+/// Variants:
+/// - `Tokens(TokenSet)`: The token must be one of these.
+/// - `Custom(TokenCheck)`: Asks a function, which
+///   returns the identity the token stands for, or `None` to refuse it (e.g.
+///   after checking a signature or asking another service). It runs on a
+///   worker thread, so it may block, and is responsible for comparing
+///   secrets in constant time itself.
+#[derive(Clone)]
+pub enum TokenVerifier {
+    Tokens(TokenSet),
+    Custom(TokenCheck),
+}
+
+/// A function checking a bearer token for `TokenVerifier::Custom`.
+pub type TokenCheck = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+impl TokenVerifier {
+    /// Returns the identity `token` stands for, if it is good.
+    fn identify(&self, token: &str) -> Option<String> {
+        match self {
+            TokenVerifier::Tokens(tokens) => tokens.identify(token).map(str::to_owned),
+            TokenVerifier::Custom(verify) => verify(token),
+        }
+    }
+}
+
+impl fmt::Debug for TokenVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenVerifier::Tokens(tokens) => f.debug_tuple("Tokens").field(tokens).finish(),
+            TokenVerifier::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Middleware asking for `Authorization: Bearer <token>` on the paths under
+/// `prefixes`, and letting through only tokens `verifier` accepts, with the
+/// identity they stand for in `HttpRequest::user`.
+///
+/// A request with no bearer token gets `401 Unauthorized` with
+/// `WWW-Authenticate: Bearer`; one with a token `verifier` refuses gets the
+/// same with `error="invalid_token"` added, or `403 Forbidden` if
+/// `forbid_invalid` is set. Other paths are let through untouched. Tokens in
+/// the query string are not accepted.
+///
+/// # Fields
+/// - `prefixes` (*Vec<String>*): The protected paths, as for `BasicAuth`:
+///   `/api/` protects `/api` and everything under it, `/metrics` only itself.
+/// - `verifier` (*TokenVerifier*): Which tokens are good.
+/// - `forbid_invalid` (*bool*): Answer a refused token with `403` rather
+///   than `401`, for clients that would otherwise retry it.
+///
+/// # Example
+/// ```
+/// let mut tokens = TokenSet::new();
+/// tokens.insert("s3cr3t-t0ken", "billing-service");
+/// router.wrap(BearerAuth {
+///     prefixes: vec![String::from("/api/")],
+///     verifier: TokenVerifier::Tokens(tokens),
+///     forbid_invalid: false,
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct BearerAuth {
+    pub prefixes: Vec<String>,
+    pub verifier: TokenVerifier,
+    pub forbid_invalid: bool,
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        match protects(&self.prefixes, &request.path) {
+            Some(true) => {}
+            Some(false) => return next(request),
+            None => return HttpResponse::text(StatusCode::BAD_REQUEST, "bad request"),
+        }
+        let token = request
+            .authorization()
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token);
+        let Some(token) = token else {
+            return HttpResponse::text(StatusCode::UNAUTHORIZED, "unauthorized").with_header(
+                headers::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer"),
+            );
+        };
+        match self.verifier.identify(token) {
+            Some(identity) => {
+                request.user = Some(identity);
+                next(request)
+            }
+            None if self.forbid_invalid => HttpResponse::text(StatusCode::FORBIDDEN, "forbidden"),
+            None => HttpResponse::text(StatusCode::UNAUTHORIZED, "unauthorized").with_header(
+                headers::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer error=\"invalid_token\""),
+            ),
+        }
+    }
+}
+
+/// Returns `target` with the values of query parameters that carry
+/// credentials (`access_token`, as RFC 6750 §2.3 names it, and the common
+/// `token` and `api_key`) replaced by `REDACTED`, for logging.
+///
+/// # Example
+/// ```
+/// assert_eq!(redact_query("/a?access_token=xyz&b=1"), "/a?access_token=REDACTED&b=1");
+/// ```
+pub fn redact_query(target: &str) -> Cow<'_, str> {
+    const SECRET: [&str; 3] = ["access_token", "token", "api_key"];
+    let Some((path, query)) = target.split_once('?') else {
+        return Cow::Borrowed(target);
+    };
+    let secret = |pair: &str| {
+        let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+        SECRET
+            .iter()
+            .any(|secret| name.eq_ignore_ascii_case(secret))
+    };
+    if !query.split('&').any(secret) {
+        return Cow::Borrowed(target);
+    }
+    let query: Vec<Cow<'_, str>> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if secret(pair) => Cow::Owned(format!("{name}=REDACTED")),
+            _ => Cow::Borrowed(pair),
+        })
+        .collect();
+    Cow::Owned(format!("{path}?{}", query.join("&")))
+}
+
+/// Returns `true` if `path` is one of `prefixes` protects: everything under
+/// a prefix ending in `/` (and the directory itself), or exactly the path
+/// otherwise.
+///
+/// `path` is decoded and normalized first, as static files are looked up,
+/// so `/%61dmin/` or `/x/../admin/` are protected like `/admin/`.
+///
+/// # Returns
+/// `None` if `path` doesn't decode or climbs above the root, so the request
+/// can be refused rather than guessed about.
+fn protects(prefixes: &[String], path: &str) -> Option<bool> {
+    let decoded = util::percent_decode(path).filter(|decoded| !decoded.contains('\0'))?;
+    let path = util::normalize_path(&decoded)?;
+    Some(
+        prefixes
+            .iter()
+            .any(|prefix| match prefix.strip_suffix('/') {
+                Some(dir) => path == dir || path.starts_with(prefix.as_str()),
+                None => path == *prefix,
+            }),
+    )
+}
+
+/// Compares `a` and `b` in a time that depends only on their lengths, so a
+/// guess can't be refined by timing how soon it is rejected.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,260 @@
+//! Response bodies that are produced piece by piece.
+//!
+//! Most responses hold their whole body in memory (`Body::Text` /
+//! `Body::Binary`). A `StreamBody` instead pulls its bytes from a
+//! `BodySource` on demand, so large files or generated content never have to
+//! be buffered at once. A stream with a known length is framed with
+//! `Content-Length`; one without is sent with `Transfer-Encoding: chunked`,
+//! which is also the only framing that can carry trailer fields, or, for
+//! HTTP/1.0 clients, ended by closing the connection.
+//!
+//! A source whose bytes arrive from elsewhere (e.g. `http::sse`) doesn't
+//! have to block the event loop while it waits for them: it returns
+//! `WouldBlock` and wakes the reactor through its `BodyWaker` once there is
+//! more.
+use crate::http::headers::HeaderMap;
+use crate::io::file::ChunkedReader;
+#[cfg(any(unix, windows))]
+use crate::io::file::MappedReader;
+use crate::io::nonblocking::{ReactorHandle, ReactorMsg};
+use mio::Token;
+use std::io;
+
+/// A producer of body bytes.
+///
+/// # Example
+/// ```
+/// struct Countdown(u8);
+///
+/// impl BodySource for Countdown {
+///     fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+///         if self.0 == 0 {
+///             return Ok(None);
+///         }
+///         self.0 -= 1;
+///         Ok(Some(format!("{}\n", self.0).into_bytes()))
+///     }
+/// }
+/// ```
+pub trait BodySource: Send {
+    /// Returns the next piece of the body, or `Ok(None)` once the body is complete.
+    ///
+    /// Empty pieces are allowed and simply skipped. A source with nothing to
+    /// give yet returns an error of kind `WouldBlock`; the reactor asks again
+    /// after `BodyWaker::wake`, and at least once a second regardless. Any
+    /// other error cuts the response short.
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Called once, as the reactor starts sending the body, with the waker
+    /// for its connection. Sources that never return `WouldBlock` ignore it.
+    fn attach(&mut self, _waker: BodyWaker) {}
+
+    /// Returns the trailer fields to send after the last chunk.
+    ///
+    /// Called once, after `next_chunk` has returned `Ok(None)`, so values that
+    /// depend on the whole body (a checksum, the time it took) can be computed
+    /// while streaming. Only fields declared with `StreamBody::declare_trailer`
+    /// are sent, and only when the body is chunked.
+    fn trailers(&mut self) -> HeaderMap {
+        HeaderMap::new()
+    }
+}
+
+/// A response body pulled from a `BodySource`.
+///
+/// # Fields
+/// - `source` (*Box<dyn BodySource>*): Where the bytes come from.
+/// - `length` (*Option<u64>*): The exact body length if known up front. `None` means
+///   chunked, or ended by closing the connection if `until_close` is set.
+/// - `until_close` (*bool*): The body has no framing; it ends when the connection closes.
+/// - `trailer_names` (*Vec<String>*): Trailer fields announced in the `Trailer` header.
+/// - `finished` (*bool*): Whether the terminating piece has been produced.
+pub struct StreamBody {
+    source: Box<dyn BodySource>,
+    length: Option<u64>,
+    until_close: bool,
+    trailer_names: Vec<String>,
+    finished: bool,
+}
+
+impl StreamBody {
+    /// Creates a stream body of exactly `length` bytes, framed with `Content-Length`.
+    pub fn sized(source: impl BodySource + 'static, length: u64) -> StreamBody {
+        StreamBody {
+            source: Box::new(source),
+            length: Some(length),
+            until_close: false,
+            trailer_names: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Creates a stream body of unknown length, sent with `Transfer-Encoding: chunked`.
+    pub fn chunked(source: impl BodySource + 'static) -> StreamBody {
+        StreamBody {
+            source: Box::new(source),
+            length: None,
+            until_close: false,
+            trailer_names: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Creates a stream body of unknown length without framing: the
+    /// connection closes once it is sent, which is how the end shows. For
+    /// HTTP/1.0 clients, which don't understand chunked bodies.
+    pub fn until_close(source: impl BodySource + 'static) -> StreamBody {
+        StreamBody {
+            source: Box::new(source),
+            length: None,
+            until_close: true,
+            trailer_names: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Announces a trailer field (e.g. `X-Checksum`) in the `Trailer` header.
+    ///
+    /// The value is supplied later by `BodySource::trailers`. Declaring a
+    /// trailer on a sized body has no effect: trailers only exist in the
+    /// chunked encoding.
+    pub fn declare_trailer(&mut self, name: &str) -> &mut StreamBody {
+        self.trailer_names.push(name.to_string());
+        self
+    }
+
+    /// Returns the body length, or `None` for chunked bodies.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Returns `true` if the body is sent with `Transfer-Encoding: chunked`.
+    pub fn is_chunked(&self) -> bool {
+        self.length.is_none() && !self.until_close
+    }
+
+    /// Returns `true` if the body ends by closing the connection.
+    pub fn is_until_close(&self) -> bool {
+        self.until_close
+    }
+
+    /// Hands the source the waker for the connection the body is sent on
+    /// (see `BodySource::attach`).
+    pub(crate) fn attach(&mut self, waker: BodyWaker) {
+        self.source.attach(waker);
+    }
+
+    /// Returns the framing header lines for the response head, each ending in CRLF.
+    pub(crate) fn framing_headers(&self) -> String {
+        match self.length {
+            Some(length) => format!("Content-Length: {length}\r\n"),
+            None if self.until_close => String::new(),
+            None if self.trailer_names.is_empty() => String::from("Transfer-Encoding: chunked\r\n"),
+            None => format!(
+                "Transfer-Encoding: chunked\r\nTrailer: {}\r\n",
+                self.trailer_names.join(", ")
+            ),
+        }
+    }
+
+    /// Returns the next piece of the body exactly as it goes on the wire.
+    ///
+    /// For sized bodies that's the raw bytes. For chunked bodies each piece is
+    /// wrapped as `<hex size>\r\n<data>\r\n`, and the end of the stream yields
+    /// the last chunk `0\r\n`, the declared trailer fields, and the final `\r\n`.
+    ///
+    /// # Returns
+    /// - `Ok(Some(bytes))`: The next piece to send.
+    /// - `Ok(None)`: The body is complete.
+    /// - `Err(io::Error)`: The source failed; the response can't be completed.
+    pub fn next_wire_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        loop {
+            match self.source.next_chunk()? {
+                Some(data) if data.is_empty() => continue,
+                Some(data) if self.is_chunked() => {
+                    let mut framed = format!("{:X}\r\n", data.len()).into_bytes();
+                    framed.extend_from_slice(&data);
+                    framed.extend_from_slice(b"\r\n");
+                    return Ok(Some(framed));
+                }
+                Some(data) => return Ok(Some(data)),
+                None => {
+                    self.finished = true;
+                    if !self.is_chunked() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(self.last_chunk()));
+                }
+            }
+        }
+    }
+
+    /// Serializes the terminating chunk with any declared trailer fields.
+    fn last_chunk(&mut self) -> Vec<u8> {
+        let mut last = String::from("0\r\n");
+        if !self.trailer_names.is_empty() {
+            let trailers = self.source.trailers();
+            for (name, value) in trailers.iter() {
+                if self
+                    .trailer_names
+                    .iter()
+                    .any(|n| n.eq_ignore_ascii_case(name))
+                {
+                    last.push_str(&format!("{name}: {value}\r\n"));
+                }
+            }
+        }
+        last.push_str("\r\n");
+        last.into_bytes()
+    }
+}
+
+/// Wakes the reactor for a stream body whose source returned `WouldBlock`
+/// (see `BodySource::next_chunk`). Clones wake the same connection; waking
+/// one that has closed does nothing.
+#[derive(Clone)]
+pub struct BodyWaker {
+    token: Token,
+    reactor: ReactorHandle,
+}
+
+impl BodyWaker {
+    pub(crate) fn new(token: Token, reactor: ReactorHandle) -> BodyWaker {
+        BodyWaker { token, reactor }
+    }
+
+    /// Tells the reactor the source has more to give.
+    ///
+    /// # Errors
+    /// Fails with `BrokenPipe` if the server has stopped.
+    pub fn wake(&self) -> io::Result<()> {
+        self.reactor
+            .send(ReactorMsg::BodyReady { token: self.token })
+    }
+
+    /// Gives up on the response: the reactor closes its connection, cutting
+    /// the body short.
+    ///
+    /// # Errors
+    /// Fails with `BrokenPipe` if the server has stopped.
+    pub fn abort(&self) -> io::Result<()> {
+        self.reactor
+            .send(ReactorMsg::AbortResponse { token: self.token })
+    }
+}
+
+impl BodySource for ChunkedReader {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        ChunkedReader::next_chunk(self)
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl BodySource for MappedReader {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        MappedReader::next_chunk(self)
+    }
+}
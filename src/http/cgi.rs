@@ -0,0 +1,360 @@
+//! CGI/1.1 scripts (RFC 3875).
+//!
+//! When enabled, `GET /cgi-bin/hello.sh/extra?name=x` runs `hello.sh` from
+//! the script directory with `PATH_INFO=/extra` and `QUERY_STRING=name=x`
+//! in its environment and the request body on its standard input. What the
+//! script prints is a block of CGI header lines (`Status`, `Content-Type`,
+//! `Location` and any other response header), a blank line and the body.
+//!
+//! Scripts run on the pool thread handling the request, one process per
+//! request, and their output is read whole before it is sent. The script's
+//! standard error goes to the server's.
+use crate::http::headers::{HeaderMap, HeaderName, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::{Body, ErrorPage, HttpResponse};
+use crate::http::status::StatusCode;
+use crate::log;
+use crate::server::ServerConfig;
+use crate::util;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running script is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// CGI settings.
+///
+/// # Fields
+/// - `prefix` (*String*): Request paths under this prefix run scripts. Defaults to `/cgi-bin/`.
+/// - `root` (*PathBuf*): The directory scripts are run from. The first component of
+///   the request path after `prefix` names the script; the rest is its `PATH_INFO`.
+///   Defaults to `cgi-bin`, resolved against the working directory at startup like
+///   the document root.
+/// - `timeout` (*Duration*): How long a script may run. One that takes longer is
+///   killed and the client gets `504 Gateway Timeout`. Defaults to 30 seconds.
+#[derive(Debug, Clone)]
+pub struct CgiConfig {
+    pub prefix: String,
+    pub root: PathBuf,
+    pub timeout: Duration,
+}
+
+impl Default for CgiConfig {
+    fn default() -> CgiConfig {
+        CgiConfig {
+            prefix: String::from("/cgi-bin/"),
+            root: PathBuf::from("cgi-bin"),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A script found for a request path.
+struct Script {
+    // The script file, canonical.
+    file: PathBuf,
+    // The request path up to and including the script, e.g. `/cgi-bin/hello.sh`.
+    name: String,
+    // The rest of the decoded request path, e.g. `/extra`; empty if there is none.
+    path_info: String,
+}
+
+impl CgiConfig {
+    /// Replaces `root` with its absolute, canonical form.
+    ///
+    /// # Errors
+    /// Fails if the root doesn't exist, with the path in the message.
+    pub fn resolve_root(&mut self) -> io::Result<()> {
+        self.root = std::fs::canonicalize(&self.root).map_err(|e| {
+            io::Error::new(e.kind(), format!("CGI root {}: {e}", self.root.display()))
+        })?;
+        Ok(())
+    }
+
+    /// Returns `true` if a request for `path` runs a script.
+    ///
+    /// Like `UploadConfig::matches`, this looks at the path as sent (after
+    /// percent-decoding), so `/cgi-bin/../x` is refused by `run` instead of
+    /// being served as a file.
+    pub fn matches(&self, path: &str) -> bool {
+        let decoded = util::percent_decode(path).unwrap_or_else(|| path.to_string());
+        decoded
+            .strip_prefix(self.prefix.trim_end_matches('/'))
+            .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    /// Runs the script `request` names and returns the response it printed.
+    ///
+    /// # Errors
+    /// - `ErrorPage::BadRequest` for an undecodable path.
+    /// - `ErrorPage::NotFound` if there is no script by that name.
+    /// - `ErrorPage::PermissionDenied` for hidden components, anything outside
+    ///   the root, or a script that isn't executable.
+    /// - `ErrorPage::GatewayTimeout` if the script ran out of time and was killed.
+    /// - `ErrorPage::BadGateway` if the script couldn't be started, crashed,
+    ///   exited with an error or printed no valid CGI headers.
+    pub(crate) fn run(
+        &self,
+        request: &HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<HttpResponse, ErrorPage> {
+        let script = self.find_script(request, config)?;
+        let mut child = self
+            .command(&script, request)
+            .spawn()
+            .map_err(|e| failed(&script, &e.to_string()))?;
+
+        // Feed stdin and drain stdout on their own threads, so a script that
+        // prints before it reads can't deadlock against us.
+        let mut stdin = child.stdin.take();
+        let body = request.body.clone();
+        let writer = thread::spawn(move || {
+            if let Some(stdin) = &mut stdin {
+                // A script that exits without reading its input is fine.
+                let _ = stdin.write_all(&body);
+            }
+        });
+        let mut stdout = child.stdout.take();
+        let reader = thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(stdout) = &mut stdout {
+                stdout.read_to_end(&mut output)?;
+            }
+            Ok::<_, io::Error>(output)
+        });
+
+        let status = self
+            .wait(&mut child)
+            .map_err(|e| failed(&script, &e.to_string()))?;
+        let _ = writer.join();
+        let Some(status) = status else {
+            log::error(format_args!(
+                "CGI script {} killed after {:?}",
+                script.file.display(),
+                self.timeout
+            ));
+            return Err(ErrorPage::GatewayTimeout);
+        };
+        let output = match reader.join() {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => return Err(failed(&script, &e.to_string())),
+            Err(_) => return Err(failed(&script, "reading its output panicked")),
+        };
+        if !status.success() {
+            return Err(failed(&script, &status.to_string()));
+        }
+        parse_output(output).ok_or_else(|| failed(&script, "no valid CGI headers"))
+    }
+
+    /// Finds the script a request path names, and the `PATH_INFO` after it.
+    fn find_script(
+        &self,
+        request: &HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<Script, ErrorPage> {
+        let decoded = match util::percent_decode(&request.path) {
+            Some(decoded) if !decoded.contains('\0') => decoded,
+            _ => return Err(ErrorPage::BadRequest),
+        };
+        let Some(normalized) = util::normalize_path(&decoded) else {
+            return Err(ErrorPage::PermissionDenied);
+        };
+        if config.is_hidden_path(&normalized) {
+            return Err(ErrorPage::PermissionDenied);
+        }
+        let prefix = self.prefix.trim_end_matches('/');
+        let rest = normalized
+            .strip_prefix(prefix)
+            .filter(|rest| rest.starts_with('/'))
+            .ok_or(ErrorPage::PermissionDenied)?;
+        let (name, path_info) = match rest[1..].find('/') {
+            Some(slash) => rest.split_at(slash + 1),
+            None => (rest, ""),
+        };
+        if name == "/" {
+            return Err(ErrorPage::NotFound);
+        }
+        let file = match std::fs::canonicalize(self.root.join(&name[1..])) {
+            Ok(file) if !file.starts_with(&self.root) => return Err(ErrorPage::PermissionDenied),
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ErrorPage::NotFound),
+            Err(_) => return Err(ErrorPage::PermissionDenied),
+        };
+        if !is_executable(&file) {
+            return Err(ErrorPage::PermissionDenied);
+        }
+        Ok(Script {
+            file,
+            name: format!("{prefix}{name}"),
+            path_info: path_info.to_owned(),
+        })
+    }
+
+    /// Returns the command running `script` for `request`, with the CGI
+    /// environment and piped standard input and output.
+    fn command(&self, script: &Script, request: &HttpRequest) -> Command {
+        let mut command = Command::new(&script.file);
+        command
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(dir) = script.file.parent() {
+            command.current_dir(dir);
+        }
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        let host = request.header("Host").unwrap_or_default();
+        let (server_name, server_port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => (name, port),
+            _ => (host, if request.secure { "443" } else { "80" }),
+        };
+        command
+            .env("GATEWAY_INTERFACE", "CGI/1.1")
+            .env(
+                "SERVER_SOFTWARE",
+                concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+            )
+            .env("SERVER_PROTOCOL", &request.version)
+            .env("SERVER_NAME", server_name)
+            .env("SERVER_PORT", server_port)
+            .env("REQUEST_METHOD", request.method.as_str())
+            .env("REQUEST_URI", &request.target)
+            .env("SCRIPT_NAME", &script.name)
+            .env("SCRIPT_FILENAME", &script.file)
+            .env("QUERY_STRING", request.query.as_deref().unwrap_or_default());
+        if !script.path_info.is_empty() {
+            command
+                .env("PATH_INFO", &script.path_info)
+                .env("PATH_TRANSLATED", self.root.join(&script.path_info[1..]));
+        }
+        if let Some(peer) = request.peer {
+            command.env("REMOTE_ADDR", peer.to_string());
+        }
+        if request.secure {
+            command.env("HTTPS", "on");
+        }
+        if !request.body.is_empty() {
+            command.env("CONTENT_LENGTH", request.body.len().to_string());
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            command.env("CONTENT_TYPE", content_type);
+        }
+        for (name, value) in request.headers.iter() {
+            // These have variables of their own, or are the server's business.
+            // `Proxy` would become `HTTP_PROXY`, which HTTP clients in the
+            // script take for their outbound proxy ("httpoxy").
+            let skipped = [
+                "Content-Length",
+                "Content-Type",
+                "Proxy",
+                "Proxy-Authorization",
+            ]
+            .iter()
+            .any(|skipped| skipped.eq_ignore_ascii_case(name));
+            if !skipped {
+                let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+                command.env(name, value);
+            }
+        }
+        command
+    }
+
+    /// Waits for `child` to exit, killing it once `timeout` has passed.
+    ///
+    /// # Returns
+    /// The exit status, or `None` if the child was killed.
+    fn wait(&self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Logs a script that failed to produce a response and returns the page for it.
+fn failed(script: &Script, reason: &str) -> ErrorPage {
+    log::error(format_args!(
+        "CGI script {} failed: {reason}",
+        script.file.display()
+    ));
+    ErrorPage::BadGateway
+}
+
+/// Returns `true` if `file` is a file the server may execute.
+#[cfg(unix)]
+fn is_executable(file: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(file)
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Returns `true` if `file` is a file the server may execute.
+#[cfg(not(unix))]
+fn is_executable(file: &Path) -> bool {
+    file.is_file()
+}
+
+/// Splits a script's output into the response it describes.
+///
+/// Header lines end with LF or CRLF. `Status` sets the status (default `200
+/// OK`, or `302 Found` with a `Location`), `Content-Type` the content type,
+/// and every other field is sent as is.
+///
+/// # Returns
+/// `None` if the header block is malformed or has none of `Content-Type`,
+/// `Location` and `Status`.
+fn parse_output(mut output: Vec<u8>) -> Option<HttpResponse> {
+    let mut headers = HeaderMap::new();
+    let mut status = None;
+    let mut content_type = None;
+    let mut pos = 0;
+    loop {
+        let end = pos + output[pos..].iter().position(|&b| b == b'\n')?;
+        let line = std::str::from_utf8(&output[pos..end]).ok()?;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        pos = end + 1;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        let value = value.trim();
+        // A name that isn't a token, or a value with a stray CR or NUL,
+        // makes the block malformed rather than reaching the client.
+        let name = HeaderName::try_from(name).ok()?;
+        let value = HeaderValue::try_from(value).ok()?;
+        if name.as_str().eq_ignore_ascii_case("Status") {
+            let code = value.as_str().split(' ').next()?.parse().ok()?;
+            status = Some(StatusCode::from_u16(code)?);
+        } else if name.as_str().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value);
+        } else {
+            headers.append(name, value);
+        }
+    }
+    let status = match status {
+        Some(status) => status,
+        None if headers.contains("Location") => StatusCode::FOUND,
+        None if content_type.is_some() => StatusCode::OK,
+        None => return None,
+    };
+    output.drain(..pos);
+    Some(HttpResponse {
+        status,
+        content_type: content_type.unwrap_or_default(),
+        headers,
+        body: Body::Binary(output),
+    })
+}
@@ -0,0 +1,123 @@
+//! Cross-Origin Resource Sharing (CORS).
+//!
+//! Browsers only let a page on one origin read responses from another origin
+//! if the server says so through `Access-Control-*` headers. This module
+//! decides which headers to add to a response, and answers preflight
+//! (`OPTIONS` + `Access-Control-Request-Method`) requests on its own so they
+//! never reach the filesystem.
+use crate::http::headers::{self, HeaderMap, HeaderValue};
+use crate::http::request::{HttpRequest, Method};
+use crate::http::response::{HttpResponse, empty_response};
+use crate::http::status::StatusCode;
+
+/// Which origins are allowed to make cross-origin requests.
+///
+/// Variants:
+/// - `Any`: Every origin is allowed and `Access-Control-Allow-Origin: *` is sent.
+/// - `List(Vec<String>)`: Only these exact origins (e.g. `https://app.example.com`)
+///   are allowed. Matching is a plain string compare, so `https://example.com.evil.org`
+///   never matches `https://example.com`.
+#[derive(Debug, Clone)]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+/// CORS policy for the server.
+///
+/// # Fields
+/// - `allowed_origins` (*AllowedOrigins*): Origins that get CORS headers.
+/// - `allowed_methods` (*Vec<Method>*): Methods advertised in preflight responses.
+/// - `allowed_headers` (*Vec<String>*): Request headers advertised in preflight responses.
+/// - `max_age` (*Option<u64>*): Seconds a browser may cache a preflight result.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<String>,
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: AllowedOrigins::Any,
+            allowed_methods: vec![Method::Get, Method::Head, Method::Options],
+            allowed_headers: Vec::new(),
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Returns the value for `Access-Control-Allow-Origin` if `origin` is allowed.
+    fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        match &self.allowed_origins {
+            AllowedOrigins::Any => Some(HeaderValue::from_static("*")),
+            AllowedOrigins::List(list) => list
+                .iter()
+                .any(|allowed| allowed == origin)
+                .then_some(origin)
+                .and_then(|origin| HeaderValue::try_from(origin).ok()),
+        }
+    }
+
+    /// Answers a CORS preflight request.
+    ///
+    /// A preflight is an `OPTIONS` request carrying both `Origin` and
+    /// `Access-Control-Request-Method`. It always gets a `204 No Content`;
+    /// the `Access-Control-*` headers are only added when the origin is allowed.
+    ///
+    /// # Returns
+    /// - `Some(HttpResponse)`: The request was a preflight and this is the answer.
+    /// - `None`: The request is not a preflight and should be handled normally.
+    pub(crate) fn preflight(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        if request.method != Method::Options {
+            return None;
+        }
+        let origin = request.header("Origin")?;
+        request.header("Access-Control-Request-Method")?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(allow_origin) = self.allow_origin(origin) {
+            let methods: Vec<&str> = self.allowed_methods.iter().map(Method::as_str).collect();
+            headers.insert(headers::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            if let Ok(methods) = HeaderValue::try_from(methods.join(", ")) {
+                headers.insert(headers::ACCESS_CONTROL_ALLOW_METHODS, methods);
+            }
+            if !self.allowed_headers.is_empty()
+                && let Ok(allowed) = HeaderValue::try_from(self.allowed_headers.join(", "))
+            {
+                headers.insert(headers::ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+            }
+            if let Some(max_age) = self.max_age {
+                headers.insert(headers::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+            }
+        }
+        if matches!(self.allowed_origins, AllowedOrigins::List(_)) {
+            headers.insert(headers::VARY, HeaderValue::from(headers::ORIGIN));
+        }
+
+        let mut response = empty_response(StatusCode::NO_CONTENT);
+        response.headers = headers;
+        Some(response)
+    }
+
+    /// Adds `Access-Control-Allow-Origin` to a normal (non-preflight) response
+    /// when the request carries an allowed `Origin`.
+    pub(crate) fn apply(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        if matches!(self.allowed_origins, AllowedOrigins::List(_)) {
+            response
+                .headers
+                .append(headers::VARY, HeaderValue::from(headers::ORIGIN));
+        }
+        let Some(origin) = request.header("Origin") else {
+            return;
+        };
+        if let Some(allow_origin) = self.allow_origin(origin) {
+            response
+                .headers
+                .insert(headers::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+    }
+}
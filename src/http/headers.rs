@@ -0,0 +1,511 @@
+//! Storage and lookup for HTTP header fields.
+//!
+//! Header names are case-insensitive (RFC 7230 §3.2), so every lookup here
+//! compares names with `eq_ignore_ascii_case`. The original casing is kept
+//! so that serialized responses look the way they were written.
+//!
+//! Fields only go in as a `HeaderName` and a `HeaderValue`, which check what
+//! they hold when they are made: a name is a token, and a value has no CR, LF
+//! or NUL. Whatever a value was built from (a request's path, a file's name,
+//! an upstream's answer), it can't end its header line early and start
+//! another one.
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// A header field name: a non-empty token (RFC 7230 §3.2.6).
+///
+/// Compared ignoring ASCII case; `as_str` gives it as it was written.
+///
+/// # Example
+/// ```
+/// let name = HeaderName::try_from("X-Frame-Options")?;
+/// assert_eq!(name, HeaderName::from_static("x-frame-options"));
+/// assert!(HeaderName::try_from("X-Bad\r\nSet-Cookie").is_err());
+/// ```
+#[derive(Debug, Clone, Eq)]
+pub struct HeaderName(Cow<'static, str>);
+
+impl HeaderName {
+    /// Returns the name `name`, checked at compile time when used in a constant.
+    ///
+    /// # Panics
+    /// If `name` isn't a token.
+    pub const fn from_static(name: &'static str) -> HeaderName {
+        assert!(is_token(name), "invalid header name");
+        HeaderName(Cow::Borrowed(name))
+    }
+
+    /// Returns the name as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &HeaderName) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl TryFrom<&str> for HeaderName {
+    type Error = InvalidHeader;
+
+    fn try_from(name: &str) -> Result<HeaderName, InvalidHeader> {
+        HeaderName::try_from(name.to_owned())
+    }
+}
+
+impl TryFrom<String> for HeaderName {
+    type Error = InvalidHeader;
+
+    fn try_from(name: String) -> Result<HeaderName, InvalidHeader> {
+        if is_token(&name) {
+            Ok(HeaderName(Cow::Owned(name)))
+        } else {
+            Err(InvalidHeader::Name)
+        }
+    }
+}
+
+impl fmt::Display for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A header field value: any text without CR, LF or NUL.
+///
+/// # Example
+/// ```
+/// let value = HeaderValue::try_from(format!("/files/{name}"))?;
+/// assert!(HeaderValue::try_from("/a\r\nSet-Cookie: x=1").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeaderValue(Cow<'static, str>);
+
+impl HeaderValue {
+    /// Returns the value `value`, checked at compile time when used in a
+    /// constant.
+    ///
+    /// # Panics
+    /// If `value` holds a CR, LF or NUL.
+    pub const fn from_static(value: &'static str) -> HeaderValue {
+        assert!(is_field_value(value), "invalid header value");
+        HeaderValue(Cow::Borrowed(value))
+    }
+
+    /// Returns the value as text.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns `true` if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl TryFrom<&str> for HeaderValue {
+    type Error = InvalidHeader;
+
+    fn try_from(value: &str) -> Result<HeaderValue, InvalidHeader> {
+        HeaderValue::try_from(value.to_owned())
+    }
+}
+
+impl TryFrom<String> for HeaderValue {
+    type Error = InvalidHeader;
+
+    fn try_from(value: String) -> Result<HeaderValue, InvalidHeader> {
+        if is_field_value(&value) {
+            Ok(HeaderValue(Cow::Owned(value)))
+        } else {
+            Err(InvalidHeader::Value)
+        }
+    }
+}
+
+impl From<u64> for HeaderValue {
+    fn from(n: u64) -> HeaderValue {
+        HeaderValue(Cow::Owned(n.to_string()))
+    }
+}
+
+impl From<HeaderName> for HeaderValue {
+    // A token is always a valid value, e.g. for `Vary` or `Allow`.
+    fn from(name: HeaderName) -> HeaderValue {
+        HeaderValue(name.0)
+    }
+}
+
+impl fmt::Display for HeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Why a header name or value was refused.
+///
+/// Variants:
+/// - `Name`: The name is empty or has a character a token can't.
+/// - `Value`: The value has a CR, LF or NUL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidHeader {
+    Name,
+    Value,
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidHeader::Name => f.write_str("invalid header name"),
+            InvalidHeader::Value => f.write_str("header value contains CR, LF or NUL"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidHeader {}
+
+// The characters a token may have besides letters and digits.
+const TOKEN_SYMBOLS: &[u8] = b"!#$%&'*+-.^_`|~";
+
+/// Returns `true` if `name` is a token: one or more of the characters
+/// RFC 7230 §3.2.6 allows in one.
+pub(crate) const fn is_token(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_alphanumeric() && !contains(TOKEN_SYMBOLS, bytes[i]) {
+            return false;
+        }
+        i += 1;
+    }
+    !bytes.is_empty()
+}
+
+/// Returns `true` if `bytes` contains `b` (`<[u8]>::contains` isn't `const`).
+const fn contains(bytes: &[u8], b: u8) -> bool {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Returns `true` if `value` can be sent as a field value: it has no CR, LF
+/// or NUL.
+const fn is_field_value(value: &str) -> bool {
+    !contains(value.as_bytes(), b'\r')
+        && !contains(value.as_bytes(), b'\n')
+        && !contains(value.as_bytes(), b'\0')
+}
+
+// The names used across the crate, written the usual way.
+pub const ACCEPT: HeaderName = HeaderName::from_static("Accept");
+pub const ACCESS_CONTROL_ALLOW_HEADERS: HeaderName =
+    HeaderName::from_static("Access-Control-Allow-Headers");
+pub const ACCESS_CONTROL_ALLOW_METHODS: HeaderName =
+    HeaderName::from_static("Access-Control-Allow-Methods");
+pub const ACCESS_CONTROL_ALLOW_ORIGIN: HeaderName =
+    HeaderName::from_static("Access-Control-Allow-Origin");
+pub const ACCESS_CONTROL_MAX_AGE: HeaderName = HeaderName::from_static("Access-Control-Max-Age");
+pub const ALLOW: HeaderName = HeaderName::from_static("Allow");
+pub const AUTHORIZATION: HeaderName = HeaderName::from_static("Authorization");
+pub const CACHE_CONTROL: HeaderName = HeaderName::from_static("Cache-Control");
+pub const CONNECTION: HeaderName = HeaderName::from_static("Connection");
+pub const CONTENT_DISPOSITION: HeaderName = HeaderName::from_static("Content-Disposition");
+pub const CONTENT_LENGTH: HeaderName = HeaderName::from_static("Content-Length");
+pub const CONTENT_TYPE: HeaderName = HeaderName::from_static("Content-Type");
+pub const ETAG: HeaderName = HeaderName::from_static("ETag");
+pub const FORWARDED: HeaderName = HeaderName::from_static("Forwarded");
+pub const HOST: HeaderName = HeaderName::from_static("Host");
+pub const IF_NONE_MATCH: HeaderName = HeaderName::from_static("If-None-Match");
+pub const LOCATION: HeaderName = HeaderName::from_static("Location");
+pub const ORIGIN: HeaderName = HeaderName::from_static("Origin");
+pub const SEC_WEBSOCKET_ACCEPT: HeaderName = HeaderName::from_static("Sec-WebSocket-Accept");
+pub const SEC_WEBSOCKET_VERSION: HeaderName = HeaderName::from_static("Sec-WebSocket-Version");
+pub const STRICT_TRANSPORT_SECURITY: HeaderName =
+    HeaderName::from_static("Strict-Transport-Security");
+pub const TRANSFER_ENCODING: HeaderName = HeaderName::from_static("Transfer-Encoding");
+pub const UPGRADE: HeaderName = HeaderName::from_static("Upgrade");
+pub const VARY: HeaderName = HeaderName::from_static("Vary");
+pub const WWW_AUTHENTICATE: HeaderName = HeaderName::from_static("WWW-Authenticate");
+
+/// An ordered collection of HTTP header fields.
+///
+/// Headers are stored as `(name, value)` pairs in insertion order. The same
+/// name may appear more than once (e.g. `Vary` or `Set-Cookie`), which is why
+/// this is a `Vec` and not a `HashMap`.
+///
+/// # Example
+/// ```
+/// let mut headers = HeaderMap::new();
+/// headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+/// assert_eq!(headers.get("content-type"), Some("text/html"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderMap {
+    /// Creates an empty `HeaderMap`.
+    pub fn new() -> HeaderMap {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets a header, replacing every existing field with the same name.
+    pub fn insert(&mut self, name: HeaderName, value: HeaderValue) {
+        self.remove(name.as_str());
+        self.append(name, value);
+    }
+
+    /// Adds a header field without touching existing fields of the same name.
+    pub fn append(&mut self, name: HeaderName, value: HeaderValue) {
+        self.entries.push((name, value));
+    }
+
+    /// Returns the value of the first field named `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the values of every field named `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if at least one field named `name` is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Removes every field named `name`.
+    pub fn remove(&mut self, name: &str) {
+        self.retain(|n, _| !n.eq_ignore_ascii_case(name));
+    }
+
+    /// Keeps only the fields for which `keep(name, value)` returns `true`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &str) -> bool) {
+        self.entries.retain(|(n, v)| keep(n.as_str(), v.as_str()));
+    }
+
+    /// Iterates over all `(name, value)` pairs in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of header fields.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no header fields.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// What the proxies in front of the server recorded about a request, from
+/// the `Forwarded` header (RFC 7239) or from `X-Forwarded-For`,
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` (see `ForwardedHeader`).
+///
+/// Nothing here is checked: any client can send these headers, so only the
+/// hops trusted proxies added are worth believing. `HttpRequest::forwarded_client`
+/// picks those out.
+///
+/// # Fields
+/// - `hops` (*Vec<ForwardedHop>*): One per proxy, in the order they were
+///   added: the first proxy's (naming the client, if it told the truth) first
+///   and the nearest proxy's last.
+///
+/// # Example
+/// ```
+/// let mut headers = HeaderMap::new();
+/// headers.insert(FORWARDED, HeaderValue::from_static("for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\""));
+/// let forwarded = Forwarded::from_headers(&headers, ForwardedHeader::Forwarded);
+/// assert_eq!(forwarded.hops[0].proto.as_deref(), Some("https"));
+/// assert_eq!(forwarded.hops[1].address(), "2001:db8::1".parse().ok());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Forwarded {
+    pub hops: Vec<ForwardedHop>,
+}
+
+/// Which header trusted proxies record their hops in.
+///
+/// Only that header is read; the other is whatever the client sent and
+/// proves nothing, even behind a trusted proxy.
+///
+/// Variants:
+/// - `XForwarded`: `X-Forwarded-For`, `-Proto` and `-Host`, as nginx and most
+///   load balancers write them. The default.
+/// - `Forwarded`: The standard `Forwarded` header (RFC 7239).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    #[default]
+    XForwarded,
+    Forwarded,
+}
+
+impl ForwardedHeader {
+    /// Parses a configured header name: `"x-forwarded"` or `"forwarded"`,
+    /// ignoring case.
+    pub fn parse(name: &str) -> Result<ForwardedHeader, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "x-forwarded" | "x-forwarded-for" => Ok(ForwardedHeader::XForwarded),
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            _ => Err(format!("{name}: expected \"forwarded\" or \"x-forwarded\"")),
+        }
+    }
+}
+
+/// One hop of `Forwarded`: what a proxy saw of the request it was sent.
+///
+/// # Fields
+/// - `node` (*Option<String>*): Who sent it (`for=`, or an entry of
+///   `X-Forwarded-For`), unquoted: an address, maybe with a port, `unknown`
+///   or an obfuscated name.
+/// - `proto` (*Option<String>*): The scheme it came in with (`proto=`), e.g. `https`.
+/// - `host` (*Option<String>*): The `Host` it came in with (`host=`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedHop {
+    pub node: Option<String>,
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Forwarded {
+    /// Collects the hops recorded in `headers`, reading only `header`.
+    ///
+    /// The comma-separated `X-Forwarded-*` lists are lined up from the end,
+    /// where the nearest proxy added its entry, so with one proxy
+    /// `X-Forwarded-Proto: https` describes the last `X-Forwarded-For` hop.
+    pub fn from_headers(headers: &HeaderMap, header: ForwardedHeader) -> Forwarded {
+        if header == ForwardedHeader::Forwarded {
+            let hops = headers
+                .get_all("Forwarded")
+                .flat_map(|value| split_unquoted(value, ','))
+                .filter(|element| !element.trim().is_empty())
+                .map(ForwardedHop::parse)
+                .collect();
+            return Forwarded { hops };
+        }
+        let list = |name: &str| -> Vec<String> {
+            headers
+                .get_all(name)
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        let (nodes, protos, hosts) = (
+            list("X-Forwarded-For"),
+            list("X-Forwarded-Proto"),
+            list("X-Forwarded-Host"),
+        );
+        let len = nodes.len().max(protos.len()).max(hosts.len());
+        let at = |list: &[String], hop: usize| {
+            (hop + list.len())
+                .checked_sub(len)
+                .and_then(|i| list.get(i).cloned())
+        };
+        let hops = (0..len)
+            .map(|hop| ForwardedHop {
+                node: at(&nodes, hop),
+                proto: at(&protos, hop),
+                host: at(&hosts, hop),
+            })
+            .collect();
+        Forwarded { hops }
+    }
+}
+
+impl ForwardedHop {
+    /// Parses one element of a `Forwarded` header, e.g.
+    /// `for="[2001:db8::1]:4711";proto=https`. Parameter names ignore case;
+    /// unknown parameters, and repeats of known ones, are skipped.
+    fn parse(element: &str) -> ForwardedHop {
+        let mut hop = ForwardedHop::default();
+        for pair in split_unquoted(element, ';') {
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let field = match name.trim().to_ascii_lowercase().as_str() {
+                "for" => &mut hop.node,
+                "proto" => &mut hop.proto,
+                "host" => &mut hop.host,
+                _ => continue,
+            };
+            if field.is_none() {
+                *field = Some(unquote(value.trim()));
+            }
+        }
+        hop
+    }
+
+    /// Returns the IP address in `node`, without its port, if it is one:
+    /// `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` or `[2001:db8::1]:4711`.
+    pub fn address(&self) -> Option<IpAddr> {
+        let node = self.node.as_deref()?.trim();
+        if let Some(bracketed) = node.strip_prefix('[') {
+            return bracketed.split_once(']')?.0.parse().ok();
+        }
+        node.parse()
+            .ok()
+            .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+    }
+}
+
+/// Splits `value` at every `separator` outside a quoted string.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Returns `value` without its quotes and backslash escapes if it is a
+/// quoted string, or as it is otherwise.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_owned();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
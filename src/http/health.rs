@@ -0,0 +1,72 @@
+//! Built-in health probes for load balancers and orchestrators.
+//!
+//! `healthz` answers `200 ok` for as long as the event loop runs. `readyz`
+//! answers `200 ready` once the server is serving (it only starts after the
+//! document root was checked and every address bound) and `503 draining`
+//! from the moment a shutdown is asked for, so a load balancer stops sending
+//! traffic before the server stops accepting it (see
+//! `HealthConfig::drain_delay`).
+//!
+//! Probes are answered on the event loop itself: they skip the router, the
+//! middleware and the filesystem, and get through even while every worker
+//! thread is busy.
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::{HttpRequest, Method};
+use crate::http::response::HttpResponse;
+use crate::http::status::StatusCode;
+use std::time::Duration;
+
+/// Health probe settings.
+///
+/// # Fields
+/// - `healthz` (*String*): The liveness probe's path. Defaults to `/healthz`.
+/// - `readyz` (*String*): The readiness probe's path. Defaults to `/readyz`.
+/// - `log` (*bool*): Print a line per probe at `LogLevel::Info`, like
+///   `RequestLog`. Defaults to `false`: probes come every few seconds and
+///   would drown out everything else.
+/// - `drain_delay` (*Duration*): How long the server keeps accepting and
+///   serving after a shutdown is asked for, with `readyz` failing, before it
+///   starts draining. A second signal skips the rest of the wait. Defaults to
+///   zero.
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub healthz: String,
+    pub readyz: String,
+    pub log: bool,
+    pub drain_delay: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig {
+            healthz: String::from("/healthz"),
+            readyz: String::from("/readyz"),
+            log: false,
+            drain_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Answers `request` if it is a `GET` or `HEAD` of one of the probes.
+    /// `ready` is `false` once a shutdown has been asked for.
+    ///
+    /// # Returns
+    /// - `Some(HttpResponse)`: The probe's answer, never cached.
+    /// - `None`: Not a probe; the request is handled normally.
+    pub(crate) fn probe(&self, request: &HttpRequest, ready: bool) -> Option<HttpResponse> {
+        if !matches!(request.method, Method::Get | Method::Head) {
+            return None;
+        }
+        let response = if request.path == self.healthz {
+            HttpResponse::text(StatusCode::OK, "ok")
+        } else if request.path == self.readyz && ready {
+            HttpResponse::text(StatusCode::OK, "ready")
+        } else if request.path == self.readyz {
+            HttpResponse::text(StatusCode::SERVICE_UNAVAILABLE, "draining")
+        } else {
+            return None;
+        };
+        Some(response.with_header(headers::CACHE_CONTROL, HeaderValue::from_static("no-store")))
+    }
+}
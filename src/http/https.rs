@@ -0,0 +1,165 @@
+//! Sending plain-HTTP clients to HTTPS, and keeping them there.
+//!
+//! A listener in redirect mode (see `HttpsRedirectConfig`) serves nothing:
+//! every request on it gets `301 Moved Permanently` to the same path and
+//! query on `https://`, answered on the event loop. A request body is never
+//! read; the connection closes after the redirect instead, so its bytes
+//! can't be taken for the next request.
+//!
+//! Once a client has come over, `Strict-Transport-Security` (see
+//! `HstsConfig`) tells its browser to use HTTPS from then on, without going
+//! through the redirect again. The header is only sent on TLS connections;
+//! browsers ignore it over plain HTTP.
+//!
+//! # Example
+//! ```toml
+//! addresses = ["[::]:80", "[::]:443"]
+//!
+//! [redirect_to_https]
+//! addresses = ["[::]:80"]
+//! canonical_host = "example.com"
+//!
+//! [hsts]
+//! max_age = 31536000
+//! ```
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::{HttpResponse, empty_response};
+use crate::http::status::StatusCode;
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+/// Listeners that redirect every request to HTTPS.
+///
+/// # Fields
+/// - `addresses` (*Vec<String>*): Entries of `ServerConfig::addresses` in
+///   redirect mode, e.g. `[::]:80`. Empty (the default) means every address
+///   not served over TLS.
+/// - `canonical_host` (*Option<String>*): The host redirected to when the
+///   request's `Host` is missing or isn't a valid host name, without a port.
+///   `None` (the default) answers those requests with `400 Bad Request`.
+/// - `https_port` (*Option<u16>*): The port HTTPS is served on, put in the
+///   `Location` unless it is 443. Defaults to `None`, meaning 443.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpsRedirectConfig {
+    pub addresses: Vec<String>,
+    pub canonical_host: Option<String>,
+    pub https_port: Option<u16>,
+}
+
+impl HttpsRedirectConfig {
+    /// Returns `true` if connections arriving on `address` are redirected,
+    /// unless that address serves TLS.
+    pub fn serves(&self, address: &str) -> bool {
+        self.addresses.is_empty() || self.addresses.iter().any(|plain| plain == address)
+    }
+
+    /// Returns where `request` is redirected to: `https://`, its host
+    /// (the `Host` header's name, or else `canonical_host`), `https_port`
+    /// if any, then its path and query as sent.
+    ///
+    /// `None` if neither host is usable.
+    pub fn location(&self, request: &HttpRequest) -> Option<String> {
+        let host = request
+            .header("Host")
+            .and_then(host_name)
+            .or_else(|| self.canonical_host.clone())?;
+        let mut location = format!("https://{host}");
+        if let Some(port) = self.https_port.filter(|&port| port != 443) {
+            location.push_str(&format!(":{port}"));
+        }
+        // Only origin-form targets have a path of their own to keep.
+        if request.path.starts_with('/') {
+            location.push_str(&request.path);
+        } else {
+            location.push('/');
+        }
+        if let Some(query) = &request.query {
+            location.push('?');
+            location.push_str(query);
+        }
+        Some(location)
+    }
+
+    /// Returns the redirect for `request` (see `location`), or `None` to
+    /// answer it with `400 Bad Request`.
+    pub(crate) fn redirect(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let location = HeaderValue::try_from(self.location(request)?).ok()?;
+        let mut response = empty_response(StatusCode::MOVED_PERMANENTLY);
+        response.headers.insert(headers::LOCATION, location);
+        Some(response)
+    }
+}
+
+/// `Strict-Transport-Security` settings.
+///
+/// # Fields
+/// - `max_age` (*Duration*): How long browsers keep to HTTPS after seeing the
+///   header, in whole seconds. Defaults to a year.
+/// - `include_subdomains` (*bool*): Whether that holds for every subdomain,
+///   too. Defaults to `false`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HstsConfig {
+    pub max_age: Duration,
+    pub include_subdomains: bool,
+}
+
+impl Default for HstsConfig {
+    fn default() -> HstsConfig {
+        HstsConfig {
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            include_subdomains: false,
+        }
+    }
+}
+
+impl HstsConfig {
+    /// Adds `Strict-Transport-Security` to `response` if `request` came over
+    /// TLS and the response doesn't have one already.
+    pub fn apply(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        if !request.secure || response.header("Strict-Transport-Security").is_some() {
+            return;
+        }
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if let Ok(value) = HeaderValue::try_from(value) {
+            response
+                .headers
+                .insert(headers::STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+}
+
+/// Returns the host name in a `Host` header value, in lowercase and without
+/// its port, if it is one: a DNS name, an IPv4 address or a bracketed IPv6
+/// address, followed by an optional numeric port.
+pub(crate) fn host_name(host: &str) -> Option<String> {
+    let host = host.trim();
+    let (name, port) = match host.strip_prefix('[') {
+        Some(rest) => {
+            let (address, port) = rest.split_once(']')?;
+            address.parse::<Ipv6Addr>().ok()?;
+            (&host[..address.len() + 2], port)
+        }
+        None => host.split_at(host.find(':').unwrap_or(host.len())),
+    };
+    if let Some(port) = port.strip_prefix(':') {
+        port.bytes().all(|b| b.is_ascii_digit()).then_some(())?;
+        port.parse::<u16>().ok()?;
+    } else if !port.is_empty() {
+        return None;
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    let valid = name.starts_with('[') || (name.len() <= 253 && name.split('.').all(valid_label));
+    valid.then(|| name.to_ascii_lowercase())
+}
@@ -0,0 +1,68 @@
+//! A Prometheus scrape endpoint.
+//!
+//! `GET` (or `HEAD`) of `MetricsConfig::path` is answered on the event loop
+//! with `ServerStats::write_prometheus`, so scrapes skip the router and the
+//! filesystem and go on working while every worker is busy.
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::{HttpRequest, Method};
+use crate::http::response::HttpResponse;
+use crate::http::status::StatusCode;
+use crate::stats::ServerStats;
+use crate::util::Cidr;
+
+// Enough for the whole exposition, so a scrape allocates once.
+const EXPOSITION_CAPACITY: usize = 4096;
+
+/// Metrics endpoint settings.
+///
+/// # Fields
+/// - `path` (*String*): Where the metrics are served. Defaults to `/metrics`.
+/// - `allow` (*Vec<Cidr>*): The client addresses that may scrape; anyone else
+///   gets `403 Forbidden`. Matched against the connection's peer, never a
+///   forwarded address. Empty (the default) allows everyone.
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub path: String,
+    pub allow: Vec<Cidr>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            path: String::from("/metrics"),
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Answers `request` if it is a `GET` or `HEAD` of `path`.
+    ///
+    /// # Returns
+    /// - `Some(HttpResponse)`: The metrics from `stats`, or `403` for a client
+    ///   `allow` leaves out.
+    /// - `None`: Not a scrape; the request is handled normally.
+    pub(crate) fn serve(&self, request: &HttpRequest, stats: &ServerStats) -> Option<HttpResponse> {
+        if !matches!(request.method, Method::Get | Method::Head) || request.path != self.path {
+            return None;
+        }
+        let allowed = self.allow.is_empty()
+            || request
+                .peer
+                .is_some_and(|peer| self.allow.iter().any(|range| range.contains(peer)));
+        if !allowed {
+            return Some(HttpResponse::text(StatusCode::FORBIDDEN, "forbidden"));
+        }
+        let mut exposition = String::with_capacity(EXPOSITION_CAPACITY);
+        // Writing to a `String` can't fail.
+        let _ = stats.write_prometheus(&mut exposition);
+        Some(
+            HttpResponse::bytes(
+                StatusCode::OK,
+                HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+                exposition.into_bytes(),
+            )
+            .with_header(headers::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        )
+    }
+}
@@ -0,0 +1,385 @@
+//! Forwarding requests under a path prefix to another HTTP server.
+//!
+//! With a `ProxyConfig` for `/api`, `GET /api/users` is sent on to the
+//! upstream (say an application server on `127.0.0.1:3000`) while everything
+//! else is still served from disk. The request keeps its method, headers and
+//! body, minus the hop-by-hop headers, plus `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host`; the upstream's status, headers
+//! and body are relayed back the same way.
+//!
+//! This is a first cut: the exchange is a blocking HTTP/1.1 request made from
+//! the pool thread handling the request, on a new connection each time, and
+//! the upstream's response is read whole before it is relayed.
+use crate::http::headers::{HeaderMap, HeaderName, HeaderValue};
+use crate::http::request::{HttpRequest, Method};
+use crate::http::response::{Body, ErrorPage, HttpResponse};
+use crate::http::status::StatusCode;
+use crate::log;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Header fields that concern a single connection rather than the message
+/// (RFC 9110 §7.6.1), so they are never passed on in either direction. Fields
+/// named in `Connection` are dropped as well.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// The largest upstream response head accepted, in bytes.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Forwarding of the requests under a prefix to an upstream server (see
+/// `ServerConfig::proxies`).
+///
+/// # Fields
+/// - `prefix` (*String*): Request paths, as sent, that are the prefix or continue
+///   it with a `/` are forwarded: `/api` covers `/api` and `/api/users`, but not
+///   `/apis`. A trailing `/` makes no difference.
+/// - `upstream` (*String*): Where to, as `http://host:port`, optionally followed by
+///   a path that is put in front of every forwarded path. Without a port, 80.
+/// - `strip_prefix` (*bool*): When `true`, the prefix is removed from the path
+///   before forwarding (`/api/users` becomes `/users`). Defaults to `false`.
+/// - `connect_timeout` (*Duration*): How long connecting to the upstream may take.
+///   Defaults to 5 seconds.
+/// - `timeout` (*Duration*): How long the upstream may go without accepting or
+///   sending data once connected. Defaults to 30 seconds.
+///
+/// # Example
+/// ```
+/// let mut api = ProxyConfig::new("/api", "http://127.0.0.1:3000")?;
+/// api.strip_prefix = true;
+/// let config = ServerConfig::builder().proxy(api).build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub prefix: String,
+    pub upstream: String,
+    pub strip_prefix: bool,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+}
+
+impl ProxyConfig {
+    /// Returns a proxy from `prefix` to `upstream` with the default settings.
+    ///
+    /// # Errors
+    /// The reason, if `prefix` doesn't start with `/` or `upstream` isn't an
+    /// `http://` URL.
+    pub fn new(prefix: &str, upstream: &str) -> Result<ProxyConfig, String> {
+        if !prefix.starts_with('/') {
+            return Err(format!("proxy prefix {prefix} must start with /"));
+        }
+        if split_upstream(upstream).is_none() {
+            return Err(format!(
+                "proxy upstream {upstream}: expected http://host:port"
+            ));
+        }
+        Ok(ProxyConfig {
+            prefix: prefix.to_owned(),
+            upstream: upstream.to_owned(),
+            strip_prefix: false,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Returns the part of the request `path` below the prefix, starting
+    /// with `/`, or `None` if the request isn't forwarded.
+    pub fn strip<'p>(&self, path: &'p str) -> Option<&'p str> {
+        match path.strip_prefix(self.prefix.trim_end_matches('/'))? {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    /// Sends `request` to the upstream and returns its response.
+    ///
+    /// # Errors
+    /// - `ErrorPage::GatewayTimeout` if connecting, sending or receiving took
+    ///   longer than the timeouts allow.
+    /// - `ErrorPage::BadGateway` if the upstream can't be reached, or its
+    ///   response isn't valid HTTP/1.x or is cut short.
+    pub(crate) fn forward(&self, request: &HttpRequest) -> Result<HttpResponse, ErrorPage> {
+        let (authority, base) = split_upstream(&self.upstream).ok_or(ErrorPage::BadGateway)?;
+        let path = match self.strip(&request.path) {
+            Some(rest) if self.strip_prefix => rest,
+            _ => &request.path,
+        };
+        let mut target = format!("{}{path}", base.trim_end_matches('/'));
+        if let Some(query) = &request.query {
+            target.push('?');
+            target.push_str(query);
+        }
+        let head = request_head(request, authority, &target);
+
+        let mut stream = self.connect(authority)?;
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(&request.body))
+            .map_err(|e| self.failed(&e))?;
+        read_response(&mut stream, request.method == Method::Head).map_err(|e| self.failed(&e))
+    }
+
+    /// Connects to the first address of `authority` that accepts.
+    fn connect(&self, authority: &str) -> Result<TcpStream, ErrorPage> {
+        let address = if has_port(authority) {
+            authority.to_owned()
+        } else {
+            format!("{authority}:80")
+        };
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+        for address in address.to_socket_addrs().map_err(|e| self.failed(&e))? {
+            match TcpStream::connect_timeout(&address, self.connect_timeout) {
+                Ok(stream) => {
+                    stream
+                        .set_read_timeout(Some(self.timeout))
+                        .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+                        .map_err(|e| self.failed(&e))?;
+                    return Ok(stream);
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(self.failed(&error))
+    }
+
+    /// Logs a failed exchange with the upstream and returns the page for it.
+    fn failed(&self, error: &io::Error) -> ErrorPage {
+        log::error(format_args!(
+            "proxy {} to {}: {error}",
+            self.prefix, self.upstream
+        ));
+        match error.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorPage::GatewayTimeout,
+            _ => ErrorPage::BadGateway,
+        }
+    }
+}
+
+/// Splits an `http://` URL into its authority and path, e.g.
+/// `http://127.0.0.1:3000/v1` into `127.0.0.1:3000` and `/v1`.
+fn split_upstream(upstream: &str) -> Option<(&str, &str)> {
+    let rest = upstream.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, ""),
+    };
+    let valid = !authority.is_empty()
+        && !authority.contains(['@', '?', '#'])
+        && !authority.contains(char::is_whitespace);
+    valid.then_some((authority, path))
+}
+
+/// Returns `true` if `authority` ends in a port (not just an IPv6 literal).
+fn has_port(authority: &str) -> bool {
+    authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'))
+}
+
+/// Returns `true` if the header `name` must not be forwarded: a hop-by-hop
+/// header, one named in `Connection` (`listed`), or `Content-Length`, which
+/// is recomputed.
+fn is_hop_by_hop(name: &str, listed: &[&str]) -> bool {
+    HOP_BY_HOP
+        .iter()
+        .chain(listed)
+        .chain(&["Content-Length"])
+        .any(|hop| hop.eq_ignore_ascii_case(name))
+}
+
+/// Returns the names listed in the `Connection` headers of `headers`.
+fn connection_options(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Builds the head of the request sent to the upstream.
+///
+/// `Host` names the upstream; the client's is passed on in `X-Forwarded-Host`.
+/// The client's own `X-Forwarded-Proto` and `X-Forwarded-Host` are replaced,
+/// while `X-Forwarded-For` gets the peer appended. The connection is closed
+/// after one exchange.
+fn request_head(request: &HttpRequest, authority: &str, target: &str) -> String {
+    let listed = connection_options(&request.headers);
+    let mut head = format!(
+        "{} {target} HTTP/1.1\r\nHost: {authority}\r\n",
+        request.method.as_str()
+    );
+    for (name, value) in request.headers.iter() {
+        let replaced = [
+            "Host",
+            "X-Forwarded-For",
+            "X-Forwarded-Proto",
+            "X-Forwarded-Host",
+        ]
+        .iter()
+        .any(|replaced| replaced.eq_ignore_ascii_case(name));
+        if !replaced && !is_hop_by_hop(name, &listed) {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    let mut forwarded_for: Vec<String> = request
+        .headers
+        .get_all("X-Forwarded-For")
+        .map(|value| value.trim().to_owned())
+        .collect();
+    if let Some(peer) = request.peer {
+        forwarded_for.push(peer.to_string());
+    }
+    if !forwarded_for.is_empty() {
+        head.push_str(&format!(
+            "X-Forwarded-For: {}\r\n",
+            forwarded_for.join(", ")
+        ));
+    }
+    let proto = if request.secure { "https" } else { "http" };
+    head.push_str(&format!("X-Forwarded-Proto: {proto}\r\n"));
+    if let Some(host) = request.header("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+    }
+    if !request.body.is_empty()
+        || matches!(request.method, Method::Post | Method::Put | Method::Patch)
+    {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    head
+}
+
+/// Reads the upstream's response to a request and turns it into the one
+/// relayed to the client. Interim `1xx` responses are skipped.
+///
+/// # Errors
+/// `io::ErrorKind::InvalidData` for anything that isn't a well-formed
+/// response, `UnexpectedEof` for one that is cut short, and whatever reading
+/// the stream fails with.
+fn read_response(stream: &mut TcpStream, head_only: bool) -> io::Result<HttpResponse> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+    let mut buf: Vec<u8> = Vec::new();
+    let (status, headers) = loop {
+        let head_end = loop {
+            if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end;
+            }
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(invalid("response head too large"));
+            }
+            if read_more(stream, &mut buf)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        };
+        let head = std::str::from_utf8(&buf[..head_end])
+            .map_err(|_| invalid("response head is not UTF-8"))?;
+        let (status, headers) =
+            parse_head(head).ok_or_else(|| invalid("malformed response head"))?;
+        buf.drain(..head_end + 4);
+        if !(100..200).contains(&status.as_u16()) || status.as_u16() == 101 {
+            break (status, headers);
+        }
+    };
+
+    let chunked = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .last()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+    let body = if head_only || status.is_bodiless() {
+        Vec::new()
+    } else if chunked {
+        stream.read_to_end(&mut buf)?;
+        decode_chunked(&buf).ok_or_else(|| invalid("malformed chunked body"))?
+    } else if let Some(length) = headers.get("Content-Length") {
+        let length: usize = length
+            .trim()
+            .parse()
+            .map_err(|_| invalid("bad Content-Length"))?;
+        while buf.len() < length {
+            if read_more(stream, &mut buf)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        buf.truncate(length);
+        buf
+    } else {
+        stream.read_to_end(&mut buf)?;
+        buf
+    };
+
+    let listed = connection_options(&headers);
+    let content_type = headers
+        .get("Content-Type")
+        .and_then(|content_type| HeaderValue::try_from(content_type).ok())
+        .unwrap_or_default();
+    let mut relayed = headers.clone();
+    relayed.retain(|name, _| {
+        !is_hop_by_hop(name, &listed) && !name.eq_ignore_ascii_case("Content-Type")
+    });
+    Ok(HttpResponse {
+        status,
+        content_type,
+        headers: relayed,
+        body: Body::Binary(body),
+    })
+}
+
+/// Reads whatever is available from `stream` onto the end of `buf`.
+fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; 8192];
+    let read = stream.read(&mut chunk)?;
+    buf.extend_from_slice(&chunk[..read]);
+    Ok(read)
+}
+
+/// Parses a response head (without the blank line) into its status and headers.
+fn parse_head(head: &str) -> Option<(StatusCode, HeaderMap)> {
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next()?;
+    let (version, rest) = status_line.split_once(' ')?;
+    if !version.starts_with("HTTP/1.") {
+        return None;
+    }
+    let code = rest.split(' ').next()?;
+    let status = StatusCode::from_u16(code.parse().ok()?)?;
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.append(
+            HeaderName::try_from(name).ok()?,
+            HeaderValue::try_from(value.trim()).ok()?,
+        );
+    }
+    Some((status, headers))
+}
+
+/// Decodes a complete chunked body, dropping chunk extensions and trailers.
+fn decode_chunked(mut bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = bytes.windows(2).position(|window| window == b"\r\n")?;
+        let line = std::str::from_utf8(&bytes[..line_end]).ok()?;
+        let size = line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        bytes = &bytes[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        let chunk = bytes.get(..size)?;
+        body.extend_from_slice(chunk);
+        bytes = bytes.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
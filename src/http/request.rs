@@ -0,0 +1,636 @@
+//! Parsing of incoming HTTP/1.x requests.
+//!
+//! The parser works on raw bytes taken straight from a connection's read
+//! buffer. It never blocks and never consumes anything itself: it either
+//! returns a complete request head together with the number of bytes it
+//! occupied, or reports that more bytes are needed.
+use crate::http::headers::{self, Forwarded, ForwardedHeader, HeaderMap, HeaderName, HeaderValue};
+use crate::util::Cidr;
+use std::net::IpAddr;
+
+/// The most header fields `parse_request` accepts in one request head; the
+/// default of `ServerConfig::max_header_fields`.
+pub const MAX_HEADER_FIELDS: usize = 100;
+
+/// The longest request head, in bytes, `parse_request` accepts: the request
+/// line and header fields with their line breaks. The default of
+/// `ServerConfig::max_head_size`.
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// An HTTP request method.
+///
+/// Known methods get their own variant; anything else that is still a valid
+/// token ends up in `Other` so the dispatch layer can decide what to do with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Trace,
+    Connect,
+    Other(String),
+}
+
+impl Method {
+    /// Maps a method token from the request line to a `Method`.
+    ///
+    /// Method names are case-sensitive (RFC 7231 §4.1), so `get` is *not* `GET`.
+    pub fn from_token(token: &str) -> Method {
+        match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    /// Returns the method as it appears on the wire.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Other(token) => token,
+        }
+    }
+}
+
+/// A parsed HTTP request.
+///
+/// # Fields
+/// - `method` (*Method*): The request method.
+/// - `target` (*String*): The raw request target exactly as sent (e.g. `/a/b?x=1`).
+/// - `path` (*String*): The path portion of the target, without the query.
+/// - `query` (*Option<String>*): The query string without the leading `?`, if any.
+/// - `version` (*String*): The protocol version (e.g. `HTTP/1.1`).
+/// - `headers` (*HeaderMap*): The request header fields.
+/// - `body` (*Vec<u8>*): The request body, empty until the body has been read.
+/// - `peer` (*Option<IpAddr>*): The address the connection came from, set by the
+///   reactor. `None` for Unix socket clients and requests built by hand. Behind a
+///   proxy this is the proxy; see `client_address` for the client itself.
+/// - `secure` (*bool*): Whether the request arrived over TLS, set by the reactor.
+/// - `id` (*Option<String>*): The request's ID, set by the reactor when
+///   `ServerConfig::request_id` is (see `http::request_id`). `None` otherwise.
+/// - `user` (*Option<String>*): Who the client proved to be, set by an
+///   authentication middleware such as `BasicAuth` or `BearerAuth` for the
+///   layers and handlers after it. `None` until then.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub target: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub peer: Option<IpAddr>,
+    pub secure: bool,
+    pub id: Option<String>,
+    pub user: Option<String>,
+}
+
+impl HttpRequest {
+    /// Returns the value of the header `name`, if present.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns `true` if the connection should stay open after this request.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends
+    /// `Connection: close`; HTTP/1.0 ones close unless it sends
+    /// `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_token = |wanted: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|value| value.split(','))
+                .any(|token| token.trim().eq_ignore_ascii_case(wanted))
+        };
+        if has_token("close") {
+            return false;
+        }
+        self.version != "HTTP/1.0" || has_token("keep-alive")
+    }
+
+    /// Returns the scheme and the credentials of the `Authorization` header,
+    /// e.g. `("Basic", "YWxpY2U6c2VjcmV0")`. Schemes are case-insensitive
+    /// (RFC 9110 §11.1), so compare them with `eq_ignore_ascii_case`.
+    ///
+    /// `None` without the header, or if it has no credentials after the
+    /// scheme.
+    pub fn authorization(&self) -> Option<(&str, &str)> {
+        let value = self.header("Authorization")?.trim();
+        let (scheme, credentials) = value.split_once([' ', '\t'])?;
+        let credentials = credentials.trim_start_matches([' ', '\t']);
+        (!scheme.is_empty() && !credentials.is_empty()).then_some((scheme, credentials))
+    }
+
+    /// Returns `true` if the client would rather have HTML than anything
+    /// else: its `Accept` header names `text/html` with a quality above zero
+    /// and no lower than that of any other type, as browsers' do when
+    /// following a link. `*/*` alone, or no `Accept` at all, isn't enough.
+    pub fn prefers_html(&self) -> bool {
+        let (mut html, mut best) = (0.0_f32, 0.0_f32);
+        for range in self
+            .headers
+            .get_all("Accept")
+            .flat_map(|value| value.split(','))
+        {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let quality = params
+                .find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("q").then_some(value)
+                })
+                .map_or(1.0, |value| value.trim().parse().unwrap_or(0.0));
+            if media_type.eq_ignore_ascii_case("text/html") {
+                html = html.max(quality);
+            }
+            best = best.max(quality);
+        }
+        html > 0.0 && html >= best
+    }
+
+    /// Returns the address of the client the request comes from; see
+    /// `forwarded_client`.
+    ///
+    /// # Example
+    /// ```
+    /// let (mut request, _) =
+    ///     parse_request(b"GET / HTTP/1.1\r\nX-Forwarded-For: 1.2.3.4, 10.0.0.2\r\n\r\n").unwrap();
+    /// request.peer = "10.0.0.1".parse().ok();
+    /// let proxies = [Cidr::parse("10.0.0.0/8").unwrap()];
+    /// let header = ForwardedHeader::XForwarded;
+    /// assert_eq!(request.client_address(&proxies, header), "1.2.3.4".parse().ok());
+    /// assert_eq!(request.client_address(&[], header), "10.0.0.1".parse().ok());
+    /// ```
+    pub fn client_address(
+        &self,
+        trusted_proxies: &[Cidr],
+        header: ForwardedHeader,
+    ) -> Option<IpAddr> {
+        self.forwarded_client(trusted_proxies, header)
+            .map(|client| client.address)
+    }
+
+    /// Returns the client the request comes from, as far as the proxies in
+    /// `trusted_proxies`, which record their hops in `header`, vouch for it.
+    ///
+    /// That is `peer`, unless `peer` is one of `trusted_proxies`. Then the hops
+    /// the proxies recorded in `header` (see `Forwarded`) are walked back from
+    /// the nearest one, past every trusted proxy, and the first address that
+    /// isn't one is the client. Addresses further back were written by the
+    /// client itself and prove nothing, and so does the other header. A hop
+    /// that isn't an IP address (`unknown`, an obfuscated name) ends the walk
+    /// at the proxy that reported it.
+    ///
+    /// `proto` and `host` come from the last hop walked, or the nearest one
+    /// before it that has them, so a proxy that sets `X-Forwarded-Proto`
+    /// without forwarding the one it received still counts.
+    ///
+    /// `None` without a `peer`.
+    pub fn forwarded_client(
+        &self,
+        trusted_proxies: &[Cidr],
+        header: ForwardedHeader,
+    ) -> Option<ForwardedClient> {
+        let trusted = |address: IpAddr| trusted_proxies.iter().any(|range| range.contains(address));
+        let mut client = ForwardedClient {
+            address: self.peer?,
+            proto: None,
+            host: None,
+        };
+        if !trusted(client.address) {
+            return Some(client);
+        }
+        for hop in Forwarded::from_headers(&self.headers, header)
+            .hops
+            .into_iter()
+            .rev()
+        {
+            // A trusted proxy wrote this hop, so what it says about the
+            // request it got holds.
+            let address = hop.address();
+            client.proto = hop.proto.or(client.proto);
+            client.host = hop.host.or(client.host);
+            let Some(address) = address else {
+                break;
+            };
+            client.address = address;
+            if !trusted(address) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The client a request comes from, as far as trusted proxies vouch for it
+/// (see `HttpRequest::forwarded_client`).
+///
+/// # Fields
+/// - `address` (*IpAddr*): The client's address.
+/// - `proto` (*Option<String>*): The scheme the client used, e.g. `https`, if a
+///   trusted proxy said.
+/// - `host` (*Option<String>*): The `Host` the client sent, if a trusted proxy said.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedClient {
+    pub address: IpAddr,
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+/// Reasons a request could not be parsed.
+///
+/// Variants:
+/// - `Incomplete`: The head or body has not fully arrived yet; read more bytes.
+/// - `Malformed`: The bytes can never form a valid request. Carries a short reason.
+/// - `TooLarge`: The body is larger than the limit the caller allows.
+/// - `TooManyHeaders`: The head has more header fields than the caller allows.
+/// - `HeadTooLarge`: The head is longer than the caller allows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Incomplete,
+    Malformed(&'static str),
+    TooLarge,
+    TooManyHeaders,
+    HeadTooLarge,
+}
+
+/// How the body of a request is delimited (RFC 7230 §3.3.3).
+///
+/// Variants:
+/// - `None`: The request has no body.
+/// - `Length(u64)`: The body is exactly this many bytes (`Content-Length`).
+/// - `Chunked`: The body uses `Transfer-Encoding: chunked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+/// Parses a request head (request line and header fields) from `buf`,
+/// allowing up to `MAX_HEADER_FIELDS` header fields and `MAX_HEAD_SIZE` bytes.
+///
+/// # Parameters
+/// - `buf`: The bytes read from the connection so far.
+///
+/// # Returns
+/// - `Ok((HttpRequest, usize))`: The parsed request (with an empty body) and
+///   the number of bytes the head occupied, including the final `\r\n\r\n`.
+/// - `Err(ParseError::Incomplete)`: More bytes are needed.
+/// - `Err(ParseError::Malformed(_))`: The head is invalid.
+/// - `Err(ParseError::TooManyHeaders)`: The head has too many header fields.
+/// - `Err(ParseError::HeadTooLarge)`: The head is too long.
+///
+/// # Example
+/// ```
+/// let (req, used) = parse_request(b"GET /index.html HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+/// assert_eq!(req.method, Method::Get);
+/// assert_eq!(used, 37);
+/// ```
+pub fn parse_request(buf: &[u8]) -> Result<(HttpRequest, usize), ParseError> {
+    parse_request_limited(buf, MAX_HEADER_FIELDS, MAX_HEAD_SIZE)
+}
+
+/// Parses a request head like `parse_request`, allowing up to
+/// `max_header_fields` header fields and `max_head_size` bytes (see
+/// `find_head_end`).
+///
+/// The head must follow RFC 7230's syntax: the method is a token, the target
+/// has no control characters, every header name is a token (so no bytes
+/// above 0x7F, and no whitespace before the colon) and no value has a CR, LF
+/// or NUL. Anything else is `Malformed`; the caller can't trust where this
+/// request ends, so it should answer `400 Bad Request` and close.
+pub fn parse_request_limited(
+    buf: &[u8],
+    max_header_fields: usize,
+    max_head_size: usize,
+) -> Result<(HttpRequest, usize), ParseError> {
+    let head_len = find_head_end(buf, 0, max_head_size)?;
+
+    let head = std::str::from_utf8(&buf[..head_len - 4])
+        .map_err(|_| ParseError::Malformed("request head is not valid UTF-8"))?;
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+        _ => return Err(ParseError::Malformed("invalid request line")),
+    };
+    if !headers::is_token(method) {
+        return Err(ParseError::Malformed("invalid method"));
+    }
+    if target.chars().any(char::is_control) {
+        return Err(ParseError::Malformed("invalid request target"));
+    }
+    if !version.starts_with("HTTP/") {
+        return Err(ParseError::Malformed("invalid protocol version"));
+    }
+
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        if headers.len() == max_header_fields {
+            return Err(ParseError::TooManyHeaders);
+        }
+        let (name, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => return Err(ParseError::Malformed("header line without a colon")),
+        };
+        let Ok(name) = HeaderName::try_from(name) else {
+            return Err(ParseError::Malformed("invalid header name"));
+        };
+        let Ok(value) = HeaderValue::try_from(value.trim_matches(|c| c == ' ' || c == '\t')) else {
+            return Err(ParseError::Malformed("invalid header value"));
+        };
+        headers.append(name, value);
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let request = HttpRequest {
+        method: Method::from_token(method),
+        target: target.to_string(),
+        path,
+        query,
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+        peer: None,
+        secure: false,
+        id: None,
+        user: None,
+    };
+
+    Ok((request, head_len))
+}
+
+/// Determines how the body of `request` is framed.
+///
+/// A request carrying both `Transfer-Encoding` and `Content-Length`, several
+/// different `Content-Length` values, or a transfer coding other than a final
+/// `chunked` is rejected: those are exactly the ambiguities request smuggling
+/// relies on.
+pub fn body_framing(request: &HttpRequest) -> Result<BodyFraming, ParseError> {
+    let lengths: Vec<&str> = request.headers.get_all("Content-Length").collect();
+    let encodings: Vec<&str> = request.headers.get_all("Transfer-Encoding").collect();
+
+    if !encodings.is_empty() {
+        if !lengths.is_empty() {
+            return Err(ParseError::Malformed(
+                "both Transfer-Encoding and Content-Length",
+            ));
+        }
+        let last = encodings
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .next_back()
+            .unwrap_or("");
+        return if last.eq_ignore_ascii_case("chunked") {
+            Ok(BodyFraming::Chunked)
+        } else {
+            Err(ParseError::Malformed("unsupported transfer coding"))
+        };
+    }
+
+    let mut length = None;
+    for value in lengths.iter().flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::Malformed("invalid Content-Length"));
+        }
+        let parsed: u64 = value
+            .parse()
+            .map_err(|_| ParseError::Malformed("invalid Content-Length"))?;
+        if length.is_some_and(|previous| previous != parsed) {
+            return Err(ParseError::Malformed("conflicting Content-Length values"));
+        }
+        length = Some(parsed);
+    }
+    Ok(match length {
+        Some(0) | None => BodyFraming::None,
+        Some(length) => BodyFraming::Length(length),
+    })
+}
+
+/// Reads a request body framed as `framing` from the start of `buf`.
+///
+/// Chunked bodies are decoded; chunk extensions and trailer fields are
+/// accepted and dropped.
+///
+/// # Parameters
+/// - `buf`: The bytes following the request head.
+/// - `framing`: The framing from `body_framing`.
+/// - `limit`: The largest body, in bytes, the caller is willing to accept.
+///
+/// # Returns
+/// - `Ok((Vec<u8>, usize))`: The decoded body and the number of bytes of `buf` it occupied.
+/// - `Err(ParseError::Incomplete)`: More bytes are needed.
+/// - `Err(ParseError::TooLarge)`: The body exceeds `limit`. This is reported as
+///   soon as it is known, before the whole body has arrived.
+/// - `Err(ParseError::Malformed(_))`: The chunked encoding is invalid.
+pub fn read_body(
+    buf: &[u8],
+    framing: BodyFraming,
+    limit: u64,
+) -> Result<(Vec<u8>, usize), ParseError> {
+    match framing {
+        BodyFraming::None => Ok((Vec::new(), 0)),
+        BodyFraming::Length(length) if length > limit => Err(ParseError::TooLarge),
+        BodyFraming::Length(length) if (buf.len() as u64) < length => Err(ParseError::Incomplete),
+        BodyFraming::Length(length) => Ok((buf[..length as usize].to_vec(), length as usize)),
+        BodyFraming::Chunked => read_chunked(buf, limit),
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body; see `read_body`.
+fn read_chunked(buf: &[u8], limit: u64) -> Result<(Vec<u8>, usize), ParseError> {
+    ChunkedDecoder::new(limit).decode(buf)
+}
+
+/// Where a `ChunkedDecoder` is in the body.
+///
+/// Variants:
+/// - `Size`: At a chunk-size line.
+/// - `Data(u64)`: Inside a chunk's data, with this many bytes of it to go.
+/// - `DataEnd`: At the `\r\n` after a chunk's data.
+/// - `Trailer`: Past the last chunk, at a trailer field or the blank line.
+#[derive(Debug, Clone, Copy)]
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailer,
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body as it arrives, picking up
+/// where it stopped the last time instead of starting over, so a body sent
+/// in many small pieces costs no more than one sent at once.
+#[derive(Debug)]
+pub(crate) struct ChunkedDecoder {
+    limit: u64,
+    state: ChunkState,
+    // The decoded body so far.
+    body: Vec<u8>,
+    // How much of the input has been decoded.
+    pos: usize,
+    // How far the input has been searched for the end of a line that
+    // hadn't ended yet.
+    scanned: usize,
+}
+
+impl ChunkedDecoder {
+    /// Creates a decoder for a body of at most `limit` bytes, decoded.
+    pub(crate) fn new(limit: u64) -> ChunkedDecoder {
+        ChunkedDecoder {
+            limit,
+            state: ChunkState::Size,
+            body: Vec::new(),
+            pos: 0,
+            scanned: 0,
+        }
+    }
+
+    /// Decodes what `buf` holds beyond what earlier calls decoded. `buf`
+    /// starts where the body does every time, with the bytes read since the
+    /// last call added at its end.
+    ///
+    /// # Returns
+    /// As `read_body`: once the body is complete, the whole of it and the
+    /// number of bytes of `buf` it occupied.
+    pub(crate) fn decode(&mut self, buf: &[u8]) -> Result<(Vec<u8>, usize), ParseError> {
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line_end = self.line_end(buf)?;
+                    let line = std::str::from_utf8(&buf[self.pos..line_end])
+                        .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+                    let size_field = line.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size_field, 16)
+                        .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+                    // The size comes straight from the client; check it
+                    // before adding.
+                    let fits = usize::try_from(size)
+                        .ok()
+                        .and_then(|size| (line_end + 2).checked_add(size))
+                        .and_then(|data_end| data_end.checked_add(2))
+                        .is_some();
+                    if !fits || size > self.limit.saturating_sub(self.body.len() as u64) {
+                        return Err(ParseError::TooLarge);
+                    }
+                    self.pos = line_end + 2;
+                    self.state = match size {
+                        0 => ChunkState::Trailer,
+                        size => ChunkState::Data(size),
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    let available = (buf.len() - self.pos) as u64;
+                    let take = remaining.min(available) as usize;
+                    self.body.extend_from_slice(&buf[self.pos..self.pos + take]);
+                    self.pos += take;
+                    if (take as u64) < remaining {
+                        self.state = ChunkState::Data(remaining - take as u64);
+                        return Err(ParseError::Incomplete);
+                    }
+                    self.state = ChunkState::DataEnd;
+                }
+                ChunkState::DataEnd => {
+                    let end = buf
+                        .get(self.pos..self.pos + 2)
+                        .ok_or(ParseError::Incomplete)?;
+                    if end != b"\r\n" {
+                        return Err(ParseError::Malformed("chunk not terminated by CRLF"));
+                    }
+                    self.pos += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailer => {
+                    // Trailer fields, then the blank line that ends the body.
+                    let line_end = self.line_end(buf)?;
+                    let blank = line_end == self.pos;
+                    self.pos = line_end + 2;
+                    if blank {
+                        return Ok((std::mem::take(&mut self.body), self.pos));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the offset in `buf` of the `\r\n` ending the line at `pos`,
+    /// searching only what wasn't searched before.
+    fn line_end(&mut self, buf: &[u8]) -> Result<usize, ParseError> {
+        // The `\r\n` may have begun in the last byte searched.
+        let from = self.scanned.saturating_sub(1).max(self.pos);
+        match find_line_end(&buf[from..]) {
+            Some(end) => Ok(from + end),
+            None => {
+                self.scanned = buf.len();
+                Err(ParseError::Incomplete)
+            }
+        }
+    }
+}
+
+/// Returns the offset of the next `\r\n` in `buf`, if there is one.
+fn find_line_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
+/// Finds the end of the request head at the start of `buf`.
+///
+/// The search resumes at `scanned`, the length `buf` had the last time this
+/// returned `Incomplete` for the same head (0 the first time), so the bytes
+/// already searched aren't searched again each time more arrive.
+///
+/// # Returns
+/// - `Ok(usize)`: The length of the head, including the final `\r\n\r\n`.
+/// - `Err(ParseError::Incomplete)`: The head hasn't ended yet, and still fits
+///   in `max_head_size` bytes.
+/// - `Err(ParseError::HeadTooLarge)`: The head is longer than `max_head_size`.
+/// - `Err(ParseError::Malformed(_))`: Its request line alone is.
+pub fn find_head_end(
+    buf: &[u8],
+    scanned: usize,
+    max_head_size: usize,
+) -> Result<usize, ParseError> {
+    // The terminator may have begun in the last three bytes searched.
+    let from = scanned.saturating_sub(3).min(buf.len());
+    let end = buf[from..]
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| from + pos + 4);
+    match end {
+        Some(end) if end <= max_head_size => Ok(end),
+        None if buf.len() <= max_head_size => Err(ParseError::Incomplete),
+        _ => match find_line_end(&buf[..max_head_size.min(buf.len())]) {
+            Some(_) => Err(ParseError::HeadTooLarge),
+            None => Err(ParseError::Malformed("request line too long")),
+        },
+    }
+}
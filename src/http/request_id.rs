@@ -0,0 +1,65 @@
+//! Request IDs, for following one request through the logs.
+//!
+//! Every request gets an ID as soon as it is parsed: the one the client (or
+//! a proxy in front) sent in `RequestIdConfig::header`, if that is trusted
+//! and looks like one, or a new one from `util::request_id`. Handlers find it
+//! in `HttpRequest::id`, the response carries it back in the same header, and
+//! the access log and every error logged while handling the request name it
+//! (see `log::error`).
+use crate::http::headers::{HeaderName, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::HttpResponse;
+use crate::util;
+
+// Longer incoming IDs are replaced rather than copied into every log line.
+const MAX_INCOMING_LEN: usize = 128;
+
+/// Request ID settings.
+///
+/// # Fields
+/// - `header` (*String*): The header an incoming ID is read from and the
+///   response's is written to. Defaults to `X-Request-Id`.
+/// - `trust_incoming` (*bool*): Keep the ID a request arrives with, so one
+///   ID follows it across services. Only visible ASCII of at most 128 bytes
+///   is kept; anything else gets a new ID. Defaults to `true`; turn it off
+///   when clients could use it to muddle the logs.
+#[derive(Debug, Clone)]
+pub struct RequestIdConfig {
+    pub header: String,
+    pub trust_incoming: bool,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> RequestIdConfig {
+        RequestIdConfig {
+            header: String::from("X-Request-Id"),
+            trust_incoming: true,
+        }
+    }
+}
+
+impl RequestIdConfig {
+    /// Sets `request.id`: the incoming one if trusted, otherwise a new one.
+    pub(crate) fn assign(&self, request: &mut HttpRequest) {
+        let incoming = request
+            .header(&self.header)
+            .filter(|id| self.trust_incoming && is_valid(id));
+        request.id = Some(incoming.map_or_else(util::request_id, str::to_owned));
+    }
+
+    /// Copies the ID of `request` into the header of `response`.
+    pub(crate) fn echo(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        // `ServerConfig::build` checked the name.
+        if let Some(id) = &request.id
+            && let Ok(name) = HeaderName::try_from(self.header.as_str())
+            && let Ok(id) = HeaderValue::try_from(id.as_str())
+        {
+            response.headers.insert(name, id);
+        }
+    }
+}
+
+/// Returns `true` if `id` is fit to be kept: 1 to 128 bytes of visible ASCII.
+fn is_valid(id: &str) -> bool {
+    (1..=MAX_INCOMING_LEN).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic())
+}
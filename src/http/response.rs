@@ -4,11 +4,23 @@
 //! `/public` directory, as well as helpers for detecting and returning
 //! appropriate MIME types. All functions here are synchronous and
 //! blocking. Future implementations may be asynchronous.
+use crate::http::body::StreamBody;
+use crate::http::headers::{self, HeaderMap, HeaderName, HeaderValue};
+use crate::http::request::{HttpRequest, Method};
+use crate::http::rewrite::{self, Outcome};
+use crate::http::status::StatusCode;
+use crate::http::template::{self, TemplateConfig};
+use crate::http::websocket;
 use crate::io;
-use mime_guess::{from_path, mime};
+use crate::io::assets::AssetSource;
+use crate::io::file::{FileBody, FileError};
+use crate::log;
+use crate::server::{RouteMatch, ServerConfig, SpaFallback};
+use crate::util;
+use mime_guess::from_path;
 use std::io::Write;
-use std::net::TcpStream;
 use std::path::Path;
+use std::time::SystemTime;
 
 /// An enumeration representing different types of error pages that can be displayed in an application.
 ///
@@ -16,71 +28,149 @@ use std::path::Path;
 /// or messages to the users. Each variant corresponds to a specific error scenario.
 ///
 /// Variants:
+/// - `BadRequest`: Indicates that the request could not be understood (HTTP 400).
 /// - `NotFound`: Indicates that the requested resource could not be found (HTTP 404).
 /// - `PermissionDenied`: Indicates that the user does not have the necessary permissions
 ///   to access the requested resource (HTTP 403).
+/// - `MethodNotAllowed`: Indicates that the method is known but not supported for the
+///   requested resource (HTTP 405).
+/// - `RequestTimeout`: Indicates that the request didn't arrive in time (HTTP 408).
+/// - `Conflict`: Indicates that the request conflicts with the state of the target,
+///   e.g. an upload whose parent directory doesn't exist (HTTP 409).
+/// - `PayloadTooLarge`: Indicates that the request body exceeds the configured limit (HTTP 413).
+/// - `MisdirectedRequest`: Indicates that the server doesn't serve the requested host (HTTP 421).
+/// - `HeaderFieldsTooLarge`: Indicates that the request has more header fields than the
+///   configured limit (HTTP 431).
 /// - `InternalServerError`: Indicates that an unexpected server error has occurred (HTTP 500).
+/// - `NotImplemented`: Indicates that the method is not recognized at all (HTTP 501).
+/// - `BadGateway`: Indicates that a proxied upstream couldn't be reached or sent an
+///   invalid response (HTTP 502).
+/// - `ServiceUnavailable`: Indicates that the server is at its connection limit (HTTP 503).
+/// - `GatewayTimeout`: Indicates that a proxied upstream didn't answer in time (HTTP 504).
 ///
 /// Use this enum to clearly define and handle error scenarios in your application.
-enum ErrorPage {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPage {
+    BadRequest,
     NotFound,
     PermissionDenied,
+    MethodNotAllowed,
+    RequestTimeout,
+    Conflict,
+    PayloadTooLarge,
+    MisdirectedRequest,
+    HeaderFieldsTooLarge,
     InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
 }
 
 /// Returns the path to the error page for the given error page variant.
 impl ErrorPage {
-    /// Returns the file path of the HTML page corresponding to the error type.
+    /// Returns the path of the HTML page corresponding to the error type.
     ///
-    /// This function maps the current `ErrorPage` variant to its associated
-    /// HTML file path, which represents the error page to be displayed.
+    /// Error pages live at the top of the asset source and are named after
+    /// their status code: `/400.html`, `/403.html`, `/404.html`, `/405.html`,
+    /// `/409.html`, `/413.html`, `/421.html`, `/431.html`, `/500.html`,
+    /// `/501.html`, `/502.html`, `/503.html` and `/504.html`.
     ///
     /// # Returns
     ///
-    /// A `String` containing the file path of the error page.
-    ///
-    /// # Variants
-    ///
-    /// * `ErrorPage::NotFound` - Returns `"public/404.html"`, the path for the 404 Not Found error page.
-    /// * `ErrorPage::PermissionDenied` - Returns `"public/403.html"`, the path for the 403 Permission Denied error page.
-    /// * `ErrorPage::InternalServerError` - Returns `"public/500.html"`, the path for the 500 Internal Server Error page.
+    /// A `String` containing the root-relative path of the error page.
     ///
     /// # Example
     ///
     /// ```
     /// let error = ErrorPage::NotFound;
-    /// assert_eq!(error.path(), "public/404.html");
+    /// assert_eq!(error.path(), "/404.html");
     /// ```
     fn path(&self) -> String {
-        match self {
-            ErrorPage::NotFound => String::from("public/404.html"),
-            ErrorPage::PermissionDenied => String::from("public/403.html"),
-            ErrorPage::InternalServerError => String::from("public/500.html"),
-        }
+        format!("/{}.html", self.status().as_u16())
     }
 
-    /// Returns the HTTP response status line as a `String` corresponding to the error type.
-    ///
-    /// This method matches the current variant of the `ErrorPage` enum and returns a properly formatted
-    /// HTTP response status line as a `String`.
+    /// Returns the HTTP status code corresponding to the error type.
     ///
     /// # Variants
     ///
-    /// - `ErrorPage::NotFound`: Returns `"HTTP/1.1 404 NOT FOUND"`
-    /// - `ErrorPage::PermissionDenied`: Returns `"HTTP/1.1 403 PERMISSION DENIED"`
-    /// - `ErrorPage::InternalServerError`: Returns `"HTTP/1.1 500 INTERNAL SERVER ERROR"`
+    /// - `ErrorPage::BadRequest`: Returns `StatusCode::BAD_REQUEST` (`400 Bad Request`)
+    /// - `ErrorPage::NotFound`: Returns `StatusCode::NOT_FOUND` (`404 Not Found`)
+    /// - `ErrorPage::PermissionDenied`: Returns `StatusCode::FORBIDDEN` (`403 Forbidden`)
+    /// - `ErrorPage::MethodNotAllowed`: Returns `StatusCode::METHOD_NOT_ALLOWED` (`405 Method Not Allowed`)
+    /// - `ErrorPage::RequestTimeout`: Returns `StatusCode::REQUEST_TIMEOUT` (`408 Request Timeout`)
+    /// - `ErrorPage::Conflict`: Returns `StatusCode::CONFLICT` (`409 Conflict`)
+    /// - `ErrorPage::PayloadTooLarge`: Returns `StatusCode::PAYLOAD_TOO_LARGE` (`413 Payload Too Large`)
+    /// - `ErrorPage::MisdirectedRequest`: Returns `StatusCode::MISDIRECTED_REQUEST`
+    ///   (`421 Misdirected Request`)
+    /// - `ErrorPage::HeaderFieldsTooLarge`: Returns `StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE`
+    ///   (`431 Request Header Fields Too Large`)
+    /// - `ErrorPage::InternalServerError`: Returns `StatusCode::INTERNAL_SERVER_ERROR`
+    ///   (`500 Internal Server Error`)
+    /// - `ErrorPage::NotImplemented`: Returns `StatusCode::NOT_IMPLEMENTED` (`501 Not Implemented`)
+    /// - `ErrorPage::BadGateway`: Returns `StatusCode::BAD_GATEWAY` (`502 Bad Gateway`)
+    /// - `ErrorPage::ServiceUnavailable`: Returns `StatusCode::SERVICE_UNAVAILABLE`
+    ///   (`503 Service Unavailable`)
+    /// - `ErrorPage::GatewayTimeout`: Returns `StatusCode::GATEWAY_TIMEOUT` (`504 Gateway Timeout`)
     ///
     /// # Examples
     ///
     /// ```
     /// let error = ErrorPage::NotFound;
-    /// assert_eq!(error.status(), "HTTP/1.1 404 NOT FOUND");
+    /// assert_eq!(error.status().status_line(), "HTTP/1.1 404 Not Found");
     /// ```
-    fn status(&self) -> String {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorPage::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorPage::NotFound => StatusCode::NOT_FOUND,
+            ErrorPage::PermissionDenied => StatusCode::FORBIDDEN,
+            ErrorPage::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorPage::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorPage::Conflict => StatusCode::CONFLICT,
+            ErrorPage::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorPage::MisdirectedRequest => StatusCode::MISDIRECTED_REQUEST,
+            ErrorPage::HeaderFieldsTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ErrorPage::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorPage::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ErrorPage::BadGateway => StatusCode::BAD_GATEWAY,
+            ErrorPage::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorPage::GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Returns the built-in HTML for this error, compiled into the binary.
+    ///
+    /// This is served byte-for-byte whenever the error page file on disk is
+    /// missing or unreadable, so producing an error response never depends on
+    /// the state of the filesystem.
+    fn fallback(&self) -> &'static str {
         match self {
-            ErrorPage::NotFound => String::from("HTTP/1.1 404 NOT FOUND"),
-            ErrorPage::PermissionDenied => String::from("HTTP/1.1 403 PERMISSION DENIED"),
-            ErrorPage::InternalServerError => String::from("HTTP/1.1 500 INTERNAL SERVER ERROR"),
+            ErrorPage::BadRequest => include_str!("fallback/400.html"),
+            ErrorPage::NotFound => include_str!("fallback/404.html"),
+            ErrorPage::PermissionDenied => include_str!("fallback/403.html"),
+            ErrorPage::MethodNotAllowed => include_str!("fallback/405.html"),
+            ErrorPage::RequestTimeout => include_str!("fallback/408.html"),
+            ErrorPage::Conflict => include_str!("fallback/409.html"),
+            ErrorPage::PayloadTooLarge => include_str!("fallback/413.html"),
+            ErrorPage::MisdirectedRequest => include_str!("fallback/421.html"),
+            ErrorPage::HeaderFieldsTooLarge => include_str!("fallback/431.html"),
+            ErrorPage::InternalServerError => include_str!("fallback/500.html"),
+            ErrorPage::NotImplemented => include_str!("fallback/501.html"),
+            ErrorPage::BadGateway => include_str!("fallback/502.html"),
+            ErrorPage::ServiceUnavailable => include_str!("fallback/503.html"),
+            ErrorPage::GatewayTimeout => include_str!("fallback/504.html"),
+        }
+    }
+}
+
+impl From<&FileError> for ErrorPage {
+    /// Maps a failure to access the file being served to the page for it:
+    /// `NotFound` to 404, `PermissionDenied` to 403, anything else to 500.
+    fn from(error: &FileError) -> ErrorPage {
+        match error {
+            FileError::NotFound => ErrorPage::NotFound,
+            FileError::PermissionDenied => ErrorPage::PermissionDenied,
+            FileError::Io(_) => ErrorPage::InternalServerError,
         }
     }
 }
@@ -91,22 +181,26 @@ impl ErrorPage {
 /// including its status, content type, and body.
 ///
 /// # Fields
-/// - `status` (*String*): The HTTP status code and description (e.g., "200 OK", "404 Not Found").
-/// - `content_type` (*String*): The MIME type of the content being returned (e.g., "text/html", "application/json").
+/// - `status` (*StatusCode*): The HTTP status code (e.g., `StatusCode::OK`, `StatusCode::NOT_FOUND`).
+/// - `content_type` (*HeaderValue*): The MIME type of the content being returned (e.g., "text/html", "application/json").
+///   Left empty for responses without a body such as `204 No Content`.
+/// - `headers` (*HeaderMap*): Additional header fields (e.g. CORS headers).
 /// - `body` (*Body*): The actual data being sent as part of the response. The `Body` type represents the content of the response and may encapsulate text, binary data, etc.
 ///
 /// # Example
 /// ```
 /// let response = HttpResponse {
-///     status: String::from("200 OK"),
-///     content_type: String::from("application/json"),
+///     status: StatusCode::OK,
+///     content_type: HeaderValue::from_static("application/json"),
+///     headers: HeaderMap::new(),
 ///     body: Body::Text(String::from("{\"key\": \"value\"}")),
 /// };
 /// ```
-struct HttpResponse {
-    status: String,
-    content_type: String,
-    body: Body,
+pub struct HttpResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) content_type: HeaderValue,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Body,
 }
 
 /// An `enum` representing the possible types of body content.
@@ -125,6 +219,14 @@ struct HttpResponse {
 ///   Represents the body content as binary data.
 ///   Useful for handling non-text data such as images, files, or other raw byte streams.
 ///
+/// - `Stream(StreamBody)`
+///   Represents content produced piece by piece (see `http::body`), sent either with a
+///   known `Content-Length` or with `Transfer-Encoding: chunked` (optionally with trailers).
+///
+/// - `File(FileBody)`
+///   Represents a file sent as-is with `Content-Length`. The reactor sends it with
+///   `sendfile(2)` where available instead of copying it through the write buffer.
+///
 /// # Examples
 ///
 /// ```rust
@@ -134,56 +236,269 @@ struct HttpResponse {
 /// // A binary body containing raw byte data
 /// let binary_body = Body::Binary(vec![0xDE, 0xAD, 0xBE, 0xEF]);
 /// ```
-enum Body {
+pub enum Body {
     Text(String),
     Binary(Vec<u8>),
+    Stream(StreamBody),
+    File(FileBody),
 }
 
-/// Handles an incoming HTTP connection by constructing and sending an HTTP response.
-///
-/// # Arguments
-///
-/// * `stream` - A reference to the `TcpStream` representing the client's connection.
-/// * `response` - A `String` containing the content to be included in the HTTP response body.
-///
-/// # Functionality
+/// The methods the static file handler supports, as sent in `Allow` headers.
+const STATIC_ALLOW: HeaderValue = HeaderValue::from_static("GET, HEAD, OPTIONS");
+
+/// Handles a parsed HTTP request and returns the serialized response bytes.
 ///
-/// 1. Creates an `HttpResponse` object by processing the provided `response` string with `create_http_response`.
-/// 2. Writes the generated HTTP response to the provided `TcpStream` using the `write_response` function.
+/// This is `handle` followed by serialization: stream bodies are drained
+/// completely into the returned buffer, and `HEAD` responses are serialized
+/// without their body. The reactor uses `handle` and
+/// `HttpResponse::start_writing` instead so large bodies are never buffered.
 ///
 /// # Example
 ///
 /// ```
-/// use std::net::{TcpListener, TcpStream};
+/// let (request, _) = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+/// let bytes = http_handler(&request, &ServerConfig::default());
+/// assert!(bytes.starts_with(b"HTTP/1.1 200 OK"));
+/// ```
+pub fn http_handler(request: &HttpRequest, config: &ServerConfig) -> Vec<u8> {
+    let http_response = handle(request, config);
+    if request.method == Method::Head {
+        return http_response.head().into_bytes();
+    }
+    build_response(http_response)
+}
+
+/// Handles a parsed HTTP request and returns the `HttpResponse` to send.
 ///
-/// fn main() {
-///     let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+/// # Arguments
 ///
-///     for stream in listener.incoming() {
-///         let stream = stream.unwrap();
-///         http_handler(&stream, "Hello, World!".to_string());
-///     }
-/// }
-/// ```
+/// * `request` - The parsed `HttpRequest` to answer.
+/// * `config` - The `ServerConfig` the response should follow (CORS policy, ...).
 ///
-/// # Dependencies
+/// # Functionality
 ///
-/// This function relies on two helper functions:
-/// - `create_http_response` - To construct an `HttpResponse` object from the provided response content.
-/// - `write_response` - To send the `HttpResponse` over the `TcpStream`.
+/// The request is answered by the site its `Host` names, if `config` has
+/// virtual hosts (see `ServerConfig::site_for`); `config` below is that
+/// site's. Its `config.rewrites` come first: a redirect is answered with
+/// an empty response pointing to the new location, a rewritten request is
+/// answered below in place of the original, and one caught in a rewrite loop
+/// gets `500 Internal Server Error` (see `http::rewrite`). Everything but the
+/// latter runs inside the middleware of `config.router`, if any (see
+/// `server::Middleware`), which sees the rewritten request.
 ///
-/// # Notes
+/// 1. If CORS is configured and the request is a preflight, answers it directly
+///    without touching the filesystem.
+/// 2. If a `config.router` route matches the path, answers with its handler, or,
+///    if only routes for other methods match, with `405 Method Not Allowed`
+///    (`204 No Content` for `OPTIONS`) and the methods that do in `Allow`. A
+///    websocket route answers with the opening handshake, see `http::websocket`.
+/// 3. If one of `config.proxies` covers the path, forwards the request to its
+///    upstream and relays the answer (`502 Bad Gateway` or `504 Gateway Timeout`
+///    if there is none), see `http::proxy`.
+/// 4. If `config.cgi` is set and the path is under its prefix, runs the script
+///    it names and answers with its output, see `http::cgi`.
+/// 5. Otherwise dispatches on the method:
+///    - `GET`/`HEAD`: creates an `HttpResponse` for the requested path with `create_http_response`.
+///    - `PUT` under the upload prefix (if `config.upload` is set): stores the body, see `http::upload`.
+///    - `OPTIONS`: `204 No Content` listing the supported methods in `Allow`.
+///    - Any other known method: `405 Method Not Allowed` with an `Allow` header.
+///    - An unrecognized method token (`Method::Other`): `501 Not Implemented`.
+/// 6. Adds any CORS headers.
+///
+/// None of these responses close the connection, so keep-alive can continue.
+/// `HEAD` requests get the full `GET` response; dropping the body is up to
+/// the caller.
+pub fn handle(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    let config = match config.site_for(request) {
+        Ok(site) => site,
+        Err(page) => return page_response(page, request, config),
+    };
+    match rewrite::apply(&config.rewrites, request) {
+        Outcome::Unchanged => config
+            .router
+            .serve(request, |request| respond(request, config)),
+        Outcome::Rewritten(path, query) => {
+            // `target` stays as sent, for the logs.
+            let rewritten = HttpRequest {
+                path,
+                query,
+                ..request.clone()
+            };
+            config
+                .router
+                .serve(&rewritten, |request| respond(request, config))
+        }
+        Outcome::Redirect(status, location) => config.router.serve(request, |request| {
+            // The target is built from the request's path, which mustn't
+            // carry a line break into the header.
+            let Ok(location) = HeaderValue::try_from(location.as_str()) else {
+                return page_response(ErrorPage::BadRequest, request, config);
+            };
+            let mut response = empty_response(status);
+            response.headers.insert(headers::LOCATION, location);
+            response
+        }),
+        Outcome::Loop => {
+            log::error(format_args!(
+                "rewrite rules loop on {}; giving up after {} rewrites",
+                request.path,
+                rewrite::MAX_REWRITES
+            ));
+            page_response(ErrorPage::InternalServerError, request, config)
+        }
+    }
+}
+
+/// Answers a request once the middleware has passed it on; see `handle`.
+fn respond(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    if let Some(cors) = &config.cors
+        && let Some(preflight) = cors.preflight(request)
+    {
+        return preflight;
+    }
+
+    let mut http_response: HttpResponse = match config.router.lookup(request) {
+        RouteMatch::Handler(handler) => handler(request),
+        RouteMatch::WebSocket(_) => websocket_response(request, config),
+        RouteMatch::MethodNotAllowed(allow) if request.method == Method::Options => {
+            let mut response = empty_response(StatusCode::NO_CONTENT);
+            if let Ok(allow) = HeaderValue::try_from(format!("{allow}, OPTIONS")) {
+                response.headers.insert(headers::ALLOW, allow);
+            }
+            response
+        }
+        RouteMatch::MethodNotAllowed(allow) => {
+            let mut response = page_response(ErrorPage::MethodNotAllowed, request, config);
+            if let Ok(allow) = HeaderValue::try_from(allow) {
+                response.headers.insert(headers::ALLOW, allow);
+            }
+            response
+        }
+        RouteMatch::NoRoute => match config.proxy_for(&request.path) {
+            Some(proxy) => proxy
+                .forward(request)
+                .unwrap_or_else(|page| page_response(page, request, config)),
+            None => match &config.cgi {
+                Some(cgi) if cgi.matches(&request.path) => cgi
+                    .run(request, config)
+                    .unwrap_or_else(|page| page_response(page, request, config)),
+                _ => static_response(request, config),
+            },
+        },
+    };
+    if let Some(cors) = &config.cors {
+        cors.apply(request, &mut http_response);
+    }
+    http_response
+}
+
+/// Answers a request for a websocket route with the opening handshake (see
+/// `websocket::handshake`). Only `GET` can open a websocket.
+fn websocket_response(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    if request.method != Method::Get {
+        let mut response = page_response(ErrorPage::MethodNotAllowed, request, config);
+        response
+            .headers
+            .insert(headers::ALLOW, HeaderValue::from_static("GET"));
+        return response;
+    }
+    websocket::handshake(request).unwrap_or_else(|page| page_response(page, request, config))
+}
+
+/// Answers a request no route matched, from the static files.
+fn static_response(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    match request.method {
+        Method::Get | Method::Head => {
+            let response = create_http_response(request, config);
+            match &config.spa_fallback {
+                Some(spa)
+                    if response.status() == StatusCode::NOT_FOUND && spa.covers(&request.path) =>
+                {
+                    spa_response(request, config, spa, response)
+                }
+                _ => response,
+            }
+        }
+        Method::Put
+            if config
+                .upload
+                .as_ref()
+                .is_some_and(|upload| upload.matches(&request.path)) =>
+        {
+            upload_response(request, config)
+        }
+        Method::Options => {
+            let mut response = empty_response(StatusCode::NO_CONTENT);
+            response.headers.insert(headers::ALLOW, STATIC_ALLOW);
+            response
+        }
+        Method::Other(_) => page_response(ErrorPage::NotImplemented, request, config),
+        _ => {
+            let mut response = page_response(ErrorPage::MethodNotAllowed, request, config);
+            response.headers.insert(headers::ALLOW, STATIC_ALLOW);
+            response
+        }
+    }
+}
+
+/// Answers a request for a missing path `spa` covers: with its index page
+/// if the client prefers HTML, with `not_found` otherwise.
+fn spa_response(
+    request: &HttpRequest,
+    config: &ServerConfig,
+    spa: &SpaFallback,
+    not_found: HttpResponse,
+) -> HttpResponse {
+    let mut response = if request.prefers_html() {
+        let mut index = request.clone();
+        index.path = spa.index.clone();
+        create_http_response(&index, config)
+    } else {
+        not_found
+    };
+    response
+        .headers
+        .append(headers::VARY, HeaderValue::from(headers::ACCEPT));
+    response
+}
+
+/// Stores an upload and answers `201 Created` (with `Location`) for a new
+/// file or `204 No Content` for an overwritten one.
+fn upload_response(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    let Some(upload) = &config.upload else {
+        return page_response(ErrorPage::MethodNotAllowed, request, config);
+    };
+    match upload.store(request, config) {
+        Ok(true) => empty_response(StatusCode::NO_CONTENT),
+        Ok(false) => {
+            let mut response = empty_response(StatusCode::CREATED);
+            if let Ok(location) = HeaderValue::try_from(request.path.as_str()) {
+                response.headers.insert(headers::LOCATION, location);
+            }
+            response
+        }
+        Err(page) => page_response(page, request, config),
+    }
+}
+
+/// Returns the serialized response for an error page.
 ///
-/// - Ensure that the `response` string is properly formatted to be suitable for inclusion in an HTTP response.
-/// - It is assumed that `create_http_response` and `write_response` are implemented elsewhere in the codebase.
+/// Used when there is no request to hand to `http_handler`, for example
+/// when the request could not be parsed at all. The connection is always
+/// closed after such a response, so it carries `Connection: close`.
 ///
-/// # Errors
+/// # Example
 ///
-/// If the `write_response` function fails, the error will not be handled in this function.
-/// The caller of this function may want to log or handle any network-related errors outside of this context.
-pub fn http_handler(response: String) -> Vec<u8>{
-    let http_response: HttpResponse = create_http_response(response);
-    build_response(http_response)
+/// ```
+/// let bytes = error_handler(ErrorPage::BadRequest, &ServerConfig::default());
+/// assert!(bytes.starts_with(b"HTTP/1.1 400"));
+/// ```
+pub fn error_handler(page: ErrorPage, config: &ServerConfig) -> Vec<u8> {
+    let mut response = error_response(page, config);
+    response
+        .headers
+        .insert(headers::CONNECTION, HeaderValue::from_static("close"));
+    build_response(response)
 }
 
 /// Creates an HTTP response based on the given file path or error page response.
@@ -193,68 +508,453 @@ pub fn http_handler(response: String) -> Vec<u8>{
 /// reads the file's content, assigns the appropriate MIME type, and prepares the response
 /// with the content as either text or binary data, depending on the file type and encoding.
 ///
-/// If the file cannot be read (e.g., it does not exist or there is a permission issue), the
-/// function falls back to using an "Internal Server Error" page. In such a case, it retrieves
-/// the fallback error page path, reads its content, and updates the MIME type accordingly.
+/// Files larger than `config.max_in_memory_file_size` aren't read into memory: they are
+/// sent from disk piece by piece (`sendfile`, a memory mapping, or chunked reads;
+/// see `open_stream`), with `Content-Length` taken from the file's metadata.
+///
+/// Files on disk carry an `ETag` (see `entity_tag`); a request whose
+/// `If-None-Match` matches it gets `304 Not Modified` without the file being read.
+///
+/// Whether the file exists isn't checked up front; it is simply opened, and a
+/// failure is answered by `file_error_response`: a missing file gets the 404
+/// page, one the server may not read the 403 page, and anything else the 500 page.
 ///
 /// # Parameters
-/// - `response`: A `String` representing a response indicator, which is used to determine
-///   the HTTP status and the associated file path that should be served.
+/// - `request`: The request whose path is used to determine the HTTP status and the
+///   associated file path that should be served.
+/// - `config`: The server configuration. Files whose extension is listed in
+///   `force_attachment_extensions` are sent with `Content-Disposition: attachment`.
 ///
 /// # Returns
 /// - An `HttpResponse` containing:
-///   - `status`: The HTTP status code as a `String`.
-///   - `content_type`: The MIME type of the response content as a `String`.
+///   - `status`: The HTTP status code as a `StatusCode`.
+///   - `content_type`: The MIME type of the response content as a `HeaderValue`.
 ///   - `body`: The response body, which is either text or binary data.
 ///
-/// # Errors
-/// - If both the requested file and the fallback error file cannot be read,
-///   this function will panic due to the `unwrap()` call on reading the error file.
-///
 /// # Example
 /// ```
-/// let response = create_http_response(String::from("/index.html"));
+/// let response = create_http_response(&request, &ServerConfig::default());
 /// println!("HTTP Status: {}", response.status);
 /// println!("Content Type: {}", response.content_type);
 /// ```
 ///
 /// # Dependencies
 /// - This function makes use of external helper functions such as
-///   - `status_filename(response: String) -> (String, String)`: Determines the HTTP status
-///     and corresponding file path.
-///   - `from_path(path: &str) -> Mime`: Determines the MIME type of file based on its path.
-///   - `io::file::read_file_bytes(path: &str) -> Result<Vec<u8>, IoError>`: Reads file content
-///     as a byte vector.
-///   - `ErrorPage::InternalServerError`: Contains the status code and path for the internal server
-///     error fallback page.
+///   - `status_filename(path, source, config) -> Result<Resolved, ErrorPage>`:
+///     Determines the HTTP status and corresponding file path (or a redirect), or the
+///     error page to show instead.
+///   - `AssetSource::read(path: &str) -> Result<Vec<u8>, IoError>`: Reads file content
+///     as a byte vector, from `config.asset_source()`.
+///   - `page_response(page: ErrorPage, ...) -> HttpResponse`: Builds an error page response
+///     that cannot fail.
 ///
 /// # Notes
-/// - If the file's MIME type is `text/*`, the function attempts to decode the file contents
-///   as UTF-8. If decoding fails, the content is returned as binary data.
-/// - The function logs an error message to `stderr` if the requested file cannot be read.
-///
-/// # Warning
-/// - Use caution with the `unwrap()` call when reading the fallback error file, as it will cause
-///   the program to panic in case of an unrecoverable error.
-fn create_http_response(response: String) -> HttpResponse {
-    let (mut status, mut filename) = status_filename(response);
-    let mut mime = from_path(&filename).first_or_octet_stream();
-    let bytes = match io::file::read_file_bytes(&filename) {
+/// - The function logs an error message to `stderr` if the requested file cannot be read
+///   for a reason other than not existing or not being readable.
+fn create_http_response(request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    let source = config.asset_source();
+    let (status, filename) = match status_filename(&request.path, source.as_ref(), config) {
+        Ok(Resolved::File(status, filename)) => (status, filename),
+        Ok(Resolved::Redirect(mut location)) => {
+            if let Some(query) = &request.query {
+                location.push('?');
+                location.push_str(query);
+            }
+            let Ok(location) = HeaderValue::try_from(location) else {
+                return page_response(ErrorPage::BadRequest, request, config);
+            };
+            let mut response = empty_response(StatusCode::MOVED_PERMANENTLY);
+            response.headers.insert(headers::LOCATION, location);
+            return response;
+        }
+        Ok(Resolved::Listing(dir)) => {
+            return match source.read_dir(&dir) {
+                Ok(entries) => HttpResponse {
+                    status: StatusCode::OK,
+                    content_type: HeaderValue::from_static("text/html; charset=utf-8"),
+                    headers: HeaderMap::new(),
+                    body: Body::Text(render_directory_listing(
+                        &request.path,
+                        entries,
+                        config.listing_show_hidden,
+                    )),
+                },
+                Err(e) => file_error_response(&dir, e.into(), request, config),
+            };
+        }
+        Err(page) => return page_response(page, request, config),
+    };
+    if let Some(templates) = &config.templates
+        && templates.renders(&filename)
+    {
+        return template_response(
+            request,
+            config,
+            templates,
+            source.as_ref(),
+            status,
+            &filename,
+        );
+    }
+    let content_type = content_type_for(&filename, config);
+    let (size, modified) = match source.metadata(&filename) {
+        Ok(meta) => (meta.size, meta.modified),
+        Err(e) => return file_error_response(&filename, e, request, config),
+    };
+    let fs_path = source.fs_path(&filename);
+    let etag = match (fs_path.as_deref(), modified) {
+        (Some(fs_path), Some(modified)) => Some(entity_tag(fs_path, size, modified, config)),
+        // e.g. the embedded copy of the site, hashed when it was built
+        _ => source
+            .content_hash(&filename)
+            .map(|hash| format!("\"{hash:016x}\"")),
+    }
+    .and_then(|etag| HeaderValue::try_from(etag).ok());
+    let cache_control = config
+        .cache_control_for(&filename)
+        .and_then(|value| HeaderValue::try_from(value).ok());
+    if let Some(etag) = &etag
+        && request
+            .header("If-None-Match")
+            .is_some_and(|tags| etag_matches(tags, etag.as_str()))
+    {
+        let mut response = empty_response(StatusCode::NOT_MODIFIED);
+        response.headers.insert(headers::ETAG, etag.clone());
+        if let Some(cache_control) = cache_control {
+            response
+                .headers
+                .insert(headers::CACHE_CONTROL, cache_control);
+        }
+        return response;
+    }
+    let read = if let Some(fs_path) = &fs_path
+        && !config.serves_from_memory(size)
+    {
+        open_stream(fs_path, size, config).map(|body| HttpResponse {
+            status,
+            content_type,
+            headers: HeaderMap::new(),
+            body,
+        })
+    } else {
+        read_small_file(&filename, source.as_ref(), modified, config)
+            .map(|bytes| file_response(status, content_type, bytes))
+    };
+
+    match read {
+        Ok(mut response) => {
+            if config.forces_attachment(&filename)
+                && let Some(name) = Path::new(&filename).file_name()
+            {
+                response = response.attachment(&name.to_string_lossy());
+            }
+            if let Some(etag) = etag {
+                response.headers.insert(headers::ETAG, etag);
+            }
+            if let Some(cache_control) = cache_control {
+                response
+                    .headers
+                    .insert(headers::CACHE_CONTROL, cache_control);
+            }
+            response
+        }
+        Err(e) => file_error_response(&filename, e.into(), request, config),
+    }
+}
+
+/// Renders the template `filename` with the context `templates.provider`
+/// assembles for `request` (see `http::template`). A template that doesn't
+/// parse is logged and answered with the 500 page.
+fn template_response(
+    request: &HttpRequest,
+    config: &ServerConfig,
+    templates: &TemplateConfig,
+    source: &dyn AssetSource,
+    status: StatusCode,
+    filename: &str,
+) -> HttpResponse {
+    let template = match source.read(filename) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => return file_error_response(filename, e.into(), request, config),
+    };
+    let context = (templates.provider)(request);
+    match template::render(&template, &context) {
+        Ok(page) => HttpResponse {
+            status,
+            content_type: HeaderValue::from_static("text/html; charset=utf-8"),
+            headers: HeaderMap::new(),
+            body: Body::Text(page),
+        },
+        Err(reason) => {
+            log::error(format_args!("template {}: {}", filename, reason));
+            page_response(ErrorPage::InternalServerError, request, config)
+        }
+    }
+}
+
+/// Returns the `ETag` for the file at `fs_path`, quotes included.
+///
+/// Files up to `config.etag_hash_limit` get a strong tag made from a hash of
+/// their contents, looked up in `config.hash_cache` so the file is only read
+/// for hashing when its size or mtime changed. Bigger files, and files that
+/// can't be hashed, get a weak tag from their size and mtime instead.
+///
+/// # Example
+/// ```
+/// entity_tag("/srv/www/welcome.html", 512, modified, &config); // "\"3f1c9a0e5b7d2c84\""
+/// entity_tag("/srv/www/video.mp4", 1 << 30, modified, &config); // "W/\"40000000-17f9a3b2c1d\""
+/// ```
+fn entity_tag(fs_path: &str, size: u64, modified: SystemTime, config: &ServerConfig) -> String {
+    if size <= config.etag_hash_limit {
+        match config
+            .hash_cache
+            .get_or_compute(fs_path, size, modified, || io::file::hash_file(fs_path))
+        {
+            Ok(hash) => return format!("\"{hash:016x}\""),
+            Err(e) => log::error(format_args!("Error hashing file {}: {}", fs_path, e)),
+        }
+    }
+    let nanos = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    format!("W/\"{size:x}-{nanos:x}\"")
+}
+
+/// Returns `true` if the `If-None-Match` value `tags` matches `etag`.
+///
+/// `*` matches anything. Otherwise the comma-separated tags are compared
+/// with the weak comparison `If-None-Match` calls for: a `W/` prefix on
+/// either side is ignored.
+fn etag_matches(tags: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Answers `request` with the error page for a failure to access `path`.
+///
+/// This is the one place filesystem errors become statuses (see
+/// `From<&FileError> for ErrorPage`). Missing and forbidden files are an
+/// ordinary outcome and aren't logged; anything else is.
+fn file_error_response(
+    path: &str,
+    error: FileError,
+    request: &HttpRequest,
+    config: &ServerConfig,
+) -> HttpResponse {
+    if let FileError::Io(e) = &error {
+        log::error(format_args!("Error reading file {}: {}", path, e));
+    }
+    page_response(ErrorPage::from(&error), request, config)
+}
+
+/// Reads a file that is served from memory, going through `config.file_cache`
+/// when caching is enabled.
+///
+/// The cache is keyed by filesystem path, so it only applies to files on disk.
+/// A cached copy is only used if it was read at the file's current mtime.
+/// Files without an mtime are never cached, since staleness couldn't be detected,
+/// and neither is a file that grew past `config.max_in_memory_file_size` after it
+/// was stat'ed.
+fn read_small_file(
+    filename: &str,
+    source: &dyn AssetSource,
+    modified: Option<SystemTime>,
+    config: &ServerConfig,
+) -> std::io::Result<Vec<u8>> {
+    let (Some(cache), Some(key), Some(_)) =
+        (&config.file_cache, source.fs_path(filename), modified)
+    else {
+        return source.read(filename);
+    };
+    if let Some(bytes) = cache.get(&key, modified) {
+        return Ok(bytes.to_vec());
+    }
+    let bytes = source.read(filename)?;
+    if config.serves_from_memory(bytes.len() as u64) {
+        cache.insert(&key, modified, bytes.clone());
+    }
+    Ok(bytes)
+}
+
+/// Opens a large file as a body that is sent piece by piece.
+///
+/// Files over `config.mmap_threshold` are served from a memory mapping where
+/// the platform supports it. Otherwise, with `config.sendfile`, the file
+/// becomes a `Body::File` that the reactor can hand to `sendfile(2)`; without
+/// it the file is read in chunks.
+fn open_stream(filename: &str, size: u64, config: &ServerConfig) -> std::io::Result<Body> {
+    #[cfg(any(unix, windows))]
+    if config
+        .mmap_threshold
+        .is_some_and(|threshold| size > threshold)
+    {
+        let (reader, length) = io::file::MappedReader::open(filename, config.stream_chunk_size)?;
+        return Ok(Body::Stream(StreamBody::sized(reader, length)));
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = size;
+    if config.sendfile {
+        let (file, _) = FileBody::open(filename, config.stream_chunk_size)?;
+        return Ok(Body::File(file));
+    }
+    let (reader, length) = io::file::ChunkedReader::open(filename, config.stream_chunk_size)?;
+    Ok(Body::Stream(StreamBody::sized(reader, length)))
+}
+
+/// Creates the response for an error page while answering `request`.
+///
+/// If `config.error_pages` has a template for the page's status code, the
+/// template is rendered with the request context: `{{path}}`, `{{status}}`,
+/// `{{reason}}`, `{{client}}` (see `HttpRequest::client_address`) and
+/// `{{request_id}}` (see `http::request_id`) are replaced with HTML-escaped
+/// values. Any other placeholder renders as empty, and so do `{{client}}`
+/// and `{{request_id}}` when the request has no peer or ID. Templating only
+/// ever runs on these configured files, never on regular static files. If
+/// the template can't be read, the failure is logged and the default page
+/// from `error_response` is used.
+pub(crate) fn page_response(
+    page: ErrorPage,
+    request: &HttpRequest,
+    config: &ServerConfig,
+) -> HttpResponse {
+    let status = page.status();
+    if let Some(template_path) = config.error_pages.get(&status.as_u16()) {
+        match std::fs::read_to_string(template_path) {
+            Ok(template) => {
+                let html = util::fill_placeholders(&template, |name| match name {
+                    "path" => Some(request.path.clone()),
+                    "status" => Some(status.as_u16().to_string()),
+                    "reason" => Some(status.reason_phrase().to_string()),
+                    "client" => request
+                        .client_address(&config.trusted_proxies, config.forwarded_header)
+                        .map(|address| address.to_string()),
+                    "request_id" => request.id.clone(),
+                    _ => None,
+                });
+                let content_type = HeaderValue::from_static("text/html");
+                return file_response(status, content_type, html.into_bytes());
+            }
+            Err(e) => {
+                log::error(format_args!(
+                    "Error reading error page template {}: {}",
+                    template_path, e
+                ));
+            }
+        }
+    }
+    error_response(page, config)
+}
+
+/// Creates the response for an error page.
+///
+/// The page is read from disk first. If that fails, the failure is logged and
+/// the built-in copy from `ErrorPage::fallback` is used instead, so this
+/// function never panics regardless of what is (or isn't) on the filesystem.
+fn error_response(page: ErrorPage, config: &ServerConfig) -> HttpResponse {
+    let filename = page.path();
+    let bytes = match config.asset_source().read(&filename) {
         Ok(bytes) => bytes,
         Err(e) => {
-            eprintln!("Error reading file {}: {}", filename, e);
-            status = String::from(ErrorPage::InternalServerError.status());
-            filename = String::from(ErrorPage::InternalServerError.path());
-            mime = from_path(&filename).first_or_octet_stream();
-            io::file::read_file_bytes(&filename).unwrap()
+            log::error(format_args!(
+                "Error reading error page {}: {}; using built-in page",
+                filename, e
+            ));
+            page.fallback().as_bytes().to_vec()
         }
     };
+    let content_type = from_path(&filename).first_or_octet_stream().to_string();
+    let content_type = HeaderValue::try_from(content_type).unwrap_or_default();
+    file_response(page.status(), content_type, bytes)
+}
+
+/// Creates a response with no body and no `Content-Type`.
+pub(crate) fn empty_response(status: StatusCode) -> HttpResponse {
+    HttpResponse {
+        status,
+        content_type: HeaderValue::default(),
+        headers: HeaderMap::new(),
+        body: Body::Binary(Vec::new()),
+    }
+}
+
+/// Content types for extensions made of two parts, which `mime_guess` would
+/// otherwise judge by the last part alone (`.tar.gz` is more than "some gzip").
+const COMPOUND_MIME_TYPES: &[(&str, &str)] = &[
+    ("tar.gz", "application/gzip"),
+    ("tar.bz2", "application/x-bzip2"),
+    ("tar.xz", "application/x-xz"),
+    ("js.map", "application/json"),
+    ("css.map", "application/json"),
+    ("user.js", "text/javascript"),
+];
+
+/// Returns the `Content-Type` to serve `filename` with.
+///
+/// Resolution order:
+/// 1. The last two extensions (e.g. `tar.gz`) against the configured `mime_overrides`,
+///    then against `COMPOUND_MIME_TYPES`.
+/// 2. The last extension against the configured `mime_overrides`.
+/// 3. `mime_guess`, falling back to the configured `default_mime`.
+///
+/// Extension compares ignore case. A name that merely contains dots, such as
+/// `jquery.min.js`, matches no compound entry and is still judged by `.js`.
+/// A configured type that can't be sent as a header (it has a line break)
+/// gives `application/octet-stream` instead.
+fn content_type_for(filename: &str, config: &ServerConfig) -> HeaderValue {
+    HeaderValue::try_from(mime_type_for(filename, config))
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"))
+}
+
+/// Returns the MIME type `filename` resolves to; see `content_type_for`.
+fn mime_type_for(filename: &str, config: &ServerConfig) -> String {
+    let name = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let parts: Vec<&str> = name.split('.').skip(1).collect();
 
-    let body = if mime.type_() == mime::TEXT {
+    let lookup_override = |ext: &str| {
+        config
+            .mime_overrides
+            .iter()
+            .find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(ext))
+            .map(|(_, content_type)| content_type.clone())
+    };
+
+    if parts.len() >= 2 {
+        let compound = parts[parts.len() - 2..].join(".");
+        let builtin = COMPOUND_MIME_TYPES
+            .iter()
+            .find(|(ext, _)| *ext == compound)
+            .map(|(_, content_type)| content_type.to_string());
+        if let Some(content_type) = lookup_override(&compound).or(builtin) {
+            return content_type;
+        }
+    }
+    if let Some(ext) = parts.last()
+        && let Some(content_type) = lookup_override(ext)
+    {
+        return content_type;
+    }
+
+    match from_path(filename).first() {
+        Some(mime) => mime.to_string(),
+        None => config.default_mime.clone(),
+    }
+}
+
+/// Wraps file contents in an `HttpResponse` with the given `Content-Type`.
+///
+/// If the content type is `text/*`, the contents are decoded as UTF-8.
+/// If decoding fails, the content is returned as binary data.
+fn file_response(status: StatusCode, content_type: HeaderValue, bytes: Vec<u8>) -> HttpResponse {
+    let body = if content_type.as_str().starts_with("text/") {
         // Try for text first, if that fails, fall back to binary
-        match String::from_utf8(bytes.clone()) {
+        match String::from_utf8(bytes) {
             Ok(text) => Body::Text(text),
-            Err(_) => Body::Binary(bytes),
+            Err(e) => Body::Binary(e.into_bytes()),
         }
     } else {
         Body::Binary(bytes)
@@ -262,83 +962,433 @@ fn create_http_response(response: String) -> HttpResponse {
 
     HttpResponse {
         status,
-        content_type: mime.to_string(),
+        content_type,
+        headers: HeaderMap::new(),
         body,
     }
 }
 
-/// Returns the status and file path of the inputted response string.
+/// The outcome of mapping a request path onto the asset source.
+///
+/// Paths are root-relative (`/docs/index.html`), as the `AssetSource` expects them.
+///
+/// Variants:
+/// - `File(StatusCode, String)`: Serve the file at the given path with the given status.
+/// - `Redirect(String)`: Redirect the client to the given location (e.g. a directory
+///   requested without its trailing slash).
+/// - `Listing(String)`: Render a listing of the given directory.
+enum Resolved {
+    File(StatusCode, String),
+    Redirect(String),
+    Listing(String),
+}
+
+/// Returns the status and file path for the requested path.
 ///
-/// Handles both the 404 and 403 logic by checking the path exists and determining
-/// if it tries to access improper files.
+/// Handles the 403 logic by determining if the path tries to access improper
+/// files. Whether the file exists is left to the read, which yields the 404.
+/// The path is percent-decoded and normalized (see `util::normalize_path`)
+/// before any check runs; a malformed escape yields 400 and a path that
+/// climbs above the root yields 403, as does anything that `confine` finds
+/// outside the asset source. Any component starting with `.` (e.g. `/.env`,
+/// `/a/.git/config`) yields 403 unless it sits under one of the configured
+/// `hidden_allowlist` prefixes.
+///
+/// `/` maps to the configured `index` document. Any other directory is first
+/// redirected to its trailing-slash form (`/docs` → `/docs/`) so relative links
+/// inside it resolve correctly, then the configured `index_files` are tried in
+/// order. If none exists and `directory_listing` is enabled, a generated listing
+/// is served instead of a 404. Extensionless paths that don't exist get `.html`
+/// appended when `clean_urls` is enabled.
 ///
 /// # Parameters
-/// - `response`: the `String` to parse for the status and filename/path.
+/// - `path`: the request path to map to a status and filename/path.
+/// - `source`: the `AssetSource` files are looked up in.
+/// - `config`: the server configuration providing `index`, `index_files`,
+///   `directory_listing`, and `clean_urls`.
 ///
 /// # Returns
-/// - `(String, String)`: The status and filepath.
-fn status_filename(response: String) -> (String, String) {
-    let parts: Vec<&str> = response.split_whitespace().collect();
-    if parts.len() < 2 {
-        return (
-            String::from("HTTP/1.1 404"),
-            String::from("public/404.html"),
-        );
+/// - `Ok(Resolved)`: The file to serve, or where to redirect.
+/// - `Err(ErrorPage)`: The error page to serve instead.
+fn status_filename(
+    path: &str,
+    source: &dyn AssetSource,
+    config: &ServerConfig,
+) -> Result<Resolved, ErrorPage> {
+    if path.is_empty() {
+        return Err(ErrorPage::NotFound);
     }
+    let decoded = match util::percent_decode(path) {
+        Some(decoded) if !decoded.contains('\0') => decoded,
+        _ => return Err(ErrorPage::BadRequest),
+    };
+    let Some(normalized) = util::normalize_path(&decoded) else {
+        return Err(ErrorPage::PermissionDenied);
+    };
 
-    let path = parts[1];
-
-    if path.contains("..") {
-        (
-            String::from(ErrorPage::PermissionDenied.status()),
-            String::from(ErrorPage::PermissionDenied.path()),
-        )
-    } else if path == "/" {
-        (
+    if config.is_hidden_path(&normalized) {
+        Err(ErrorPage::PermissionDenied)
+    } else if normalized == "/" {
+        Ok(Resolved::File(
             // Landing page if no path is specified
-            String::from("HTTP/1.1 200 OK"),
-            String::from("public/welcome.html"),
-        )
+            StatusCode::OK,
+            format!("/{}", config.index.trim_start_matches('/')),
+        ))
     } else {
-        let mut path = String::from(path);
+        let mut file_path = normalized;
 
-        path.insert_str(0, "public");
+        if source.metadata(&file_path).is_ok_and(|meta| meta.is_dir) {
+            if !path.ends_with('/') {
+                // Built from the normalized path, which starts with exactly
+                // one `/`: the raw `//evil.example` would be a redirect to
+                // another site.
+                let mut location = util::percent_encode(&file_path, |b| {
+                    b.is_ascii_alphanumeric() || b"/-._~!$&'()*+,;=:@".contains(&b)
+                });
+                if !location.ends_with('/') {
+                    location.push('/');
+                }
+                return Ok(Resolved::Redirect(location));
+            }
+            return match find_index_file(&file_path, source, config) {
+                Some(index) => confine(Resolved::File(StatusCode::OK, index), source),
+                None if config.directory_listing => confine(Resolved::Listing(file_path), source),
+                None => Err(ErrorPage::NotFound),
+            };
+        }
 
-        if Path::new(&path).extension().is_none() {
-            path.push_str(".html");
+        // An extensionless file that exists (e.g. an ACME challenge token
+        // under `/.well-known/`) is served as it is.
+        if config.clean_urls
+            && Path::new(&file_path).extension().is_none()
+            && source.metadata(&file_path).is_err()
+        {
+            file_path.push_str(".html");
         }
 
-        if !Path::new(&path).exists() {
-            return (
-                String::from(ErrorPage::NotFound.status()),
-                String::from(ErrorPage::NotFound.path()),
-            );
+        confine(Resolved::File(StatusCode::OK, file_path), source)
+    }
+}
+
+/// Checks that a resolved file or directory really lives inside the asset source.
+///
+/// See `AssetSource::contains`; for the filesystem this is the canonical-path
+/// check that keeps symlinks from leading outside the document root.
+///
+/// # Returns
+/// - `Ok(resolved)` if the target is inside the source.
+/// - `Err(ErrorPage::PermissionDenied)` otherwise.
+fn confine(resolved: Resolved, source: &dyn AssetSource) -> Result<Resolved, ErrorPage> {
+    match &resolved {
+        Resolved::File(_, path) | Resolved::Listing(path) if !source.contains(path) => {
+            Err(ErrorPage::PermissionDenied)
         }
+        _ => Ok(resolved),
+    }
+}
 
-        (String::from("HTTP/1.1 200 OK"), format!("{}", path))
+/// Renders an HTML page listing the entries of a directory.
+///
+/// Directories come first, then files, each group sorted alphabetically
+/// (case-insensitively). Links are relative to the current path, so the page
+/// works wherever the directory is mounted. Every name is percent-encoded in
+/// `href`s and HTML-escaped in text, so a file called `<script>.txt` is shown
+/// literally instead of being executed.
+///
+/// # Parameters
+/// - `request_path`: The request path of the directory, used for the title.
+/// - `entries`: The directory entries from `io::file::read_dir_entries`.
+/// - `show_hidden`: Whether to include entries whose name starts with `.`.
+fn render_directory_listing(
+    request_path: &str,
+    mut entries: Vec<io::file::DirEntryInfo>,
+    show_hidden: bool,
+) -> String {
+    entries.retain(|entry| show_hidden || !entry.name.starts_with('.'));
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let title = util::html_escape(request_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n    <meta charset=\"utf-8\">\n    \
+         <title>Index of {title}</title>\n</head>\n<body>\n    <h1>Index of {title}</h1>\n    \
+         <table>\n        <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n"
+    );
+    if request_path != "/" {
+        html.push_str("        <tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
     }
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = util::percent_encode(&entry.name, |b| {
+            b.is_ascii_alphanumeric() || b"-._~".contains(&b)
+        });
+        let size = if entry.is_dir {
+            String::from("-")
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry
+            .modified
+            .map(util::format_timestamp)
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "        <tr><td><a href=\"{}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            util::html_escape(&href),
+            util::html_escape(&entry.name),
+        ));
+    }
+    html.push_str("    </table>\n</body>\n</html>\n");
+    html
+}
+
+/// Returns the first of the configured `index_files` that exists in `dir`.
+fn find_index_file(dir: &str, source: &dyn AssetSource, config: &ServerConfig) -> Option<String> {
+    config
+        .index_files
+        .iter()
+        .map(|name| format!("{}/{}", dir.trim_end_matches('/'), name))
+        .find(|candidate| source.metadata(candidate).is_ok_and(|meta| meta.is_file))
 }
 
-/// Writes an HTTP response to the provided TCP stream.
+impl HttpResponse {
+    /// Creates a `text/plain` response, e.g. for a `Router` handler.
+    ///
+    /// # Example
+    /// ```
+    /// let response = HttpResponse::text(StatusCode::OK, "ok");
+    /// ```
+    pub fn text(status: StatusCode, text: impl Into<String>) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+            headers: HeaderMap::new(),
+            body: Body::Text(text.into()),
+        }
+    }
+
+    /// Creates a response with `bytes` as its body, served as `content_type`.
+    pub fn bytes(status: StatusCode, content_type: HeaderValue, bytes: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type,
+            headers: HeaderMap::new(),
+            body: Body::Binary(bytes),
+        }
+    }
+
+    /// Creates a response whose body is produced piece by piece by `body`,
+    /// served as `content_type` (see `http::body`).
+    pub fn stream(status: StatusCode, content_type: HeaderValue, body: StreamBody) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type,
+            headers: HeaderMap::new(),
+            body: Body::Stream(body),
+        }
+    }
+
+    /// Adds a header field, replacing any earlier value of `name`.
+    ///
+    /// # Example
+    /// ```
+    /// let response = HttpResponse::text(StatusCode::OK, "ok")
+    ///     .with_header(headers::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    /// ```
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> HttpResponse {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Marks the response as a download with `Content-Disposition: attachment`.
+    ///
+    /// The plain `filename` parameter only ever carries a sanitized ASCII
+    /// version of the name (control characters dropped, quotes and
+    /// backslashes escaped, non-ASCII replaced with `_`). If the name isn't
+    /// pure ASCII, the exact UTF-8 name is also sent as an RFC 5987
+    /// `filename*` parameter, which browsers prefer when present.
+    ///
+    /// Because control characters, including CR and LF, are always removed,
+    /// a filename taken from the request path can't inject extra header lines.
+    ///
+    /// # Example
+    /// ```
+    /// let response = HttpResponse::bytes(StatusCode::OK, content_type, report)
+    ///     .attachment("résumé.pdf");
+    /// // Content-Disposition: attachment; filename="r_sum_.pdf"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf
+    /// ```
+    pub fn attachment(mut self, filename: &str) -> HttpResponse {
+        let clean: String = filename.chars().filter(|c| !c.is_control()).collect();
+
+        let mut value = String::from("attachment; filename=\"");
+        for c in clean.chars() {
+            match c {
+                '"' | '\\' => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                c if c.is_ascii() => value.push(c),
+                _ => value.push('_'),
+            }
+        }
+        value.push('"');
+
+        if !clean.is_ascii() {
+            // attr-char from RFC 5987 §3.2.1
+            let encoded = util::percent_encode(&clean, |b| {
+                b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b)
+            });
+            value.push_str(&format!("; filename*=UTF-8''{encoded}"));
+        }
+
+        if let Ok(value) = HeaderValue::try_from(value) {
+            self.headers.insert(headers::CONTENT_DISPOSITION, value);
+        }
+        self
+    }
+
+    /// Returns the status code.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the value of the header field `name`, if set.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the in-memory body as bytes, or an empty slice for stream bodies.
+    fn body_bytes(&self) -> &[u8] {
+        match &self.body {
+            Body::Text(text) => text.as_bytes(),
+            Body::Binary(binary) => binary,
+            Body::Stream(_) | Body::File(_) => &[],
+        }
+    }
+
+    /// Serializes the status line and header fields, including the blank
+    /// line that ends the head.
+    ///
+    /// The status line uses the canonical reason phrase for the status code.
+    /// The framing header (`Content-Length`, or `Transfer-Encoding: chunked`
+    /// plus `Trailer` for chunked streams) and `Content-Type` come first,
+    /// followed by any additional headers. Responses that can't have a body
+    /// (e.g. `204 No Content`) carry neither.
+    fn head(&self) -> String {
+        let mut head = format!("{}\r\n", self.status.status_line());
+        if !self.status.is_bodiless() {
+            match &self.body {
+                Body::Stream(stream) => head.push_str(&stream.framing_headers()),
+                Body::File(file) => {
+                    head.push_str(&format!("Content-Length: {}\r\n", file.remaining()))
+                }
+                _ => head.push_str(&format!("Content-Length: {}\r\n", self.body_bytes().len())),
+            }
+        }
+        if !self.content_type.is_empty() {
+            head.push_str(&format!("Content-Type: {}\r\n", self.content_type));
+        }
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// Starts sending the response through a connection's write queue.
+    ///
+    /// The head and any in-memory body are pushed onto `out` as separate
+    /// chunks, so the body is moved rather than copied behind the head. A
+    /// stream or file body is not drained; it is handed back so the caller can
+    /// send it piece by piece as the socket drains, keeping the queue small.
+    ///
+    /// # Parameters
+    /// - `out`: The connection's write queue.
+    /// - `head_only`: `true` for `HEAD` requests, which never get a body.
+    ///
+    /// # Returns
+    /// - `Some(Body)`: The rest of the body (`Body::Stream` or `Body::File`), still to be sent.
+    /// - `None`: Everything is already in `out`.
+    pub fn start_writing(self, out: &mut impl Extend<Vec<u8>>, head_only: bool) -> Option<Body> {
+        out.extend([self.head().into_bytes()]);
+        if head_only || self.status.is_bodiless() {
+            return None;
+        }
+        match self.body {
+            Body::Text(text) => out.extend([text.into_bytes()]),
+            Body::Binary(binary) => out.extend([binary]),
+            body @ (Body::Stream(_) | Body::File(_)) => return Some(body),
+        }
+        None
+    }
+
+    /// Writes the serialized response into any `Write` sink.
+    ///
+    /// The status line, headers, and body are written straight into `w`,
+    /// so the body is copied exactly once (into the sink itself). Stream
+    /// bodies are drained piece by piece, already framed for the wire.
+    ///
+    /// # Parameters
+    /// - `w`: The sink to write into, e.g. a connection's write buffer or a socket.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The total number of bytes written.
+    /// - `Err(io::Error)`: The sink failed to accept the bytes, or a stream
+    ///   body's source failed part-way.
+    ///
+    /// # Example
+    /// ```
+    /// let mut out = Vec::new();
+    /// let written = response.write_to(&mut out)?;
+    /// assert_eq!(written, out.len());
+    /// ```
+    pub(crate) fn write_to(&mut self, w: &mut impl Write) -> std::io::Result<usize> {
+        let head = self.head();
+        w.write_all(head.as_bytes())?;
+        let mut written = head.len();
+
+        if self.status.is_bodiless() {
+            return Ok(written);
+        }
+        match &mut self.body {
+            Body::Stream(stream) => {
+                while let Some(piece) = stream.next_wire_chunk()? {
+                    w.write_all(&piece)?;
+                    written += piece.len();
+                }
+            }
+            Body::File(file) => {
+                while let Some(piece) = file.next_chunk()? {
+                    w.write_all(&piece)?;
+                    written += piece.len();
+                }
+            }
+            _ => {
+                let body = self.body_bytes();
+                w.write_all(body)?;
+                written += body.len();
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Serializes an HTTP response into the bytes sent over the wire.
 ///
-/// This function serializes the HTTP headers and body into bytes
-/// and sends them over the given `TcpStream`. It ensures that
-/// `Content-Length` and `Content-Type` are properly set based on
-/// the `HttpResponse` struct.
+/// A thin wrapper around `HttpResponse::write_to` with a `Vec<u8>` sink.
+/// Writing into a `Vec` can't fail, so an error here means a stream body's
+/// source failed; it is logged and the bytes produced so far are returned.
 ///
 /// # Parameters
-/// - `stream`: The open `TcpStream` representing the client connection.
 /// - `http_response`: The HTTP response to send, including status,
 ///   headers, and body.
-fn build_response(http_response: HttpResponse) -> Vec<u8> {
-    let status = http_response.status;
-    let mime = http_response.content_type;
-    let (length, mut body_bytes): (usize, &[u8]) = match &http_response.body {
-        Body::Text(text) => (text.len(), text.as_bytes()),
-        Body::Binary(binary) => (binary.len(), binary),
-    };
-
-    let header = format!("{status}\r\nContent-Length: {length}\r\nContent-Type: {mime}\r\n\r\n");
-    let mut body = body_bytes.to_vec();
-    header.as_bytes().to_vec().into_iter().chain(body.into_iter()).collect()
+fn build_response(mut http_response: HttpResponse) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(http_response.body_bytes().len() + 256);
+    if let Err(e) = http_response.write_to(&mut bytes) {
+        log::error(format_args!("Error producing response body: {}", e));
+    }
+    bytes
 }
@@ -0,0 +1,229 @@
+//! URL rewrite and redirect rules, applied before a request is routed.
+//!
+//! A rule matches the request path, as sent, against a pattern: an exact path
+//! (`/about-us`), a prefix (`/img/*`), or any pattern with `*`s in it
+//! (`/blog/*.html`). Each `*` matches any run of characters, `/` included,
+//! and what it matched can be put in the rule's target as `$1` to `$9`, in
+//! order. A rule either rewrites the request internally, so it is answered
+//! as if it had asked for the target, or redirects the client there.
+//!
+//! Rules are tried in order and the first match wins. A rewritten request
+//! goes through the rules again, so rewrites can chain, up to
+//! `MAX_REWRITES`; one that rewrites more often than that is taken for a
+//! loop and gets `500 Internal Server Error`. Its query string is kept,
+//! unless the target brings one of its own.
+//!
+//! In a configuration file, rules go in a `[rewrites]` table, the pattern
+//! as the key and the action as the value: `rewrite <target>` or a redirect
+//! status followed by the target.
+//!
+//! # Example
+//! ```toml
+//! [rewrites]
+//! "/blog/*.html" = "301 /posts/$1/"
+//! "/img/*" = "rewrite /static/images/$1"
+//! ```
+use crate::http::request::HttpRequest;
+use crate::http::status::StatusCode;
+use crate::util;
+
+/// How many internal rewrites one request may go through.
+pub const MAX_REWRITES: usize = 10;
+
+/// What a matching rule does.
+///
+/// Variants:
+/// - `Rewrite(String)`: Answers the request as if it were for the target, a
+///   path starting with `/` (and optionally a query). The client isn't told.
+/// - `Redirect(StatusCode, String)`: Sends the client to the target, a path
+///   or an absolute URL, with this status: 301, 302, 303, 307 or 308.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteAction {
+    Rewrite(String),
+    Redirect(StatusCode, String),
+}
+
+/// One rule: where it applies, and what it does there.
+///
+/// # Fields
+/// - `pattern` (*String*): The paths it applies to, starting with `/`, with
+///   at most nine `*`s (see the module docs).
+/// - `action` (*RewriteAction*): What it does, its target naming the `*`s'
+///   matches as `$1` to `$9`.
+///
+/// # Example
+/// ```
+/// let rule = RewriteRule::new(
+///     "/img/*",
+///     RewriteAction::Rewrite(String::from("/static/images/$1")),
+/// )?;
+/// let config = ServerConfig::builder().rewrite(rule).build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteRule {
+    pub pattern: String,
+    pub action: RewriteAction,
+}
+
+impl RewriteRule {
+    /// Returns a rule doing `action` on the paths matching `pattern`.
+    ///
+    /// # Errors
+    /// The reason, if `pattern` doesn't start with `/` or has more than nine
+    /// `*`s, the target names a `*` the pattern doesn't have, a rewrite's
+    /// target doesn't start with `/`, or a redirect's status isn't one.
+    pub fn new(pattern: &str, action: RewriteAction) -> Result<RewriteRule, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("rewrite pattern {pattern} must start with /"));
+        }
+        let wildcards = pattern.matches('*').count();
+        if wildcards > 9 {
+            return Err(format!("rewrite pattern {pattern} has more than nine *s"));
+        }
+        let target = match &action {
+            RewriteAction::Rewrite(target) if !target.starts_with('/') => {
+                return Err(format!("rewrite target {target} must start with /"));
+            }
+            RewriteAction::Redirect(status, _) if !is_redirect(*status) => {
+                return Err(format!("{} is not a redirect status", status.as_u16()));
+            }
+            RewriteAction::Rewrite(target) | RewriteAction::Redirect(_, target) => target,
+        };
+        if let Some(n) = references(target).find(|&n| n > wildcards) {
+            return Err(format!(
+                "target {target} uses ${n}, but {pattern} has {wildcards} *s"
+            ));
+        }
+        Ok(RewriteRule {
+            pattern: pattern.to_owned(),
+            action,
+        })
+    }
+
+    /// Parses a rule as written in a `[rewrites]` table: `pattern` is the
+    /// key and `action` the value, `rewrite /target` or e.g. `301 /target`.
+    ///
+    /// # Errors
+    /// The reason, if `action` is neither, or as `new`.
+    pub fn parse(pattern: &str, action: &str) -> Result<RewriteRule, String> {
+        let Some((kind, target)) = action.trim().split_once(char::is_whitespace) else {
+            return Err(format!(
+                "expected \"rewrite <target>\" or \"<status> <target>\", got {action:?}"
+            ));
+        };
+        let target = target.trim().to_owned();
+        let action = match kind {
+            "rewrite" => RewriteAction::Rewrite(target),
+            code => {
+                let status = code
+                    .parse()
+                    .ok()
+                    .and_then(StatusCode::from_u16)
+                    .ok_or(format!("expected rewrite or a redirect status, got {code}"))?;
+                RewriteAction::Redirect(status, target)
+            }
+        };
+        RewriteRule::new(pattern, action)
+    }
+
+    /// Returns what each `*` of the pattern matched in `path`, if it matches.
+    fn captures<'p>(&self, path: &'p str) -> Option<Vec<&'p str>> {
+        util::wildcard_captures(&self.pattern, path)
+    }
+}
+
+/// What the rules make of a request (see `apply`).
+///
+/// Variants:
+/// - `Unchanged`: No rule matches it.
+/// - `Rewritten(String, Option<String>)`: The path and query to answer the
+///   request for instead.
+/// - `Redirect(StatusCode, String)`: The client is to be sent to this
+///   location, query included, with this status.
+/// - `Loop`: Rewrites went on past `MAX_REWRITES`.
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    Unchanged,
+    Rewritten(String, Option<String>),
+    Redirect(StatusCode, String),
+    Loop,
+}
+
+/// Runs `request` through `rules`; see the module docs.
+pub(crate) fn apply(rules: &[RewriteRule], request: &HttpRequest) -> Outcome {
+    let (mut path, mut query) = (request.path.clone(), request.query.clone());
+    for rewrites in 0..=MAX_REWRITES {
+        let matched = rules
+            .iter()
+            .find_map(|rule| Some((rule, rule.captures(&path)?)));
+        let Some((rule, captures)) = matched else {
+            if rewrites == 0 {
+                return Outcome::Unchanged;
+            }
+            return Outcome::Rewritten(path, query);
+        };
+        match &rule.action {
+            RewriteAction::Redirect(status, target) => {
+                let mut location = substitute(target, &captures);
+                if let Some(query) = query.filter(|_| !location.contains('?')) {
+                    location.push('?');
+                    location.push_str(&query);
+                }
+                return Outcome::Redirect(*status, location);
+            }
+            RewriteAction::Rewrite(target) => {
+                let target = substitute(target, &captures);
+                match target.split_once('?') {
+                    Some((new_path, new_query)) => {
+                        query = Some(new_query.to_owned()).filter(|query| !query.is_empty());
+                        path = new_path.to_owned();
+                    }
+                    None => path = target,
+                }
+            }
+        }
+    }
+    Outcome::Loop
+}
+
+/// Returns `target` with `$1` to `$9` replaced by `captures`.
+fn substitute(target: &str, captures: &[&str]) -> String {
+    let mut out = String::with_capacity(target.len());
+    let mut chars = target.chars().peekable();
+    while let Some(c) = chars.next() {
+        let capture = chars
+            .peek()
+            .and_then(|d| d.to_digit(10))
+            .filter(|&n| c == '$' && n >= 1)
+            .and_then(|n| captures.get(n as usize - 1));
+        match capture {
+            Some(capture) => {
+                out.push_str(capture);
+                chars.next();
+            }
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// Returns the numbers of the captures `target` names (`$1` to `$9`).
+fn references(target: &str) -> impl Iterator<Item = usize> + '_ {
+    target
+        .as_bytes()
+        .windows(2)
+        .filter(|pair| pair[0] == b'$' && (b'1'..=b'9').contains(&pair[1]))
+        .map(|pair| usize::from(pair[1] - b'0'))
+}
+
+/// Returns `true` if `status` is one a rule may redirect with.
+fn is_redirect(status: StatusCode) -> bool {
+    [
+        StatusCode::MOVED_PERMANENTLY,
+        StatusCode::FOUND,
+        StatusCode::SEE_OTHER,
+        StatusCode::TEMPORARY_REDIRECT,
+        StatusCode::PERMANENT_REDIRECT,
+    ]
+    .contains(&status)
+}
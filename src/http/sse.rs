@@ -0,0 +1,281 @@
+//! Server-Sent Events (the `text/event-stream` format of the HTML standard).
+//!
+//! A handler answers with `event_stream`, which returns the response to send
+//! and an `SseSender`. The response stays open; whatever is sent through the
+//! sender, from any thread, is written to the client as it arrives:
+//! ```
+//! router.get("/events", |request: &HttpRequest| {
+//!     let (response, events) = sse::event_stream(request, &SseConfig::default());
+//!     std::thread::spawn(move || {
+//!         for tick in 0.. {
+//!             if events.send(&SseEvent::data(tick.to_string())).is_err() {
+//!                 break; // the client has gone
+//!             }
+//!             std::thread::sleep(Duration::from_secs(1));
+//!         }
+//!     });
+//!     response
+//! });
+//! ```
+//!
+//! The stream ends once every sender is dropped. Until then it is kept
+//! alive with a comment line whenever nothing else was sent for
+//! `SseConfig::keep_alive`, which also shows soon enough when the client has
+//! gone. Events are buffered while the client is slow to read; once more
+//! than `SseConfig::max_buffered` bytes are waiting the connection is
+//! dropped, and the client is expected to reconnect (with `Last-Event-ID`,
+//! if events carry an `id`).
+use crate::http::body::{BodySource, BodyWaker, StreamBody};
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::HttpResponse;
+use crate::http::status::StatusCode;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Event stream settings.
+///
+/// # Fields
+/// - `keep_alive` (*Duration*): How long the stream may go quiet before a comment
+///   line is sent to keep proxies from timing it out. Defaults to 15 seconds.
+/// - `max_buffered` (*usize*): The most bytes of events held for a client that
+///   isn't reading them. An event that would go over closes the connection.
+///   Defaults to 256 KiB.
+#[derive(Debug, Clone)]
+pub struct SseConfig {
+    pub keep_alive: Duration,
+    pub max_buffered: usize,
+}
+
+impl Default for SseConfig {
+    fn default() -> SseConfig {
+        SseConfig {
+            keep_alive: Duration::from_secs(15),
+            max_buffered: 256 * 1024,
+        }
+    }
+}
+
+/// One event.
+///
+/// # Fields
+/// - `event` (*Option<String>*): The event type, for `addEventListener`; `None` is
+///   the default `message` type.
+/// - `data` (*String*): The payload. Each line goes out as its own `data:` line,
+///   and the client joins them back with `\n`.
+/// - `id` (*Option<String>*): The id the client sends back in `Last-Event-ID`
+///   when it reconnects.
+/// - `retry` (*Option<Duration>*): How long the client should wait before
+///   reconnecting.
+///
+/// # Example
+/// ```
+/// let event = SseEvent::data("42").event("count").id("7");
+/// assert_eq!(event.to_string(), "event: count\nid: 7\ndata: 42\n\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    /// Creates an event of the default type with `data` as its payload.
+    pub fn data(data: impl Into<String>) -> SseEvent {
+        SseEvent {
+            data: data.into(),
+            ..SseEvent::default()
+        }
+    }
+
+    /// Sets the event type.
+    pub fn event(mut self, event: impl Into<String>) -> SseEvent {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the event id.
+    pub fn id(mut self, id: impl Into<String>) -> SseEvent {
+        self.id = Some(id.into());
+        self
+    }
+}
+
+impl std::fmt::Display for SseEvent {
+    /// Formats the event as it goes on the wire, blank line included. Line
+    /// breaks in `event` and `id`, which would start a new field, are dropped.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+        }
+        writeln!(f)
+    }
+}
+
+/// What the senders and the body of one stream share.
+struct Shared {
+    // Formatted events not yet handed to the reactor.
+    queue: Vec<u8>,
+    // Set once the reactor starts sending the body.
+    waker: Option<BodyWaker>,
+    senders: usize,
+    // The connection has closed (or was dropped for falling behind).
+    closed: bool,
+}
+
+/// Sends events to one client's stream. Clones send to the same stream,
+/// which ends once all of them are dropped.
+pub struct SseSender {
+    shared: Arc<Mutex<Shared>>,
+    max_buffered: usize,
+}
+
+impl SseSender {
+    /// Queues `event` for the client and wakes the reactor to send it.
+    ///
+    /// # Errors
+    /// `BrokenPipe` if the client has gone, the server has stopped, or the
+    /// client fell more than `SseConfig::max_buffered` bytes behind; the
+    /// connection is closed in that last case. Nothing more can be sent.
+    pub fn send(&self, event: &SseEvent) -> io::Result<()> {
+        let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "the event stream has closed");
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            return Err(gone());
+        }
+        let event = event.to_string();
+        if shared.queue.len() + event.len() > self.max_buffered {
+            shared.closed = true;
+            if let Some(waker) = &shared.waker {
+                let _ = waker.abort();
+            }
+            return Err(gone());
+        }
+        shared.queue.extend_from_slice(event.as_bytes());
+        match &shared.waker {
+            Some(waker) => waker.wake(),
+            // The reactor takes what's queued as soon as it starts the body.
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` once the client has gone; see `send`.
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared).closed
+    }
+}
+
+impl Clone for SseSender {
+    fn clone(&self) -> SseSender {
+        lock(&self.shared).senders += 1;
+        SseSender {
+            shared: Arc::clone(&self.shared),
+            max_buffered: self.max_buffered,
+        }
+    }
+}
+
+impl Drop for SseSender {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.senders -= 1;
+        if shared.senders == 0
+            && let Some(waker) = &shared.waker
+        {
+            // Lets the body see that the stream has ended.
+            let _ = waker.wake();
+        }
+    }
+}
+
+/// The body side of a stream: hands the reactor what the senders queued.
+struct EventSource {
+    shared: Arc<Mutex<Shared>>,
+    keep_alive: Duration,
+    last_sent: Instant,
+}
+
+impl BodySource for EventSource {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            // Only a sender closes the stream while the body is alive: the
+            // client fell behind, possibly before the body even started.
+            return Err(io::Error::other("the client fell too far behind"));
+        }
+        if !shared.queue.is_empty() {
+            self.last_sent = Instant::now();
+            return Ok(Some(std::mem::take(&mut shared.queue)));
+        }
+        if shared.senders == 0 {
+            return Ok(None);
+        }
+        if self.last_sent.elapsed() >= self.keep_alive {
+            self.last_sent = Instant::now();
+            return Ok(Some(b": keep-alive\n\n".to_vec()));
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn attach(&mut self, waker: BodyWaker) {
+        lock(&self.shared).waker = Some(waker);
+    }
+}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        lock(&self.shared).closed = true;
+    }
+}
+
+/// Locks `shared`, carrying on if a thread panicked while holding it: the
+/// queue is only ever appended to or taken whole, so it is never left torn.
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts an event stream for `request`.
+///
+/// # Returns
+/// The `200 OK` response to answer `request` with (`text/event-stream`,
+/// chunked, or ended by closing the connection for an HTTP/1.0 client) and
+/// the sender for its events.
+pub fn event_stream(request: &HttpRequest, config: &SseConfig) -> (HttpResponse, SseSender) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: Vec::new(),
+        waker: None,
+        senders: 1,
+        closed: false,
+    }));
+    let source = EventSource {
+        shared: Arc::clone(&shared),
+        keep_alive: config.keep_alive,
+        last_sent: Instant::now(),
+    };
+    let body = if request.version == "HTTP/1.0" {
+        StreamBody::until_close(source)
+    } else {
+        StreamBody::chunked(source)
+    };
+    let content_type = HeaderValue::from_static("text/event-stream");
+    let response = HttpResponse::stream(StatusCode::OK, content_type, body)
+        .with_header(headers::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    let sender = SseSender {
+        shared,
+        max_buffered: config.max_buffered,
+    };
+    (response, sender)
+}
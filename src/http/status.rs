@@ -0,0 +1,143 @@
+//! HTTP status codes and their canonical reason phrases.
+//!
+//! Reason phrases follow RFC 7231 §6.1 (plus the handful of later codes the
+//! server uses), so the status lines we emit look exactly like the ones
+//! client libraries expect, e.g. `HTTP/1.1 404 Not Found`.
+use std::fmt;
+
+/// An HTTP status code.
+///
+/// Use the associated constants (`StatusCode::NOT_FOUND`, ...) for codes the
+/// server emits itself, or `StatusCode::from_u16` for arbitrary codes.
+///
+/// # Example
+/// ```
+/// assert_eq!(StatusCode::NOT_FOUND.status_line(), "HTTP/1.1 404 Not Found");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const ACCEPTED: StatusCode = StatusCode(202);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const CONFLICT: StatusCode = StatusCode(409);
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const MISDIRECTED_REQUEST: StatusCode = StatusCode(421);
+    pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    /// Creates a status code from its numeric value.
+    ///
+    /// # Returns
+    /// - `Some(StatusCode)` for values in `100..=599`.
+    /// - `None` otherwise.
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        (100..=599).contains(&code).then_some(StatusCode(code))
+    }
+
+    /// Returns the numeric value of the status code.
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns the canonical reason phrase, or `""` for unregistered codes.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            305 => "Use Proxy",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            426 => "Upgrade Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+
+    /// Returns `true` for status codes whose responses never carry a body
+    /// (1xx, 204 and 304).
+    pub fn is_bodiless(&self) -> bool {
+        matches!(self.0, 100..=199 | 204 | 304)
+    }
+
+    /// Returns the full HTTP/1.1 status line without the trailing CRLF.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(StatusCode::FORBIDDEN.status_line(), "HTTP/1.1 403 Forbidden");
+    /// ```
+    pub fn status_line(&self) -> String {
+        format!("HTTP/1.1 {self}")
+    }
+}
+
+impl fmt::Display for StatusCode {
+    /// Formats as `<code> <reason phrase>`, e.g. `404 Not Found`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason_phrase())
+    }
+}
@@ -0,0 +1,251 @@
+//! Minimal server-side templates, for a few pages that aren't worth a
+//! template engine.
+//!
+//! Files under the document root with `TemplateConfig::extension` aren't
+//! served as they are: they are rendered with the `Context` the config's
+//! provider assembles for the request, and sent as HTML. The syntax:
+//! - `{{name}}`: The value of `name`, HTML-escaped.
+//! - `{{{name}}}`: The value of `name`, as it is.
+//! - `{{#each items}}...{{/each}}`: The part in between once per entry of the
+//!   list `items`, where the entry's keys are variables, too (and hide the
+//!   outer ones of the same name). Blocks may nest.
+//!
+//! Variables the context doesn't have render empty, and so does a list it
+//! doesn't have. A template that doesn't parse (a tag or block that is never
+//! closed, a `{{/each}}` without its `{{#each}}`, an unknown block) is an
+//! error, logged and answered with `500 Internal Server Error`.
+//!
+//! # Example
+//! ```html
+//! <h1>{{title}}</h1>
+//! <ul>
+//! {{#each uploads}}  <li><a href="{{url}}">{{name}}</a> ({{size}} bytes)</li>
+//! {{/each}}</ul>
+//! ```
+use crate::http::request::HttpRequest;
+use crate::util::html_escape;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The values a template is rendered with.
+///
+/// # Fields
+/// - `vars` (*HashMap<String, String>*): Variables by name.
+/// - `lists` (*HashMap<String, Vec<HashMap<String, String>>>*): Lists for
+///   `{{#each}}` by name, each entry its own variables by name.
+///
+/// # Example
+/// ```
+/// let mut context = Context::new();
+/// context
+///     .set("title", "Uploads")
+///     .list("uploads", vec![HashMap::from([(String::from("name"), String::from("a.txt"))])]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    pub vars: HashMap<String, String>,
+    pub lists: HashMap<String, Vec<HashMap<String, String>>>,
+}
+
+impl Context {
+    /// Returns an empty context.
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// Sets the variable `name` to `value`.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> &mut Context {
+        self.vars.insert(name.to_owned(), value.into());
+        self
+    }
+
+    /// Sets the list `name` to `items`.
+    pub fn list(&mut self, name: &str, items: Vec<HashMap<String, String>>) -> &mut Context {
+        self.lists.insert(name.to_owned(), items);
+        self
+    }
+}
+
+/// A function assembling the context a template is rendered with for a
+/// request (see `TemplateConfig`).
+pub type ContextProvider = Arc<dyn Fn(&HttpRequest) -> Context + Send + Sync>;
+
+/// Template settings.
+///
+/// # Fields
+/// - `extension` (*String*): Files with this extension, without the dot, are
+///   rendered rather than served. Compared ignoring case. Defaults to `tpl`.
+/// - `provider` (*ContextProvider*): Assembles each request's context. It
+///   runs on a worker thread, so it may block; `HttpRequest::path` tells it
+///   which page is being rendered.
+///
+/// # Example
+/// ```
+/// let templates = TemplateConfig::new(|request: &HttpRequest| {
+///     let mut context = Context::new();
+///     context.set("path", request.path.clone());
+///     context
+/// });
+/// let config = ServerConfig::builder().templates(templates).build()?;
+/// ```
+#[derive(Clone)]
+pub struct TemplateConfig {
+    pub extension: String,
+    pub provider: ContextProvider,
+}
+
+impl TemplateConfig {
+    /// Returns a config rendering `.tpl` files with the contexts of `provider`.
+    pub fn new(
+        provider: impl Fn(&HttpRequest) -> Context + Send + Sync + 'static,
+    ) -> TemplateConfig {
+        TemplateConfig {
+            extension: String::from("tpl"),
+            provider: Arc::new(provider),
+        }
+    }
+
+    /// Returns `true` if the file `filename` is a template.
+    pub(crate) fn renders(&self, filename: &str) -> bool {
+        Path::new(filename)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(&self.extension))
+    }
+}
+
+impl fmt::Debug for TemplateConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemplateConfig")
+            .field("extension", &self.extension)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Renders `template` with `context`; see the module docs.
+///
+/// # Errors
+/// The reason, with its line, if `template` doesn't parse.
+///
+/// # Example
+/// ```
+/// let mut context = Context::new();
+/// context.set("name", "<b>");
+/// assert_eq!(render("{{name}} {{{name}}}", &context)?, "&lt;b&gt; <b>");
+/// ```
+pub fn render(template: &str, context: &Context) -> Result<String, String> {
+    let nodes = parse(template)?;
+    let mut out = String::with_capacity(template.len());
+    render_nodes(&nodes, context, &mut Vec::new(), &mut out);
+    Ok(out)
+}
+
+/// A piece of a parsed template.
+///
+/// Variants:
+/// - `Text(&str)`: Copied as it is.
+/// - `Var(&str, bool)`: A variable, HTML-escaped if the flag is set.
+/// - `Each(&str, Vec<Node>)`: A block repeated for each entry of a list.
+enum Node<'t> {
+    Text(&'t str),
+    Var(&'t str, bool),
+    Each(&'t str, Vec<Node<'t>>),
+}
+
+/// Parses `template` into nodes.
+fn parse(template: &str) -> Result<Vec<Node<'_>>, String> {
+    let line = |at: usize| template[..at].matches('\n').count() + 1;
+    // The blocks still open, innermost last: their list, the line they
+    // start on and the nodes before them.
+    let mut open: Vec<(&str, usize, Vec<Node<'_>>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut at = 0;
+    while let Some(start) = template[at..].find("{{").map(|start| at + start) {
+        if start > at {
+            nodes.push(Node::Text(&template[at..start]));
+        }
+        let raw = template[start..].starts_with("{{{");
+        let (opening, close) = if raw { ("{{{", "}}}") } else { ("{{", "}}") };
+        let inner_start = start + opening.len();
+        let Some(len) = template[inner_start..].find(close) else {
+            return Err(format!("line {}: {opening} is never closed", line(start)));
+        };
+        at = inner_start + len + close.len();
+        let tag = template[inner_start..inner_start + len].trim();
+        if raw && (tag.starts_with('#') || tag.starts_with('/')) {
+            return Err(format!("line {}: blocks take two braces", line(start)));
+        }
+        match tag
+            .strip_prefix('#')
+            .map(|block| block.split_once(char::is_whitespace))
+        {
+            Some(Some(("each", list))) if !list.trim().is_empty() => {
+                open.push((list.trim(), line(start), mem::take(&mut nodes)));
+            }
+            Some(_) => return Err(format!("line {}: unknown block {{{{{tag}}}}}", line(start))),
+            None if tag == "/each" => {
+                let Some((list, _, outer)) = open.pop() else {
+                    return Err(format!(
+                        "line {}: {{{{/each}}}} without {{{{#each}}}}",
+                        line(start)
+                    ));
+                };
+                let body = mem::replace(&mut nodes, outer);
+                nodes.push(Node::Each(list, body));
+            }
+            None if tag.starts_with('/') => {
+                return Err(format!(
+                    "line {}: unknown block end {{{{{tag}}}}}",
+                    line(start)
+                ));
+            }
+            None if tag.is_empty() => return Err(format!("line {}: empty tag", line(start))),
+            None => nodes.push(Node::Var(tag, !raw)),
+        }
+    }
+    if let Some((list, start, _)) = open.pop() {
+        return Err(format!(
+            "line {start}: {{{{#each {list}}}}} is never closed"
+        ));
+    }
+    if at < template.len() {
+        nodes.push(Node::Text(&template[at..]));
+    }
+    Ok(nodes)
+}
+
+/// Appends `nodes`, rendered, to `out`. `scopes` holds the entries of the
+/// enclosing `{{#each}}` blocks, innermost last.
+fn render_nodes<'c>(
+    nodes: &[Node<'_>],
+    context: &'c Context,
+    scopes: &mut Vec<&'c HashMap<String, String>>,
+    out: &mut String,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(name, escape) => {
+                let value = scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(*name))
+                    .or_else(|| context.vars.get(*name));
+                match value {
+                    Some(value) if *escape => out.push_str(&html_escape(value)),
+                    Some(value) => out.push_str(value),
+                    None => {}
+                }
+            }
+            Node::Each(list, body) => {
+                for entry in context.lists.get(*list).into_iter().flatten() {
+                    scopes.push(entry);
+                    render_nodes(body, context, scopes, out);
+                    scopes.pop();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! File uploads with `PUT`.
+//!
+//! When enabled, `PUT /uploads/build.tar.gz` stores the request body as
+//! `build.tar.gz` in the upload root. Uploads are a separate tree from the
+//! document root and go through the same path checks as reads: the path is
+//! percent-decoded and normalized, hidden components are refused, and the
+//! directory the file lands in must canonicalize to a path inside the root.
+use crate::http::request::HttpRequest;
+use crate::http::response::ErrorPage;
+use crate::io::file::write_file_atomic;
+use crate::log;
+use crate::server::ServerConfig;
+use crate::util;
+use std::io;
+use std::path::PathBuf;
+
+/// Upload settings.
+///
+/// # Fields
+/// - `prefix` (*String*): Request paths under this prefix are uploads. Defaults to `/uploads/`.
+/// - `root` (*PathBuf*): The directory uploads are written to. The part of the
+///   request path after `prefix` is used as the path inside it. Defaults to `uploads`,
+///   resolved against the working directory at startup like the document root.
+/// - `max_size` (*u64*): The largest accepted body in bytes. Bigger uploads get
+///   `413 Payload Too Large`. Defaults to 100 MiB.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub prefix: String,
+    pub root: PathBuf,
+    pub max_size: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> UploadConfig {
+        UploadConfig {
+            prefix: String::from("/uploads/"),
+            root: PathBuf::from("uploads"),
+            max_size: 100 * 1024 * 1024,
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Replaces `root` with its absolute, canonical form.
+    ///
+    /// # Errors
+    /// Fails if the root doesn't exist, with the path in the message.
+    pub fn resolve_root(&mut self) -> io::Result<()> {
+        self.root = std::fs::canonicalize(&self.root).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("upload root {}: {e}", self.root.display()),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Returns the path inside the upload root for the request path `path`,
+    /// or `None` if the normalized path isn't under `prefix` (or can't be decoded).
+    fn relative_path(&self, path: &str) -> Option<String> {
+        let decoded = util::percent_decode(path)?;
+        let normalized = util::normalize_path(&decoded)?;
+        let rest = normalized.strip_prefix(self.prefix.trim_end_matches('/'))?;
+        rest.starts_with('/').then(|| rest.to_string())
+    }
+
+    /// Returns `true` if a `PUT` to `path` is an upload.
+    ///
+    /// This looks at the path as sent (after percent-decoding), before
+    /// normalization, so `/uploads/../x` is still treated as an upload and
+    /// refused by `store` instead of falling through to another handler.
+    pub fn matches(&self, path: &str) -> bool {
+        let decoded = util::percent_decode(path).unwrap_or_else(|| path.to_string());
+        let prefix = self.prefix.trim_end_matches('/');
+        decoded
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    /// Stores the body of the `PUT` request `request` in the upload root.
+    ///
+    /// # Returns
+    /// - `Ok(true)`: An existing file was overwritten (answered with `204 No Content`).
+    /// - `Ok(false)`: A new file was created (answered with `201 Created`).
+    /// - `Err(ErrorPage)`: `400` for undecodable paths, `403` for hidden
+    ///   components or anything outside the root, `409` if the parent directory
+    ///   doesn't exist or the target is a directory, `500` if writing failed.
+    pub(crate) fn store(
+        &self,
+        request: &HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<bool, ErrorPage> {
+        let decoded = match util::percent_decode(&request.path) {
+            Some(decoded) if !decoded.contains('\0') => decoded,
+            _ => return Err(ErrorPage::BadRequest),
+        };
+        let Some(normalized) = util::normalize_path(&decoded) else {
+            return Err(ErrorPage::PermissionDenied);
+        };
+        if config.is_hidden_path(&normalized) {
+            return Err(ErrorPage::PermissionDenied);
+        }
+        let relative = self
+            .relative_path(&request.path)
+            .ok_or(ErrorPage::PermissionDenied)?;
+        if relative.ends_with('/') {
+            return Err(ErrorPage::Conflict);
+        }
+
+        let target = self.root.join(relative.trim_start_matches('/'));
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(ErrorPage::Conflict);
+        };
+        let parent = match std::fs::canonicalize(parent) {
+            Ok(parent) if !parent.starts_with(&self.root) => {
+                return Err(ErrorPage::PermissionDenied);
+            }
+            Ok(parent) if parent.is_dir() => parent,
+            Ok(_) => return Err(ErrorPage::Conflict),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ErrorPage::Conflict),
+            Err(e) => {
+                log::error(format_args!(
+                    "Error resolving upload directory {}: {}",
+                    parent.display(),
+                    e
+                ));
+                return Err(ErrorPage::InternalServerError);
+            }
+        };
+        let target = parent.join(name);
+        if target.is_dir() {
+            return Err(ErrorPage::Conflict);
+        }
+
+        write_file_atomic(&target, &request.body).map_err(|e| {
+            log::error(format_args!(
+                "Error writing upload {}: {}",
+                target.display(),
+                e
+            ));
+            match e.kind() {
+                io::ErrorKind::PermissionDenied => ErrorPage::PermissionDenied,
+                _ => ErrorPage::InternalServerError,
+            }
+        })
+    }
+}
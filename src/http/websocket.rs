@@ -0,0 +1,489 @@
+//! WebSocket connections (RFC 6455).
+//!
+//! A route added with `Router::websocket` answers a valid opening handshake
+//! (`GET` with `Upgrade: websocket`, `Connection: Upgrade`,
+//! `Sec-WebSocket-Version: 13` and a `Sec-WebSocket-Key`) with `101 Switching
+//! Protocols`. Once that is sent the connection stops speaking HTTP: the
+//! reactor reads frames off it, answers pings and close frames itself, and
+//! hands each whole message to the route's `WebSocketHandler` on the pool.
+//! A connection's events run one at a time, so the handler sees messages
+//! in the order they arrived.
+//!
+//! Client frames must be masked. A frame that breaks the protocol, a text
+//! message that isn't UTF-8, or a message bigger than
+//! `WebSocketConfig::max_message_size` closes the connection with the
+//! matching close code.
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::HttpRequest;
+use crate::http::response::{ErrorPage, HttpResponse, empty_response};
+use crate::http::status::StatusCode;
+use crate::io::nonblocking::{ReactorHandle, ReactorMsg};
+use crate::util;
+use mio::Token;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Appended to the client's key before hashing it (RFC 6455 §1.3).
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Close code: the connection did what it was for.
+pub const CLOSE_NORMAL: u16 = 1000;
+/// Close code: the server is going away, e.g. shutting down.
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+/// Close code: a frame broke the protocol.
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// Close code: a text message wasn't UTF-8.
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+/// Close code: a message was bigger than `WebSocketConfig::max_message_size`.
+pub const CLOSE_TOO_BIG: u16 = 1009;
+/// Close code: the handler panicked.
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+/// Close code: the server is too busy to handle the connection's messages.
+pub const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// WebSocket settings, shared by every `Router::websocket` route of a site.
+///
+/// # Fields
+/// - `max_message_size` (*usize*): The largest message accepted, in bytes, after
+///   fragments are put together. A bigger one closes the connection with
+///   `CLOSE_TOO_BIG` as soon as its frame header says so. Defaults to 1 MiB.
+/// - `idle_timeout` (*Duration*): How long a connection may go without a frame in
+///   either direction before it is closed. Clients that want to stay connected
+///   while quiet send pings. Defaults to 60 seconds.
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    pub max_message_size: usize,
+    pub idle_timeout: Duration,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: 1024 * 1024,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A whole message, put together from its fragments.
+///
+/// Variants:
+/// - `Text(String)`: A text message.
+/// - `Binary(Vec<u8>)`: A binary message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// What a `Router::websocket` route does with its connections.
+///
+/// The methods run on the worker pool, never on the event loop, so they may
+/// block; but while one runs, the connection's next message waits.
+///
+/// Functions and closures taking `(&WebSocket, Message)` are handlers too,
+/// with nothing to do on open and close.
+///
+/// # Example
+/// ```
+/// router.websocket("/echo", |socket: &WebSocket, message: Message| {
+///     let _ = socket.send(message);
+/// });
+/// ```
+pub trait WebSocketHandler: Send + Sync {
+    /// Called once the `101` response is sent, before any message.
+    fn on_open(&self, _socket: &WebSocket) {}
+
+    /// Called for each message the client sends.
+    fn on_message(&self, socket: &WebSocket, message: Message);
+
+    /// Called once the connection is gone, with the code of the client's
+    /// close frame, or `None` if it closed without one. Messages still
+    /// queued for the handler by then are dropped.
+    fn on_close(&self, _socket: &WebSocket, _code: Option<u16>) {}
+}
+
+impl<F> WebSocketHandler for F
+where
+    F: Fn(&WebSocket, Message) + Send + Sync,
+{
+    fn on_message(&self, socket: &WebSocket, message: Message) {
+        self(socket, message)
+    }
+}
+
+/// One open websocket connection, as seen by its handler. Clones refer to
+/// the same connection and may be sent to other threads, e.g. to push
+/// messages to the client without waiting for it to say something.
+///
+/// Sending to a connection that has closed does nothing.
+#[derive(Clone)]
+pub struct WebSocket {
+    token: Token,
+    reactor: ReactorHandle,
+    path: String,
+}
+
+impl WebSocket {
+    /// Returns the request path the connection was opened with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Sends `message` to the client.
+    ///
+    /// # Errors
+    /// Fails with `BrokenPipe` if the server has stopped.
+    pub fn send(&self, message: Message) -> io::Result<()> {
+        let frame = match &message {
+            Message::Text(text) => encode_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(bytes) => encode_frame(OP_BINARY, bytes),
+        };
+        self.reactor.send(ReactorMsg::WebSocketSend {
+            token: self.token,
+            frame,
+            close: false,
+        })
+    }
+
+    /// Sends a close frame with `code` and `reason` and closes the connection
+    /// once it is out. Nothing sent after it reaches the client.
+    ///
+    /// # Errors
+    /// Fails with `BrokenPipe` if the server has stopped.
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        self.reactor.send(ReactorMsg::WebSocketSend {
+            token: self.token,
+            frame: close_frame(code, reason),
+            close: true,
+        })
+    }
+}
+
+/// Returns the `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+///
+/// # Example
+/// ```
+/// // The example from RFC 6455 §1.3.
+/// assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+/// ```
+pub fn accept_key(key: &str) -> String {
+    util::base64_encode(&util::sha1(format!("{key}{HANDSHAKE_GUID}").as_bytes()))
+}
+
+/// Answers an opening handshake for a websocket route.
+///
+/// # Returns
+/// - `Ok(HttpResponse)`: `101 Switching Protocols` with `Sec-WebSocket-Accept`;
+///   or `426 Upgrade Required` for a request that doesn't ask for a websocket
+///   or asks for a version other than 13.
+/// - `Err(ErrorPage::BadRequest)`: The request asks for a websocket but isn't
+///   HTTP/1.1, doesn't say `Connection: Upgrade` or has no valid key.
+pub(crate) fn handshake(request: &HttpRequest) -> Result<HttpResponse, ErrorPage> {
+    let wants_websocket = request
+        .header("Upgrade")
+        .is_some_and(|upgrade| has_token(upgrade, "websocket"));
+    if !wants_websocket || request.header("Sec-WebSocket-Version") != Some("13") {
+        return Ok(HttpResponse::text(
+            StatusCode::UPGRADE_REQUIRED,
+            "this is a websocket endpoint",
+        )
+        .with_header(headers::UPGRADE, HeaderValue::from_static("websocket"))
+        .with_header(
+            headers::SEC_WEBSOCKET_VERSION,
+            HeaderValue::from_static("13"),
+        ));
+    }
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .map(str::trim)
+        // Base64 of 16 bytes: 22 characters and two `=` of padding.
+        .filter(|key| key.len() == 24 && key.ends_with("=="))
+        .ok_or(ErrorPage::BadRequest)?;
+    let connection_upgrade = request
+        .header("Connection")
+        .is_some_and(|connection| has_token(connection, "upgrade"));
+    if request.version != "HTTP/1.1" || !connection_upgrade {
+        return Err(ErrorPage::BadRequest);
+    }
+
+    let accept = HeaderValue::try_from(accept_key(key)).map_err(|_| ErrorPage::BadRequest)?;
+    let mut response = empty_response(StatusCode::SWITCHING_PROTOCOLS);
+    response
+        .headers
+        .insert(headers::UPGRADE, HeaderValue::from_static("websocket"));
+    response
+        .headers
+        .insert(headers::CONNECTION, HeaderValue::from_static("Upgrade"));
+    response
+        .headers
+        .insert(headers::SEC_WEBSOCKET_ACCEPT, accept);
+    Ok(response)
+}
+
+/// Returns `true` if the comma-separated header `value` lists `token`, ignoring case.
+fn has_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Builds an unmasked frame, as servers send them, with FIN set.
+pub(crate) fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Builds the pong answering a ping with `payload`.
+pub(crate) fn pong_frame(payload: &[u8]) -> Vec<u8> {
+    encode_frame(OP_PONG, payload)
+}
+
+/// Builds a close frame. The reason is cut short, at a character boundary,
+/// to fit the 125 bytes a control frame may carry.
+pub(crate) fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    encode_frame(OP_CLOSE, &payload)
+}
+
+/// What a client sent, once a frame (or the last fragment of a message) is in.
+///
+/// Variants:
+/// - `Message(Message)`: A whole message.
+/// - `Ping(Vec<u8>)`: A ping, with the payload the pong must echo.
+/// - `Pong`: A pong, which needs no answer.
+/// - `Close(Option<u16>)`: A close frame, with its code if it had one.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Incoming {
+    Message(Message),
+    Ping(Vec<u8>),
+    Pong,
+    Close(Option<u16>),
+}
+
+/// Takes frames out of a connection's read buffer and puts fragmented
+/// messages back together.
+pub(crate) struct FrameReader {
+    max_message_size: usize,
+    // The opcode and payload so far of a fragmented message.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl FrameReader {
+    pub(crate) fn new(max_message_size: usize) -> FrameReader {
+        FrameReader {
+            max_message_size,
+            partial: None,
+        }
+    }
+
+    /// Takes the next message or control frame out of `buffer`. Fragments
+    /// of a message that isn't complete yet are taken out and kept.
+    ///
+    /// # Returns
+    /// - `Ok(Some(Incoming))`: A message or control frame.
+    /// - `Ok(None)`: More bytes are needed.
+    /// - `Err(u16)`: The close code to fail the connection with.
+    pub(crate) fn next(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Incoming>, u16> {
+        loop {
+            let Some((fin, opcode, payload)) = self.take_frame(buffer)? else {
+                return Ok(None);
+            };
+            match opcode {
+                OP_PING => return Ok(Some(Incoming::Ping(payload))),
+                OP_PONG => return Ok(Some(Incoming::Pong)),
+                OP_CLOSE => return close_code(&payload).map(|code| Some(Incoming::Close(code))),
+                OP_CONTINUATION => {
+                    let Some((_, message)) = &mut self.partial else {
+                        return Err(CLOSE_PROTOCOL_ERROR);
+                    };
+                    message.extend_from_slice(&payload);
+                }
+                _ if self.partial.is_some() => return Err(CLOSE_PROTOCOL_ERROR),
+                _ => self.partial = Some((opcode, payload)),
+            }
+            if fin && let Some((opcode, payload)) = self.partial.take() {
+                return message(opcode, payload).map(|message| Some(Incoming::Message(message)));
+            }
+        }
+    }
+
+    /// Takes one whole frame out of `buffer`, unmasked.
+    ///
+    /// # Returns
+    /// `(fin, opcode, payload)`, `None` if the frame isn't all there, or the
+    /// close code for a frame that breaks the protocol or the size limit.
+    fn take_frame(&self, buffer: &mut Vec<u8>) -> Result<Option<(bool, u8, Vec<u8>)>, u16> {
+        let [first, second, ..] = buffer[..] else {
+            return Ok(None);
+        };
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0F;
+        if first & 0x70 != 0 || second & 0x80 == 0 {
+            // Reserved bits without an extension, or an unmasked client frame.
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        let control = opcode & 0x08 != 0;
+        if !matches!(
+            opcode,
+            OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG
+        ) {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        let (len, header_len) = match second & 0x7F {
+            126 => match buffer.get(2..4) {
+                Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+                None => return Ok(None),
+            },
+            127 => match buffer.get(2..10) {
+                Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap_or_default()), 10),
+                None => return Ok(None),
+            },
+            len => (u64::from(len), 2),
+        };
+        // The most significant bit of a 64-bit length must be 0.
+        if len >> 63 != 0 || control && (!fin || len > 125) {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        let so_far = self
+            .partial
+            .as_ref()
+            .filter(|_| opcode == OP_CONTINUATION)
+            .map_or(0, |(_, message)| message.len() as u64);
+        match so_far.checked_add(len) {
+            Some(total) if control || total <= self.max_message_size as u64 => {}
+            _ => return Err(CLOSE_TOO_BIG),
+        }
+        let start: usize = header_len + 4;
+        let Some(end) = usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+        else {
+            return Err(CLOSE_TOO_BIG);
+        };
+        if buffer.len() < end {
+            return Ok(None);
+        }
+        let mask = [
+            buffer[header_len],
+            buffer[header_len + 1],
+            buffer[header_len + 2],
+            buffer[header_len + 3],
+        ];
+        let payload: Vec<u8> = buffer[start..end]
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask)
+            .collect();
+        buffer.drain(..end);
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+/// Reads the code of a close frame's payload, checking the reason is UTF-8.
+fn close_code(payload: &[u8]) -> Result<Option<u16>, u16> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(CLOSE_PROTOCOL_ERROR),
+        [high, low, reason @ ..] => {
+            std::str::from_utf8(reason).map_err(|_| CLOSE_INVALID_DATA)?;
+            Ok(Some(u16::from_be_bytes([*high, *low])))
+        }
+    }
+}
+
+/// Turns a reassembled payload into a `Message`.
+fn message(opcode: u8, payload: Vec<u8>) -> Result<Message, u16> {
+    match opcode {
+        OP_TEXT => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| CLOSE_INVALID_DATA),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+/// Something for a connection's handler to do, in the order they happened.
+///
+/// Variants:
+/// - `Open`: The handshake is done.
+/// - `Message(Message)`: A message arrived.
+pub(crate) enum Event {
+    Open,
+    Message(Message),
+}
+
+/// The websocket side of a connection: its handler, the handle given to
+/// the handler, and the events waiting for it. Built on the pool with the
+/// `101` response and carried by the connection from then on.
+pub struct Session {
+    pub(crate) handler: Arc<dyn WebSocketHandler>,
+    pub(crate) socket: WebSocket,
+    pub(crate) reader: FrameReader,
+    pub(crate) inbox: VecDeque<Event>,
+    // An event is being handled on the pool; the next waits until it's done.
+    pub(crate) busy: bool,
+    // The `101` is sent and `on_open` queued, so `on_close` is owed.
+    pub(crate) open: bool,
+    pub(crate) close_code: Option<u16>,
+    pub(crate) idle_timeout: Duration,
+}
+
+impl Session {
+    pub(crate) fn new(
+        handler: Arc<dyn WebSocketHandler>,
+        token: Token,
+        reactor: ReactorHandle,
+        path: String,
+        config: &WebSocketConfig,
+    ) -> Session {
+        Session {
+            handler,
+            socket: WebSocket {
+                token,
+                reactor,
+                path,
+            },
+            reader: FrameReader::new(config.max_message_size),
+            inbox: VecDeque::new(),
+            busy: false,
+            open: false,
+            close_code: None,
+            idle_timeout: config.idle_timeout,
+        }
+    }
+}
+
+/// Runs `event` through `handler`; called on the pool.
+pub(crate) fn deliver(handler: &dyn WebSocketHandler, socket: &WebSocket, event: Event) {
+    match event {
+        Event::Open => handler.on_open(socket),
+        Event::Message(message) => handler.on_message(socket, message),
+    }
+}
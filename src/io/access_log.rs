@@ -0,0 +1,445 @@
+//! The access log: a line per response, once its last byte is sent.
+//!
+//! Reactors hand each finished response's `AccessRecord` to an
+//! `AccessLogger`, which only queues it on a channel. A dedicated thread
+//! formats the records and writes them out, flushing whenever the channel
+//! runs dry, so the event loop never waits on the disk or the terminal.
+use crate::http::auth::redact_query;
+use crate::http::request::HttpRequest;
+use crate::http::status::StatusCode;
+use crate::log;
+use crate::util::{civil_from_unix, format_rfc3339, json_escape};
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Where access log lines go.
+///
+/// Variants:
+/// - `Stdout`: Standard output, along with the console messages. The default.
+/// - `File(PathBuf)`: Appended to this file, which is created if need be.
+/// - `Channel(mpsc::Sender<String>)`: Sent down a channel, a line at a time
+///   without the line break, e.g. to ship them elsewhere or check them in tests.
+#[derive(Debug, Clone, Default)]
+pub enum LogTarget {
+    #[default]
+    Stdout,
+    File(PathBuf),
+    Channel(mpsc::Sender<String>),
+}
+
+/// One part of an `AccessLogFormat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Literal(String),
+    // `%h`
+    Client,
+    // `%t`
+    Time,
+    // `%r`
+    RequestLine,
+    // `%s` or `%>s`
+    Status,
+    // `%b`
+    Bytes,
+    // `%D`
+    Micros,
+    // `%{Referer}i`
+    Referer,
+    // `%{User-Agent}i`
+    UserAgent,
+    // `%L`
+    RequestId,
+    // The whole line, as `AccessLogFormat::json` describes.
+    Json,
+}
+
+/// The layout of an access log line, in the `%` directives of Apache's
+/// `LogFormat`:
+/// - `%h`: The client's address (see `ServerConfig::trusted_proxies`), `-` if unknown.
+/// - `%t`: When the request arrived, e.g. `[10/Oct/2000:13:55:36 +0000]` (UTC).
+/// - `%r`: The request line, `-` for a request that never got that far
+///   (`400`, `408`, `413`).
+/// - `%s`, `%>s`: The status code.
+/// - `%b`: Response bytes sent, head included; `-` for none.
+/// - `%D`: How long the response took, in microseconds, from the request
+///   being parsed to its last byte being sent.
+/// - `%{Referer}i`, `%{User-Agent}i`: Those request headers, `-` if absent.
+/// - `%L`: The request ID (see `http::request_id`), `-` if there is none.
+/// - `%%`: A `%`.
+///
+/// `%r`, the headers and the request ID are escaped as Apache does (`\"`,
+/// `\\`, `\xHH`), so a line can't be forged by what the client sends. Tokens
+/// in the query strings of `%r` and the `Referer` are blanked (see
+/// `http::auth::redact_query`), and no directive logs `Authorization`.
+///
+/// Or a line is a JSON object instead; see `json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogFormat {
+    fields: Vec<Field>,
+}
+
+impl AccessLogFormat {
+    /// The Common Log Format: `%h - - %t "%r" %>s %b`.
+    pub fn common() -> AccessLogFormat {
+        let literal = |text: &str| Field::Literal(String::from(text));
+        AccessLogFormat {
+            fields: vec![
+                Field::Client,
+                literal(" - - "),
+                Field::Time,
+                literal(" \""),
+                Field::RequestLine,
+                literal("\" "),
+                Field::Status,
+                literal(" "),
+                Field::Bytes,
+            ],
+        }
+    }
+
+    /// The Combined Log Format: the common one with
+    /// `"%{Referer}i" "%{User-Agent}i"` added.
+    pub fn combined() -> AccessLogFormat {
+        let mut format = AccessLogFormat::common();
+        format.fields.extend([
+            Field::Literal(String::from(" \"")),
+            Field::Referer,
+            Field::Literal(String::from("\" \"")),
+            Field::UserAgent,
+            Field::Literal(String::from("\"")),
+        ]);
+        format
+    }
+
+    /// One JSON object per line, with the fields `ts` (when the request
+    /// arrived, RFC 3339), `level` (always `info`), `request_id`, `method`,
+    /// `path`, `status`, `duration_ms`, `peer` (the client's address) and
+    /// `bytes`. A field with no value (the method and path of a malformed
+    /// request, say) is `null`.
+    pub fn json() -> AccessLogFormat {
+        AccessLogFormat {
+            fields: vec![Field::Json],
+        }
+    }
+
+    /// Parses a custom layout, such as `%h "%r" %s %D`.
+    ///
+    /// # Errors
+    /// The first directive that isn't one of those listed on the type.
+    pub fn parse(template: &str) -> Result<AccessLogFormat, String> {
+        let mut fields = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('%') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            let directive = ["%", "h", "t", "r", "s", ">s", "b", "D", "L"]
+                .into_iter()
+                .chain(["{Referer}i", "{User-Agent}i"])
+                .find(|directive| rest.starts_with(directive))
+                .ok_or_else(|| {
+                    let shown: String = rest.chars().take(12).collect();
+                    format!("unknown access log directive %{shown}")
+                })?;
+            rest = &rest[directive.len()..];
+            let field = match directive {
+                "%" => {
+                    literal.push('%');
+                    continue;
+                }
+                "h" => Field::Client,
+                "t" => Field::Time,
+                "r" => Field::RequestLine,
+                "s" | ">s" => Field::Status,
+                "b" => Field::Bytes,
+                "D" => Field::Micros,
+                "L" => Field::RequestId,
+                "{Referer}i" => Field::Referer,
+                _ => Field::UserAgent,
+            };
+            if !literal.is_empty() {
+                fields.push(Field::Literal(std::mem::take(&mut literal)));
+            }
+            fields.push(field);
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            fields.push(Field::Literal(literal));
+        }
+        Ok(AccessLogFormat { fields })
+    }
+
+    /// Appends the line for `record` to `out`, without the line break.
+    fn write(&self, record: &AccessRecord, out: &mut String) -> fmt::Result {
+        let dash_or = |out: &mut String, value: &Option<String>| match value {
+            Some(value) => escape(value, out),
+            None => out.write_char('-'),
+        };
+        for field in &self.fields {
+            match field {
+                Field::Literal(text) => out.write_str(text)?,
+                Field::Client => match record.client {
+                    Some(ip) => write!(out, "{ip}")?,
+                    None => out.write_char('-')?,
+                },
+                Field::Time => write_clf_time(record.time, out)?,
+                Field::RequestLine => dash_or(out, &record.request_line)?,
+                Field::Status => {
+                    write!(out, "{}", record.status.map_or(0, |status| status.as_u16()))?
+                }
+                Field::Bytes if record.bytes == 0 => out.write_char('-')?,
+                Field::Bytes => write!(out, "{}", record.bytes)?,
+                Field::Micros => write!(out, "{}", record.duration.as_micros())?,
+                Field::Referer => dash_or(out, &record.referer)?,
+                Field::UserAgent => dash_or(out, &record.user_agent)?,
+                Field::RequestId => dash_or(out, &record.request_id)?,
+                Field::Json => write_json(record, out)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for AccessLogFormat {
+    fn default() -> AccessLogFormat {
+        AccessLogFormat::combined()
+    }
+}
+
+/// Access log settings.
+///
+/// # Fields
+/// - `format` (*AccessLogFormat*): The layout of a line. Defaults to the
+///   Combined Log Format.
+/// - `target` (*LogTarget*): Where lines go. Defaults to standard output.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub target: LogTarget,
+}
+
+/// What the access log knows of one response, collected by the reactor
+/// as the request arrives and its response is sent.
+#[derive(Debug)]
+pub(crate) struct AccessRecord {
+    pub(crate) client: Option<IpAddr>,
+    pub(crate) time: SystemTime,
+    pub(crate) started: Instant,
+    pub(crate) request_id: Option<String>,
+    pub(crate) method: Option<String>,
+    pub(crate) path: Option<String>,
+    pub(crate) request_line: Option<String>,
+    pub(crate) referer: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) status: Option<StatusCode>,
+    pub(crate) bytes: u64,
+    pub(crate) duration: Duration,
+}
+
+impl AccessRecord {
+    /// Starts the record of a response to `client`, for `request` if there
+    /// is a whole one.
+    pub(crate) fn new(client: Option<IpAddr>, request: Option<&HttpRequest>) -> AccessRecord {
+        AccessRecord {
+            client,
+            time: SystemTime::now(),
+            started: Instant::now(),
+            request_id: request.and_then(|request| request.id.clone()),
+            method: request.map(|request| request.method.as_str().to_owned()),
+            path: request.map(|request| request.path.clone()),
+            request_line: request.map(|request| {
+                format!(
+                    "{} {} {}",
+                    request.method.as_str(),
+                    redact_query(&request.target),
+                    request.version
+                )
+            }),
+            referer: request.and_then(|request| {
+                request
+                    .header("Referer")
+                    .map(|referer| redact_query(referer).into_owned())
+            }),
+            user_agent: request.and_then(|request| request.header("User-Agent").map(str::to_owned)),
+            status: None,
+            bytes: 0,
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+/// Queues records for the access log thread. Clones share the thread.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLogger(mpsc::Sender<AccessRecord>);
+
+impl AccessLogger {
+    /// Queues `record`, its duration measured up to now. Records of
+    /// responses that never got a status are dropped.
+    pub(crate) fn log(&self, mut record: AccessRecord) {
+        if record.status.is_none() {
+            return;
+        }
+        record.duration = record.started.elapsed();
+        // The thread only stops once every logger is gone.
+        let _ = self.0.send(record);
+    }
+}
+
+/// The access log thread of a server. Dropping it waits for the thread to
+/// write what's queued, once every `AccessLogger` is gone.
+pub(crate) struct AccessLog {
+    logger: Option<AccessLogger>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AccessLog {
+    /// Opens the target of `config` and starts the thread writing to it.
+    ///
+    /// # Errors
+    /// If the log file can't be opened, or the thread can't be started.
+    pub(crate) fn start(config: &AccessLogConfig) -> io::Result<AccessLog> {
+        let mut sink = match &config.target {
+            LogTarget::Stdout => Sink::Writer(BufWriter::new(Box::new(io::stdout()))),
+            LogTarget::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| {
+                        io::Error::new(e.kind(), format!("access log {}: {e}", path.display()))
+                    })?;
+                Sink::Writer(BufWriter::new(Box::new(file)))
+            }
+            LogTarget::Channel(sender) => Sink::Channel(sender.clone()),
+        };
+        let format = config.format.clone();
+        let (sender, records) = mpsc::channel::<AccessRecord>();
+        let thread = thread::Builder::new()
+            .name(String::from("access-log"))
+            .spawn(move || {
+                let mut line = String::new();
+                while let Ok(mut record) = records.recv() {
+                    loop {
+                        line.clear();
+                        let _ = format.write(&record, &mut line);
+                        sink.write_line(&mut line);
+                        match records.try_recv() {
+                            Ok(next) => record = next,
+                            Err(_) => break,
+                        }
+                    }
+                    sink.flush();
+                }
+            })?;
+        Ok(AccessLog {
+            logger: Some(AccessLogger(sender)),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns a logger feeding this log's thread.
+    pub(crate) fn logger(&self) -> Option<AccessLogger> {
+        self.logger.clone()
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        self.logger = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Where the access log thread puts lines.
+enum Sink {
+    Writer(BufWriter<Box<dyn Write + Send>>),
+    Channel(mpsc::Sender<String>),
+}
+
+impl Sink {
+    /// Writes `line`, with a line break, or sends it as it is.
+    fn write_line(&mut self, line: &mut String) {
+        match self {
+            Sink::Writer(out) => {
+                line.push('\n');
+                if let Err(e) = out.write_all(line.as_bytes()) {
+                    log::error(format_args!("access log error: {}", e));
+                }
+            }
+            Sink::Channel(sender) => {
+                let _ = sender.send(line.clone());
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Sink::Writer(out) = self
+            && let Err(e) = out.flush()
+        {
+            log::error(format_args!("access log error: {}", e));
+        }
+    }
+}
+
+/// Writes `value` with `"` and `\` backslash-escaped and other bytes that
+/// aren't printable ASCII as `\xHH`.
+fn escape(value: &str, out: &mut String) -> fmt::Result {
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(out, "\\{}", byte as char)?,
+            b' '..=b'~' => out.write_char(byte as char)?,
+            _ => write!(out, "\\x{byte:02x}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the JSON object for `record` (see `AccessLogFormat::json`).
+fn write_json(record: &AccessRecord, out: &mut String) -> fmt::Result {
+    let string_or_null = |out: &mut String, value: Option<&str>| match value {
+        Some(value) => write!(out, "\"{}\"", json_escape(value)),
+        None => out.write_str("null"),
+    };
+    write!(
+        out,
+        "{{\"ts\":\"{}\",\"level\":\"info\",\"request_id\":",
+        format_rfc3339(record.time)
+    )?;
+    string_or_null(out, record.request_id.as_deref())?;
+    out.write_str(",\"method\":")?;
+    string_or_null(out, record.method.as_deref())?;
+    out.write_str(",\"path\":")?;
+    string_or_null(out, record.path.as_deref())?;
+    write!(
+        out,
+        ",\"status\":{},\"duration_ms\":{:.3},\"peer\":",
+        record.status.map_or(0, |status| status.as_u16()),
+        record.duration.as_secs_f64() * 1000.0
+    )?;
+    string_or_null(out, record.client.map(|ip| ip.to_string()).as_deref())?;
+    write!(out, ",\"bytes\":{}}}", record.bytes)
+}
+
+/// Writes `time` as `[10/Oct/2000:13:55:36 +0000]`.
+fn write_clf_time(time: SystemTime, out: &mut String) -> fmt::Result {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (year, month, day, hour, minute, second) = civil_from_unix(secs);
+    write!(
+        out,
+        "[{day:02}/{}/{year:04}:{hour:02}:{minute:02}:{second:02} +0000]",
+        MONTHS[month as usize - 1]
+    )
+}
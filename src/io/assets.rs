@@ -0,0 +1,294 @@
+//! Where the served files come from.
+//!
+//! Request handling never touches the filesystem directly; it asks an
+//! `AssetSource` for metadata, contents and directory entries by
+//! root-relative path (`/docs/index.html`). `FilesystemSource` serves the
+//! configured document root. With the `embed` feature, `EmbeddedSource`
+//! serves a copy of the site compiled into the binary instead, so the server
+//! can ship as a single file (see `build.rs` for how the copy is made).
+//! `MountedSource` puts a site's `mounts` on top of either one.
+use crate::io::file::{self, DirEntryInfo, FileError, FileMeta};
+use crate::server::Mount;
+use std::io;
+use std::path::Path;
+
+/// A tree of files the server can serve.
+///
+/// Every `path` is root-relative and already normalized: it starts with `/`
+/// and contains no `.` or `..` components.
+pub trait AssetSource {
+    /// Returns the metadata of the file or directory at `path`.
+    fn metadata(&self, path: &str) -> Result<FileMeta, FileError>;
+
+    /// Returns the contents of the file at `path`.
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// Lists the directory at `path`.
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>>;
+
+    /// Returns `true` if `path` stays inside the source once links are resolved.
+    ///
+    /// `path` doesn't have to exist. Sources without links always contain
+    /// every path.
+    fn contains(&self, _path: &str) -> bool {
+        true
+    }
+
+    /// Returns the filesystem path backing `path`, if the source is the filesystem.
+    ///
+    /// Streaming, memory mapping, `sendfile` and the file cache all need a
+    /// real file and are skipped when this is `None`.
+    fn fs_path(&self, _path: &str) -> Option<String> {
+        None
+    }
+
+    /// Returns the content hash of the file at `path`, if the source knows it
+    /// without reading the file.
+    ///
+    /// Only sources whose files can't change while the server runs have one;
+    /// files on disk are hashed on demand through `ServerConfig::hash_cache`.
+    fn content_hash(&self, _path: &str) -> Option<u64> {
+        None
+    }
+}
+
+/// Serves the files under a directory on disk.
+///
+/// # Fields
+/// - `root` (*&Path*): The canonical document root.
+/// - `follow_symlinks` (*bool*): Whether symlinks may lead outside `root`.
+pub struct FilesystemSource<'a> {
+    pub root: &'a Path,
+    pub follow_symlinks: bool,
+}
+
+impl FilesystemSource<'_> {
+    fn join(&self, path: &str) -> String {
+        format!("{}{}", self.root.to_string_lossy(), path)
+    }
+}
+
+impl AssetSource for FilesystemSource<'_> {
+    fn metadata(&self, path: &str) -> Result<FileMeta, FileError> {
+        file::metadata(&self.join(path))
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        file::read_file_bytes(&self.join(path))
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>> {
+        file::read_dir_entries(&self.join(path))
+    }
+
+    /// The lexical normalization done before any lookup already stops `..`
+    /// from climbing out, but this check is done on canonical paths: the
+    /// target (or, if it doesn't exist yet, its nearest existing ancestor)
+    /// must canonicalize to a path under the root. That is what stops a
+    /// symlink such as `public/etc -> /etc` from exposing its target, while
+    /// symlinks that stay inside the root keep working. A missing target is
+    /// judged by its ancestor, so a 404 stays a 404.
+    ///
+    /// With `follow_symlinks` the check is skipped and symlinks may point anywhere.
+    fn contains(&self, path: &str) -> bool {
+        if self.follow_symlinks {
+            return true;
+        }
+        let joined = self.join(path);
+        let mut candidate = Path::new(&joined);
+        loop {
+            match std::fs::canonicalize(candidate) {
+                Ok(canonical) => return canonical.starts_with(self.root),
+                Err(_) => match candidate.parent() {
+                    Some(parent) => candidate = parent,
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    fn fs_path(&self, path: &str) -> Option<String> {
+        Some(self.join(path))
+    }
+}
+
+/// Serves some URL prefixes from directories of their own (see
+/// `ServerConfig::mounts`) and everything else from `base`.
+///
+/// Each path goes to the mount with the longest prefix it is under, with the
+/// prefix stripped, so the mount's `FilesystemSource` confines it to the
+/// mount directory. A mount that falls through hands paths it doesn't have
+/// on to the next matching mount, and finally to `base`.
+pub struct MountedSource<'a> {
+    base: Box<dyn AssetSource + 'a>,
+    // Longest prefix first; equally long prefixes keep their configured order.
+    mounts: Vec<(&'a Mount, FilesystemSource<'a>)>,
+}
+
+impl<'a> MountedSource<'a> {
+    /// Returns `base` with `mounts` on top. `follow_symlinks` applies to the
+    /// mount directories as it does to the document root.
+    pub fn new(
+        base: Box<dyn AssetSource + 'a>,
+        mounts: &'a [Mount],
+        follow_symlinks: bool,
+    ) -> MountedSource<'a> {
+        let mut mounts: Vec<_> = mounts
+            .iter()
+            .map(|mount| {
+                let source = FilesystemSource {
+                    root: &mount.directory,
+                    follow_symlinks,
+                };
+                (mount, source)
+            })
+            .collect();
+        mounts
+            .sort_by_key(|(mount, _)| std::cmp::Reverse(mount.prefix.trim_end_matches('/').len()));
+        MountedSource { base, mounts }
+    }
+
+    /// Returns the source serving `path` and the path within it.
+    fn pick<'p>(&self, path: &'p str) -> (&dyn AssetSource, &'p str) {
+        for (mount, source) in &self.mounts {
+            let Some(rest) = mount.strip(path) else {
+                continue;
+            };
+            if mount.fall_through && matches!(source.metadata(rest), Err(FileError::NotFound)) {
+                continue;
+            }
+            return (source, rest);
+        }
+        (self.base.as_ref(), path)
+    }
+}
+
+impl AssetSource for MountedSource<'_> {
+    fn metadata(&self, path: &str) -> Result<FileMeta, FileError> {
+        let (source, path) = self.pick(path);
+        source.metadata(path)
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let (source, path) = self.pick(path);
+        source.read(path)
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>> {
+        let (source, path) = self.pick(path);
+        source.read_dir(path)
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        let (source, path) = self.pick(path);
+        source.contains(path)
+    }
+
+    fn fs_path(&self, path: &str) -> Option<String> {
+        let (source, path) = self.pick(path);
+        source.fs_path(path)
+    }
+
+    fn content_hash(&self, path: &str) -> Option<u64> {
+        let (source, path) = self.pick(path);
+        source.content_hash(path)
+    }
+}
+
+#[cfg(feature = "embed")]
+mod embedded {
+    // Generated by build.rs: `static EMBEDDED: &[(&str, &[u8], u64)]` of path,
+    // contents and content hash, sorted by path.
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+}
+
+/// Serves the copy of the site compiled into the binary.
+///
+/// Files have no modification time, but a content hash computed at build
+/// time for their ETags; directories exist implicitly wherever an embedded
+/// file path has them as a prefix.
+#[cfg(feature = "embed")]
+pub struct EmbeddedSource;
+
+#[cfg(feature = "embed")]
+impl EmbeddedSource {
+    fn entry(&self, path: &str) -> Option<&'static (&'static str, &'static [u8], u64)> {
+        embedded::EMBEDDED
+            .binary_search_by(|(name, _, _)| (*name).cmp(path))
+            .ok()
+            .map(|index| &embedded::EMBEDDED[index])
+    }
+
+    fn file(&self, path: &str) -> Option<&'static [u8]> {
+        self.entry(path).map(|(_, bytes, _)| *bytes)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        embedded::EMBEDDED
+            .iter()
+            .any(|(name, _, _)| name.starts_with(&prefix))
+    }
+}
+
+#[cfg(feature = "embed")]
+impl AssetSource for EmbeddedSource {
+    fn metadata(&self, path: &str) -> Result<FileMeta, FileError> {
+        if let Some(bytes) = self.file(path) {
+            return Ok(FileMeta {
+                size: bytes.len() as u64,
+                modified: None,
+                is_dir: false,
+                is_file: true,
+            });
+        }
+        if self.is_dir(path) {
+            return Ok(FileMeta {
+                size: 0,
+                modified: None,
+                is_dir: true,
+                is_file: false,
+            });
+        }
+        Err(FileError::NotFound)
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        self.file(path)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let mut entries: Vec<DirEntryInfo> = Vec::new();
+        for (name, bytes, _) in embedded::EMBEDDED {
+            let Some(rest) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            let (child, is_dir) = match rest.split_once('/') {
+                Some((dir, _)) => (dir, true),
+                None => (rest, false),
+            };
+            if entries.iter().any(|entry| entry.name == child) {
+                continue;
+            }
+            entries.push(DirEntryInfo {
+                name: child.to_string(),
+                is_dir,
+                size: if is_dir { 0 } else { bytes.len() as u64 },
+                modified: None,
+            });
+        }
+        if entries.is_empty() && !self.is_dir(path) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        Ok(entries)
+    }
+
+    /// The hash `build.rs` computed, the same `io::file::hash_file` gives the
+    /// file on disk, so both sources send the same ETags.
+    fn content_hash(&self, path: &str) -> Option<u64> {
+        self.entry(path).map(|(_, _, hash)| *hash)
+    }
+}
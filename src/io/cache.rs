@@ -0,0 +1,195 @@
+//! In-memory cache of small static files.
+//!
+//! Entries are keyed by the filesystem path the response was built from
+//! (e.g. `/srv/www/index.html`) and remember the modification time they were
+//! read at, so a lookup with a newer mtime misses. The watcher in
+//! `io::watcher` invalidates entries as soon as files change on disk, so
+//! edits show up even before anything re-stats the file.
+//!
+//! `HashCache` keeps the content hashes used as ETags the same way, so a file
+//! is only hashed again once its size or mtime changes.
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A change on disk that makes cached bytes stale.
+///
+/// Variants:
+/// - `Changed(String)`: The file at this path was created, modified or replaced.
+/// - `Removed(String)`: The file or directory at this path is gone. For a
+///   directory every entry below it is dropped too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    Changed(String),
+    Removed(String),
+}
+
+/// A cached file body.
+#[derive(Debug)]
+struct CachedFile {
+    bytes: Arc<Vec<u8>>,
+    modified: Option<SystemTime>,
+}
+
+/// Thread-safe cache of file contents, shared by the pool's workers.
+///
+/// # Example
+/// ```
+/// let cache = FileCache::new();
+/// cache.insert("/srv/www/index.html", meta.modified, bytes);
+/// assert!(cache.get("/srv/www/index.html", meta.modified).is_some());
+/// cache.apply(Invalidation::Changed(String::from("/srv/www/index.html")));
+/// assert!(cache.get("/srv/www/index.html", meta.modified).is_none());
+/// ```
+#[derive(Debug, Default)]
+pub struct FileCache {
+    entries: Mutex<HashMap<String, CachedFile>>,
+}
+
+impl FileCache {
+    /// Creates an empty cache.
+    pub fn new() -> FileCache {
+        FileCache::default()
+    }
+
+    /// Returns the cached bytes for `path` if they were read at mtime `modified`.
+    ///
+    /// An entry with a different mtime is stale and is dropped.
+    pub fn get(&self, path: &str, modified: Option<SystemTime>) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.get(path)?;
+        if entry.modified != modified {
+            entries.remove(path);
+            return None;
+        }
+        Some(Arc::clone(&entry.bytes))
+    }
+
+    /// Stores the contents of `path` as read at mtime `modified`.
+    pub fn insert(&self, path: &str, modified: Option<SystemTime>, bytes: Vec<u8>) {
+        let entry = CachedFile {
+            bytes: Arc::new(bytes),
+            modified,
+        };
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_string(), entry);
+    }
+
+    /// Drops whatever `invalidation` makes stale.
+    pub fn apply(&self, invalidation: Invalidation) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match invalidation {
+            Invalidation::Changed(path) => {
+                entries.remove(&path);
+            }
+            Invalidation::Removed(path) => {
+                let dir = format!("{}/", path.trim_end_matches('/'));
+                entries.retain(|cached, _| *cached != path && !cached.starts_with(&dir));
+            }
+        }
+    }
+
+    /// Drops every entry.
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Returns the number of cached files.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A content hash and the metadata it was computed at.
+#[derive(Debug)]
+struct HashedFile {
+    size: u64,
+    modified: SystemTime,
+    hash: u64,
+}
+
+/// Thread-safe cache of file content hashes, shared by the pool's workers.
+///
+/// Entries are keyed by filesystem path and are only reused while the file
+/// still has the size and mtime it was hashed at. A rewrite that keeps both
+/// (within the filesystem's timestamp resolution) isn't noticed; that is the
+/// same trade-off every mtime-based validator makes.
+///
+/// # Example
+/// ```
+/// let hashes = HashCache::new();
+/// let hash = hashes.get_or_compute(path, meta.size, modified, || hash_file(path))?;
+/// // Hashed once; the second call returns the cached value.
+/// assert_eq!(hashes.get_or_compute(path, meta.size, modified, || unreachable!())?, hash);
+/// ```
+#[derive(Debug, Default)]
+pub struct HashCache {
+    entries: Mutex<HashMap<String, HashedFile>>,
+}
+
+impl HashCache {
+    /// Creates an empty cache.
+    pub fn new() -> HashCache {
+        HashCache::default()
+    }
+
+    /// Returns the hash of `path` at `size` and `modified`, calling `compute`
+    /// to hash the file only if no matching entry exists.
+    ///
+    /// The lock isn't held while `compute` runs, so hashing a large file
+    /// doesn't block lookups for other files. Two workers missing on the same
+    /// file at once may both hash it; the results are identical.
+    ///
+    /// # Errors
+    /// Returns the error from `compute`; nothing is cached in that case.
+    pub fn get_or_compute(
+        &self,
+        path: &str,
+        size: u64,
+        modified: SystemTime,
+        compute: impl FnOnce() -> io::Result<u64>,
+    ) -> io::Result<u64> {
+        if let Some(entry) = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(path)
+            && entry.size == size
+            && entry.modified == modified
+        {
+            return Ok(entry.hash);
+        }
+        let hash = compute()?;
+        let entry = HashedFile {
+            size,
+            modified,
+            hash,
+        };
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_string(), entry);
+        Ok(hash)
+    }
+
+    /// Returns the number of cached hashes.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
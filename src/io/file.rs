@@ -1,5 +1,465 @@
+//! Blocking filesystem helpers used to serve static content and store uploads.
+use std::fmt;
 use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
 pub fn read_file_bytes(filename: &str) -> std::io::Result<Vec<u8>> {
     fs::read(filename)
 }
+
+/// Reads `len` bytes of the file at `path`, starting at byte `offset`.
+///
+/// A range that runs past the end of the file is cut short at EOF rather than
+/// failing, and an `offset` at or beyond EOF yields an empty `Vec`. The range
+/// handler validates ranges against `metadata` first (and answers 416 for
+/// unsatisfiable ones), so an empty result here only means the file shrank.
+///
+/// # Example
+/// ```
+/// // bytes 100..=199
+/// let slice = read_file_range("public/video.mp4", 100, 100)?;
+/// ```
+pub fn read_file_range(path: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(len).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Hashes the contents of the file at `path` with 64-bit FNV-1a.
+///
+/// The file is read in 64 KiB pieces, so hashing a large file doesn't hold it
+/// in memory. FNV isn't cryptographic; it only has to tell versions of the
+/// same file apart for ETags, and its output is stable across builds.
+///
+/// # Example
+/// ```
+/// let hash = hash_file("public/welcome.html")?;
+/// println!("ETag: \"{hash:016x}\"");
+/// ```
+pub fn hash_file(path: &str) -> io::Result<u64> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut hash = OFFSET_BASIS;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(hash),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &byte in &buf[..n] {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+}
+
+/// Writes `bytes` to `path` so that readers only ever see the old or the new
+/// contents, never a partial file.
+///
+/// The bytes go to a temporary file in the same directory first, are flushed
+/// to disk, and the temporary file is then renamed over `path`. If anything
+/// fails the temporary file is removed and `path` is left untouched.
+///
+/// # Returns
+/// - `Ok(true)`: An existing file at `path` was replaced.
+/// - `Ok(false)`: `path` didn't exist before.
+/// - `Err(io::Error)`: Nothing was written (e.g. the directory doesn't exist).
+pub fn write_file_atomic(path: &Path, bytes: &[u8]) -> io::Result<bool> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(
+        ".{name}.{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| {
+            let existed = fs::symlink_metadata(path).is_ok();
+            fs::rename(&temp, path)?;
+            Ok(existed)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Size, timestamp and kind of a file, as needed to answer a request
+/// without reading the file.
+///
+/// # Fields
+/// - `size` (*u64*): Size in bytes.
+/// - `modified` (*Option<SystemTime>*): Last modification time, if the platform provides it.
+/// - `is_dir` (*bool*): Whether the path is a directory.
+/// - `is_file` (*bool*): Whether the path is a regular file.
+#[derive(Debug, Clone, Copy)]
+pub struct FileMeta {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub is_dir: bool,
+    pub is_file: bool,
+}
+
+/// Why a file's metadata couldn't be read.
+///
+/// Variants:
+/// - `NotFound`: Nothing exists at the path, or a part of it that should be a
+///   directory is a file (maps to 404).
+/// - `PermissionDenied`: The path exists but the server may not stat it (maps to 403).
+/// - `Io(io::Error)`: Any other failure (maps to 500).
+#[derive(Debug)]
+pub enum FileError {
+    NotFound,
+    PermissionDenied,
+    Io(io::Error),
+}
+
+impl From<io::Error> for FileError {
+    fn from(error: io::Error) -> FileError {
+        match error.kind() {
+            // `/index.html/` asks for a directory that's really a file
+            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => FileError::NotFound,
+            io::ErrorKind::PermissionDenied => FileError::PermissionDenied,
+            _ => FileError::Io(error),
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::NotFound => write!(f, "file not found"),
+            FileError::PermissionDenied => write!(f, "permission denied"),
+            FileError::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// Returns the metadata of the file or directory at `path`.
+///
+/// Symlinks are followed (`stat`, not `lstat`), so the result describes what
+/// serving the path would actually read.
+///
+/// # Example
+/// ```
+/// let meta = metadata("public/welcome.html")?;
+/// assert!(meta.is_file);
+/// ```
+pub fn metadata(path: &str) -> Result<FileMeta, FileError> {
+    let meta = fs::metadata(path)?;
+    Ok(FileMeta {
+        size: meta.len(),
+        modified: meta.modified().ok(),
+        is_dir: meta.is_dir(),
+        is_file: meta.is_file(),
+    })
+}
+
+/// A single entry of a directory, as needed to render a listing.
+///
+/// # Fields
+/// - `name` (*String*): The entry's file name (lossily converted to UTF-8).
+/// - `is_dir` (*bool*): Whether the entry is a directory (symlinks are followed).
+/// - `size` (*u64*): Size in bytes. `0` for directories.
+/// - `modified` (*Option<SystemTime>*): Last modification time, if the platform provides it.
+#[derive(Debug, Clone)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Lists the entries of the directory at `path`.
+///
+/// Entries whose metadata can't be read (e.g. a dangling symlink) are skipped
+/// rather than failing the whole listing. The order is whatever the OS returns;
+/// callers sort as they see fit.
+///
+/// # Errors
+/// Returns the underlying `io::Error` if the directory itself can't be read.
+pub fn read_dir_entries(path: &str) -> io::Result<Vec<DirEntryInfo>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        // fs::metadata follows symlinks, matching what serving the entry would do
+        let Ok(meta) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        entries.push(DirEntryInfo {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: meta.is_dir(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified().ok(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads a file in fixed-size chunks on demand instead of all at once.
+///
+/// The length is taken from the file's metadata when it is opened, and
+/// exactly that many bytes are produced even if the file grows meanwhile. If
+/// the file shrinks while being read, `next_chunk` fails with
+/// `UnexpectedEof` so the caller can abort instead of sending a short body
+/// after having promised a longer one.
+pub struct ChunkedReader {
+    file: fs::File,
+    chunk_size: usize,
+    remaining: u64,
+}
+
+impl ChunkedReader {
+    /// Opens `path` for chunked reading.
+    ///
+    /// # Returns
+    /// - `Ok((ChunkedReader, u64))`: The reader and the file length in bytes.
+    /// - `Err(io::Error)`: The file couldn't be opened or stat'ed.
+    pub fn open(path: &str, chunk_size: usize) -> io::Result<(ChunkedReader, u64)> {
+        let file = fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        let reader = ChunkedReader {
+            file,
+            chunk_size: chunk_size.max(1),
+            remaining: length,
+        };
+        Ok((reader, length))
+    }
+
+    /// Opens `path` for chunked reading of `len` bytes starting at `offset`,
+    /// for ranges too large to read with `read_file_range`.
+    ///
+    /// # Errors
+    /// Fails with `io::ErrorKind::UnexpectedEof` if the range doesn't lie
+    /// entirely within the file, so the caller can answer 416 instead of
+    /// promising bytes that don't exist.
+    pub fn open_range(
+        path: &str,
+        offset: u64,
+        len: u64,
+        chunk_size: usize,
+    ) -> io::Result<ChunkedReader> {
+        let mut file = fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        if offset.checked_add(len).is_none_or(|end| end > length) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "range past end of file",
+            ));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(ChunkedReader {
+            file,
+            chunk_size: chunk_size.max(1),
+            remaining: len,
+        })
+    }
+
+    /// Reads the next chunk of at most `chunk_size` bytes.
+    ///
+    /// # Returns
+    /// - `Ok(Some(bytes))`: The next chunk.
+    /// - `Ok(None)`: All bytes have been read.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let want = self.remaining.min(self.chunk_size as u64) as usize;
+        let mut chunk = vec![0u8; want];
+        self.file.read_exact(&mut chunk)?;
+        self.remaining -= want as u64;
+        Ok(Some(chunk))
+    }
+}
+
+/// A byte range of an open file that is sent straight from the file.
+///
+/// On Linux the reactor hands the file descriptor to `sendfile(2)`, so the
+/// bytes go from the page cache to the socket without ever being copied into
+/// user space. Everywhere else, and whenever `sendfile` fails, the same body
+/// is read in chunks with `next_chunk` and goes through the write buffer.
+///
+/// The offset is tracked here rather than in the file's cursor, so a partial
+/// send after `WouldBlock` simply resumes from where it stopped.
+pub struct FileBody {
+    file: fs::File,
+    offset: u64,
+    remaining: u64,
+    chunk_size: usize,
+    sendfile: bool,
+}
+
+impl FileBody {
+    /// Opens the whole file at `path` as a body.
+    ///
+    /// # Returns
+    /// - `Ok((FileBody, u64))`: The body and its length in bytes.
+    /// - `Err(io::Error)`: The file couldn't be opened or stat'ed.
+    pub fn open(path: &str, chunk_size: usize) -> io::Result<(FileBody, u64)> {
+        let file = fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        let body = FileBody {
+            file,
+            offset: 0,
+            remaining: length,
+            chunk_size: chunk_size.max(1),
+            sendfile: cfg!(target_os = "linux"),
+        };
+        Ok((body, length))
+    }
+
+    /// Returns the number of bytes still to be sent.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Returns `true` while the body should be sent with `send_to`, `false` once
+    /// it has to go through `next_chunk` instead.
+    pub fn uses_sendfile(&self) -> bool {
+        self.sendfile
+    }
+
+    /// Sends as much of the body as the socket `socket` accepts with `sendfile(2)`.
+    ///
+    /// # Returns
+    /// - `Ok(n)`: `n` bytes were sent; `0` only once the body is complete.
+    /// - `Err(io::Error)`: `WouldBlock` when the socket is full, `UnexpectedEof` if
+    ///   the file shrank, or the error `sendfile` reported. If the kernel can't
+    ///   `sendfile` this file at all (`EINVAL`, `ENOSYS`, ...), the error kind is
+    ///   `Unsupported` and `uses_sendfile` turns `false`, so the caller can carry
+    ///   on with `next_chunk` from the same offset.
+    #[cfg(target_os = "linux")]
+    pub fn send_to(&mut self, socket: std::os::fd::RawFd) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let count = self.remaining.min(1 << 30) as usize;
+        let mut offset = self.offset as libc::off_t;
+        // SAFETY: both descriptors are open for the duration of the call and
+        // `offset` is a valid, exclusively borrowed off_t.
+        let sent = unsafe { libc::sendfile(socket, self.file.as_raw_fd(), &mut offset, count) };
+        if sent < 0 {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP) => {
+                    self.sendfile = false;
+                    Err(io::Error::new(io::ErrorKind::Unsupported, error))
+                }
+                _ => Err(error),
+            };
+        }
+        if sent == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file truncated while being served",
+            ));
+        }
+        self.offset += sent as u64;
+        self.remaining -= sent as u64;
+        Ok(sent as usize)
+    }
+
+    /// Reads the next chunk of at most `chunk_size` bytes, for the buffered path.
+    ///
+    /// # Returns
+    /// - `Ok(Some(bytes))`: The next chunk.
+    /// - `Ok(None)`: All bytes have been read.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let want = self.remaining.min(self.chunk_size as u64) as usize;
+        let mut chunk = vec![0u8; want];
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        self.file.read_exact(&mut chunk)?;
+        self.offset += want as u64;
+        self.remaining -= want as u64;
+        Ok(Some(chunk))
+    }
+}
+
+/// Serves a file from a read-only memory mapping instead of `read` calls.
+///
+/// Chunks are copied straight out of the page cache, skipping the read
+/// buffer `ChunkedReader` fills. The mapping lives as long as the reader,
+/// i.e. for the whole response.
+///
+/// A mapped file that is truncated underneath us would fault on access
+/// (`SIGBUS`) instead of returning an error, so the file's length is checked
+/// before every chunk and a shrunken file fails with `UnexpectedEof`, letting
+/// the caller abort the connection. Only available where `memmap2` supports
+/// the platform.
+#[cfg(any(unix, windows))]
+pub struct MappedReader {
+    file: fs::File,
+    map: memmap2::Mmap,
+    chunk_size: usize,
+    position: usize,
+}
+
+#[cfg(any(unix, windows))]
+impl MappedReader {
+    /// Maps `path` for chunked reading.
+    ///
+    /// # Returns
+    /// - `Ok((MappedReader, u64))`: The reader and the file length in bytes.
+    /// - `Err(io::Error)`: The file couldn't be opened or mapped.
+    pub fn open(path: &str, chunk_size: usize) -> io::Result<(MappedReader, u64)> {
+        let file = fs::File::open(path)?;
+        // SAFETY: the mapping is read-only and every access is preceded by a
+        // length check in `next_chunk`, which turns truncation into an error.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let length = map.len() as u64;
+        let reader = MappedReader {
+            file,
+            map,
+            chunk_size: chunk_size.max(1),
+            position: 0,
+        };
+        Ok((reader, length))
+    }
+
+    /// Copies the next chunk of at most `chunk_size` bytes out of the mapping.
+    ///
+    /// # Returns
+    /// - `Ok(Some(bytes))`: The next chunk.
+    /// - `Ok(None)`: The whole mapping has been read.
+    /// - `Err(io::Error)`: The file shrank since it was mapped.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.position == self.map.len() {
+            return Ok(None);
+        }
+        if self.file.metadata()?.len() < self.map.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file truncated while being served",
+            ));
+        }
+        let end = self.map.len().min(self.position + self.chunk_size);
+        let chunk = self.map[self.position..end].to_vec();
+        self.position = end;
+        Ok(Some(chunk))
+    }
+}
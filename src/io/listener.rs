@@ -0,0 +1,525 @@
+//! Listening sockets and the options set on them and on accepted streams.
+//!
+//! On Unix the TCP listener is built from raw socket calls, because options
+//! such as `SO_REUSEPORT` have to be set before `bind` and the backlog is given
+//! to `listen`, neither of which `std::net::TcpListener::bind` exposes. With
+//! `SO_REUSEPORT` every reactor binds its own listener to the same address and
+//! the kernel spreads incoming connections across them. Elsewhere the
+//! listener is bound with the platform defaults.
+//!
+//! On Unix an address can also be a filesystem path, for a Unix domain socket
+//! (e.g. behind a local reverse proxy). `Listener` and `Stream` hide which
+//! kind a socket is from the reactor.
+use crate::error::ServerError;
+use crate::util::Cidr;
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+use std::fmt;
+use std::io;
+use std::io::{IoSlice, Read, Write};
+#[cfg(unix)]
+use std::mem;
+use std::net::{IpAddr, Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where a listener is bound, parsed from one of `ServerConfig::addresses`.
+///
+/// Variants:
+/// - `Tcp(SocketAddr)`: An IP address and port, e.g. `127.0.0.1:8080` or `[::]:8080`.
+/// - `Unix(PathBuf)`: A Unix domain socket path, written `unix:/run/app.sock` or just
+///   as a path containing a `/` (`./app.sock`). Unix only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl BindAddress {
+    /// Parses `address` as a socket path or an IP address and port.
+    ///
+    /// # Errors
+    /// `ServerError::InvalidAddress` if `address` is neither.
+    pub fn parse(address: &str) -> Result<BindAddress, ServerError> {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(BindAddress::Unix(PathBuf::from(path)));
+        }
+        #[cfg(unix)]
+        if address.contains('/') {
+            return Ok(BindAddress::Unix(PathBuf::from(address)));
+        }
+        address
+            .parse()
+            .map(BindAddress::Tcp)
+            .map_err(|e| ServerError::InvalidAddress {
+                address: address.to_string(),
+                reason: e.to_string(),
+            })
+    }
+}
+
+/// A bound listener of either kind.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Accepts one waiting connection.
+    ///
+    /// # Errors
+    /// `WouldBlock` once nothing is waiting, like the underlying `accept`.
+    pub fn accept(&self) -> io::Result<(Stream, Peer)> {
+        match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, addr)| (Stream::Tcp(stream), Peer::Tcp(addr))),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| (Stream::Unix(stream), Peer::Unix)),
+        }
+    }
+}
+
+/// An accepted connection of either kind.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Shuts down the sending side: the peer reads EOF after what was already
+    /// sent, and can still send to us.
+    pub fn shutdown_write(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Write),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Write),
+        }
+    }
+}
+
+/// Who is on the other end of a `Stream`, for logs.
+///
+/// Variants:
+/// - `Tcp(SocketAddr)`: The client's address; IPv6 ones print in brackets.
+/// - `Unix`: A local process. Clients of Unix sockets are almost always unnamed.
+#[derive(Debug, Clone, Copy)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix,
+}
+
+impl Peer {
+    /// Returns the client's IP address, or `None` for a Unix socket client.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Tcp(addr) => Some(addr.ip()),
+            #[cfg(unix)]
+            Peer::Unix => None,
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            Peer::Unix => f.write_str("unix socket"),
+        }
+    }
+}
+
+/// TCP options for the listener and for every accepted connection.
+///
+/// # Fields
+/// - `nodelay` (*bool*): Set `TCP_NODELAY` on accepted streams, so small
+///   responses go out at once instead of waiting on Nagle's algorithm.
+///   Defaults to `true`.
+/// - `keepalive` (*Option<Duration>*): Enable TCP keepalive probes on accepted
+///   streams after this much idle time, so dead peers are noticed. `None` (the
+///   default) leaves keepalive off. Unix only.
+/// - `backlog` (*u32*): How many not-yet-accepted connections the kernel queues
+///   before refusing more. Defaults to 1024. Unix only.
+/// - `reuseaddr` (*bool*): Set `SO_REUSEADDR` on the listener, so a restart
+///   doesn't fail on connections of the previous process still in `TIME_WAIT`.
+///   Defaults to `true`. Unix only.
+/// - `ipv6_only` (*bool*): Set `IPV6_V6ONLY` on IPv6 listeners, so `[::]:8080` only
+///   takes IPv6 connections and `0.0.0.0:8080` can be bound next to it. With `false`
+///   an IPv6 wildcard also accepts IPv4 clients (as `::ffff:a.b.c.d`), so one
+///   address serves both. Defaults to `true`, rather than the platform's own
+///   default, which varies. Unix only.
+/// - `unix_mode` (*Option<u32>*): Permission bits given to Unix domain socket files
+///   after binding (e.g. `0o660`, so only the proxy's group may connect). `None` (the
+///   default) keeps what the umask gives. Unix only.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub backlog: u32,
+    pub reuseaddr: bool,
+    pub ipv6_only: bool,
+    pub unix_mode: Option<u32>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            backlog: 1024,
+            reuseaddr: true,
+            ipv6_only: true,
+            unix_mode: None,
+        }
+    }
+}
+
+/// Which clients may connect, by IP address, checked as each connection is
+/// accepted and before anything is read from it.
+///
+/// # Fields
+/// - `allow` (*Vec<Cidr>*): If not empty, only clients in one of these ranges are
+///   served. Empty (the default) allows everyone not denied.
+/// - `deny` (*Vec<Cidr>*): Clients in any of these ranges are refused, even if they
+///   are also allowed. Empty by default.
+/// - `respond_forbidden` (*bool*): Send refused clients a canned `403 Forbidden`
+///   (best effort, not on TLS listeners) before hanging up, instead of just hanging
+///   up. Defaults to `false`.
+///
+/// Unix socket clients have no address and are never refused.
+///
+/// # Example
+/// ```
+/// let filter = IpFilter {
+///     allow: vec![Cidr::parse("10.0.0.0/8").unwrap()],
+///     deny: vec![Cidr::parse("10.6.6.0/24").unwrap()],
+///     ..IpFilter::default()
+/// };
+/// assert!(filter.permits("10.1.2.3".parse().unwrap()));
+/// assert!(!filter.permits("10.6.6.6".parse().unwrap()));
+/// assert!(!filter.permits("192.0.2.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    pub respond_forbidden: bool,
+}
+
+impl IpFilter {
+    /// Returns `true` if a client at `address` may connect.
+    pub fn permits(&self, address: IpAddr) -> bool {
+        !self.deny.iter().any(|range| range.contains(address))
+            && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(address)))
+    }
+}
+
+/// Binds a non-blocking listener to `addr` with `options` applied.
+///
+/// `reuseport` sets `SO_REUSEPORT`, so other listeners can bind the same
+/// address; it is ignored where the option doesn't exist.
+///
+/// # Errors
+/// Fails if the socket can't be created or configured, or if `addr` is taken.
+#[cfg(unix)]
+pub fn bind(addr: SocketAddr, options: &SocketOptions, reuseport: bool) -> io::Result<TcpListener> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owning the descriptor right away closes it on every error path below.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if options.reuseaddr {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    }
+    if reuseport {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    }
+    if addr.is_ipv6() {
+        let only = libc::c_int::from(options.ipv6_only);
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, only)?;
+    }
+
+    let (storage, len) = raw_address(addr);
+    let backlog = options.backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+    let bound = unsafe { libc::bind(fd, (&raw const storage).cast(), len) };
+    if bound < 0 || unsafe { libc::listen(fd, backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener))
+}
+
+#[cfg(not(unix))]
+pub fn bind(
+    addr: SocketAddr,
+    _options: &SocketOptions,
+    _reuseport: bool,
+) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+/// Binds a non-blocking Unix domain socket listener at `path`, with its
+/// permissions set from `options.unix_mode`.
+///
+/// A socket file left behind by a previous run is removed first, but only if
+/// nothing is listening on it any more.
+///
+/// # Errors
+/// Fails if another process is listening at `path`, if something other than
+/// a socket is in the way, or if binding fails.
+#[cfg(unix)]
+pub fn bind_unix(
+    path: &Path,
+    options: &SocketOptions,
+) -> io::Result<std::os::unix::net::UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another process is listening on {}", path.display()),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    if let Some(mode) = options.unix_mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// Applies the per-connection `options` to a freshly accepted `stream`.
+/// Only TCP streams have options to set.
+///
+/// # Errors
+/// Fails if the kernel rejects one of the options.
+pub fn configure_stream(stream: &Stream, options: &SocketOptions) -> io::Result<()> {
+    let Stream::Tcp(stream) = stream else {
+        return Ok(());
+    };
+    stream.set_nodelay(options.nodelay)?;
+    #[cfg(unix)]
+    if let Some(idle) = options.keepalive {
+        let fd = stream.as_raw_fd();
+        let secs = idle.as_secs().clamp(1, libc::c_int::MAX as u64) as libc::c_int;
+        set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, secs)?;
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPALIVE, secs)?;
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        let _ = secs;
+    }
+    Ok(())
+}
+
+/// Sets the integer socket option `name` at `level` on `fd` to `value`.
+#[cfg(unix)]
+fn set_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            (&raw const value).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Converts `addr` to the C representation `bind` expects.
+///
+/// # Returns
+/// The address, and how many bytes of the storage it fills.
+#[cfg(unix)]
+fn raw_address(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // All-zero is a valid value for these plain C structs.
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in>().write(sin) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let mut sin6: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_scope_id = v6.scope_id();
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in6>().write(sin6) };
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+// `Listener` and `Stream` are registered with the reactor's `Poll` like the
+// sockets they wrap, so they forward `Source` to them.
+
+impl Source for Listener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.register(registry, token, interests),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.reregister(registry, token, interests),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.deregister(registry),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.deregister(registry),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.register(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.reregister(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.deregister(registry),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write_vectored(bufs),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
@@ -1,97 +1,1232 @@
-use crate::thread_pool::ThreadPool;
-use mio::net::{TcpListener, TcpStream};
-use mio::{Events, Interest, Poll, Token};
+use crate::error::ServerError;
+use crate::http::body::BodyWaker;
+use crate::http::headers::{self, HeaderValue};
+use crate::http::request::{
+    BodyFraming, ChunkedDecoder, HttpRequest, Method, ParseError, body_framing, find_head_end,
+    parse_request_limited, read_body,
+};
+use crate::http::response::{Body, ErrorPage, HttpResponse, error_handler, handle, page_response};
+use crate::http::status::StatusCode;
+use crate::http::websocket::{self, Event, Incoming, Session};
+use crate::io::access_log::{AccessLog, AccessLogger, AccessRecord};
+#[cfg(unix)]
+use crate::io::listener::bind_unix;
+use crate::io::listener::{BindAddress, Listener, Peer, Stream, bind, configure_stream};
+use crate::io::precompress::precompress;
+#[cfg(feature = "tls")]
+use crate::io::tls::TlsSession;
+use crate::io::watcher::Watcher;
+use crate::log::{self, LogFormat, LogLevel};
+use crate::server::{Assets, RouteMatch, ServerConfig};
+use crate::stats::LoopPhase;
+use crate::thread_pool::{CancellationToken, DEFAULT_QUEUE_DEPTH, ThreadPool, panic_message};
+use mio::{Events, Interest, Poll, Token, Waker};
+#[cfg(unix)]
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook_mio::v0_8::Signals;
+use std::collections::VecDeque;
 use std::io;
-use std::io::{Read, Write};
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::io::{IoSlice, Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 struct Connection {
-    stream: TcpStream,
+    stream: Stream,
     read_buffer: Vec<u8>,
-    write_buffer: Vec<u8>,
+    // How much of `read_buffer` has been searched for the end of a head
+    // that hasn't ended yet (see `find_head_end`).
+    head_scanned: usize,
+    // Where decoding the chunked body of the request being received got
+    // to, so each read decodes only the bytes it brought.
+    chunked: Option<ChunkedDecoder>,
+    write_queue: WriteQueue,
     state: State,
     keep_alive: bool,
+    // The part of the current response that is sent after `write_queue`
+    // drains (`Body::Stream` or `Body::File`).
+    body: Option<Body>,
+    // The stream body's source had nothing to give the last time it was
+    // asked; it is asked again on `ReactorMsg::BodyReady` and every sweep.
+    body_waiting: bool,
+    // When bytes last moved in either direction (or the connection went
+    // idle); the timeout sweep measures from here.
+    last_activity: Instant,
+    // When the first byte of the request being received arrived, and how
+    // many bytes of it have arrived since. `None` between requests.
+    request_started_at: Option<Instant>,
+    request_bytes: u64,
+    // When the request being answered was parsed, until the last byte of
+    // its response is out (see `ServerStats::record_duration`).
+    request_parsed_at: Option<Instant>,
+    // What the access log will say of the response being answered, if
+    // there is an access log.
+    access: Option<AccessRecord>,
+    // The ID of the request being answered (see `http::request_id`), for
+    // the errors logged meanwhile.
+    request_id: Option<String>,
+    // The interest the stream is registered with right now.
+    current_interest: Interest,
+    // The peer shut down its write side: nothing more will arrive, but it
+    // still reads what we send.
+    peer_closed: bool,
+    // Index into `Reactor::listeners` (and `config.addresses`) of the
+    // listener the connection arrived on.
+    listener: usize,
+    // The client's address, for logs.
+    peer: Peer,
+    // The listener only redirects to HTTPS (see `http::https`): request
+    // bodies aren't read, and the connection closes after one came.
+    redirect_to_https: bool,
+    // `write_queue` went over `HIGH_WATER_MARK` and hasn't drained below
+    // `LOW_WATER_MARK` since: the body isn't read further and neither is the socket.
+    throttled: bool,
+    // Cancelled if the connection closes while its request is on the pool,
+    // so the work is skipped (see `start_next_request`).
+    cancel: Option<CancellationToken>,
+    // While the request is on the pool: when it has to be answered by, and
+    // what the client gets if it isn't (see `sweep_timeouts`).
+    deadline: Option<(Instant, ErrorPage)>,
+    // Set once the connection is upgraded to a websocket (from the moment
+    // the `101` is queued); see `State::WebSocket`.
+    websocket: Option<Box<Session>>,
+    // Set for connections on a TLS listener; all socket I/O goes through it.
+    #[cfg(feature = "tls")]
+    tls: Option<Box<TlsSession>>,
 }
 
+impl Connection {
+    /// Returns `true` while the connection is waiting for (the rest of) a request.
+    fn is_reading(&self) -> bool {
+        matches!(self.state, State::ReadingHeader | State::ReadingBody)
+    }
+
+    /// Returns `true` while a request is being handled on the pool.
+    fn in_flight(&self) -> bool {
+        matches!(self.state, State::ReadyToRespond)
+    }
+
+    /// Returns `true` while bytes are waiting to be written: queued response
+    /// bytes, or encrypted records (handshake replies included) a TLS session
+    /// couldn't send yet.
+    fn output_pending(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if self.tls.as_ref().is_some_and(|tls| tls.wants_write()) {
+            return true;
+        }
+        !self.write_queue.is_empty()
+    }
+
+    /// Reads from the socket into `buf`, decrypting first on a TLS connection.
+    fn read_some(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return tls.read_plain(&mut self.stream, buf);
+        }
+        self.stream.read(buf)
+    }
+
+    /// Writes the front of `write_queue` to the socket, encrypting it first on
+    /// a TLS connection. See `WriteQueue::write_to`.
+    fn write_queued(&mut self) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return self.write_queue.write_to(&mut tls.writer(&mut self.stream));
+        }
+        self.write_queue.write_to(&mut self.stream)
+    }
+
+    /// Sends encrypted records a TLS session is still holding. Does nothing
+    /// on a plain connection.
+    ///
+    /// # Errors
+    /// `WouldBlock` if the socket filled up before everything was sent.
+    fn flush_tls(&mut self) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return tls.flush(&mut self.stream);
+        }
+        Ok(())
+    }
+
+    /// Writes `bytes` straight to the socket (through TLS if the connection
+    /// uses it), best effort: whatever doesn't fit in the socket buffer is lost.
+    fn write_best_effort(&mut self, bytes: &[u8]) {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            if let Ok(n) = tls.writer(&mut self.stream).write(bytes) {
+                self.sent(n);
+            }
+            return;
+        }
+        if let Ok(n) = self.stream.write(bytes) {
+            self.sent(n);
+        }
+    }
+
+    /// Notes that `n` bytes of the response went out.
+    fn sent(&mut self, n: usize) {
+        self.last_activity = Instant::now();
+        if let Some(record) = &mut self.access {
+            record.bytes += n as u64;
+        }
+    }
+
+    /// Returns `true` if the connection's body may be sent with `sendfile`,
+    /// which hands the kernel the file's bytes as they are: not under TLS.
+    #[cfg(target_os = "linux")]
+    fn can_sendfile(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return false;
+        }
+        true
+    }
+
+    /// Takes the next complete request out of `read_buffer`.
+    ///
+    /// Moves to `ReadingBody` once the head has arrived but the body hasn't,
+    /// and to `ReadyToRespond` when the whole request is there. On a
+    /// redirecting connection the body is left unread, along with anything
+    /// after it (see `has_body`). Does nothing unless the connection is reading.
+    ///
+    /// # Returns
+    /// - `Ok(Some(request))`: A complete request, now removed from the buffer.
+    /// - `Ok(None)`: More bytes are needed, or the connection isn't reading.
+    /// - `Err(ParseError)`: The request is malformed, or its head or body too large.
+    fn take_request(&mut self, config: &ServerConfig) -> Result<Option<HttpRequest>, ParseError> {
+        if !self.is_reading() {
+            return Ok(None);
+        }
+        let scanned = find_head_end(&self.read_buffer, self.head_scanned, config.max_head_size);
+        let head_len = match scanned {
+            Ok(head_len) => head_len,
+            Err(ParseError::Incomplete) => {
+                self.head_scanned = self.read_buffer.len();
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        // Whatever follows is searched afresh once this request is taken.
+        self.head_scanned = 0;
+        let (mut request, head_len) = parse_request_limited(
+            &self.read_buffer[..head_len],
+            config.max_header_fields,
+            config.max_head_size,
+        )?;
+        let framing = body_framing(&request)?;
+        if self.redirect_to_https && has_body(framing) {
+            self.read_buffer.clear();
+            self.state = State::ReadyToRespond;
+            return Ok(Some(request));
+        }
+        let limit = config.body_limit(&request);
+        let body = &self.read_buffer[head_len..];
+        let read = match framing {
+            BodyFraming::Chunked => self
+                .chunked
+                .get_or_insert_with(|| ChunkedDecoder::new(limit))
+                .decode(body),
+            framing => read_body(body, framing, limit),
+        };
+        match read {
+            Ok((body, body_len)) => {
+                request.body = body;
+                self.chunked = None;
+                self.read_buffer.drain(..head_len + body_len);
+                self.state = State::ReadyToRespond;
+                Ok(Some(request))
+            }
+            Err(ParseError::Incomplete) => {
+                self.state = State::ReadingBody;
+                Ok(None)
+            }
+            Err(e) => {
+                self.chunked = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Queues `response` for writing: `ReadyToRespond` → `WritingHeader`.
+    /// Does nothing in any other state, where a response isn't awaited.
+    fn begin_response(&mut self, response: HttpResponse, head_only: bool) {
+        if !self.in_flight() {
+            return;
+        }
+        self.body = response.start_writing(&mut self.write_queue, head_only);
+        self.state = State::WritingHeader;
+        self.last_activity = Instant::now();
+        self.update_throttle();
+    }
+
+    /// Queues an error response and closes once it is sent: any state → `Closed`.
+    ///
+    /// Used when the rest of the stream can't be framed reliably.
+    fn fail(&mut self, response: Vec<u8>) {
+        self.read_buffer.clear();
+        self.chunked = None;
+        self.write_queue.clear();
+        self.write_queue.push(response);
+        self.body = None;
+        self.state = State::Closed;
+    }
+
+    /// Notes that the buffered part of the response is out and the rest is
+    /// streamed from the body: `WritingHeader` → `WritingBody`.
+    fn head_flushed(&mut self) {
+        if matches!(self.state, State::WritingHeader) {
+            self.state = State::WritingBody;
+        }
+    }
+
+    /// Ends a fully flushed response: back to `ReadingHeader` on a keep-alive
+    /// connection, `Closed` otherwise, or on to `WebSocket` after a `101`
+    /// that upgraded it. Does nothing unless a response is being written.
+    ///
+    /// # Returns
+    /// `true` if the connection stays open for another request (or frames).
+    fn finish_response(&mut self) -> bool {
+        if !matches!(self.state, State::WritingHeader | State::WritingBody) {
+            return false;
+        }
+        if let Some(session) = &mut self.websocket {
+            session.open = true;
+            session.inbox.push_back(Event::Open);
+            self.state = State::WebSocket;
+            self.last_activity = Instant::now();
+            self.request_started_at = None;
+            return true;
+        }
+        if !self.keep_alive {
+            self.state = State::Closed;
+            return false;
+        }
+        self.state = State::ReadingHeader;
+        self.last_activity = Instant::now();
+        trim_buffer(&mut self.read_buffer);
+        // A pipelined request that already arrived starts its clock now.
+        if !self.read_buffer.is_empty() {
+            self.request_started_at = Some(self.last_activity);
+            self.request_bytes = self.read_buffer.len() as u64;
+        }
+        true
+    }
+
+    /// Throttles the connection once `write_queue` holds more than
+    /// `HIGH_WATER_MARK` bytes, and lifts it once they drop below `LOW_WATER_MARK`.
+    fn update_throttle(&mut self) {
+        let pending = self.write_queue.len();
+        if pending > HIGH_WATER_MARK {
+            self.throttled = true;
+        } else if pending < LOW_WATER_MARK {
+            self.throttled = false;
+        }
+    }
+
+    /// Returns the interest the connection's state calls for: `READABLE`
+    /// while waiting for a request, `WRITABLE` too while a response (or TLS
+    /// handshake data) is queued so a pipelined request can be read meanwhile,
+    /// and `WRITABLE` alone when the connection closes once the queue is
+    /// flushed or while it is throttled.
+    fn desired_interest(&self) -> Interest {
+        if !self.output_pending() && self.body.is_none() {
+            Interest::READABLE
+        } else if !self.peer_closed
+            && !self.throttled
+            && (self.is_reading() || self.keep_alive && !matches!(self.state, State::Closed))
+        {
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            Interest::WRITABLE
+        }
+    }
+
+    /// Reregisters the stream with `want`, unless that is already its interest.
+    fn set_interest(&mut self, poll: &Poll, token: Token, want: Interest) -> io::Result<()> {
+        if self.current_interest != want {
+            poll.registry().reregister(&mut self.stream, token, want)?;
+            REREGISTRATIONS.fetch_add(1, Ordering::Relaxed);
+            self.current_interest = want;
+        }
+        Ok(())
+    }
+}
+
+/// The bytes waiting to go out on a connection, as a queue of chunks.
+///
+/// A response's head and body are separate chunks, and `write_to` hands as
+/// many of them as it can to a single vectored write, so bodies are never
+/// copied just to sit behind their head.
+#[derive(Default)]
+struct WriteQueue {
+    chunks: VecDeque<Vec<u8>>,
+    // How much of the front chunk has already been written.
+    offset: usize,
+    // The bytes still to be written, across all chunks.
+    len: usize,
+}
+
+impl WriteQueue {
+    // The most slices passed to one `write_vectored` call; more than a
+    // response ever has queued at once.
+    const MAX_SLICES: usize = 16;
+
+    /// Appends `chunk` to the back of the queue. Empty chunks are dropped.
+    fn push(&mut self, chunk: Vec<u8>) {
+        if !chunk.is_empty() {
+            self.len += chunk.len();
+            self.chunks.push_back(chunk);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.offset = 0;
+        self.len = 0;
+    }
+
+    /// Writes as much of the queue as `w` accepts in one vectored write and
+    /// drops what was written, resuming mid-chunk next time if needed.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The number of bytes written; `0` only if the queue is
+    ///   empty or `w` accepted nothing (the peer is gone).
+    /// - `Err(io::Error)`: The write failed, e.g. with `WouldBlock`; nothing was consumed.
+    fn write_to(&mut self, w: &mut impl Write) -> io::Result<usize> {
+        let mut slices = Vec::with_capacity(self.chunks.len().min(Self::MAX_SLICES));
+        for (i, chunk) in self.chunks.iter().take(Self::MAX_SLICES).enumerate() {
+            let start = if i == 0 { self.offset } else { 0 };
+            slices.push(IoSlice::new(&chunk[start..]));
+        }
+        let written = w.write_vectored(&slices)?;
+        self.advance(written);
+        Ok(written)
+    }
+
+    /// Drops the first `n` queued bytes, which may span several chunks.
+    fn advance(&mut self, mut n: usize) {
+        self.len -= n.min(self.len);
+        while let Some(front) = self.chunks.front() {
+            let left = front.len() - self.offset;
+            if n < left {
+                self.offset += n;
+                return;
+            }
+            n -= left;
+            self.chunks.pop_front();
+            self.offset = 0;
+        }
+    }
+}
+
+impl Extend<Vec<u8>> for WriteQueue {
+    fn extend<I: IntoIterator<Item = Vec<u8>>>(&mut self, chunks: I) {
+        for chunk in chunks {
+            self.push(chunk);
+        }
+    }
+}
+
+/// Read buffers left behind by closed connections, handed to new ones so
+/// that every accept doesn't start from an empty `Vec` and grow it again.
+#[derive(Default)]
+struct BufferPool {
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    // The most buffers kept around at once; the rest are freed.
+    const MAX_POOLED: usize = 64;
+
+    /// Returns a pooled buffer, or a new one (counted in `buffer_allocations`)
+    /// if the pool is empty.
+    fn take(&mut self) -> Vec<u8> {
+        self.free.pop().unwrap_or_else(|| {
+            BUFFER_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            Vec::with_capacity(INITIAL_BUFFER_CAPACITY)
+        })
+    }
+
+    /// Empties `buffer` and keeps it for the next `take`, unless the pool is full.
+    fn give(&mut self, mut buffer: Vec<u8>) {
+        if self.free.len() < Self::MAX_POOLED {
+            buffer.clear();
+            trim_buffer(&mut buffer);
+            self.free.push(buffer);
+        }
+    }
+}
+
+/// Work handed to the reactor from another thread through a `ReactorHandle`.
+///
+/// Variants:
+/// - `EnqueueResponse`: A response for the connection at `token`, dropped if that
+///   connection has closed since the request was read (see `Reactor`). `head_only`
+///   drops the body, for `HEAD`. `upgrade` is set for the `101` of a websocket
+///   route: the session the connection carries once the response is sent.
+/// - `WebSocketSend`: A frame for the websocket connection at `token`, dropped if it
+///   has closed. `close` closes the connection once the frame is sent.
+/// - `WebSocketDone`: The pool is done with an event of the websocket connection at
+///   `token`; the next one may go.
+/// - `BodyReady`: The stream body being sent on the connection at `token` has more
+///   to give (see `BodyWaker`).
+/// - `AbortResponse`: The stream body being sent on the connection at `token` can't
+///   be finished; the connection is closed.
+/// - `Shutdown`: Stop accepting and drain, exactly as on SIGTERM.
+pub enum ReactorMsg {
+    EnqueueResponse {
+        token: Token,
+        response: HttpResponse,
+        head_only: bool,
+        upgrade: Option<Box<Session>>,
+    },
+    WebSocketSend {
+        token: Token,
+        frame: Vec<u8>,
+        close: bool,
+    },
+    WebSocketDone {
+        token: Token,
+    },
+    BodyReady {
+        token: Token,
+    },
+    AbortResponse {
+        token: Token,
+    },
+    Shutdown,
+}
+
+/// A cloneable way for other threads to hand the reactor work and wake it.
+///
+/// # Example
+/// ```
+/// let handle = reactor.handle.clone();
+/// std::thread::spawn(move || handle.send(ReactorMsg::Shutdown));
+/// ```
+#[derive(Clone)]
+pub struct ReactorHandle {
+    sender: mpsc::Sender<ReactorMsg>,
+    waker: Arc<Waker>,
+}
+
+impl ReactorHandle {
+    /// Queues `msg` for the reactor and wakes it.
+    ///
+    /// # Errors
+    /// Fails with `BrokenPipe` if the reactor has exited, or if waking it failed.
+    pub fn send(&self, msg: ReactorMsg) -> io::Result<()> {
+        self.sender
+            .send(msg)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "reactor has exited"))?;
+        self.wake()
+    }
+
+    /// Wakes the reactor without sending anything. The loop treats a wakeup
+    /// with no messages queued as a no-op.
+    pub fn wake(&self) -> io::Result<()> {
+        self.waker.wake()
+    }
+}
+
+/// Where a connection is in the request/response cycle.
+///
+/// Variants:
+/// - `ReadingHeader`: Waiting for the head of the next request.
+/// - `ReadingBody`: The head has arrived; waiting for the rest of the body.
+/// - `ReadyToRespond`: The request is complete and being handled on the pool.
+/// - `WritingHeader`: The head (and any in-memory body) is being written.
+/// - `WritingBody`: A streamed body is being written.
+/// - `WebSocket`: The connection was upgraded and carries websocket frames
+///   until either side closes it (see `http::websocket`).
+/// - `Closed`: The connection closes as soon as the write buffer is empty.
+/// - `Lingering`: Everything is sent and the write side shut down; input is
+///   discarded until the peer's EOF or `linger_timeout`, then the connection closes.
 enum State {
     ReadingHeader,
     ReadingBody,
     WritingHeader,
     WritingBody,
     ReadyToRespond,
+    WebSocket,
     Closed,
+    Lingering,
 }
 
-const LISTENER: Token = Token(0);
+// Listener `i` is registered as Token(i); slab keys map to tokens at or above
+// MAX_LISTENERS (see `connection_token`), and the top values can never collide
+// with a connection.
+const MAX_LISTENERS: usize = 64;
+const WAKER: Token = Token(usize::MAX);
+
+// Connection buffers allocated so far, across every reactor in the process.
+static BUFFER_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of connection buffers allocated so far, for metrics.
+/// Buffers reused from the pool or across keep-alive requests aren't counted.
+pub fn buffer_allocations() -> usize {
+    BUFFER_ALLOCATIONS.load(Ordering::Relaxed)
+}
 
+// Connections reregistered with a new interest so far, across every reactor
+// in the process.
+static REREGISTRATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of times a connection was reregistered with poll so
+/// far, for metrics. Interest that didn't change isn't reregistered, so isn't counted.
+pub fn reregistrations() -> usize {
+    REREGISTRATIONS.load(Ordering::Relaxed)
+}
+
+// The accept counter of every reactor started in the process, in start order.
+static REACTOR_ACCEPTS: Mutex<Vec<Arc<AtomicUsize>>> = Mutex::new(Vec::new());
+
+/// Returns how many connections each reactor has accepted, in the order the
+/// reactors started, for metrics.
+pub fn accepted_per_reactor() -> Vec<usize> {
+    REACTOR_ACCEPTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|accepted| accepted.load(Ordering::Relaxed))
+        .collect()
+}
+
+// What a newly allocated read buffer starts with: enough for a typical request head.
+const INITIAL_BUFFER_CAPACITY: usize = 4096;
+// The most capacity a buffer keeps between requests. One huge request
+// shouldn't pin its memory for the rest of the connection's life.
+const MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+// Above this many queued output bytes a connection is throttled: no more body
+// chunks are produced and no more requests read until the client catches up.
+const HIGH_WATER_MARK: usize = 256 * 1024;
+// Below this many queued output bytes a throttled connection resumes.
+const LOW_WATER_MARK: usize = 64 * 1024;
+
+#[cfg(unix)]
+const SIGNALS: Token = Token(usize::MAX - 1);
+// How often connections are checked against their timeouts.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// How long accepting stops once the process is out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(250);
+// Pools bigger than this are almost certainly a mistake, and get a warning.
+const MAX_SENSIBLE_WORKERS: usize = 1024;
+
+/// Event loop tuning.
+///
+/// # Fields
+/// - `poll_timeout` (*Duration*): The longest one wait for events may last. The
+///   wait is cut shorter when the next timeout sweep or the drain deadline comes
+///   first, so a long value doesn't delay either. Defaults to 1 second.
+/// - `event_capacity` (*usize*): How many events one wait can return. More pending
+///   events are picked up by the next wait. Defaults to 1024.
+/// - `slab_capacity` (*usize*): Connection slots each reactor allocates up front.
+///   The slab grows past it on demand, but never past `max_connections`.
+///   Defaults to 1024.
+/// - `workers` (*usize*): Threads in each reactor's pool, which runs the request
+///   handlers. Must be at least 1. Defaults to `default_workers()`.
+/// - `job_queue_depth` (*usize*): How many requests may wait for a free worker of
+///   each reactor's thread pool. Requests beyond it get `503 Service Unavailable`
+///   right away. Defaults to `thread_pool::DEFAULT_QUEUE_DEPTH` (1024).
+/// - `loop_metrics` (*bool*): Time every iteration and its accept, read and write
+///   handling into `ServerConfig::stats` (see `stats::StatsSnapshot`). Costs a few
+///   clock reads per event. Defaults to `false`.
+/// - `slow_iteration` (*Option<Duration>*): Log a warning for every iteration that
+///   takes longer than this, a sign that something blocks the loop thread. `None`
+///   turns the check off. Defaults to 100 ms.
+#[derive(Debug, Clone)]
+pub struct ReactorConfig {
+    pub poll_timeout: Duration,
+    pub event_capacity: usize,
+    pub slab_capacity: usize,
+    pub workers: usize,
+    pub job_queue_depth: usize,
+    pub loop_metrics: bool,
+    pub slow_iteration: Option<Duration>,
+}
+
+impl Default for ReactorConfig {
+    fn default() -> ReactorConfig {
+        ReactorConfig {
+            poll_timeout: Duration::from_secs(1),
+            event_capacity: 1024,
+            slab_capacity: 1024,
+            workers: default_workers(),
+            job_queue_depth: DEFAULT_QUEUE_DEPTH,
+            loop_metrics: false,
+            slow_iteration: Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Returns the default `ReactorConfig::workers`: one per CPU the process may
+/// use, but at least 2, so one slow disk read doesn't hold up every request,
+/// and at most 16, as every reactor of a `Server` has a pool of its own.
+/// Falls back to 4 if the CPU count is unknown.
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get().clamp(2, 16))
+}
+
+// Connection tokens hold `key + MAX_LISTENERS` in their low `SLOT_BITS` bits
+// and the slot's generation in the rest.
+const SLOT_BITS: u32 = usize::BITS / 2;
+const SLOT_MASK: usize = (1 << SLOT_BITS) - 1;
+// Generations wrap at this value, so they always fit above the slot bits.
+const MAX_GENERATION: usize = usize::MAX >> SLOT_BITS;
+
+/// Returns the token a connection in slab slot `key` is registered with
+/// while the slot is at `generation`.
+fn connection_token(key: usize, generation: usize) -> Token {
+    Token(generation << SLOT_BITS | (key + MAX_LISTENERS))
+}
+
+/// Splits a connection token into its slab key and generation.
+fn token_slot(token: Token) -> (usize, usize) {
+    ((token.0 & SLOT_MASK) - MAX_LISTENERS, token.0 >> SLOT_BITS)
+}
+
+/// The event loop: owns the listeners, every connection and the thread pool.
+///
+/// Sockets are only ever touched on the reactor's thread. Building a response
+/// may block on the disk, so when a connection reaches `ReadyToRespond` the
+/// request is handed to the pool instead. The job sends the response back
+/// as a `ReactorMsg::EnqueueResponse` through a `ReactorHandle`, which wakes
+/// the loop (`WAKER` token); `handle_messages` then queues the response on the connection,
+/// unless the connection is gone. Meanwhile the loop keeps accepting and
+/// serving other connections.
+///
+/// A slab slot is reused as soon as its connection closes, while events and
+/// pool results for the old connection may still be on their way. Each slot
+/// therefore has a generation, bumped when its connection is removed and
+/// carried in the connection's token; `slot` resolves a token only while the
+/// generations match, so anything addressed to a previous occupant is dropped.
 struct Reactor {
     poll: Poll,
-    listener: TcpListener,
+    // One per entry of `config.addresses`, in the same order.
+    listeners: Vec<Listener>,
     conns: slab::Slab<Connection>,
     pool: ThreadPool,
+    config: Arc<ServerConfig>,
+    handle: ReactorHandle,
+    messages: mpsc::Receiver<ReactorMsg>,
+    // The generation of every slab slot ever used, by key.
+    generations: Vec<usize>,
+    buffers: BufferPool,
+    // Connections this reactor has accepted; also listed in `REACTOR_ACCEPTS`.
+    accepted: Arc<AtomicUsize>,
+    // Sent as-is to connections over `config.max_connections`, built once at
+    // startup so shedding load doesn't touch the disk.
+    overload_response: Vec<u8>,
+    // Sent as-is to clients `config.ip_filter` refuses, if it says to.
+    forbidden_response: Vec<u8>,
+    last_sweep: Instant,
+    // Set once SIGINT/SIGTERM arrives: the moment draining gives up.
+    drain_deadline: Option<Instant>,
+    // Set when a shutdown is asked for while `health.drain_delay` holds it
+    // off: the moment draining starts. `/readyz` fails from then on.
+    drain_at: Option<Instant>,
+    // Set when `accept` ran out of file descriptors: no accepting until then.
+    accepts_paused_until: Option<Instant>,
+    // The fd shortage has been logged and no accept has succeeded since.
+    fd_shortage_logged: bool,
+    // Set when `config.access_log` is.
+    access_log: Option<AccessLogger>,
+    #[cfg(unix)]
+    signals: Signals,
 }
 
 impl Reactor {
-    fn new(addr: &str) -> io::Result<Self> {
+    /// Builds a reactor serving `listeners`, with its own poll, waker,
+    /// signal registration and `pool`, logging responses to `access_log`.
+    ///
+    /// `config` must already have been through `prepare`.
+    fn new(
+        mut listeners: Vec<Listener>,
+        config: Arc<ServerConfig>,
+        pool: ThreadPool,
+        access_log: Option<AccessLogger>,
+    ) -> Result<Self, ServerError> {
         let poll = Poll::new()?;
-        let mut listener = TcpListener::bind(addr.parse().unwrap())?;
-        let pool = ThreadPool::new(4);
+        for (i, listener) in listeners.iter_mut().enumerate() {
+            poll.registry()
+                .register(listener, Token(i), Interest::READABLE)?;
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (sender, messages) = mpsc::channel();
+        #[cfg(unix)]
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        #[cfg(unix)]
         poll.registry()
-            .register(&mut listener, LISTENER, Interest::READABLE)?;
+            .register(&mut signals, SIGNALS, Interest::READABLE)?;
+        let overload_response = error_handler(ErrorPage::ServiceUnavailable, &config);
+        let forbidden_response = error_handler(ErrorPage::PermissionDenied, &config);
+        let accepted = Arc::new(AtomicUsize::new(0));
+        REACTOR_ACCEPTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::clone(&accepted));
         Ok(Self {
             poll,
-            listener,
-            conns: slab::Slab::with_capacity(1024),
+            listeners,
+            conns: slab::Slab::with_capacity(
+                config.reactor.slab_capacity.min(config.max_connections),
+            ),
             pool,
+            config,
+            handle: ReactorHandle { sender, waker },
+            messages,
+            generations: Vec::new(),
+            buffers: BufferPool::default(),
+            accepted,
+            overload_response,
+            forbidden_response,
+            last_sweep: Instant::now(),
+            drain_deadline: None,
+            drain_at: None,
+            accepts_paused_until: None,
+            fd_shortage_logged: false,
+            access_log,
+            #[cfg(unix)]
+            signals,
         })
     }
     fn event_loop(&mut self) -> io::Result<()> {
-        let mut events = Events::with_capacity(1024);
+        let mut events = Events::with_capacity(self.config.reactor.event_capacity.max(1));
+        let timed =
+            self.config.reactor.loop_metrics || self.config.reactor.slow_iteration.is_some();
 
         loop {
-            self.poll
-                .poll(&mut events, Some(Duration::from_millis(1000)))?;
+            match self.poll.poll(&mut events, Some(self.poll_timeout())) {
+                Ok(()) => {}
+                // A signal arriving during the wait; it is picked up through `SIGNALS`.
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::error(format_args!(
+                        "{}: waiting for events failed: {}; stopping",
+                        thread::current().name().unwrap_or("reactor"),
+                        e
+                    ));
+                    return Err(e);
+                }
+            }
+            let woke_at = timed.then(Instant::now);
 
             for event in events.iter() {
                 let token = event.token();
 
-                if token == LISTENER {
-                    self.accept_ready()?;
+                #[cfg(unix)]
+                if token == SIGNALS {
+                    if self.signals.pending().count() > 0 {
+                        self.request_shutdown()?;
+                    }
+                    continue;
+                }
+                if token.0 < self.listeners.len() {
+                    self.timed(LoopPhase::Accept, |reactor| reactor.accept_ready(token.0))?;
+                } else if token == WAKER {
+                    self.timed(LoopPhase::Write, Reactor::handle_messages)?;
                 } else {
                     self.handle_connection_event(token, event)?;
                 }
             }
+
+            if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+                self.sweep_timeouts()?;
+                self.poll_waiting_bodies()?;
+                self.last_sweep = Instant::now();
+            }
+
+            // Listener readiness is edge-triggered, so connections that queued
+            // up during the pause have to be fetched without waiting for an event.
+            if self
+                .accepts_paused_until
+                .is_some_and(|until| Instant::now() >= until)
+            {
+                self.accepts_paused_until = None;
+                for listener in 0..self.listeners.len() {
+                    self.accept_ready(listener)?;
+                }
+            }
+
+            if let Some(woke_at) = woke_at {
+                self.end_iteration(woke_at, events.iter().count());
+            }
+
+            if self.drain_at.is_some_and(|at| Instant::now() >= at) {
+                self.begin_shutdown()?;
+            }
+            if let Some(deadline) = self.drain_deadline {
+                if self.conns.is_empty() {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    log::warn(format_args!(
+                        "Drain deadline passed; closing {} connections",
+                        self.conns.len()
+                    ));
+                    let remaining: Vec<usize> = self.conns.iter().map(|(idx, _)| idx).collect();
+                    for idx in remaining {
+                        self.close_connection(idx);
+                    }
+                    return Ok(());
+                }
+            }
         }
     }
-    fn accept_ready(&mut self) -> io::Result<()> {
+
+    /// Runs `handler`, adding the time it took to `phase` if `loop_metrics` is on.
+    fn timed<T>(&mut self, phase: LoopPhase, handler: impl FnOnce(&mut Self) -> T) -> T {
+        if !self.config.reactor.loop_metrics {
+            return handler(self);
+        }
+        let started = Instant::now();
+        let result = handler(self);
+        self.config.stats.record_phase(phase, started.elapsed());
+        result
+    }
+
+    /// Records an iteration that woke up at `woke_at` and handled `events`
+    /// events, and warns if it took longer than `slow_iteration`.
+    fn end_iteration(&self, woke_at: Instant, events: usize) {
+        let took = woke_at.elapsed();
+        if self.config.reactor.loop_metrics {
+            self.config.stats.record_iteration(events, took);
+        }
+        if let Some(limit) = self.config.reactor.slow_iteration
+            && took > limit
+        {
+            log::warn(format_args!(
+                "event loop iteration took {:?} for {} events; \
+                 something is blocking the reactor thread",
+                took, events
+            ));
+        }
+    }
+
+    /// Returns how long the next wait for events may last: `poll_timeout`,
+    /// or less if the next sweep, the start or deadline of draining, or the
+    /// end of an accept pause is due sooner.
+    fn poll_timeout(&self) -> Duration {
+        let now = Instant::now();
+        let deadline = [
+            self.drain_at,
+            self.drain_deadline,
+            self.accepts_paused_until,
+        ]
+        .into_iter()
+        .flatten()
+        .fold(self.last_sweep + SWEEP_INTERVAL, Instant::min);
+        self.config
+            .reactor
+            .poll_timeout
+            .min(deadline.saturating_duration_since(now))
+    }
+
+    /// Answers a shutdown request (a signal or `ReactorMsg::Shutdown`): starts
+    /// draining, or with a `health.drain_delay` only fails `/readyz` and
+    /// starts draining once the delay is over. A second request while
+    /// waiting starts draining at once.
+    fn request_shutdown(&mut self) -> io::Result<()> {
+        let delay = self
+            .config
+            .health
+            .as_ref()
+            .map_or(Duration::ZERO, |health| health.drain_delay);
+        if self.drain_at.is_some() || self.drain_deadline.is_some() || delay.is_zero() {
+            return self.begin_shutdown();
+        }
+        log::info(format_args!("Shutting down in {delay:?}: no longer ready"));
+        self.drain_at = Some(Instant::now() + delay);
+        Ok(())
+    }
+
+    /// Starts a graceful shutdown.
+    ///
+    /// The listeners are deregistered so no new connections are accepted.
+    /// Connections with a response being built or written are marked to close
+    /// once it is sent, websocket connections are sent a close frame (`1001
+    /// Going Away`) and closed once it is out, and all others are closed
+    /// right away. So are connections whose stream body is waiting for its
+    /// source (e.g. an event stream) with nothing left to send: there's no
+    /// telling when, if ever, it would end. The event loop
+    /// returns when the last connection is gone or `config.drain_timeout`
+    /// runs out, whichever comes first.
+    fn begin_shutdown(&mut self) -> io::Result<()> {
+        if self.drain_deadline.is_some() {
+            return Ok(());
+        }
+        self.drain_at = None;
+        self.drain_deadline = Some(Instant::now() + self.config.drain_timeout);
+        // A listener that can't be deregistered is harmless: `accept_ready`
+        // ignores it once draining.
+        for listener in &mut self.listeners {
+            if let Err(e) = self.poll.registry().deregister(listener) {
+                log::error(format_args!("deregister error: {}", e));
+            }
+        }
+
+        let mut idle = Vec::new();
+        let mut websockets = Vec::new();
+        for (idx, conn) in self.conns.iter_mut() {
+            conn.keep_alive = false;
+            if conn.is_reading() || conn.body_waiting && !conn.output_pending() {
+                idle.push(idx);
+            } else if matches!(conn.state, State::WebSocket) {
+                conn.write_queue
+                    .push(websocket::close_frame(websocket::CLOSE_GOING_AWAY, ""));
+                conn.state = State::Closed;
+                websockets.push(idx);
+            }
+        }
+        log::info(format_args!(
+            "Shutting down: draining {} connections",
+            self.conns.len() - idle.len()
+        ));
+        for idx in idle {
+            self.close_connection(idx);
+        }
+        for idx in websockets {
+            self.handle_writable(idx, connection_token(idx, self.generations[idx]))?;
+        }
+        Ok(())
+    }
+
+    /// Closes every connection that has been quiet for longer than its timeout,
+    /// or whose request is arriving too slowly.
+    ///
+    /// Which limits apply depends on what the connection is waiting for:
+    /// - a response still being written: `write_timeout`, the client isn't reading;
+    /// - part of a request already received: `header_read_timeout` since the last
+    ///   byte, `header_deadline` since the first byte (while the head is incomplete)
+    ///   and `min_request_rate`. The client is sent `408 Request Timeout` (best
+    ///   effort) before the close. The deadline and rate catch clients that keep a
+    ///   connection busy by trickling a byte now and then, which the idle timeout
+    ///   alone never would;
+    /// - nothing at all: `keep_alive_timeout`, or the site's
+    ///   `websocket.idle_timeout` on a websocket connection;
+    /// - more of a stream body from its source: no limit, the source decides;
+    /// - the peer's EOF, after the last response: `linger_timeout`.
+    ///
+    /// A request still being handled on the pool past its `handler_timeout`
+    /// (see `start_next_request`) is answered with `503 Service Unavailable`,
+    /// or `504 Gateway Timeout` for a proxied path, in its place; the
+    /// connection closes once that is sent, and the handler's own response
+    /// is dropped whenever it comes (see `enqueue_response`).
+    fn sweep_timeouts(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let overdue: Vec<(usize, ErrorPage)> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| conn.in_flight())
+            .filter_map(|(idx, conn)| {
+                let (deadline, page) = conn.deadline?;
+                (now >= deadline).then_some((idx, page))
+            })
+            .collect();
+        for (idx, page) in overdue {
+            let token = connection_token(idx, self.generations[idx]);
+            let conn = &mut self.conns[idx];
+            log::error_in(
+                conn.request_id.as_deref(),
+                format_args!("request from {} not handled in time", conn.peer),
+            );
+            if let Some(cancel) = conn.cancel.take() {
+                cancel.cancel();
+            }
+            conn.deadline = None;
+            self.record_response(idx, page.status());
+            self.conns[idx].fail(error_handler(page, &self.config));
+            self.handle_writable(idx, token)?;
+        }
+
+        let config = &self.config;
+        let expired: Vec<(usize, bool)> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| !conn.in_flight())
+            .filter_map(|(idx, conn)| {
+                let idle = now.duration_since(conn.last_activity);
+                if matches!(conn.state, State::Lingering) {
+                    return (idle >= config.linger_timeout).then_some((idx, false));
+                }
+                if conn.output_pending() || conn.body.is_some() && !conn.body_waiting {
+                    return (idle >= config.write_timeout).then_some((idx, false));
+                }
+                if conn.body_waiting {
+                    return None;
+                }
+                if matches!(conn.state, State::WebSocket)
+                    && let Some(session) = &conn.websocket
+                {
+                    return (idle >= session.idle_timeout).then_some((idx, false));
+                }
+                let Some(started) = conn.request_started_at else {
+                    return (idle >= config.keep_alive_timeout).then_some((idx, false));
+                };
+                let elapsed = now.duration_since(started);
+                let head_late =
+                    elapsed >= config.header_deadline && matches!(conn.state, State::ReadingHeader);
+                let too_slow = config.min_request_rate.is_some_and(|rate| {
+                    elapsed >= SWEEP_INTERVAL
+                        && (conn.request_bytes as f64) < rate as f64 * elapsed.as_secs_f64()
+                });
+                (idle >= config.header_read_timeout || head_late || too_slow).then_some((idx, true))
+            })
+            .collect();
+
+        for (idx, partial_request) in expired {
+            if partial_request {
+                let response = error_handler(ErrorPage::RequestTimeout, &self.config);
+                self.record_response(idx, ErrorPage::RequestTimeout.status());
+                // The socket buffer almost always has room for a small error
+                // page; if it doesn't, the client was never going to read it.
+                self.conns[idx].write_best_effort(&response);
+            }
+            self.close_connection(idx);
+        }
+        Ok(())
+    }
+    /// Accepts every connection waiting on the listener at index `listener`.
+    ///
+    /// When the process runs out of file descriptors, accepting stops on every
+    /// listener for `ACCEPT_BACKOFF`: retrying at once would fail the same way,
+    /// over and over, while the waiting connections stay queued in the kernel.
+    /// Nothing is accepted once draining has begun.
+    fn accept_ready(&mut self, listener: usize) -> io::Result<()> {
+        if self.accepts_paused_until.is_some() || self.drain_deadline.is_some() {
+            return Ok(());
+        }
         loop {
-            match self.listener.accept() {
-                Ok((stream, _addr)) => {
-                    let conn = Connection {
+            match self.listeners[listener].accept() {
+                Ok((mut stream, peer)) => {
+                    self.fd_shortage_logged = false;
+                    if let Peer::Tcp(addr) = peer
+                        && !self.config.ip_filter.permits(addr.ip())
+                    {
+                        self.config.stats.connection_refused();
+                        if self.config.ip_filter.respond_forbidden && !self.uses_tls(listener) {
+                            self.config
+                                .stats
+                                .record_response(ErrorPage::PermissionDenied.status());
+                            let _ = stream.write(&self.forbidden_response);
+                        }
+                        continue;
+                    }
+                    #[cfg(feature = "tls")]
+                    let tls = match self.tls_session(listener) {
+                        Ok(tls) => tls,
+                        Err(e) => {
+                            log::error(format_args!("TLS error: {}", e));
+                            continue;
+                        }
+                    };
+                    // The slab check keeps this reactor's memory bounded even
+                    // if reactors race each other past the shared count.
+                    if self.config.stats.connections_active() >= self.config.max_connections as u64
+                        || self.conns.len() >= self.config.max_connections
+                    {
+                        self.config.stats.connection_refused();
+                        self.config
+                            .stats
+                            .record_response(ErrorPage::ServiceUnavailable.status());
+                        // A TLS client can't read a plaintext answer; it is
+                        // just hung up on.
+                        #[cfg(feature = "tls")]
+                        if tls.is_some() {
+                            continue;
+                        }
+                        // Best effort: the client gets a clear answer if the
+                        // socket buffer takes it, and is hung up on either way.
+                        let _ = stream.write(&self.overload_response);
+                        continue;
+                    }
+                    if let Err(e) = configure_stream(&stream, &self.config.socket) {
+                        log::error(format_args!("socket option error: {}", e));
+                    }
+                    let mut conn = Connection {
                         stream,
-                        read_buffer: Vec::new(),
-                        write_buffer: Vec::new(),
+                        read_buffer: self.buffers.take(),
+                        head_scanned: 0,
+                        chunked: None,
+                        write_queue: WriteQueue::default(),
                         state: State::ReadingHeader,
                         keep_alive: false,
+                        body: None,
+                        body_waiting: false,
+                        last_activity: Instant::now(),
+                        request_started_at: None,
+                        request_bytes: 0,
+                        request_parsed_at: None,
+                        access: None,
+                        request_id: None,
+                        current_interest: Interest::READABLE,
+                        peer_closed: false,
+                        throttled: false,
+                        cancel: None,
+                        deadline: None,
+                        websocket: None,
+                        redirect_to_https: self.redirects_to_https(listener),
+                        listener,
+                        peer,
+                        #[cfg(feature = "tls")]
+                        tls,
                     };
 
-                    // 2) Insert into slab, get index
+                    // 2) Reserve a slab slot, which gives the token
                     let entry = self.conns.vacant_entry();
                     let key = entry.key();
-                    let token = Token(key + 1); // 0 is reserved for LISTENER
+                    if key == self.generations.len() {
+                        self.generations.push(0);
+                    }
+                    let token = connection_token(key, self.generations[key]);
 
-                    // 3) Register this socket with 'poll'
-                    self.poll.registry().register(
-                        &mut entry.insert(conn).stream,
-                        token,
-                        Interest::READABLE,
-                    )?;
+                    // 3) Register this socket with 'poll', then insert it. A
+                    // socket poll won't take is dropped, which closes it.
+                    if let Err(e) =
+                        self.poll
+                            .registry()
+                            .register(&mut conn.stream, token, Interest::READABLE)
+                    {
+                        log::error(format_args!("register error for {}: {}", peer, e));
+                        self.buffers.give(conn.read_buffer);
+                        continue;
+                    }
+                    entry.insert(conn);
+                    self.accepted.fetch_add(1, Ordering::Relaxed);
+                    self.config.stats.connection_opened();
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     break;
                 }
+                Err(ref e) if is_fd_shortage(e) => {
+                    if !self.fd_shortage_logged {
+                        log::warn(format_args!(
+                            "accept failed: {}; pausing accepts for {:?} \
+                             (lower max_connections or raise the file descriptor limit)",
+                            e, ACCEPT_BACKOFF
+                        ));
+                        self.fd_shortage_logged = true;
+                    }
+                    self.accepts_paused_until = Some(Instant::now() + ACCEPT_BACKOFF);
+                    break;
+                }
                 Err(e) => {
-                    eprintln!("accept error: {}", e);
+                    log::error(format_args!("accept error: {}", e));
                     break;
                 }
             }
@@ -105,119 +1240,1482 @@ impl Reactor {
         token: Token,
         event: &mio::event::Event,
     ) -> io::Result<()> {
-        let idx = token.0 - 1;
+        let Some(idx) = self.slot(token) else {
+            // The connection this event was for has closed since.
+            return Ok(());
+        };
 
-        if event.is_readable() {
-            if let Some(conn) = self.conns.get_mut(idx) {
-                self.handle_readable(idx, token)?;
-            }
+        // Both directions are gone (or the peer reset the connection), so
+        // there is no one left to answer.
+        if event.is_write_closed() || event.is_error() {
+            self.close_connection(idx);
+            return Ok(());
         }
 
-        if event.is_writable() {
-            if let Some(conn) = self.conns.get_mut(idx) {
-                self.handle_writable(idx, token)?;
-            }
+        if event.is_readable() && self.conns.contains(idx) {
+            self.timed(LoopPhase::Read, |reactor| {
+                reactor.handle_readable(idx, token)
+            })?;
+        }
+
+        if event.is_writable() && self.conns.contains(idx) {
+            self.timed(LoopPhase::Write, |reactor| {
+                reactor.handle_writable(idx, token)
+            })?;
         }
 
         Ok(())
     }
 
+    /// Returns the slab key of the connection `token` was issued to, or `None`
+    /// if that connection has closed (even if the slot is in use again).
+    fn slot(&self, token: Token) -> Option<usize> {
+        let (key, generation) = token_slot(token);
+        (self.conns.contains(key) && self.generations[key] == generation).then_some(key)
+    }
+
+    /// Returns `true` if the listener at index `listener` serves TLS.
+    #[cfg(feature = "tls")]
+    fn uses_tls(&self, listener: usize) -> bool {
+        self.config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.serves(&self.config.addresses[listener]))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn uses_tls(&self, _listener: usize) -> bool {
+        false
+    }
+
+    /// Returns `true` if the listener at index `listener` only redirects to
+    /// HTTPS. One that serves TLS never does.
+    fn redirects_to_https(&self, listener: usize) -> bool {
+        self.config
+            .redirect_to_https
+            .as_ref()
+            .is_some_and(|redirect| redirect.serves(&self.config.addresses[listener]))
+            && !self.uses_tls(listener)
+    }
+
+    /// Starts a TLS session for a connection accepted on the listener at
+    /// index `listener`, if that listener serves TLS.
+    #[cfg(feature = "tls")]
+    fn tls_session(&self, listener: usize) -> io::Result<Option<Box<TlsSession>>> {
+        match &self.config.tls {
+            Some(tls) if tls.serves(&self.config.addresses[listener]) => {
+                tls.accept().map(|session| Some(Box::new(session)))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn handle_writable(&mut self, idx: usize, token: Token) -> io::Result<()> {
         let conn = match self.conns.get_mut(idx) {
             Some(conn) => conn,
             None => return Ok(()),
         };
+        conn.body_waiting = false;
 
-        while !conn.write_buffer.is_empty() {
-            let buf = &conn.write_buffer[..];
-
-            match conn.stream.write(buf) {
+        loop {
+            // Records a TLS session already holds go out before anything new.
+            match conn.flush_tls() {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    if !is_disconnect(&e) {
+                        log::error_in(
+                            conn.request_id.as_deref(),
+                            format_args!("write error from {}: {}", conn.peer, e),
+                        );
+                    }
+                    self.close_connection(idx);
+                    return Ok(());
+                }
+            }
+            // Top up from the pending body until the queue reaches the
+            // high-water mark; a throttled connection only drains.
+            if conn.body.is_some() && !conn.throttled && !conn.body_waiting {
+                let queue_empty = conn.write_queue.is_empty();
+                if queue_empty {
+                    conn.head_flushed();
+                }
+                #[cfg(target_os = "linux")]
+                let can_sendfile = conn.can_sendfile();
+                let Some(body) = conn.body.as_mut() else {
+                    break;
+                };
+                // Files skip the write queue once it is empty: the kernel
+                // copies them to the socket.
+                #[cfg(target_os = "linux")]
+                if queue_empty
+                    && can_sendfile
+                    && let Body::File(file) = body
+                    && file.uses_sendfile()
+                {
+                    match file.send_to(conn.stream.as_raw_fd()) {
+                        Ok(0) => {
+                            conn.body = None;
+                            break;
+                        }
+                        Ok(n) => {
+                            self.config.stats.record_written(n);
+                            conn.sent(n);
+                            continue;
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        // Falls back to reading chunks on the next pass.
+                        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => continue,
+                        Err(e) => {
+                            if !is_disconnect(&e) {
+                                log::error_in(
+                                    conn.request_id.as_deref(),
+                                    format_args!("sendfile error from {}: {}", conn.peer, e),
+                                );
+                            }
+                            self.close_connection(idx);
+                            return Ok(());
+                        }
+                    }
+                }
+                let next = match body {
+                    Body::Stream(stream) => stream.next_wire_chunk(),
+                    Body::File(file) => file.next_chunk(),
+                    Body::Text(_) | Body::Binary(_) => Ok(None),
+                };
+                match next {
+                    Ok(Some(chunk)) => {
+                        conn.write_queue.push(chunk);
+                        conn.update_throttle();
+                    }
+                    Ok(None) => conn.body = None,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        conn.body_waiting = true;
+                    }
+                    Err(e) => {
+                        // The head is already sent, so the only way to signal
+                        // the failure is to cut the response short.
+                        log::error_in(
+                            conn.request_id.as_deref(),
+                            format_args!("body stream error from {}: {}", conn.peer, e),
+                        );
+                        self.close_connection(idx);
+                        return Ok(());
+                    }
+                }
+            }
+            if conn.write_queue.is_empty() {
+                if conn.body.is_none() || conn.body_waiting {
+                    break;
+                }
+                continue;
+            }
+            match conn.write_queued() {
                 Ok(0) => {
-                    conn.state = State::Closed;
-                    self.poll.registry().deregister(&mut conn.stream)?;
-                    self.conns.remove(idx);
+                    self.close_connection(idx);
                     return Ok(());
                 }
                 Ok(n) => {
-                    self.poll.registry().reregister(
-                        &mut conn.stream,
-                        token,
-                        // Set to WRITABLE if we have queued bytes to send
-                        Interest::READABLE,
-                    )?;
-                    conn.write_buffer.drain(..n);
+                    self.config.stats.record_written(n);
+                    conn.sent(n);
+                    conn.update_throttle();
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     break;
                 }
                 Err(e) => {
-                    eprintln!("write error: {}", e);
-                    conn.state = State::Closed;
-                    self.poll.registry().deregister(&mut conn.stream)?;
-                    self.conns.remove(idx);
+                    if !is_disconnect(&e) {
+                        log::error_in(
+                            conn.request_id.as_deref(),
+                            format_args!("write error from {}: {}", conn.peer, e),
+                        );
+                    }
+                    self.close_connection(idx);
                     return Ok(());
                 }
             }
         }
+
+        // A request's time runs until the last byte of its response is out,
+        // and that is when it is logged.
+        if matches!(
+            conn.state,
+            State::WritingHeader | State::WritingBody | State::Closed
+        ) && !conn.output_pending()
+            && conn.body.is_none()
+        {
+            conn.request_id = None;
+            if let Some(parsed_at) = conn.request_parsed_at.take() {
+                self.config.stats.record_duration(parsed_at.elapsed());
+            }
+            if let Some(logger) = &self.access_log
+                && let Some(record) = conn.access.take()
+            {
+                logger.log(record);
+            }
+        }
+
+        if matches!(conn.state, State::Closed) && !conn.output_pending() {
+            return self.linger(idx, token);
+        }
+
+        // The response is fully flushed: close, or go back to waiting for
+        // the next request. Pipelined bytes stay in `read_buffer`.
+        if matches!(conn.state, State::WritingHeader | State::WritingBody)
+            && !conn.output_pending()
+            && conn.body.is_none()
+            && !conn.finish_response()
+        {
+            return self.linger(idx, token);
+        }
+
+        // A pipelined request may already be waiting in the read buffer.
+        self.start_next_request(idx, token)
+    }
+
+    /// Shuts down the write side of the connection at `idx`, whose last
+    /// response is fully sent, and lets it linger (see `State::Lingering`).
+    ///
+    /// Closing right away would reset the connection if the client has sent
+    /// anything we haven't read, such as a pipelined request, and a reset can
+    /// make the client's kernel discard response bytes it hasn't delivered
+    /// yet. Waiting for the client's EOF, or for `linger_timeout`, avoids that.
+    /// A peer that has already closed its side is closed at once.
+    fn linger(&mut self, idx: usize, token: Token) -> io::Result<()> {
+        let conn = &mut self.conns[idx];
+        // The session has nothing more to say after `close_notify`, and
+        // what arrives from here on is discarded unread.
+        #[cfg(feature = "tls")]
+        if let Some(mut tls) = conn.tls.take() {
+            tls.close(&mut conn.stream);
+        }
+        if conn.peer_closed || conn.stream.shutdown_write().is_err() {
+            self.close_connection(idx);
+            return Ok(());
+        }
+        conn.state = State::Lingering;
+        conn.last_activity = Instant::now();
+        conn.read_buffer.clear();
+        self.update_interest(idx, token);
         Ok(())
     }
 
+    /// Reads and drops whatever a lingering connection sends, and closes it
+    /// once the peer's EOF arrives.
+    fn discard_input(&mut self, idx: usize) {
+        let conn = &mut self.conns[idx];
+        let mut buf = [0u8; 4096];
+        loop {
+            match conn.stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        self.close_connection(idx);
+    }
+
+    /// Counts a response with `status` to the connection at `idx`, and notes
+    /// it for the access log. A response to no whole request (`400`, `408`,
+    /// `413`) starts its record here.
+    fn record_response(&mut self, idx: usize, status: StatusCode) {
+        self.config.stats.record_response(status);
+        if self.access_log.is_some() {
+            let conn = &mut self.conns[idx];
+            let peer = conn.peer.ip();
+            conn.access
+                .get_or_insert_with(|| AccessRecord::new(peer, None))
+                .status = Some(status);
+        }
+    }
+
+    /// Deregisters the connection at `idx` from `poll` and removes it from
+    /// the slab. Dropping the stream closes the socket.
+    ///
+    /// Every path that decides a connection is done ends here, so neither
+    /// the file descriptor nor the slab slot outlives it.
+    fn close_connection(&mut self, idx: usize) {
+        let Some(mut conn) = self.conns.try_remove(idx) else {
+            return;
+        };
+        self.generations[idx] = (self.generations[idx] + 1) % (MAX_GENERATION + 1);
+        self.config.stats.connection_closed();
+        if let Some(cancel) = &conn.cancel {
+            cancel.cancel();
+        }
+        // A response cut short is logged with what was sent of it.
+        if let Some(logger) = &self.access_log
+            && let Some(record) = conn.access.take()
+        {
+            logger.log(record);
+        }
+        if let Some(session) = conn.websocket.take()
+            && session.open
+        {
+            // With the pool full, the handler never hears of the close.
+            let _ = self.pool.try_execute(move || {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                    session
+                        .handler
+                        .on_close(&session.socket, session.close_code)
+                }));
+            });
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut conn.tls {
+            tls.close(&mut conn.stream);
+        }
+        if let Err(e) = self.poll.registry().deregister(&mut conn.stream) {
+            log::error(format_args!("deregister error: {}", e));
+        }
+        self.buffers.give(conn.read_buffer);
+    }
+
     fn handle_readable(&mut self, idx: usize, token: Token) -> io::Result<()> {
         let conn = match self.conns.get_mut(idx) {
             Some(conn) => conn,
             None => return Ok(()),
         };
+        if matches!(conn.state, State::Lingering) {
+            self.discard_input(idx);
+            return Ok(());
+        }
 
         let mut buf = [0u8; 4096];
 
         loop {
-            match conn.stream.read(&mut buf) {
+            match conn.read_some(&mut buf) {
+                // The peer is done sending. What it sent is still answered;
+                // `start_next_request` closes once nothing is left to do.
                 Ok(0) => {
-                    conn.state = State::Closed;
-                    self.poll.registry().deregister(&mut conn.stream)?;
-                    self.conns.remove(idx);
-                    return Ok(());
+                    conn.peer_closed = true;
+                    break;
                 }
                 Ok(n) => {
+                    self.config.stats.record_read(n);
                     conn.read_buffer.extend_from_slice(&buf[..n]);
+                    conn.last_activity = Instant::now();
+                    conn.request_started_at.get_or_insert(conn.last_activity);
+                    conn.request_bytes += n as u64;
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     break;
                 }
                 Err(e) => {
-                    eprintln!("read error: {}", e);
-                    conn.state = State::Closed;
-                    self.poll.registry().deregister(&mut conn.stream)?;
-                    self.conns.remove(idx);
+                    if !is_disconnect(&e) {
+                        log::error_in(
+                            conn.request_id.as_deref(),
+                            format_args!("read error from {}: {}", conn.peer, e),
+                        );
+                    }
+                    self.close_connection(idx);
                     return Ok(());
                 }
             }
         }
 
-        // TEMP TEST
-        if !conn.read_buffer.is_empty() && conn.write_buffer.is_empty() {
-            let body = b"Hello from mio\r\n";
-            let header = format!(
-                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain\r\n\r\n",
-                body.len()
+        // Send TLS handshake replies right away; what doesn't fit waits for
+        // `WRITABLE` (see `Connection::desired_interest`).
+        match conn.flush_tls() {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                if !is_disconnect(&e) {
+                    log::error_in(
+                        conn.request_id.as_deref(),
+                        format_args!("write error from {}: {}", conn.peer, e),
+                    );
+                }
+                self.close_connection(idx);
+                return Ok(());
+            }
+        }
+
+        self.start_next_request(idx, token)
+    }
+
+    /// Starts handling the next request in the connection's read buffer, then
+    /// sets the interest to match what the connection is waiting for (see
+    /// `Connection::desired_interest`).
+    ///
+    /// Nothing new is started while a response is still being built on the
+    /// pool or still being written. A websocket connection reads frames
+    /// instead (see `read_frames`).
+    fn start_next_request(&mut self, idx: usize, token: Token) -> io::Result<()> {
+        let conn = match self.conns.get_mut(idx) {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+        if matches!(conn.state, State::WebSocket) {
+            return self.read_frames(idx, token);
+        }
+
+        if conn.is_reading() {
+            match conn.take_request(&self.config) {
+                Ok(Some(mut request)) => {
+                    request.peer = conn.peer.ip();
+                    #[cfg(feature = "tls")]
+                    {
+                        request.secure = conn.tls.is_some();
+                    }
+                    if let Some(ids) = &self.config.request_id {
+                        ids.assign(&mut request);
+                    }
+                    conn.request_id = request.id.clone();
+                    conn.request_started_at = None;
+                    conn.request_bytes = 0;
+                    conn.request_parsed_at = Some(Instant::now());
+                    if self.access_log.is_some() {
+                        let client = request.client_address(
+                            &self.config.trusted_proxies,
+                            self.config.forwarded_header,
+                        );
+                        conn.access = Some(AccessRecord::new(client, Some(&request)));
+                    }
+                    conn.keep_alive = request.wants_keep_alive();
+                    let keep_alive = conn.keep_alive;
+                    if conn.redirect_to_https {
+                        return self.redirect_to_https(idx, token, &request);
+                    }
+                    // Probes and scrapes must get through however busy the pool is.
+                    let ready = self.drain_at.is_none() && self.drain_deadline.is_none();
+                    let probe = self.config.health.as_ref().and_then(|health| {
+                        let started = Instant::now();
+                        let response = health.probe(&request, ready)?;
+                        if health.log && log::enabled(LogLevel::Info) {
+                            let client = request
+                                .peer
+                                .map_or_else(|| String::from("-"), |ip| ip.to_string());
+                            log::info(format_args!(
+                                "{client} {} {} {} {:.1}ms",
+                                request.method.as_str(),
+                                request.target,
+                                response.status().as_u16(),
+                                started.elapsed().as_secs_f64() * 1000.0
+                            ));
+                        }
+                        Some(response)
+                    });
+                    if let Some(mut response) = probe.or_else(|| {
+                        let metrics = self.config.metrics.as_ref()?;
+                        metrics.serve(&request, &self.config.stats)
+                    }) {
+                        if let Some(ids) = &self.config.request_id {
+                            ids.echo(&request, &mut response);
+                        }
+                        if let Some(hsts) = &self.config.hsts {
+                            hsts.apply(&request, &mut response);
+                        }
+                        set_connection_header(&mut response, &request, keep_alive);
+                        self.record_response(idx, response.status);
+                        self.conns[idx].begin_response(response, request.method == Method::Head);
+                        return self.handle_writable(idx, token);
+                    }
+                    // Handling touches the filesystem, which can block; keep it off the event loop.
+                    conn.deadline = handling_deadline(&request, &self.config);
+                    let config = Arc::clone(&self.config);
+                    let reactor = self.handle.clone();
+                    // Work for a client that has gone is skipped, but only
+                    // reads: an upload that arrived in full is still stored.
+                    let cancel = matches!(request.method, Method::Get | Method::Head)
+                        .then(CancellationToken::new);
+                    conn.cancel = cancel.clone();
+                    let job_cancel = cancel.clone();
+                    let job = move || {
+                        // A handler that panics still owes the client an
+                        // answer; without one the connection would wait forever.
+                        let mut response = log::in_request(request.id.as_deref(), || {
+                            panic::catch_unwind(AssertUnwindSafe(|| handle(&request, &config)))
+                                .unwrap_or_else(|payload| {
+                                    log::error(format_args!(
+                                        "handler for {} panicked: {}",
+                                        request.path,
+                                        panic_message(&*payload)
+                                    ));
+                                    page_response(ErrorPage::InternalServerError, &request, &config)
+                                })
+                        });
+                        if job_cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+                            return;
+                        }
+                        if let Some(ids) = &config.request_id {
+                            ids.echo(&request, &mut response);
+                        }
+                        if let Some(hsts) = &config.hsts {
+                            hsts.apply(&request, &mut response);
+                        }
+                        let keep_alive = keep_alive && !ends_by_close(&response);
+                        set_connection_header(&mut response, &request, keep_alive);
+                        let upgrade =
+                            websocket_session(&request, &config, &response, token, &reactor);
+                        let msg = ReactorMsg::EnqueueResponse {
+                            token,
+                            response,
+                            head_only: request.method == Method::Head,
+                            upgrade,
+                        };
+                        // If the reactor has exited there's no one left to answer.
+                        if let Err(e) = reactor.send(msg)
+                            && e.kind() != io::ErrorKind::BrokenPipe
+                        {
+                            log::error(format_args!("waker error: {}", e));
+                        }
+                    };
+                    // With the pool's queue full, waiting in line would only
+                    // make the client wait longer; it is told to come back.
+                    let dispatched = match cancel {
+                        Some(cancel) => self.pool.try_execute_cancellable(cancel, job),
+                        None => self.pool.try_execute(job),
+                    };
+                    if dispatched.is_err() {
+                        self.record_response(idx, ErrorPage::ServiceUnavailable.status());
+                        self.conns[idx].fail(self.overload_response.clone());
+                        return self.handle_writable(idx, token);
+                    }
+                }
+                Ok(None) | Err(ParseError::Incomplete) => {}
+                // The rest of the stream can't be framed reliably after
+                // either error, so the connection closes once the error is sent.
+                // It goes out right away, like any response (see `enqueue_response`).
+                Err(ParseError::Malformed(reason)) => {
+                    let address = &self.config.addresses[conn.listener];
+                    log::error(format_args!(
+                        "malformed request from {} on {}: {}",
+                        conn.peer, address, reason
+                    ));
+                    self.record_response(idx, ErrorPage::BadRequest.status());
+                    self.conns[idx].fail(error_handler(ErrorPage::BadRequest, &self.config));
+                    return self.handle_writable(idx, token);
+                }
+                Err(ParseError::TooLarge) => {
+                    self.record_response(idx, ErrorPage::PayloadTooLarge.status());
+                    self.conns[idx].fail(error_handler(ErrorPage::PayloadTooLarge, &self.config));
+                    return self.handle_writable(idx, token);
+                }
+                Err(ParseError::TooManyHeaders | ParseError::HeadTooLarge) => {
+                    let page = ErrorPage::HeaderFieldsTooLarge;
+                    self.record_response(idx, page.status());
+                    self.conns[idx].fail(error_handler(page, &self.config));
+                    return self.handle_writable(idx, token);
+                }
+            }
+        }
+
+        // After a half-close, a connection with nothing in flight or queued
+        // is finished: any bytes left can never become a whole request.
+        if conn.peer_closed && conn.is_reading() {
+            self.close_connection(idx);
+            return Ok(());
+        }
+
+        self.update_interest(idx, token);
+        Ok(())
+    }
+
+    /// Answers `request`, on a listener that only redirects to HTTPS, with
+    /// the redirect (see `HttpsRedirectConfig::redirect`), or `400 Bad
+    /// Request` and a close if it names no usable host. A request that came
+    /// with a body closes the connection, too: the body was never read.
+    fn redirect_to_https(
+        &mut self,
+        idx: usize,
+        token: Token,
+        request: &HttpRequest,
+    ) -> io::Result<()> {
+        let redirect = self
+            .config
+            .redirect_to_https
+            .as_ref()
+            .and_then(|redirect| redirect.redirect(request));
+        let Some(mut response) = redirect else {
+            self.record_response(idx, ErrorPage::BadRequest.status());
+            self.conns[idx].fail(error_handler(ErrorPage::BadRequest, &self.config));
+            return self.handle_writable(idx, token);
+        };
+        if let Some(ids) = &self.config.request_id {
+            ids.echo(request, &mut response);
+        }
+        let body_left = body_framing(request).map_or(true, has_body);
+        let keep_alive = self.conns[idx].keep_alive && !body_left;
+        self.conns[idx].keep_alive = keep_alive;
+        set_connection_header(&mut response, request, keep_alive);
+        self.record_response(idx, response.status);
+        self.conns[idx].begin_response(response, request.method == Method::Head);
+        self.handle_writable(idx, token)
+    }
+
+    /// Reregisters the connection at `idx` with the interest its state calls
+    /// for (see `Connection::desired_interest`).
+    ///
+    /// If poll refuses, the connection would never get another event, so it
+    /// is closed; the other connections carry on.
+    fn update_interest(&mut self, idx: usize, token: Token) {
+        let conn = &mut self.conns[idx];
+        let want = conn.desired_interest();
+        if let Err(e) = conn.set_interest(&self.poll, token, want) {
+            log::error_in(
+                conn.request_id.as_deref(),
+                format_args!("reregister error for {}: {}", conn.peer, e),
             );
-            conn.write_buffer.extend_from_slice(header.as_bytes());
-            conn.write_buffer.extend_from_slice(body);
+            self.close_connection(idx);
         }
+    }
 
-        self.poll.registry().reregister(
-            &mut conn.stream,
-            token,
-            Interest::WRITABLE,
-        )?;
+    /// Drains every queued `ReactorMsg`.
+    ///
+    /// A response whose connection has closed, or whose slab slot now belongs
+    /// to a different connection, is dropped. A wakeup with nothing queued
+    /// (a bare `wake()`, or messages already drained) does nothing.
+    fn handle_messages(&mut self) -> io::Result<()> {
+        while let Ok(msg) = self.messages.try_recv() {
+            match msg {
+                ReactorMsg::EnqueueResponse {
+                    token,
+                    response,
+                    head_only,
+                    upgrade,
+                } => self.enqueue_response(token, response, head_only, upgrade)?,
+                ReactorMsg::WebSocketSend {
+                    token,
+                    frame,
+                    close,
+                } => self.send_frame(token, frame, close)?,
+                ReactorMsg::WebSocketDone { token } => {
+                    if let Some(idx) = self.slot(token)
+                        && let Some(session) = &mut self.conns[idx].websocket
+                    {
+                        session.busy = false;
+                        if self.dispatch_websocket(idx, token) {
+                            self.handle_writable(idx, token)?;
+                        }
+                    }
+                }
+                ReactorMsg::BodyReady { token } => {
+                    if let Some(idx) = self.slot(token) {
+                        self.handle_writable(idx, token)?;
+                    }
+                }
+                ReactorMsg::AbortResponse { token } => {
+                    if let Some(idx) = self.slot(token) {
+                        self.close_connection(idx);
+                    }
+                }
+                ReactorMsg::Shutdown => self.request_shutdown()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues `response` on the connection at `token` if it is still open, and
+    /// starts writing it right away.
+    ///
+    /// The socket is almost always writable, so waiting for a `WRITABLE` event
+    /// would only cost a poll round trip; the connection registers for one
+    /// only if the socket fills up first.
+    ///
+    /// With `upgrade`, the connection carries that websocket session once the
+    /// response is sent. No websocket is opened while draining: the client
+    /// gets `503 Service Unavailable` instead.
+    fn enqueue_response(
+        &mut self,
+        token: Token,
+        mut response: HttpResponse,
+        head_only: bool,
+        upgrade: Option<Box<Session>>,
+    ) -> io::Result<()> {
+        let Some(idx) = self.slot(token) else {
+            return Ok(());
+        };
+        // Too late: the client was already told the request timed out.
+        if !self.conns[idx].in_flight() {
+            return Ok(());
+        }
+        self.conns[idx].cancel = None;
+        self.conns[idx].deadline = None;
+        if self.drain_deadline.is_some() {
+            if upgrade.is_some() {
+                self.record_response(idx, ErrorPage::ServiceUnavailable.status());
+                self.conns[idx].fail(self.overload_response.clone());
+                return self.handle_writable(idx, token);
+            }
+            response
+                .headers
+                .insert(headers::CONNECTION, HeaderValue::from_static("close"));
+        }
+        self.record_response(idx, response.status);
+        let conn = &mut self.conns[idx];
+        if ends_by_close(&response) {
+            conn.keep_alive = false;
+        }
+        conn.websocket = upgrade;
+        conn.begin_response(response, head_only);
+        if let Some(Body::Stream(stream)) = &mut conn.body {
+            stream.attach(BodyWaker::new(token, self.handle.clone()));
+        }
+        self.handle_writable(idx, token)
+    }
 
+    /// Asks every stream body that is waiting for its source again, so a
+    /// source can send something of its own accord now and then (such as an
+    /// event stream's keep-alive comments). Runs once per sweep.
+    fn poll_waiting_bodies(&mut self) -> io::Result<()> {
+        let waiting: Vec<usize> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| conn.body_waiting)
+            .map(|(idx, _)| idx)
+            .collect();
+        for idx in waiting {
+            self.handle_writable(idx, connection_token(idx, self.generations[idx]))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the frames in the read buffer of the websocket connection at
+    /// `idx`: pings get a pong, a close frame gets one back and closes the
+    /// connection, and whole messages are queued for the handler (see
+    /// `dispatch_websocket`). A frame that breaks the protocol or the size
+    /// limit closes the connection with the matching close code. Anything
+    /// queued for the client goes out right away.
+    ///
+    /// A peer that closes its side without a close frame is closed at once.
+    fn read_frames(&mut self, idx: usize, token: Token) -> io::Result<()> {
+        let conn = &mut self.conns[idx];
+        let mut wrote = false;
+        if let Some(session) = &mut conn.websocket {
+            while matches!(conn.state, State::WebSocket) && !conn.throttled {
+                let reply = match session.reader.next(&mut conn.read_buffer) {
+                    Ok(None) => break,
+                    Ok(Some(Incoming::Message(message))) => {
+                        session.inbox.push_back(Event::Message(message));
+                        continue;
+                    }
+                    Ok(Some(Incoming::Pong)) => continue,
+                    Ok(Some(Incoming::Ping(payload))) => websocket::pong_frame(&payload),
+                    Ok(Some(Incoming::Close(code))) => {
+                        session.close_code = code;
+                        conn.state = State::Closed;
+                        websocket::close_frame(code.unwrap_or(websocket::CLOSE_NORMAL), "")
+                    }
+                    Err(code) => {
+                        conn.state = State::Closed;
+                        websocket::close_frame(code, "")
+                    }
+                };
+                conn.write_queue.push(reply);
+                wrote = true;
+            }
+        }
+        conn.update_throttle();
+        if conn.peer_closed && matches!(conn.state, State::WebSocket) {
+            self.close_connection(idx);
+            return Ok(());
+        }
+        if self.dispatch_websocket(idx, token) || wrote {
+            return self.handle_writable(idx, token);
+        }
+        self.update_interest(idx, token);
         Ok(())
     }
+
+    /// Hands the next event waiting for the handler of the websocket
+    /// connection at `idx` to the pool, unless one is still running there;
+    /// `WebSocketDone` comes back when it has finished.
+    ///
+    /// # Returns
+    /// `true` if the pool's queue was full: the connection is then closed
+    /// with `CLOSE_TRY_AGAIN_LATER`, and the close frame is queued but not sent.
+    fn dispatch_websocket(&mut self, idx: usize, token: Token) -> bool {
+        let conn = &mut self.conns[idx];
+        let Some(session) = &mut conn.websocket else {
+            return false;
+        };
+        if session.busy {
+            return false;
+        }
+        let Some(event) = session.inbox.pop_front() else {
+            return false;
+        };
+        session.busy = true;
+        let handler = Arc::clone(&session.handler);
+        let socket = session.socket.clone();
+        let reactor = self.handle.clone();
+        let job = move || {
+            let delivered = panic::catch_unwind(AssertUnwindSafe(|| {
+                websocket::deliver(handler.as_ref(), &socket, event)
+            }));
+            if delivered.is_err() {
+                let _ = socket.close(websocket::CLOSE_INTERNAL_ERROR, "");
+            }
+            if let Err(e) = reactor.send(ReactorMsg::WebSocketDone { token })
+                && e.kind() != io::ErrorKind::BrokenPipe
+            {
+                log::error(format_args!("waker error: {}", e));
+            }
+        };
+        if self.pool.try_execute(job).is_ok() {
+            return false;
+        }
+        if matches!(conn.state, State::WebSocket) {
+            conn.write_queue
+                .push(websocket::close_frame(websocket::CLOSE_TRY_AGAIN_LATER, ""));
+            conn.state = State::Closed;
+        }
+        true
+    }
+
+    /// Queues `frame` on the websocket connection at `token`, if it is still
+    /// open, and starts writing it. With `close`, the connection closes once
+    /// the frame is out.
+    fn send_frame(&mut self, token: Token, frame: Vec<u8>, close: bool) -> io::Result<()> {
+        let Some(idx) = self.slot(token) else {
+            return Ok(());
+        };
+        let conn = &mut self.conns[idx];
+        if !matches!(conn.state, State::WebSocket) {
+            return Ok(());
+        }
+        conn.write_queue.push(frame);
+        conn.update_throttle();
+        conn.last_activity = Instant::now();
+        if close {
+            conn.state = State::Closed;
+        }
+        self.handle_writable(idx, token)
+    }
+}
+
+/// Returns `true` for errors that only mean the peer has gone away, which
+/// are routine and not worth logging.
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+    )
 }
 
-pub fn run(addr: &str) -> io::Result<()> {
-    let mut reactor = Reactor::new(addr)?;
-    reactor.event_loop()?;
+/// Returns `true` if `error` means the process (`EMFILE`) or the whole system
+/// (`ENFILE`) has no file descriptors left.
+#[cfg(unix)]
+fn is_fd_shortage(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
 
+#[cfg(not(unix))]
+fn is_fd_shortage(_error: &io::Error) -> bool {
+    false
+}
+
+/// Shrinks an empty `buffer` back to `MAX_RETAINED_CAPACITY` if a big request
+/// grew it past that. A buffer still holding bytes is left alone.
+fn trim_buffer(buffer: &mut Vec<u8>) {
+    if buffer.is_empty() && buffer.capacity() > MAX_RETAINED_CAPACITY {
+        buffer.shrink_to(MAX_RETAINED_CAPACITY);
+    }
+}
+
+/// Returns when handling `request` on the pool has to be done by, and the
+/// error page the client gets if it isn't: `504 Gateway Timeout` on a path
+/// its site proxies and no route answers, `503 Service Unavailable` otherwise.
+/// `None` if its site's `handler_timeout` is off and no `Router::timeout`
+/// covers the path.
+fn handling_deadline(request: &HttpRequest, config: &ServerConfig) -> Option<(Instant, ErrorPage)> {
+    let site = config.site_for(request).unwrap_or(config);
+    let timeout = site
+        .router
+        .timeout_for(&request.path)
+        .or(site.handler_timeout)?;
+    let proxied = site.proxy_for(&request.path).is_some()
+        && matches!(site.router.lookup(request), RouteMatch::NoRoute);
+    let page = if proxied {
+        ErrorPage::GatewayTimeout
+    } else {
+        ErrorPage::ServiceUnavailable
+    };
+    Some((Instant::now() + timeout, page))
+}
+
+/// Tells the client whether the connection stays open after `response`.
+///
+/// Only the cases that differ from the protocol default are announced:
+/// `close` on an HTTP/1.1 connection, `keep-alive` on an HTTP/1.0 one.
+fn set_connection_header(response: &mut HttpResponse, request: &HttpRequest, keep_alive: bool) {
+    if !keep_alive {
+        response
+            .headers
+            .insert(headers::CONNECTION, HeaderValue::from_static("close"));
+    } else if request.version == "HTTP/1.0" {
+        response
+            .headers
+            .insert(headers::CONNECTION, HeaderValue::from_static("keep-alive"));
+    }
+}
+
+/// Returns `true` if a request framed by `framing` has a body to read.
+fn has_body(framing: BodyFraming) -> bool {
+    !matches!(framing, BodyFraming::None | BodyFraming::Length(0))
+}
+
+/// Returns `true` if `response` has a body that ends by closing the
+/// connection (see `StreamBody::until_close`).
+fn ends_by_close(response: &HttpResponse) -> bool {
+    matches!(&response.body, Body::Stream(stream) if stream.is_until_close())
+}
+
+/// Returns the session the `101` `response` opens, if `request` was for one
+/// of its site's websocket routes.
+fn websocket_session(
+    request: &HttpRequest,
+    config: &ServerConfig,
+    response: &HttpResponse,
+    token: Token,
+    reactor: &ReactorHandle,
+) -> Option<Box<Session>> {
+    if response.status != StatusCode::SWITCHING_PROTOCOLS {
+        return None;
+    }
+    let site = config.site_for(request).ok()?;
+    match site.router.lookup(request) {
+        RouteMatch::WebSocket(handler) => Some(Box::new(Session::new(
+            Arc::clone(handler),
+            token,
+            reactor.clone(),
+            request.path.clone(),
+            &site.websocket,
+        ))),
+        _ => None,
+    }
+}
+
+/// Does the startup work that happens once per process, however many
+/// reactors share `config`: loads the TLS certificate and prepares the
+/// default site and every virtual host (see `prepare_site`).
+///
+/// # Returns
+/// The watchers that were started. Dropping one stops its thread.
+fn prepare(config: &mut ServerConfig, pool: &ThreadPool) -> Result<Vec<Watcher>, ServerError> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &mut config.tls {
+        tls.load()?;
+    }
+    let mut watchers = Vec::new();
+    prepare_site(config, pool, &mut watchers)?;
+    for vhost in &mut config.virtual_hosts {
+        prepare_site(Arc::make_mut(&mut vhost.config), pool, &mut watchers)?;
+    }
+    Ok(watchers)
+}
+
+/// Resolves the paths of one site, queues the precompression pass of its
+/// document root and mounts on `pool` and starts their file watchers, if it
+/// has them, adding them to `watchers`.
+fn prepare_site(
+    config: &mut ServerConfig,
+    pool: &ThreadPool,
+    watchers: &mut Vec<Watcher>,
+) -> Result<(), ServerError> {
+    if config.assets == Assets::Filesystem {
+        config.resolve_document_root()?;
+    }
+    config.resolve_mounts()?;
+    if let Some(upload) = &mut config.upload {
+        upload.resolve_root()?;
+    }
+    if let Some(cgi) = &mut config.cgi {
+        cgi.resolve_root()?;
+    }
+    // Mounts are always on disk, whatever the document root is served from.
+    let roots = (config.assets == Assets::Filesystem)
+        .then_some(&config.document_root)
+        .into_iter()
+        .chain(config.mounts.iter().map(|mount| &mount.directory));
+    for root in roots {
+        if let Some(settings) = &config.precompress {
+            precompress(root, settings, pool);
+        }
+        if let (Some(cache), Some(interval)) = (&config.file_cache, config.watch_interval) {
+            watchers.push(Watcher::spawn(
+                &root.to_string_lossy(),
+                interval,
+                Arc::clone(cache),
+            ));
+        }
+    }
     Ok(())
 }
+
+/// Binds a listener for each of `config.addresses`, in order, for each of
+/// `reactors` reactors.
+///
+/// TCP addresses get one `SO_REUSEPORT` listener per reactor (just one listener
+/// if there is a single reactor). A Unix socket path can only be bound once, so
+/// its reactors share clones of the same listener and take turns accepting.
+///
+/// # Returns
+/// One list of listeners per reactor, each in `config.addresses` order.
+///
+/// # Errors
+/// `ServerError::InvalidAddress` or `ServerError::Bind` for the first address
+/// that doesn't parse or can't be bound, and `ServerError::Config` if there are
+/// more than `MAX_LISTENERS` addresses (or none).
+fn bind_all(config: &ServerConfig, reactors: usize) -> Result<Vec<Vec<Listener>>, ServerError> {
+    if config.addresses.is_empty() || config.addresses.len() > MAX_LISTENERS {
+        return Err(ServerError::Config(format!(
+            "between 1 and {MAX_LISTENERS} bind addresses are supported"
+        )));
+    }
+    let mut per_reactor: Vec<Vec<Listener>> = (0..reactors).map(|_| Vec::new()).collect();
+    for address in &config.addresses {
+        let named = |source| ServerError::Bind {
+            address: address.clone(),
+            source,
+        };
+        match BindAddress::parse(address)? {
+            BindAddress::Tcp(mut addr) => {
+                for listeners in &mut per_reactor {
+                    let listener = bind(addr, &config.socket, reactors > 1).map_err(named)?;
+                    // For port 0 the first bind picks the port; the other
+                    // reactors take the same one.
+                    addr = listener.local_addr().map_err(named)?;
+                    listeners.push(Listener::Tcp(listener));
+                }
+            }
+            #[cfg(unix)]
+            BindAddress::Unix(path) => {
+                let listener = bind_unix(&path, &config.socket).map_err(named)?;
+                for listeners in &mut per_reactor {
+                    let clone = listener.try_clone().map_err(named)?;
+                    listeners.push(Listener::Unix(mio::net::UnixListener::from_std(clone)));
+                }
+            }
+        }
+    }
+    Ok(per_reactor)
+}
+
+/// Starts the thread pool a reactor hands requests to, with
+/// `config.reactor.workers` threads.
+///
+/// # Errors
+/// `ServerError::Config` for zero workers, and `ServerError::Io` if a thread
+/// can't be started.
+fn worker_pool(config: &ServerConfig) -> Result<ThreadPool, ServerError> {
+    let workers = config.reactor.workers;
+    if workers == 0 {
+        return Err(ServerError::Config(String::from(
+            "reactor.workers must be at least 1",
+        )));
+    }
+    if workers > MAX_SENSIBLE_WORKERS {
+        log::warn(format_args!(
+            "starting {workers} worker threads per reactor; is that intended?"
+        ));
+    }
+    Ok(ThreadPool::builder()
+        .size(workers)
+        .queue_depth(config.reactor.job_queue_depth)
+        .build()?)
+}
+
+/// A server bound to its addresses, ready to serve.
+///
+/// With `config.reactors` above one (on Unix), every reactor runs on its own
+/// thread with its own `SO_REUSEPORT` listeners, slab and thread pool, so the
+/// kernel spreads connections across them (see `io::listener::bind`). Every
+/// reactor handles SIGINT/SIGTERM itself, so a signal drains them all.
+///
+/// # Example
+/// ```
+/// let mut config = ServerConfig::default();
+/// config.addresses = vec![String::from("127.0.0.1:0")];
+/// let running = Server::start(config)?;
+/// let address = running.local_addr().unwrap();
+/// // ... connect to `address` ...
+/// running.shutdown();
+/// running.join()?;
+/// ```
+pub struct Server {
+    config: Arc<ServerConfig>,
+    // One list of listeners, and one pool, per reactor.
+    listeners: Vec<Vec<Listener>>,
+    pools: Vec<ThreadPool>,
+    watchers: Vec<Watcher>,
+    access_log: Option<AccessLog>,
+}
+
+impl Server {
+    /// Sets the process-wide log level from `config.log_level`, checks and
+    /// completes `config` (document root, TLS certificates, precompression;
+    /// see `prepare`), starts the thread pools and binds every address.
+    ///
+    /// # Errors
+    /// Any `ServerError`: every address is bound up front, so a taken one
+    /// fails here rather than once serving has begun.
+    pub fn bind(mut config: ServerConfig) -> Result<Server, ServerError> {
+        log::set_level(config.log_level);
+        log::set_format(config.log_format);
+        if config.log_format == LogFormat::Json {
+            log::report_panics();
+        }
+        // Without `SO_REUSEPORT`, reactors can't share an address.
+        let reactors = if cfg!(unix) {
+            config.reactors.max(1)
+        } else {
+            1
+        };
+        let pools = (0..reactors)
+            .map(|_| worker_pool(&config))
+            .collect::<Result<Vec<_>, _>>()?;
+        let watchers = prepare(&mut config, &pools[0])?;
+        for pool in &pools {
+            config.stats.watch_pool(pool.monitor());
+        }
+        let listeners = bind_all(&config, reactors)?;
+        let access_log = config
+            .access_log
+            .as_ref()
+            .map(AccessLog::start)
+            .transpose()?;
+        Ok(Server {
+            config: Arc::new(config),
+            listeners,
+            pools,
+            watchers,
+            access_log,
+        })
+    }
+
+    /// Binds `config` and starts serving in the background: shorthand for
+    /// `Server::bind(config)?.spawn()`.
+    ///
+    /// # Errors
+    /// As for `bind` and `spawn`.
+    pub fn start(config: ServerConfig) -> Result<ServerHandle, ServerError> {
+        Server::bind(config)?.spawn()
+    }
+
+    /// Returns the address the first TCP listener is bound to, which tells
+    /// the port picked for an address with port 0. `None` if every address
+    /// is a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners
+            .first()?
+            .iter()
+            .find_map(|listener| match listener {
+                Listener::Tcp(listener) => listener.local_addr().ok(),
+                #[cfg(unix)]
+                Listener::Unix(_) => None,
+            })
+    }
+
+    /// Serves until shutdown. A single reactor runs on the calling thread.
+    ///
+    /// # Errors
+    /// The first reactor error; the other reactors are shut down too.
+    pub fn run(self) -> Result<(), ServerError> {
+        if self.listeners.len() > 1 {
+            return self.spawn()?.join();
+        }
+        let Server {
+            config,
+            mut listeners,
+            mut pools,
+            watchers: _watchers,
+            access_log,
+        } = self;
+        let (Some(listeners), Some(pool)) = (listeners.pop(), pools.pop()) else {
+            return Ok(());
+        };
+        let logger = access_log.as_ref().and_then(AccessLog::logger);
+        let mut reactor = Reactor::new(listeners, config, pool, logger)?;
+        reactor.event_loop()?;
+        Ok(())
+    }
+
+    /// Starts serving on a thread per reactor, and returns once they are
+    /// all running (or have failed to start, which `join` reports).
+    ///
+    /// # Errors
+    /// `ServerError::Io` if a thread can't be started.
+    pub fn spawn(self) -> Result<ServerHandle, ServerError> {
+        let local_addr = self.local_addr();
+        let Server {
+            config,
+            listeners,
+            pools,
+            watchers,
+            access_log,
+        } = self;
+        let (started, handles) = mpsc::channel();
+        let (exited, exits) = mpsc::channel();
+        let mut threads = Vec::with_capacity(listeners.len());
+        for (i, (listener, pool)) in listeners.into_iter().zip(pools).enumerate() {
+            let config = Arc::clone(&config);
+            let started = started.clone();
+            let exited = exited.clone();
+            let logger = access_log.as_ref().and_then(AccessLog::logger);
+            threads.push(
+                thread::Builder::new()
+                    .name(format!("reactor-{i}"))
+                    .spawn(move || {
+                        let result =
+                            Reactor::new(listener, config, pool, logger).and_then(|mut reactor| {
+                                let _ = started.send(reactor.handle.clone());
+                                // Lets `spawn` stop waiting for handles once every reactor is up.
+                                drop(started);
+                                Ok(reactor.event_loop()?)
+                            });
+                        let _ = exited.send(result);
+                    })?,
+            );
+        }
+        drop((started, exited));
+
+        Ok(ServerHandle {
+            local_addr,
+            reactors: handles.iter().collect(),
+            exits,
+            threads,
+            _watchers: watchers,
+            _access_log: access_log,
+        })
+    }
+}
+
+/// A server started with `Server::spawn`. Dropping the handle shuts the
+/// server down and waits for it, as `shutdown` then `join` would.
+pub struct ServerHandle {
+    local_addr: Option<SocketAddr>,
+    reactors: Vec<ReactorHandle>,
+    exits: mpsc::Receiver<Result<(), ServerError>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    // Watch the document roots for as long as the server runs.
+    _watchers: Vec<Watcher>,
+    // Dropped after the reactors stop, so every response gets written.
+    _access_log: Option<AccessLog>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server listens on; see `Server::local_addr`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Starts a graceful shutdown of every reactor, exactly as on SIGTERM.
+    /// Returns right away; `join` waits for it to finish.
+    pub fn shutdown(&self) {
+        for handle in &self.reactors {
+            let _ = handle.send(ReactorMsg::Shutdown);
+        }
+    }
+
+    /// Waits for every reactor to stop.
+    ///
+    /// # Errors
+    /// The first reactor error. The other reactors are shut down when it
+    /// happens, and it is returned once all have stopped.
+    pub fn join(mut self) -> Result<(), ServerError> {
+        let mut first_error = None;
+        // `recv` fails once every reactor thread has exited (or panicked).
+        while let Ok(result) = self.exits.recv() {
+            if let Err(e) = result
+                && first_error.is_none()
+            {
+                log::error(format_args!(
+                    "reactor error: {}; shutting down the others",
+                    e
+                ));
+                self.shutdown();
+                first_error = Some(e);
+            }
+        }
+        for thread in std::mem::take(&mut self.threads) {
+            let _ = thread.join();
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+impl Drop for ServerHandle {
+    /// Shuts down and waits, unless `join` already has.
+    fn drop(&mut self) {
+        if self.threads.is_empty() {
+            return;
+        }
+        self.shutdown();
+        for thread in std::mem::take(&mut self.threads) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a fresh keep-alive connection over a real (idle) socket.
+    fn connection() -> Connection {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let stream = std::net::TcpStream::connect(address).unwrap();
+        Connection {
+            stream: Stream::Tcp(mio::net::TcpStream::from_std(stream)),
+            read_buffer: Vec::new(),
+            head_scanned: 0,
+            chunked: None,
+            write_queue: WriteQueue::default(),
+            state: State::ReadingHeader,
+            keep_alive: true,
+            body: None,
+            body_waiting: false,
+            last_activity: Instant::now(),
+            request_started_at: None,
+            request_bytes: 0,
+            request_parsed_at: None,
+            access: None,
+            request_id: None,
+            current_interest: Interest::READABLE,
+            peer_closed: false,
+            listener: 0,
+            peer: Peer::Tcp(address),
+            redirect_to_https: false,
+            throttled: false,
+            cancel: None,
+            deadline: None,
+            websocket: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    fn ok() -> HttpResponse {
+        HttpResponse::text(StatusCode::OK, "ok")
+    }
+
+    #[test]
+    fn a_request_is_read_head_then_body() {
+        let config = ServerConfig::default();
+        let mut conn = connection();
+        conn.read_buffer
+            .extend_from_slice(b"POST /form HTTP/1.1\r\nContent-Length: 5\r\n\r\nhe");
+        assert!(conn.take_request(&config).unwrap().is_none());
+        assert!(matches!(conn.state, State::ReadingBody));
+
+        conn.read_buffer.extend_from_slice(b"llo");
+        let request = conn.take_request(&config).unwrap().unwrap();
+        assert_eq!(request.body, b"hello");
+        assert!(matches!(conn.state, State::ReadyToRespond));
+        assert!(conn.read_buffer.is_empty());
+    }
+
+    #[test]
+    fn a_request_without_a_body_is_ready_once_its_head_is_there() {
+        let config = ServerConfig::default();
+        let mut conn = connection();
+        conn.read_buffer.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        assert!(conn.take_request(&config).unwrap().is_none());
+        assert!(matches!(conn.state, State::ReadingHeader));
+
+        conn.read_buffer.extend_from_slice(b"\r\n");
+        assert!(conn.take_request(&config).unwrap().is_some());
+        assert!(matches!(conn.state, State::ReadyToRespond));
+    }
+
+    #[test]
+    fn a_response_is_written_then_the_connection_reads_again() {
+        let mut conn = connection();
+        conn.state = State::ReadyToRespond;
+        conn.begin_response(ok(), false);
+        assert!(matches!(conn.state, State::WritingHeader));
+        assert!(!conn.write_queue.is_empty());
+
+        conn.head_flushed();
+        assert!(matches!(conn.state, State::WritingBody));
+
+        assert!(conn.finish_response());
+        assert!(matches!(conn.state, State::ReadingHeader));
+    }
+
+    #[test]
+    fn a_connection_without_keep_alive_closes_after_its_response() {
+        let mut conn = connection();
+        conn.keep_alive = false;
+        conn.state = State::ReadyToRespond;
+        conn.begin_response(ok(), false);
+        assert!(!conn.finish_response());
+        assert!(matches!(conn.state, State::Closed));
+    }
+
+    #[test]
+    fn failing_closes_from_any_state() {
+        for state in [
+            State::ReadingHeader,
+            State::ReadingBody,
+            State::ReadyToRespond,
+            State::WritingBody,
+        ] {
+            let mut conn = connection();
+            conn.read_buffer.extend_from_slice(b"GET / HTTP/1.1\r\n");
+            conn.write_queue.push(b"half a response".to_vec());
+            conn.state = state;
+            conn.fail(b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec());
+            assert!(matches!(conn.state, State::Closed));
+            assert!(conn.read_buffer.is_empty());
+            assert_eq!(
+                conn.write_queue.len(),
+                b"HTTP/1.1 400 Bad Request\r\n\r\n".len()
+            );
+        }
+    }
+
+    #[test]
+    fn no_request_is_taken_while_a_response_is_awaited_or_written() {
+        let config = ServerConfig::default();
+        for state in [State::ReadyToRespond, State::WritingHeader, State::Closed] {
+            let mut conn = connection();
+            conn.read_buffer
+                .extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+            conn.state = state;
+            assert!(conn.take_request(&config).unwrap().is_none());
+            assert_eq!(conn.read_buffer, b"GET / HTTP/1.1\r\n\r\n");
+            assert!(!conn.is_reading());
+        }
+    }
+
+    #[test]
+    fn a_response_is_only_begun_for_a_complete_request() {
+        for state in [
+            State::ReadingHeader,
+            State::ReadingBody,
+            State::WritingBody,
+            State::Closed,
+        ] {
+            let mut conn = connection();
+            conn.state = state;
+            conn.begin_response(ok(), false);
+            assert!(conn.write_queue.is_empty());
+            assert!(!matches!(conn.state, State::WritingHeader));
+        }
+    }
+
+    #[test]
+    fn only_a_response_being_written_is_flushed_or_finished() {
+        let mut conn = connection();
+        conn.head_flushed();
+        assert!(matches!(conn.state, State::ReadingHeader));
+
+        conn.state = State::ReadyToRespond;
+        conn.head_flushed();
+        assert!(!conn.finish_response());
+        assert!(matches!(conn.state, State::ReadyToRespond));
+
+        conn.state = State::Closed;
+        assert!(!conn.finish_response());
+        assert!(matches!(conn.state, State::Closed));
+    }
+}
@@ -0,0 +1,263 @@
+//! Startup precompression of static assets.
+//!
+//! When enabled, the server walks the document root once before it starts
+//! listening and writes a compressed sibling next to every compressible file
+//! (`app.js` → `app.js.gz`, and `app.js.br` with the `brotli` feature). The
+//! work is spread over the thread pool. A sibling that is already at least as
+//! new as its source is left alone, so restarts only redo files that changed.
+use crate::io::file::write_file_atomic;
+use crate::log;
+use crate::thread_pool::ThreadPool;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Precompression settings.
+///
+/// # Fields
+/// - `min_size` (*u64*): Files smaller than this many bytes are skipped; the
+///   saving wouldn't be worth a second file. Defaults to 1 KiB.
+/// - `extensions` (*Vec<String>*): File extensions (without the dot) that are
+///   compressed. Defaults to common text formats plus `svg` and `wasm`; images,
+///   video and archives are already compressed and gain nothing.
+/// - `brotli` (*bool*): Also write `.br` siblings. Only available with the
+///   `brotli` feature. Defaults to `true`.
+#[derive(Debug, Clone)]
+pub struct PrecompressConfig {
+    pub min_size: u64,
+    pub extensions: Vec<String>,
+    #[cfg(feature = "brotli")]
+    pub brotli: bool,
+}
+
+impl Default for PrecompressConfig {
+    fn default() -> PrecompressConfig {
+        let extensions = [
+            "html", "htm", "css", "js", "mjs", "json", "map", "svg", "txt", "xml", "wasm",
+        ];
+        PrecompressConfig {
+            min_size: 1024,
+            extensions: extensions.iter().map(|ext| ext.to_string()).collect(),
+            #[cfg(feature = "brotli")]
+            brotli: true,
+        }
+    }
+}
+
+impl PrecompressConfig {
+    /// Returns `true` if the file at `path` should get compressed siblings.
+    fn wants(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy();
+            self.extensions
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(&ext))
+        })
+    }
+}
+
+/// What happened to one sibling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Written,
+    UpToDate,
+    NotSmaller,
+    Failed,
+}
+
+/// Counts of what a precompression pass did, one per sibling.
+///
+/// # Fields
+/// - `written` (*usize*): Siblings created or refreshed.
+/// - `up_to_date` (*usize*): Siblings that were already newer than their source.
+/// - `not_smaller` (*usize*): Files that didn't shrink, so no sibling was written.
+/// - `failed` (*usize*): Files that couldn't be read or written (each is logged).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub written: usize,
+    pub up_to_date: usize,
+    pub not_smaller: usize,
+    pub failed: usize,
+}
+
+/// Compresses every eligible file under `root`, spreading the work over `pool`.
+///
+/// Returns once every file has been handled and prints a one-line summary.
+/// Failures are logged and counted, never fatal: a file without a sibling is
+/// still served uncompressed.
+///
+/// # Example
+/// ```
+/// let pool = ThreadPool::new(4);
+/// let summary = precompress(Path::new("/srv/www"), &PrecompressConfig::default(), &pool);
+/// assert_eq!(summary.failed, 0);
+/// ```
+pub fn precompress(root: &Path, config: &PrecompressConfig, pool: &ThreadPool) -> Summary {
+    let config = Arc::new(config.clone());
+    let per_file = encoders(&config).len();
+    let jobs: Vec<_> = collect(root, &config)
+        .into_iter()
+        .map(|path| {
+            let config = Arc::clone(&config);
+            pool.execute_with_result(move || compress_file(&path, &config))
+        })
+        .collect();
+
+    let mut summary = Summary::default();
+    for job in jobs {
+        // A job that panicked counts as a failure for each of its encodings.
+        let outcomes = job
+            .wait()
+            .unwrap_or_else(|_| vec![Outcome::Failed; per_file]);
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Written => summary.written += 1,
+                Outcome::UpToDate => summary.up_to_date += 1,
+                Outcome::NotSmaller => summary.not_smaller += 1,
+                Outcome::Failed => summary.failed += 1,
+            }
+        }
+    }
+    log::info(format_args!(
+        "Precompressed {}: {} written, {} up to date, {} not smaller, {} failed",
+        root.display(),
+        summary.written,
+        summary.up_to_date,
+        summary.not_smaller,
+        summary.failed
+    ));
+    summary
+}
+
+/// Returns every file below `root` that `config` wants compressed and that
+/// is at least `config.min_size` bytes.
+///
+/// Hidden entries are skipped since they are never served, and symlinked
+/// directories aren't descended into so a link cycle can't loop forever.
+fn collect(root: &Path, config: &PrecompressConfig) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error(format_args!(
+                    "Error listing {} for precompression: {}",
+                    dir.display(),
+                    e
+                ));
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => pending.push(path),
+                Ok(_)
+                    if config.wants(&path)
+                        && fs::metadata(&path)
+                            .is_ok_and(|meta| meta.is_file() && meta.len() >= config.min_size) =>
+                {
+                    files.push(path)
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// A sibling format: its file suffix and how to produce it.
+type Encoder = (&'static str, fn(&[u8]) -> io::Result<Vec<u8>>);
+
+/// Returns the sibling formats enabled by `config`.
+fn encoders(config: &PrecompressConfig) -> Vec<Encoder> {
+    let gzip: Encoder = ("gz", gzip);
+    #[cfg(feature = "brotli")]
+    if config.brotli {
+        return vec![gzip, ("br", brotli)];
+    }
+    #[cfg(not(feature = "brotli"))]
+    let _ = config;
+    vec![gzip]
+}
+
+/// Writes the siblings of `path`, returning one outcome per sibling format.
+fn compress_file(path: &Path, config: &PrecompressConfig) -> Vec<Outcome> {
+    let encoders = encoders(config);
+    let source_modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+
+    let mut bytes = None;
+    let mut outcomes = Vec::with_capacity(encoders.len());
+    for (suffix, encode) in encoders {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(suffix);
+        let sibling = PathBuf::from(sibling);
+        if is_fresh(&sibling, source_modified) {
+            outcomes.push(Outcome::UpToDate);
+            continue;
+        }
+        if bytes.is_none() {
+            match fs::read(path) {
+                Ok(read) => bytes = Some(read),
+                Err(e) => {
+                    log::error(format_args!(
+                        "Error precompressing {}: {}",
+                        path.display(),
+                        e
+                    ));
+                    outcomes.push(Outcome::Failed);
+                    continue;
+                }
+            }
+        }
+        let original = bytes.as_deref().unwrap_or_default();
+        let outcome = match encode(original) {
+            Ok(compressed) if compressed.len() >= original.len() => Outcome::NotSmaller,
+            Ok(compressed) => match write_file_atomic(&sibling, &compressed) {
+                Ok(_) => Outcome::Written,
+                Err(e) => {
+                    log::error(format_args!("Error writing {}: {}", sibling.display(), e));
+                    Outcome::Failed
+                }
+            },
+            Err(e) => {
+                log::error(format_args!("Error compressing {}: {}", path.display(), e));
+                Outcome::Failed
+            }
+        };
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+/// Returns `true` if `sibling` exists and is at least as new as its source.
+fn is_fresh(sibling: &Path, source_modified: Option<SystemTime>) -> bool {
+    let sibling_modified = fs::metadata(sibling).and_then(|meta| meta.modified());
+    match (sibling_modified, source_modified) {
+        (Ok(sibling), Some(source)) => sibling >= source,
+        _ => false,
+    }
+}
+
+fn gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+#[cfg(feature = "brotli")]
+fn brotli(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 64 * 1024, 11, 22);
+        encoder.write_all(bytes)?;
+    }
+    Ok(compressed)
+}
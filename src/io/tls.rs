@@ -0,0 +1,186 @@
+//! HTTPS with rustls (`tls` feature only).
+//!
+//! A TLS connection goes through the same reactor state machine as a plain
+//! one; only the bytes moving between the socket and the buffers differ.
+//! `TlsSession::read_plain` feeds what the socket has into the rustls session
+//! and hands back decrypted bytes, and `TlsSession::writer` encrypts what the
+//! write queue sends. The handshake happens on the way: until it completes
+//! no plaintext comes out, and the reactor only sees pending TLS output that
+//! it flushes like any other.
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::io;
+use std::io::{IoSlice, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// HTTPS settings.
+///
+/// # Fields
+/// - `cert_chain` (*PathBuf*): PEM file with the server certificate, followed by
+///   any intermediates.
+/// - `private_key` (*PathBuf*): PEM file with the certificate's private key.
+/// - `addresses` (*Vec<String>*): Entries of `ServerConfig::addresses` served over
+///   TLS, e.g. `[::]:443`. Empty (the default) means all of them.
+///
+/// The files are read once at startup, by `load`.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub cert_chain: PathBuf,
+    pub private_key: PathBuf,
+    pub addresses: Vec<String>,
+    loaded: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl TlsConfig {
+    /// Reads the certificate chain and key and builds the rustls config that
+    /// every TLS connection shares.
+    ///
+    /// # Errors
+    /// Fails if either file can't be read or parsed, or if the key doesn't
+    /// match the certificate, with the path in the message.
+    pub fn load(&mut self) -> io::Result<()> {
+        let invalid = |path: &PathBuf, e: &dyn std::fmt::Display| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: {e}", path.display()),
+            )
+        };
+        let certs = CertificateDer::pem_file_iter(&self.cert_chain)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&self.cert_chain, &e))?;
+        let key = PrivateKeyDer::from_pem_file(&self.private_key)
+            .map_err(|e| invalid(&self.private_key, &e))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| invalid(&self.cert_chain, &e))?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        self.loaded = Some(Arc::new(config));
+        Ok(())
+    }
+
+    /// Returns `true` if connections arriving on `address` use TLS.
+    pub fn serves(&self, address: &str) -> bool {
+        self.addresses.is_empty() || self.addresses.iter().any(|tls| tls == address)
+    }
+
+    /// Starts the server side of a TLS session for a new connection.
+    ///
+    /// # Errors
+    /// Fails with `InvalidInput` if `load` hasn't been called.
+    pub fn accept(&self) -> io::Result<TlsSession> {
+        let config = self.loaded.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "TLS config was never loaded")
+        })?;
+        let conn = rustls::ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+        Ok(TlsSession { conn })
+    }
+}
+
+/// The TLS state of one connection.
+pub struct TlsSession {
+    conn: rustls::ServerConnection,
+}
+
+impl TlsSession {
+    /// Reads decrypted bytes into `buf`, pulling records off `stream` as needed.
+    ///
+    /// Behaves like `Read::read` on the plain socket: `Ok(0)` once the peer
+    /// has closed (with `close_notify` or without), `WouldBlock` when the
+    /// socket has nothing more for now. Handshake records are processed on
+    /// the way and their replies left for `flush`.
+    ///
+    /// # Errors
+    /// A TLS protocol error, such as a failed handshake, comes back as
+    /// `ConnectionAborted` after the alert telling the peer has been sent
+    /// (best effort): the connection is over, and this isn't worth logging.
+    pub fn read_plain(
+        &mut self,
+        stream: &mut (impl Read + Write),
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if self.conn.read_tls(stream)? == 0 {
+                return Ok(0);
+            }
+            if let Err(e) = self.conn.process_new_packets() {
+                let _ = self.flush(stream);
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, e));
+            }
+        }
+    }
+
+    /// Returns a writer that encrypts what is written to it and sends it on `stream`.
+    pub fn writer<'a, S: Write>(&'a mut self, stream: &'a mut S) -> TlsWriter<'a, S> {
+        TlsWriter {
+            session: self,
+            stream,
+        }
+    }
+
+    /// Returns `true` while encrypted bytes are waiting to go out.
+    pub fn wants_write(&self) -> bool {
+        self.conn.wants_write()
+    }
+
+    /// Sends the encrypted bytes waiting in the session.
+    ///
+    /// # Errors
+    /// `WouldBlock` if the socket filled up first; the rest stays queued.
+    pub fn flush(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        while self.conn.wants_write() {
+            if self.conn.write_tls(stream)? == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the peer the connection is closing (`close_notify`), best effort.
+    pub fn close(&mut self, stream: &mut impl Write) {
+        self.conn.send_close_notify();
+        let _ = self.flush(stream);
+    }
+}
+
+/// Encrypts into a `TlsSession` and sends the records on a socket. See
+/// `TlsSession::writer`.
+pub struct TlsWriter<'a, S: Write> {
+    session: &'a mut TlsSession,
+    stream: &'a mut S,
+}
+
+impl<S: Write> Write for TlsWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    /// Encrypts as much of `bufs` as the session takes and sends it.
+    ///
+    /// Records from earlier writes go out first; if they can't, this fails
+    /// with `WouldBlock` and takes nothing. Records that don't fit in the
+    /// socket after the new bytes were taken stay queued in the session (see
+    /// `TlsSession::wants_write`), so the count returned is what was
+    /// accepted, not what reached the socket.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.session.flush(self.stream)?;
+        let n = self.session.conn.writer().write_vectored(bufs)?;
+        match self.session.flush(self.stream) {
+            Ok(()) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(n),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush(self.stream)
+    }
+}
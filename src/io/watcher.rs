@@ -0,0 +1,145 @@
+//! Watches the document root and invalidates the file cache on changes.
+//!
+//! This is a polling watcher: a background thread re-scans the tree every
+//! interval and compares each path's size, mtime and (on Unix) inode with
+//! the previous scan. Polling needs no platform-specific APIs and catches
+//! everything a notification API would report for our purposes, including
+//! editors that save by writing a temporary file and renaming it over the
+//! original (the inode changes even when size and mtime happen to match).
+use crate::io::cache::{FileCache, Invalidation};
+use crate::log;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What a scan remembers about one path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    modified: Option<SystemTime>,
+    inode: u64,
+    is_dir: bool,
+}
+
+/// A running watcher thread.
+///
+/// Dropping the `Watcher` stops the thread and waits for it to finish.
+///
+/// # Example
+/// ```
+/// let cache = Arc::new(FileCache::new());
+/// let watcher = Watcher::spawn("/srv/www", Duration::from_secs(1), Arc::clone(&cache));
+/// // ... serve requests ...
+/// drop(watcher);
+/// ```
+pub struct Watcher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Starts watching `root`, applying every detected change to `cache`.
+    ///
+    /// # Parameters
+    /// - `root`: The directory to watch, in the same form the cache keys start
+    ///   with (the resolved `ServerConfig::document_root`).
+    /// - `interval`: How long to wait between scans.
+    /// - `cache`: The cache to invalidate.
+    pub fn spawn(root: &str, interval: Duration, cache: Arc<FileCache>) -> Watcher {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let root = root.to_string();
+        let thread = thread::spawn(move || {
+            let mut previous = scan(&root);
+            // Dropping the sender wakes the thread with `Disconnected`.
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let current = scan(&root);
+                for invalidation in diff(&previous, &current) {
+                    cache.apply(invalidation);
+                }
+                previous = current;
+            }
+        });
+        Watcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            log::error("file watcher thread panicked");
+        }
+    }
+}
+
+/// Records a `Stamp` for every file and directory below `root`.
+///
+/// Symlinked files are stamped with their target's metadata, matching what
+/// serving them reads; symlinked directories are not descended into, so a
+/// link cycle can't make the scan run forever.
+fn scan(root: &str) -> HashMap<String, Stamp> {
+    let mut stamps = HashMap::new();
+    let mut pending = vec![root.trim_end_matches('/').to_string()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                pending.push(path.clone());
+            }
+            stamps.insert(path, stamp(&meta));
+        }
+    }
+    stamps
+}
+
+fn stamp(meta: &fs::Metadata) -> Stamp {
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(meta);
+    #[cfg(not(unix))]
+    let inode = 0;
+    Stamp {
+        size: meta.len(),
+        modified: meta.modified().ok(),
+        inode,
+        is_dir: meta.is_dir(),
+    }
+}
+
+/// Compares two scans and returns the invalidations that turn `old` into `new`.
+fn diff(old: &HashMap<String, Stamp>, new: &HashMap<String, Stamp>) -> Vec<Invalidation> {
+    let mut invalidations = Vec::new();
+    for (path, stamp) in old {
+        match new.get(path) {
+            None => invalidations.push(Invalidation::Removed(path.clone())),
+            // A file replaced by a directory (or the reverse) drops everything at that path.
+            Some(current) if current.is_dir != stamp.is_dir => {
+                invalidations.push(Invalidation::Removed(path.clone()))
+            }
+            Some(current) if !current.is_dir && current != stamp => {
+                invalidations.push(Invalidation::Changed(path.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    // A file created since the last scan may already have been cached and
+    // rewritten before this scan saw it.
+    for (path, stamp) in new {
+        if !stamp.is_dir && !old.contains_key(path) {
+            invalidations.push(Invalidation::Changed(path.clone()));
+        }
+    }
+    invalidations
+}
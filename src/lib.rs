@@ -0,0 +1,58 @@
+//! A small HTTP/1.1 static file server built on a `mio` event loop.
+//!
+//! `Server` binds the addresses of a `ServerConfig` and serves them, either on
+//! the calling thread (`Server::run`) or in the background (`Server::spawn`):
+//! ```
+//! let mut config = ServerConfig::default();
+//! config.addresses = vec![String::from("127.0.0.1:8080")];
+//! Server::bind(config)?.run()?;
+//! ```
+pub mod cli;
+pub mod error;
+pub mod log;
+pub mod server;
+pub mod stats;
+pub mod thread_pool;
+pub mod toml;
+pub mod util;
+
+pub mod http {
+    pub mod auth;
+    pub mod body;
+    pub mod cgi;
+    pub mod cors;
+    pub mod health;
+    pub mod https;
+    pub mod metrics;
+    pub mod request;
+    pub mod request_id;
+    pub mod response;
+    pub mod rewrite;
+    pub mod sse;
+    pub mod status;
+    pub mod template;
+    pub mod upload;
+    pub mod headers;
+    pub mod proxy;
+    pub mod websocket;
+}
+
+pub mod io {
+    pub mod access_log;
+    pub mod nonblocking;
+    pub mod file;
+    pub mod assets;
+    pub mod cache;
+    pub mod watcher;
+    pub mod precompress;
+    pub mod listener;
+    #[cfg(feature = "tls")]
+    pub mod tls;
+}
+
+pub use error::ServerError;
+pub use io::nonblocking::{Server, ServerHandle};
+pub use server::{
+    CacheRule, Middleware, Mount, Router, ServerConfig, ServerConfigBuilder, SpaFallback,
+    UnknownHost, VirtualHost,
+};
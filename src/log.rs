@@ -0,0 +1,199 @@
+//! How chatty the server is on the console.
+//!
+//! Errors are always printed. Warnings (settings that look wrong, unknown
+//! configuration keys) and informational messages (startup and shutdown
+//! progress) are printed only when the process-wide level allows them. The
+//! level is a single atomic so any thread, including pool workers that
+//! never see a `ServerConfig`, can check it cheaply.
+//!
+//! Errors met while a request is being handled name it by its request ID
+//! (see `http::request_id`): the reactor passes the connection's to
+//! `error_in`, and pool workers run handlers inside `in_request`, so an
+//! `error` anywhere below picks it up. With `LogFormat::Json` every line,
+//! whatever its level, is one JSON object, for log collectors.
+use crate::util::{format_rfc3339, json_escape};
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::SystemTime;
+
+/// The least severe messages that are printed.
+///
+/// Variants:
+/// - `Error`: Only errors.
+/// - `Warn`: Errors and warnings.
+/// - `Info`: Errors, warnings and progress messages. The default.
+/// - `Debug`: Everything, including per-worker lifecycle messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    /// Parses `error`, `warn`, `info` or `debug`, ignoring case.
+    fn from_str(s: &str) -> Result<LogLevel, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("{s}: expected error, warn, info or debug")),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        })
+    }
+}
+
+/// How log lines are written.
+///
+/// Variants:
+/// - `Text`: Plain sentences, for people. The default.
+/// - `Json`: One JSON object per line with stable field names (`ts`, `level`,
+///   `request_id`, `message`; see `io::access_log` for access lines), for
+///   log collectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    /// Parses `text` or `json`, ignoring case.
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("{s}: expected text or json")),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Text as u8);
+
+thread_local! {
+    // The ID of the request this thread is handling, if any.
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the level for the whole process. `Server::bind` calls this with
+/// `ServerConfig::log_level`.
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns `true` if messages at `level` are printed.
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Sets the format for the whole process. `Server::bind` calls this with
+/// `ServerConfig::log_format`.
+pub fn set_format(format: LogFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Returns the format set for the process.
+pub fn format() -> LogFormat {
+    if FORMAT.load(Ordering::Relaxed) == LogFormat::Json as u8 {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+/// Runs `f` with `request_id` as the request this thread is handling, so
+/// `error` names it.
+pub fn in_request<T>(request_id: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let previous = REQUEST_ID.replace(request_id.map(str::to_owned));
+    let result = f();
+    REQUEST_ID.set(previous);
+    result
+}
+
+/// Reports panics through `error` rather than the default hook, so with
+/// `LogFormat::Json` they are JSON too, naming the request they interrupted.
+/// `Server::bind` installs it for `LogFormat::Json`.
+pub fn report_panics() {
+    std::panic::set_hook(Box::new(|info| error(info)));
+}
+
+/// Prints an error about the request this thread is handling (see
+/// `in_request`), if any.
+pub fn error(message: impl fmt::Display) {
+    REQUEST_ID.with_borrow(|request_id| error_in(request_id.as_deref(), message));
+}
+
+/// Prints an error about the request with `request_id`, e.g.
+/// `write error from 127.0.0.1:50000: Broken pipe (request 18c…-2a)`, or
+/// in JSON:
+/// `{"ts":"…","level":"error","request_id":"18c…-2a","message":"write error …"}`.
+pub fn error_in(request_id: Option<&str>, message: impl fmt::Display) {
+    write(LogLevel::Error, request_id, message);
+}
+
+/// Prints a warning, like `error`, if the level allows it.
+pub fn warn(message: impl fmt::Display) {
+    if enabled(LogLevel::Warn) {
+        REQUEST_ID.with_borrow(|request_id| write(LogLevel::Warn, request_id.as_deref(), message));
+    }
+}
+
+/// Prints a progress message, like `error` but to stdout, if the level
+/// allows it.
+pub fn info(message: impl fmt::Display) {
+    if enabled(LogLevel::Info) {
+        REQUEST_ID.with_borrow(|request_id| write(LogLevel::Info, request_id.as_deref(), message));
+    }
+}
+
+/// Prints a debugging message, like `error`, if the level allows it.
+pub fn debug(message: impl fmt::Display) {
+    if enabled(LogLevel::Debug) {
+        REQUEST_ID.with_borrow(|request_id| write(LogLevel::Debug, request_id.as_deref(), message));
+    }
+}
+
+/// Prints one line at `level` in the process's format: to stdout at
+/// `LogLevel::Info`, to stderr otherwise.
+fn write(level: LogLevel, request_id: Option<&str>, message: impl fmt::Display) {
+    let line = match (format(), request_id) {
+        (LogFormat::Text, None) => message.to_string(),
+        (LogFormat::Text, Some(id)) => format!("{message} (request {id})"),
+        (LogFormat::Json, _) => {
+            let request_id = request_id.map_or_else(
+                || String::from("null"),
+                |id| format!("\"{}\"", json_escape(id)),
+            );
+            format!(
+                "{{\"ts\":\"{}\",\"level\":\"{level}\",\"request_id\":{request_id},\"message\":\"{}\"}}",
+                format_rfc3339(SystemTime::now()),
+                json_escape(&message.to_string())
+            )
+        }
+    };
+    if level == LogLevel::Info {
+        println!("{line}");
+    } else {
+        eprintln!("{line}");
+    }
+}
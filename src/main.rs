@@ -1,32 +1,29 @@
-use crate::io::nonblocking;
-
-pub mod server;
-pub mod thread_pool;
-pub mod util;
-
-pub mod http {
-    pub mod request;
-    pub mod response;
-    pub mod headers;
-}
-
-pub mod io {
-    pub mod nonblocking;
-    pub mod file;
-}
-
-const ADDRESS: &str = "127.0.0.1:8080";
+use custom_http::Server;
+use custom_http::cli::{self, CONFIG_ENV, Command, HELP, VERSION};
 
 /// Entry point for the program
+///
+/// The command line, with the configuration file it names (see `cli`),
+/// adjusts the default configuration, e.g.
+/// `--config site.toml --address [::]:8080 --dual-stack`.
 fn main() {
-    nonblocking::run(ADDRESS).expect("TODO: Match errors");
+    let config = match cli::parse(std::env::args().skip(1), std::env::var_os(CONFIG_ENV)) {
+        Ok(Command::Serve(config)) => config,
+        Ok(Command::Help) => {
+            print!("{HELP}");
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("{VERSION}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = Server::bind(*config).and_then(Server::run) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
-
-// Handles a connection from a client.
-// 
-// Writes the desired page into the TcpStream.
-// fn handle_connection(mut stream: TcpStream) {
-//     let buffer = BufReader::new(&mut stream);
-//     let request_line = buffer.lines().next().unwrap().unwrap();
-//     let response = http::response::http_handler(request_line);
-// }
\ No newline at end of file
@@ -0,0 +1,1949 @@
+//! Server-wide configuration.
+//!
+//! Everything that changes how requests are answered lives in `ServerConfig`
+//! so that the HTTP layer doesn't have to hardcode policy.
+use crate::error::ServerError;
+use crate::http::auth::redact_query;
+use crate::http::cgi::CgiConfig;
+use crate::http::cors::CorsConfig;
+use crate::http::headers::{ForwardedHeader, HeaderName, HeaderValue};
+use crate::http::health::HealthConfig;
+use crate::http::https::{self, HstsConfig, HttpsRedirectConfig};
+use crate::http::metrics::MetricsConfig;
+use crate::http::proxy::ProxyConfig;
+use crate::http::request::{HttpRequest, MAX_HEAD_SIZE, MAX_HEADER_FIELDS, Method};
+use crate::http::request_id::RequestIdConfig;
+use crate::http::response::{ErrorPage, HttpResponse};
+use crate::http::rewrite::RewriteRule;
+use crate::http::template::TemplateConfig;
+use crate::http::upload::UploadConfig;
+use crate::http::websocket::{WebSocketConfig, WebSocketHandler};
+use crate::io::access_log::{AccessLogConfig, AccessLogFormat, LogTarget};
+#[cfg(feature = "embed")]
+use crate::io::assets::EmbeddedSource;
+use crate::io::assets::{AssetSource, FilesystemSource, MountedSource};
+use crate::io::cache::{FileCache, HashCache};
+use crate::io::listener::{BindAddress, IpFilter, SocketOptions};
+use crate::io::nonblocking::ReactorConfig;
+use crate::io::precompress::PrecompressConfig;
+#[cfg(feature = "tls")]
+use crate::io::tls::TlsConfig;
+use crate::log::{self, LogFormat, LogLevel};
+use crate::stats::ServerStats;
+use crate::toml;
+use crate::util::{self, Cidr};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The port an `--address` without one listens on, unless `--port` says otherwise.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Which `AssetSource` files are served from.
+///
+/// Variants:
+/// - `Filesystem`: The files under `document_root`.
+/// - `Embedded`: The copy of the site compiled into the binary (`embed` feature only).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Assets {
+    #[default]
+    Filesystem,
+    #[cfg(feature = "embed")]
+    Embedded,
+}
+
+/// Settings shared by every request the server handles.
+///
+/// # Fields
+/// - `addresses` (*Vec<String>*): The addresses to listen on, e.g. `127.0.0.1:8080`,
+///   `[::]:8080` or, on Unix, a socket path like `unix:/run/site.sock` (see
+///   `io::listener::BindAddress`). Every address is served the same way; a
+///   connection remembers which one it arrived on. Whether an IPv6 wildcard also
+///   takes IPv4 connections is up to `socket.ipv6_only`. Defaults to `127.0.0.1:8080`.
+/// - `assets` (*Assets*): Where files are served from. Defaults to the filesystem.
+/// - `document_root` (*PathBuf*): The directory files are served from. Defaults to
+///   `public`. A relative root is resolved against the working directory once, at
+///   startup, by `resolve_document_root`.
+/// - `mounts` (*Vec<Mount>*): Other directories served under URL prefixes, e.g.
+///   `/assets` from `/srv/assets` (see `Mount`). The longest matching prefix wins
+///   over shorter ones and over `document_root`. Empty by default.
+/// - `cors` (*Option<CorsConfig>*): CORS policy. `None` disables CORS headers entirely.
+/// - `force_attachment_extensions` (*Vec<String>*): File extensions (without the dot,
+///   e.g. `"zip"`, `"pdf"`) that are always sent as downloads instead of being rendered.
+/// - `index` (*String*): The document served for `/`. Defaults to `welcome.html`.
+/// - `index_files` (*Vec<String>*): File names tried, in order, when a directory is
+///   requested. Defaults to `index.html`, then `index.htm`.
+/// - `directory_listing` (*bool*): When `true`, a directory without an index file gets
+///   a generated listing instead of a 404. Defaults to `false`.
+/// - `listing_show_hidden` (*bool*): Whether generated listings include entries whose
+///   name starts with `.`. Defaults to `false`.
+/// - `error_pages` (*HashMap<u16, String>*): Error page templates by status code
+///   (e.g. `404 => "templates/404.html"`). These files are rendered with the request
+///   context (see `http::response`) and replace the built-in error page for that status.
+/// - `hidden_allowlist` (*Vec<String>*): Path prefixes under which dot-prefixed
+///   components may be served. Everything else with a component starting with `.`
+///   gets 403. Defaults to `/.well-known/` so ACME challenges keep working.
+/// - `mime_overrides` (*HashMap<String, String>*): Content types by file extension
+///   (e.g. `"map" => "application/json"`), consulted before `mime_guess`. Two-part
+///   extensions such as `"tar.gz"` are allowed and take precedence over the built-in
+///   compound table. Extensions are matched case-insensitively.
+/// - `default_mime` (*String*): Content type for files whose type can't be determined.
+///   Defaults to `application/octet-stream`.
+/// - `max_in_memory_file_size` (*u64*): Files up to this many bytes are read into memory
+///   (and may be cached); larger ones are always streamed from disk, by `sendfile`, a
+///   memory mapping or chunked reads, and never cached. Defaults to 1 MiB.
+/// - `stream_chunk_size` (*usize*): Size of each chunk read when streaming a file.
+///   Defaults to 64 KiB.
+/// - `mmap_threshold` (*Option<u64>*): Files larger than this many bytes are served from a
+///   memory mapping instead of `read` calls. `None` (the default) disables mapping; it is
+///   also ignored on platforms without mmap support.
+/// - `sendfile` (*bool*): Send streamed files with `sendfile(2)` on Linux, skipping the
+///   copy through user space. Other platforms read such files in chunks. Defaults to `true`.
+/// - `file_cache` (*Option<Arc<FileCache>>*): Keeps the contents of files served from
+///   memory (those up to `max_in_memory_file_size`) so repeat requests skip the disk.
+///   `None` (the default) disables caching.
+/// - `watch_interval` (*Option<Duration>*): When set together with `file_cache`, the
+///   server watches the document root and drops cached files as soon as they change,
+///   scanning at this interval. `None` (the default) relies on the mtime check alone.
+/// - `etag_hash_limit` (*u64*): Files up to this many bytes get a strong `ETag` made
+///   from a hash of their contents. Bigger files get a weak `W/"size-mtime"` tag so
+///   they are never read just to be hashed. Defaults to 16 MiB.
+/// - `hash_cache` (*Arc<HashCache>*): Content hashes computed for ETags, reused until
+///   a file's size or mtime changes. Shared by all clones of the config.
+/// - `cache_control` (*Vec<CacheRule>*): `Cache-Control` values for static files by
+///   path pattern (see `CacheRule`), sent with `200` and `304` responses. The first
+///   matching rule wins; files no rule matches get no header. Empty by default.
+/// - `precompress` (*Option<PrecompressConfig>*): Write `.gz` (and `.br`) siblings of
+///   compressible files under the document root at startup (see `io::precompress`).
+///   `None` (the default) disables the pass.
+/// - `follow_symlinks` (*bool*): When `true`, symlinks inside the document root may
+///   point outside of it (e.g. an asset directory linked in from elsewhere). When
+///   `false`, anything whose canonical path leaves the root gets 403. Defaults to `false`.
+/// - `upload` (*Option<UploadConfig>*): Accept `PUT` uploads under a prefix (see
+///   `http::upload`). `None` (the default) disables uploads.
+/// - `cgi` (*Option<CgiConfig>*): Run CGI scripts under a prefix (see `http::cgi`),
+///   after the router and proxies but before static files. `None` (the default)
+///   disables CGI.
+/// - `proxies` (*Vec<ProxyConfig>*): Path prefixes forwarded to upstream servers
+///   (see `http::proxy`), after the router but before static files. The longest
+///   matching prefix wins. Empty by default.
+/// - `websocket` (*WebSocketConfig*): Message size limit and idle timeout of the
+///   connections `Router::websocket` routes accept (see `http::websocket`).
+/// - `max_body_size` (*u64*): The largest request body accepted for anything other
+///   than an upload; bigger bodies get `413 Payload Too Large`. Defaults to 1 MiB.
+/// - `max_header_fields` (*usize*): The most header fields a request may have; more
+///   get `431 Request Header Fields Too Large`. Defaults to 100.
+/// - `max_head_size` (*usize*): The longest request head, in bytes. Longer heads
+///   get `431 Request Header Fields Too Large`, or `400 Bad Request` if the
+///   request line alone is longer, and the connection is closed. Defaults to 16 KiB.
+/// - `clean_urls` (*bool*): When `true`, a request path without an extension that
+///   doesn't exist has `.html` appended (`/about` serves `about.html`). When `false`,
+///   paths are used as-is. Defaults to `true`.
+/// - `rewrites` (*Vec<RewriteRule>*): Rules that rewrite request paths or redirect
+///   the client, tried in order before a request is routed (see `http::rewrite`).
+///   Empty by default.
+/// - `spa_fallback` (*Option<SpaFallback>*): Answer browser navigations to missing
+///   paths with a single-page app's index page instead of 404 (see `SpaFallback`).
+///   `None` (the default) answers them with 404.
+/// - `templates` (*Option<TemplateConfig>*): Render files with the template
+///   extension (`.tpl` by default) as HTML pages instead of serving them as they
+///   are (see `http::template`). `None` (the default) renders nothing.
+/// - `keep_alive_timeout` (*Duration*): How long a connection may sit idle between
+///   requests before it is closed. Defaults to 5 seconds.
+/// - `header_read_timeout` (*Duration*): How long a partly received request may go
+///   without new bytes before the client gets `408 Request Timeout` and the connection
+///   is closed. Defaults to 10 seconds.
+/// - `header_deadline` (*Duration*): How long a client has, from the first byte of a
+///   request, to finish sending its head, however steadily the bytes trickle in.
+///   Clients that miss it get `408 Request Timeout`. Defaults to 30 seconds.
+/// - `min_request_rate` (*Option<u64>*): The slowest a request (head and body) may
+///   arrive, in bytes per second averaged since its first byte. Slower clients get
+///   `408 Request Timeout`. `None` (the default) disables the check.
+/// - `write_timeout` (*Duration*): How long a response may go without the client
+///   reading any of it before the connection is closed. Defaults to 30 seconds.
+/// - `handler_timeout` (*Option<Duration>*): How long a request may take to be
+///   handled on the worker pool (a route, file read, CGI script or proxied request)
+///   before the client gets `503 Service Unavailable`, or `504 Gateway Timeout` on a
+///   proxied path, and the connection is closed. Whatever the handler returns later
+///   is dropped. `Router::timeout` overrides it for some paths. Checked once a
+///   second. Defaults to 60 seconds; `None` waits forever.
+/// - `drain_timeout` (*Duration*): On SIGINT or SIGTERM the server stops accepting and
+///   lets open responses finish for at most this long before closing what's left and
+///   returning from `run`. Defaults to 10 seconds.
+/// - `health` (*Option<HealthConfig>*): Answer the `/healthz` and `/readyz` probes
+///   on the event loop (see `http::health`). `None` (the default) serves those
+///   paths like any other.
+/// - `metrics` (*Option<MetricsConfig>*): Serve `stats` and the worker pools' load
+///   to Prometheus scrapes on the event loop (see `http::metrics`). `None` (the
+///   default) serves no metrics.
+/// - `linger_timeout` (*Duration*): After the last response on a connection that
+///   isn't kept alive, the write side is shut down and the connection kept open for
+///   at most this long, so the client reads the whole response before the socket
+///   closes (closing with unread input makes the kernel reset the connection, which
+///   can discard the response's tail). Anything the client still sends is dropped.
+///   Defaults to 2 seconds.
+/// - `max_connections` (*usize*): The most connections served at once, across all
+///   reactors (counted by `stats`). Connections beyond it are accepted, answered with
+///   `503 Service Unavailable` and closed, so clients get a clear answer instead of
+///   hanging. Defaults to 1024.
+/// - `reactors` (*usize*): How many event loops a `Server` runs, each on its own
+///   thread with its own listener on the same address (see `io::listener`). Defaults
+///   to the number of cores. Only Unix supports more than one.
+/// - `socket` (*SocketOptions*): TCP options for the listener and accepted
+///   connections (see `io::listener`).
+/// - `ip_filter` (*IpFilter*): Allow and deny lists of client address ranges, applied
+///   as connections are accepted (see `io::listener`). Allows everyone by default.
+/// - `trusted_proxies` (*Vec<Cidr>*): Reverse proxies whose forwarding headers
+///   are believed, so logs name the client behind them (see
+///   `HttpRequest::forwarded_client`). Empty (the default) trusts no one.
+///   Requests from anyone else have those headers ignored.
+/// - `forwarded_header` (*ForwardedHeader*): The header the trusted proxies
+///   record hops in; the other one is ignored. Defaults to `X-Forwarded-*`.
+/// - `virtual_hosts` (*Vec<VirtualHost>*): Sites chosen by the request's `Host`
+///   header, each with its own document root, router and so on (see
+///   `VirtualHost`). Empty (the default) serves every request from this config.
+/// - `unknown_host` (*UnknownHost*): What requests for a host none of
+///   `virtual_hosts` serves get. Defaults to this config's own site.
+/// - `router` (*Arc<Router>*): Dynamic endpoints, consulted before static files.
+///   Empty by default. Shared by all clones of the config.
+/// - `reactor` (*ReactorConfig*): Poll timeout, event and connection slab sizes of
+///   each event loop (see `io::nonblocking`).
+/// - `log_level` (*LogLevel*): Which console messages are printed (see `log`).
+///   Defaults to `Info`.
+/// - `log_format` (*LogFormat*): Plain text or one JSON object per line for
+///   errors (see `log`). Defaults to `Text`; set it with `set_log_format`.
+/// - `access_log` (*Option<AccessLogConfig>*): Log a line per response, in the
+///   Common or Combined Log Format, JSON or a layout of one's own, written by a
+///   thread of its own (see `io::access_log`). `None` (the default) logs nothing.
+/// - `request_id` (*Option<RequestIdConfig>*): Give every request an ID, echoed
+///   in its response and named in its log lines (see `http::request_id`).
+///   `None` (the default) gives none.
+/// - `redirect_to_https` (*Option<HttpsRedirectConfig>*): Listeners that answer
+///   every request with a redirect to HTTPS and serve nothing else (see
+///   `http::https`). `None` (the default) serves the site on every listener.
+/// - `hsts` (*Option<HstsConfig>*): Send `Strict-Transport-Security` on responses
+///   over TLS (see `http::https`). `None` (the default) sends none.
+/// - `stats` (*Arc<ServerStats>*): Connection and traffic counters, updated by every
+///   reactor. Shared by all clones of the config; read them with `stats.snapshot()`.
+/// - `tls` (*Option<TlsConfig>*): Serve HTTPS on some or all `addresses` (see
+///   `io::tls`). `None` (the default) serves plain HTTP everywhere. `tls` feature only.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub addresses: Vec<String>,
+    pub assets: Assets,
+    pub document_root: PathBuf,
+    pub mounts: Vec<Mount>,
+    pub cors: Option<CorsConfig>,
+    pub force_attachment_extensions: Vec<String>,
+    pub index: String,
+    pub index_files: Vec<String>,
+    pub directory_listing: bool,
+    pub listing_show_hidden: bool,
+    pub error_pages: HashMap<u16, String>,
+    pub hidden_allowlist: Vec<String>,
+    pub mime_overrides: HashMap<String, String>,
+    pub default_mime: String,
+    pub max_in_memory_file_size: u64,
+    pub stream_chunk_size: usize,
+    pub mmap_threshold: Option<u64>,
+    pub sendfile: bool,
+    pub file_cache: Option<Arc<FileCache>>,
+    pub watch_interval: Option<Duration>,
+    pub etag_hash_limit: u64,
+    pub hash_cache: Arc<HashCache>,
+    pub cache_control: Vec<CacheRule>,
+    pub precompress: Option<PrecompressConfig>,
+    pub follow_symlinks: bool,
+    pub upload: Option<UploadConfig>,
+    pub proxies: Vec<ProxyConfig>,
+    pub cgi: Option<CgiConfig>,
+    pub websocket: WebSocketConfig,
+    pub max_body_size: u64,
+    pub max_header_fields: usize,
+    pub max_head_size: usize,
+    pub clean_urls: bool,
+    pub rewrites: Vec<RewriteRule>,
+    pub spa_fallback: Option<SpaFallback>,
+    pub templates: Option<TemplateConfig>,
+    pub keep_alive_timeout: Duration,
+    pub header_read_timeout: Duration,
+    pub header_deadline: Duration,
+    pub min_request_rate: Option<u64>,
+    pub write_timeout: Duration,
+    pub handler_timeout: Option<Duration>,
+    pub drain_timeout: Duration,
+    pub health: Option<HealthConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub linger_timeout: Duration,
+    pub max_connections: usize,
+    pub reactors: usize,
+    pub socket: SocketOptions,
+    pub ip_filter: IpFilter,
+    pub trusted_proxies: Vec<Cidr>,
+    pub forwarded_header: ForwardedHeader,
+    pub virtual_hosts: Vec<VirtualHost>,
+    pub unknown_host: UnknownHost,
+    pub router: Arc<Router>,
+    pub reactor: ReactorConfig,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub access_log: Option<AccessLogConfig>,
+    pub request_id: Option<RequestIdConfig>,
+    pub redirect_to_https: Option<HttpsRedirectConfig>,
+    pub hsts: Option<HstsConfig>,
+    pub stats: Arc<ServerStats>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            addresses: vec![String::from("127.0.0.1:8080")],
+            assets: Assets::Filesystem,
+            document_root: PathBuf::from("public"),
+            mounts: Vec::new(),
+            cors: None,
+            force_attachment_extensions: Vec::new(),
+            index: String::from("welcome.html"),
+            index_files: vec![String::from("index.html"), String::from("index.htm")],
+            directory_listing: false,
+            listing_show_hidden: false,
+            error_pages: HashMap::new(),
+            hidden_allowlist: vec![String::from("/.well-known/")],
+            mime_overrides: HashMap::new(),
+            default_mime: String::from("application/octet-stream"),
+            max_in_memory_file_size: 1024 * 1024,
+            stream_chunk_size: 64 * 1024,
+            mmap_threshold: None,
+            sendfile: true,
+            file_cache: None,
+            watch_interval: None,
+            etag_hash_limit: 16 * 1024 * 1024,
+            hash_cache: Arc::new(HashCache::new()),
+            cache_control: Vec::new(),
+            precompress: None,
+            follow_symlinks: false,
+            upload: None,
+            proxies: Vec::new(),
+            cgi: None,
+            websocket: WebSocketConfig::default(),
+            max_body_size: 1024 * 1024,
+            max_header_fields: MAX_HEADER_FIELDS,
+            max_head_size: MAX_HEAD_SIZE,
+            clean_urls: true,
+            rewrites: Vec::new(),
+            spa_fallback: None,
+            templates: None,
+            keep_alive_timeout: Duration::from_secs(5),
+            header_read_timeout: Duration::from_secs(10),
+            header_deadline: Duration::from_secs(30),
+            min_request_rate: None,
+            write_timeout: Duration::from_secs(30),
+            handler_timeout: Some(Duration::from_secs(60)),
+            drain_timeout: Duration::from_secs(10),
+            health: None,
+            metrics: None,
+            linger_timeout: Duration::from_secs(2),
+            max_connections: 1024,
+            reactors: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            socket: SocketOptions::default(),
+            ip_filter: IpFilter::default(),
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+            virtual_hosts: Vec::new(),
+            unknown_host: UnknownHost::Default,
+            router: Arc::new(Router::new()),
+            reactor: ReactorConfig::default(),
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+            access_log: None,
+            request_id: None,
+            redirect_to_https: None,
+            hsts: None,
+            stats: Arc::new(ServerStats::default()),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Returns a builder for the settings most servers change, starting
+    /// from the defaults and checked by `ServerConfigBuilder::build`.
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder {
+            config: ServerConfig::default(),
+            addresses: Vec::new(),
+        }
+    }
+
+    /// Applies command-line flags (without the program name) on top of the
+    /// current settings.
+    ///
+    /// Flags:
+    /// - `-a`, `--address <address>` (or `--bind`): Listen on `address` (an IP
+    ///   address and port, an IP address alone, or a socket path); repeat for
+    ///   more. The first one replaces the configured addresses. An IP address
+    ///   alone takes the port from `--port`, or `DEFAULT_PORT`.
+    /// - `-p`, `--port <port>`: Listen on `port` on every configured IP address.
+    ///   Conflicts with an `--address` that has a port of its own.
+    /// - `-r`, `--root <path>`: Set `document_root`.
+    /// - `--mount <prefix>=<path>`: Serve the directory `path` under the URL
+    ///   `prefix` (see `Mount`); repeat for more.
+    /// - `--proxy <prefix>=<url>`: Forward requests under `prefix` to the upstream
+    ///   `url` (see `ProxyConfig`); repeat for more.
+    /// - `--log-level <level>`: Set `log_level` (`error`, `warn`, `info` or `debug`).
+    /// - `--log-format <format>`: `text` or `json`; see `set_log_format`.
+    /// - `--access-log <file>`: Log every response to `file` (`-` for standard
+    ///   output), in the configured `access_log.format` or the Combined Log Format.
+    /// - `--ipv6-only` / `--dual-stack`: Set `socket.ipv6_only` to `true` / `false`.
+    /// - `--reactors <n>`: Set `reactors`.
+    /// - `--workers <n>`: Set `reactor.workers`.
+    /// - `--allow <cidr>` / `--deny <cidr>`: Add a range (e.g. `10.0.0.0/8`, `::1`) to
+    ///   `ip_filter.allow` / `ip_filter.deny`; repeat for more.
+    /// - `--deny-with-403`: Set `ip_filter.respond_forbidden`.
+    /// - `--health`: Answer the health probes at their default paths (see
+    ///   `HealthConfig`).
+    /// - `--trusted-proxy <cidr>`: Add a range to `trusted_proxies`; repeat for more.
+    /// - `--tls-cert <path>` / `--tls-key <path>`: Serve every address over TLS
+    ///   with this certificate chain / private key (`tls` feature only).
+    ///
+    /// # Errors
+    /// `ServerError::Config` on an unknown flag, a missing value, or a value
+    /// that doesn't parse, naming the offending argument, and
+    /// `ServerError::InvalidAddress` for a bad `--address`.
+    pub fn apply_args(
+        &mut self,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(), ServerError> {
+        let invalid = ServerError::Config;
+        let mut args = args.into_iter();
+        let mut bind_seen = false;
+        let mut port = None;
+        // The first `--address` with a port of its own, which `--port` would contradict.
+        let mut with_port = None;
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| invalid(format!("{flag} needs a value")))
+            };
+            match flag.as_str() {
+                "-a" | "--address" | "--bind" => {
+                    let mut address = value()?;
+                    match host_only(&address) {
+                        Some(ip) => address = SocketAddr::new(ip, DEFAULT_PORT).to_string(),
+                        None => {
+                            if let BindAddress::Tcp(_) = BindAddress::parse(&address)? {
+                                with_port.get_or_insert_with(|| address.clone());
+                            }
+                        }
+                    }
+                    if !bind_seen {
+                        self.addresses.clear();
+                        bind_seen = true;
+                    }
+                    self.addresses.push(address);
+                }
+                "-p" | "--port" => {
+                    let value = value()?;
+                    port = Some(
+                        value
+                            .parse::<u16>()
+                            .map_err(|_| invalid(format!("{flag} {value}: not a port number")))?,
+                    );
+                }
+                "-r" | "--root" => self.document_root = value()?.into(),
+                "--mount" => {
+                    let value = value()?;
+                    let mount = value
+                        .split_once('=')
+                        .and_then(|(prefix, directory)| Mount::new(prefix, directory).ok())
+                        .ok_or_else(|| {
+                            invalid(format!("{flag} {value}: expected /prefix=directory"))
+                        })?;
+                    self.mounts.push(mount);
+                }
+                "--proxy" => {
+                    let value = value()?;
+                    let (prefix, upstream) = value.split_once('=').ok_or_else(|| {
+                        invalid(format!("{flag} {value}: expected /prefix=http://host:port"))
+                    })?;
+                    let proxy = ProxyConfig::new(prefix, upstream)
+                        .map_err(|e| invalid(format!("{flag} {value}: {e}")))?;
+                    self.proxies.push(proxy);
+                }
+                "--log-level" => {
+                    let value = value()?;
+                    self.log_level = value.parse().map_err(|e| invalid(format!("{flag} {e}")))?;
+                }
+                "--log-format" => {
+                    let value = value()?;
+                    let format = value.parse().map_err(|e| invalid(format!("{flag} {e}")))?;
+                    self.set_log_format(format);
+                }
+                "--access-log" => {
+                    let value = value()?;
+                    self.access_log.get_or_insert_default().target = log_target(&value);
+                }
+                "--reactors" | "--workers" => {
+                    let count = value()?;
+                    let count =
+                        count.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                            invalid(format!("{flag} {count}: not a positive number"))
+                        })?;
+                    match flag.as_str() {
+                        "--reactors" => self.reactors = count,
+                        _ => self.reactor.workers = count,
+                    }
+                }
+                "--allow" | "--deny" | "--trusted-proxy" => {
+                    let range = value()?;
+                    let cidr = Cidr::parse(&range)
+                        .ok_or_else(|| invalid(format!("{flag} {range}: not a CIDR range")))?;
+                    match flag.as_str() {
+                        "--allow" => self.ip_filter.allow.push(cidr),
+                        "--deny" => self.ip_filter.deny.push(cidr),
+                        _ => self.trusted_proxies.push(cidr),
+                    }
+                }
+                "--deny-with-403" => self.ip_filter.respond_forbidden = true,
+                "--health" => {
+                    self.health.get_or_insert_default();
+                }
+                "--ipv6-only" => self.socket.ipv6_only = true,
+                "--dual-stack" => self.socket.ipv6_only = false,
+                #[cfg(feature = "tls")]
+                "--tls-cert" => self.tls.get_or_insert_default().cert_chain = value()?.into(),
+                #[cfg(feature = "tls")]
+                "--tls-key" => self.tls.get_or_insert_default().private_key = value()?.into(),
+                _ => return Err(invalid(format!("unknown argument {flag}"))),
+            }
+        }
+        if let Some(port) = port {
+            if let Some(address) = with_port {
+                return Err(invalid(format!(
+                    "--address {address} already has a port; drop it or --port"
+                )));
+            }
+            for address in &mut self.addresses {
+                if let Ok(mut addr) = address.parse::<SocketAddr>() {
+                    addr.set_port(port);
+                    *address = addr.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `log_format`. `LogFormat::Json` is the structured logging mode:
+    /// it also turns on request IDs and a JSON access log (on standard output
+    /// unless `access_log.target` says otherwise), so every access and error
+    /// event is one JSON object per line, all naming the request they concern.
+    pub fn set_log_format(&mut self, format: LogFormat) {
+        self.log_format = format;
+        if format == LogFormat::Json {
+            self.request_id.get_or_insert_default();
+            self.access_log.get_or_insert_default().format = AccessLogFormat::json();
+        }
+    }
+
+    /// Returns the defaults with the settings of the TOML file at `path`
+    /// applied; see `apply_toml_path`.
+    ///
+    /// # Errors
+    /// As `apply_toml_path`.
+    pub fn from_toml_path(path: impl AsRef<Path>) -> Result<ServerConfig, ServerError> {
+        let mut config = ServerConfig::default();
+        config.apply_toml_path(path)?;
+        Ok(config)
+    }
+
+    /// Applies the settings of the TOML file at `path` on top of the current
+    /// ones. Settings the file leaves out keep their value; unknown keys are
+    /// ignored with a warning that lists them.
+    ///
+    /// Top-level keys, named after the fields they set:
+    /// - `addresses` (array of strings), `document_root`, `index`, `default_mime`,
+    ///   `log_level`, `log_format` (strings; see `set_log_format` for the latter).
+    /// - `workers` (sets `reactor.workers`), `reactors`, `max_connections`,
+    ///   `max_header_fields`, `max_head_size` (positive integers).
+    /// - `max_body_size`, `max_in_memory_file_size` (bytes).
+    /// - `keep_alive_timeout`, `header_read_timeout`, `header_deadline`,
+    ///   `write_timeout`, `handler_timeout`, `drain_timeout` (whole seconds;
+    ///   `handler_timeout = 0` turns that one off).
+    /// - `directory_listing`, `clean_urls`, `follow_symlinks` (booleans).
+    /// - `trusted_proxies` (array of CIDR ranges), `forwarded_header`
+    ///   (`"x-forwarded"` or `"forwarded"`).
+    ///
+    /// Tables:
+    /// - `[error_pages]`: Template paths by status code, e.g. `404 = "templates/404.html"`.
+    /// - `[mime_overrides]`: Content types by extension, e.g. `"tar.gz" = "application/gzip"`.
+    /// - `[cache_control]`: `Cache-Control` values by path pattern, in order, e.g.
+    ///   `"/assets/*" = "public, max-age=31536000, immutable"` (see `CacheRule`).
+    /// - `[mounts]`: Directories by URL prefix, e.g. `"/assets" = "/srv/assets"` (see `Mount`).
+    /// - `[proxies]`: Upstreams by path prefix, e.g. `"/api" = "http://127.0.0.1:3000"`
+    ///   (see `ProxyConfig`).
+    /// - `[rewrites]`: Rewrite and redirect rules by pattern, in order, e.g.
+    ///   `"/img/*" = "rewrite /static/images/$1"` (see `http::rewrite`).
+    /// - `[spa_fallback]`: `enabled` (boolean), `prefix` and `index` (strings) of
+    ///   the single-page app fallback (see `SpaFallback`). Any key but
+    ///   `enabled = false` turns it on.
+    /// - `[access_log]`: `enabled` (boolean), `format` (`"common"`, `"combined"`,
+    ///   `"json"` or a layout, see `AccessLogFormat`) and `path` (a file, or `"-"`
+    ///   for standard output) of the access log. Any key but `enabled = false`
+    ///   turns it on.
+    /// - `[request_id]`: `enabled`, `trust_incoming` (booleans) and `header`
+    ///   (string) of request IDs (see `RequestIdConfig`). Any key but
+    ///   `enabled = false` turns them on.
+    /// - `[metrics]`: `enabled` (boolean), `path` (string) and `allow` (array of
+    ///   CIDR ranges) of the Prometheus endpoint (see `MetricsConfig`). Any key
+    ///   but `enabled = false` turns it on.
+    /// - `[redirect_to_https]`: `enabled` (boolean), `addresses` (array of
+    ///   strings), `canonical_host` (string) and `https_port` (integer) of the
+    ///   listeners that only redirect to HTTPS (see `HttpsRedirectConfig`). Any
+    ///   key but `enabled = false` turns them on.
+    /// - `[hsts]`: `enabled`, `include_subdomains` (booleans) and `max_age`
+    ///   (seconds) of `Strict-Transport-Security` (see `HstsConfig`). Any key but
+    ///   `enabled = false` turns it on.
+    ///
+    /// See `config/custom_http.toml` for a commented example.
+    ///
+    /// # Errors
+    /// `ServerError::Io` if the file can't be read, and `ServerError::Config`
+    /// for a syntax error or a value of the wrong type, naming the file, the
+    /// line and the key.
+    pub fn apply_toml_path(&mut self, path: impl AsRef<Path>) -> Result<(), ServerError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("config file {}: {e}", path.display()))
+        })?;
+        let entries = toml::parse(&text)
+            .map_err(|e| ServerError::Config(format!("{}: {e}", path.display())))?;
+        let mut unknown = Vec::new();
+        for entry in &entries {
+            match self.apply_toml_entry(entry) {
+                Ok(true) => {}
+                Ok(false) => unknown.push(format!("{} (line {})", entry.name(), entry.line)),
+                Err(reason) => {
+                    return Err(ServerError::Config(format!(
+                        "{}: line {}: {}: {reason}",
+                        path.display(),
+                        entry.line,
+                        entry.name()
+                    )));
+                }
+            }
+        }
+        if !unknown.is_empty() {
+            log::warn(format_args!(
+                "{}: ignoring unknown keys: {}",
+                path.display(),
+                unknown.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies one entry of a configuration file. Returns `Ok(false)` for
+    /// a key that isn't a setting, and the reason for a bad value.
+    fn apply_toml_entry(&mut self, entry: &toml::Entry) -> Result<bool, String> {
+        let value = &entry.value;
+        let string = || value.as_str().map(str::to_owned).ok_or("expected a string");
+        let flag = || value.as_bool().ok_or("expected true or false");
+        let count = || {
+            value
+                .as_integer()
+                .and_then(|n| usize::try_from(n).ok())
+                .filter(|&n| n > 0)
+                .ok_or("expected a positive integer")
+        };
+        let bytes = || {
+            value
+                .as_integer()
+                .and_then(|n| u64::try_from(n).ok())
+                .ok_or("expected a number of bytes")
+        };
+        let seconds = || {
+            value
+                .as_integer()
+                .and_then(|n| u64::try_from(n).ok())
+                .map(Duration::from_secs)
+                .ok_or("expected a number of seconds")
+        };
+        match (entry.table.as_deref(), entry.key.as_str()) {
+            (None, "addresses") => {
+                let addresses = value.as_strings().ok_or("expected an array of strings")?;
+                for address in &addresses {
+                    BindAddress::parse(address).map_err(|e| e.to_string())?;
+                }
+                self.addresses = addresses;
+            }
+            (None, "trusted_proxies") => {
+                let ranges = value.as_strings().ok_or("expected an array of strings")?;
+                self.trusted_proxies = ranges
+                    .iter()
+                    .map(|range| Cidr::parse(range).ok_or(format!("{range}: not a CIDR range")))
+                    .collect::<Result<_, _>>()?;
+            }
+            (None, "forwarded_header") => {
+                self.forwarded_header = ForwardedHeader::parse(&string()?)?;
+            }
+            (None, "document_root") => self.document_root = string()?.into(),
+            (None, "index") => self.index = string()?,
+            (None, "default_mime") => self.default_mime = string()?,
+            (None, "workers") => self.reactor.workers = count()?,
+            (None, "reactors") => self.reactors = count()?,
+            (None, "max_connections") => self.max_connections = count()?,
+            (None, "max_header_fields") => self.max_header_fields = count()?,
+            (None, "max_head_size") => self.max_head_size = count()?,
+            (None, "max_body_size") => self.max_body_size = bytes()?,
+            (None, "max_in_memory_file_size") => self.max_in_memory_file_size = bytes()?,
+            (None, "keep_alive_timeout") => self.keep_alive_timeout = seconds()?,
+            (None, "header_read_timeout") => self.header_read_timeout = seconds()?,
+            (None, "header_deadline") => self.header_deadline = seconds()?,
+            (None, "write_timeout") => self.write_timeout = seconds()?,
+            (None, "handler_timeout") => {
+                self.handler_timeout = Some(seconds()?).filter(|timeout| !timeout.is_zero());
+            }
+            (None, "drain_timeout") => self.drain_timeout = seconds()?,
+            (None, "directory_listing") => self.directory_listing = flag()?,
+            (None, "clean_urls") => self.clean_urls = flag()?,
+            (None, "follow_symlinks") => self.follow_symlinks = flag()?,
+            (None, "log_level") => self.log_level = string()?.parse()?,
+            (None, "log_format") => self.set_log_format(string()?.parse()?),
+            (Some("error_pages"), code) => {
+                let code = code
+                    .parse()
+                    .ok()
+                    .filter(|code| (400..600).contains(code))
+                    .ok_or("not an error status code")?;
+                self.error_pages.insert(code, string()?);
+            }
+            (Some("mime_overrides"), extension) => {
+                self.mime_overrides.insert(extension.to_owned(), string()?);
+            }
+            (Some("cache_control"), pattern) => {
+                self.cache_control
+                    .push(CacheRule::new(pattern, &string()?)?);
+            }
+            (Some("mounts"), prefix) => self.mounts.push(Mount::new(prefix, string()?)?),
+            (Some("proxies"), prefix) => {
+                self.proxies.push(ProxyConfig::new(prefix, &string()?)?);
+            }
+            (Some("rewrites"), pattern) => {
+                self.rewrites.push(RewriteRule::parse(pattern, &string()?)?);
+            }
+            (Some("spa_fallback"), "enabled") => {
+                if flag()? {
+                    self.spa_fallback.get_or_insert_default();
+                } else {
+                    self.spa_fallback = None;
+                }
+            }
+            (Some("spa_fallback"), "prefix") => {
+                let prefix = string()?;
+                if !prefix.starts_with('/') {
+                    return Err(String::from("expected a path starting with /"));
+                }
+                self.spa_fallback.get_or_insert_default().prefix = prefix;
+            }
+            (Some("spa_fallback"), "index") => {
+                self.spa_fallback.get_or_insert_default().index = string()?;
+            }
+            (Some("access_log"), "enabled") => {
+                if flag()? {
+                    self.access_log.get_or_insert_default();
+                } else {
+                    self.access_log = None;
+                }
+            }
+            (Some("access_log"), "format") => {
+                let format = match string()?.as_str() {
+                    "common" => AccessLogFormat::common(),
+                    "combined" => AccessLogFormat::combined(),
+                    "json" => AccessLogFormat::json(),
+                    layout => AccessLogFormat::parse(layout)?,
+                };
+                self.access_log.get_or_insert_default().format = format;
+            }
+            (Some("access_log"), "path") => {
+                self.access_log.get_or_insert_default().target = log_target(&string()?);
+            }
+            (Some("request_id"), "enabled") => {
+                if flag()? {
+                    self.request_id.get_or_insert_default();
+                } else {
+                    self.request_id = None;
+                }
+            }
+            (Some("request_id"), "header") => {
+                self.request_id.get_or_insert_default().header = string()?;
+            }
+            (Some("request_id"), "trust_incoming") => {
+                self.request_id.get_or_insert_default().trust_incoming = flag()?;
+            }
+            (Some("metrics"), "enabled") => {
+                if flag()? {
+                    self.metrics.get_or_insert_default();
+                } else {
+                    self.metrics = None;
+                }
+            }
+            (Some("metrics"), "path") => self.metrics.get_or_insert_default().path = string()?,
+            (Some("metrics"), "allow") => {
+                let ranges = value.as_strings().ok_or("expected an array of strings")?;
+                let allow = ranges
+                    .iter()
+                    .map(|range| Cidr::parse(range).ok_or(format!("{range}: not a CIDR range")))
+                    .collect::<Result<_, _>>()?;
+                self.metrics.get_or_insert_default().allow = allow;
+            }
+            (Some("redirect_to_https"), "enabled") => {
+                if flag()? {
+                    self.redirect_to_https.get_or_insert_default();
+                } else {
+                    self.redirect_to_https = None;
+                }
+            }
+            (Some("redirect_to_https"), "addresses") => {
+                let addresses = value.as_strings().ok_or("expected an array of strings")?;
+                self.redirect_to_https.get_or_insert_default().addresses = addresses;
+            }
+            (Some("redirect_to_https"), "canonical_host") => {
+                let host = string()?;
+                if https::host_name(&host).is_none() {
+                    return Err(format!("{host}: not a host name"));
+                }
+                self.redirect_to_https
+                    .get_or_insert_default()
+                    .canonical_host = Some(host);
+            }
+            (Some("redirect_to_https"), "https_port") => {
+                let port = value
+                    .as_integer()
+                    .and_then(|n| u16::try_from(n).ok())
+                    .filter(|&n| n > 0)
+                    .ok_or("expected a port number")?;
+                self.redirect_to_https.get_or_insert_default().https_port = Some(port);
+            }
+            (Some("hsts"), "enabled") => {
+                if flag()? {
+                    self.hsts.get_or_insert_default();
+                } else {
+                    self.hsts = None;
+                }
+            }
+            (Some("hsts"), "max_age") => self.hsts.get_or_insert_default().max_age = seconds()?,
+            (Some("hsts"), "include_subdomains") => {
+                self.hsts.get_or_insert_default().include_subdomains = flag()?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Replaces `document_root` with its absolute, canonical form.
+    ///
+    /// Called once at startup so that a relative root means "relative to where
+    /// the server was started" and every request sees the same absolute path.
+    ///
+    /// # Errors
+    /// Fails if the root doesn't exist or isn't a directory, with the path in
+    /// the message.
+    pub fn resolve_document_root(&mut self) -> io::Result<()> {
+        let root = std::fs::canonicalize(&self.document_root).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("document root {}: {e}", self.document_root.display()),
+            )
+        })?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("document root {} is not a directory", root.display()),
+            ));
+        }
+        self.document_root = root;
+        Ok(())
+    }
+
+    /// Replaces the directory of every mount with its absolute, canonical
+    /// form, as `resolve_document_root` does for the document root.
+    ///
+    /// # Errors
+    /// Fails if a directory doesn't exist or isn't one, with the path in the
+    /// message.
+    pub fn resolve_mounts(&mut self) -> io::Result<()> {
+        for mount in &mut self.mounts {
+            let directory = std::fs::canonicalize(&mount.directory).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "mount {} directory {}: {e}",
+                        mount.prefix,
+                        mount.directory.display()
+                    ),
+                )
+            })?;
+            if !directory.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!(
+                        "mount {} directory {} is not a directory",
+                        mount.prefix,
+                        directory.display()
+                    ),
+                ));
+            }
+            mount.directory = directory;
+        }
+        Ok(())
+    }
+
+    /// Returns the config of the site `request` is for: the virtual host
+    /// serving its `Host` (port and case aside), or this config itself if
+    /// there are no virtual hosts.
+    ///
+    /// A host named exactly by a virtual host wins over a wildcard; among
+    /// wildcards, the first virtual host listing a matching one wins.
+    ///
+    /// # Errors
+    /// The error page `unknown_host` calls for, when no virtual host serves
+    /// the request's host (or it has no `Host` header).
+    pub fn site_for(&self, request: &HttpRequest) -> Result<&ServerConfig, ErrorPage> {
+        if self.virtual_hosts.is_empty() {
+            return Ok(self);
+        }
+        let host = request.header("Host").map(host_name);
+        let serving = |wildcard: bool| {
+            let host = host.as_deref()?;
+            self.virtual_hosts.iter().find(|vhost| {
+                vhost
+                    .hosts
+                    .iter()
+                    .any(|pattern| host_matches(pattern, host, wildcard))
+            })
+        };
+        match (serving(false).or_else(|| serving(true)), self.unknown_host) {
+            (Some(vhost), _) => Ok(&vhost.config),
+            (None, UnknownHost::Default) => Ok(self),
+            (None, UnknownHost::Misdirected) => Err(ErrorPage::MisdirectedRequest),
+            (None, UnknownHost::NotFound) => Err(ErrorPage::NotFound),
+        }
+    }
+
+    /// Returns the proxy that forwards requests for `path`: the one with the
+    /// longest prefix `path` is under, if any.
+    pub fn proxy_for(&self, path: &str) -> Option<&ProxyConfig> {
+        self.proxies
+            .iter()
+            .filter(|proxy| proxy.strip(path).is_some())
+            .max_by_key(|proxy| proxy.prefix.trim_end_matches('/').len())
+    }
+
+    /// Returns the `AssetSource` selected by `assets`, with `mounts` on top.
+    pub fn asset_source(&self) -> Box<dyn AssetSource + '_> {
+        let source: Box<dyn AssetSource + '_> = match self.assets {
+            Assets::Filesystem => Box::new(FilesystemSource {
+                root: &self.document_root,
+                follow_symlinks: self.follow_symlinks,
+            }),
+            #[cfg(feature = "embed")]
+            Assets::Embedded => Box::new(EmbeddedSource),
+        };
+        if self.mounts.is_empty() {
+            return source;
+        }
+        Box::new(MountedSource::new(
+            source,
+            &self.mounts,
+            self.follow_symlinks,
+        ))
+    }
+
+    /// Returns `true` if the decoded request `path` contains a component starting
+    /// with `.` that isn't covered by `hidden_allowlist`.
+    ///
+    /// Only the part of the path after a matching allowlisted prefix is checked,
+    /// so `/.well-known/acme-challenge/token` is allowed while
+    /// `/.well-known/.secret` is still hidden.
+    pub fn is_hidden_path(&self, path: &str) -> bool {
+        let rest = self
+            .hidden_allowlist
+            .iter()
+            .filter_map(|prefix| {
+                let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+                (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+            })
+            .min_by_key(|rest| rest.len())
+            .unwrap_or(path);
+        rest.split('/').any(|component| component.starts_with('.'))
+    }
+
+    /// Returns the largest body accepted for `request`: the upload limit for
+    /// `PUT`s under the upload prefix, `max_body_size` for everything else.
+    pub fn body_limit(&self, request: &HttpRequest) -> u64 {
+        match &self.upload {
+            Some(upload) if request.method == Method::Put && upload.matches(&request.path) => {
+                upload.max_size
+            }
+            _ => self.max_body_size,
+        }
+    }
+
+    /// Returns `true` if a file of `size` bytes is read into memory rather
+    /// than streamed: it fits `max_in_memory_file_size` and isn't big enough
+    /// to be memory mapped.
+    pub fn serves_from_memory(&self, size: u64) -> bool {
+        size <= self.max_in_memory_file_size
+            && self
+                .mmap_threshold
+                .is_none_or(|threshold| size <= threshold)
+    }
+
+    /// Returns the `Cache-Control` value of the first of `cache_control`
+    /// matching `filename`, if any.
+    pub fn cache_control_for(&self, filename: &str) -> Option<&str> {
+        self.cache_control
+            .iter()
+            .find(|rule| rule.matches(filename))
+            .map(|rule| rule.value.as_str())
+    }
+
+    /// Returns `true` if `filename` should be served with
+    /// `Content-Disposition: attachment`. The extension compare ignores case.
+    pub fn forces_attachment(&self, filename: &str) -> bool {
+        let Some(ext) = Path::new(filename).extension() else {
+            return false;
+        };
+        let ext = ext.to_string_lossy();
+        self.force_attachment_extensions
+            .iter()
+            .any(|forced| forced.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+    }
+}
+
+/// A `ServerConfig` under construction, from `ServerConfig::builder`.
+///
+/// Settings without a method keep their default; set them on the built
+/// config.
+///
+/// # Example
+/// ```
+/// let config = ServerConfig::builder()
+///     .address("0.0.0.0:8080")
+///     .document_root("/srv/www")
+///     .workers(8)
+///     .keep_alive_timeout(Duration::from_secs(15))
+///     .max_body_size(64 * 1024)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+    // Replace the default address once at least one is given.
+    addresses: Vec<String>,
+}
+
+impl ServerConfigBuilder {
+    /// Adds an address to listen on (see `ServerConfig::addresses`); call
+    /// again for more. Defaults to `127.0.0.1:8080` if never called.
+    pub fn address(mut self, address: impl Into<String>) -> ServerConfigBuilder {
+        self.addresses.push(address.into());
+        self
+    }
+
+    /// Sets the directory files are served from. Defaults to `public`.
+    pub fn document_root(mut self, root: impl Into<PathBuf>) -> ServerConfigBuilder {
+        self.config.document_root = root.into();
+        self
+    }
+
+    /// Adds a rewrite or redirect rule after those added before (see
+    /// `http::rewrite`).
+    pub fn rewrite(mut self, rule: RewriteRule) -> ServerConfigBuilder {
+        self.config.rewrites.push(rule);
+        self
+    }
+
+    /// Serves a single-page app's index page for navigations to missing paths
+    /// (see `SpaFallback`).
+    pub fn spa_fallback(mut self, fallback: SpaFallback) -> ServerConfigBuilder {
+        self.config.spa_fallback = Some(fallback);
+        self
+    }
+
+    /// Renders template files as pages (see `http::template`).
+    pub fn templates(mut self, templates: TemplateConfig) -> ServerConfigBuilder {
+        self.config.templates = Some(templates);
+        self
+    }
+
+    /// Adds a `Cache-Control` rule for static files (see `CacheRule`); call
+    /// again for more. The first matching rule wins.
+    pub fn cache_control(mut self, rule: CacheRule) -> ServerConfigBuilder {
+        self.config.cache_control.push(rule);
+        self
+    }
+
+    /// Serves the directory `directory` under the URL `prefix` (see `Mount`);
+    /// call again for more. `build` resolves the directory and checks the prefix.
+    pub fn mount(
+        mut self,
+        prefix: impl Into<String>,
+        directory: impl Into<PathBuf>,
+    ) -> ServerConfigBuilder {
+        self.config.mounts.push(Mount {
+            prefix: prefix.into(),
+            directory: directory.into(),
+            fall_through: false,
+        });
+        self
+    }
+
+    /// Adds a path prefix forwarded to an upstream server (see `ProxyConfig`);
+    /// call again for more.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> ServerConfigBuilder {
+        self.config.proxies.push(proxy);
+        self
+    }
+
+    /// Sets the thread pool size of each reactor (`reactor.workers`).
+    /// Defaults to `io::nonblocking::default_workers()`.
+    pub fn workers(mut self, workers: usize) -> ServerConfigBuilder {
+        self.config.reactor.workers = workers;
+        self
+    }
+
+    /// Sets the dynamic endpoints. Defaults to none.
+    pub fn router(mut self, router: Router) -> ServerConfigBuilder {
+        self.config.router = Arc::new(router);
+        self
+    }
+
+    /// Adds a virtual host serving `hosts` with `config` (see `VirtualHost`).
+    /// `build` resolves its document root, too.
+    pub fn virtual_host(
+        mut self,
+        hosts: impl IntoIterator<Item = impl Into<String>>,
+        config: ServerConfig,
+    ) -> ServerConfigBuilder {
+        self.config.virtual_hosts.push(VirtualHost {
+            hosts: hosts.into_iter().map(Into::into).collect(),
+            config: Arc::new(config),
+        });
+        self
+    }
+
+    /// Sets what requests for other hosts get. Defaults to `UnknownHost::Default`.
+    pub fn unknown_host(mut self, unknown_host: UnknownHost) -> ServerConfigBuilder {
+        self.config.unknown_host = unknown_host;
+        self
+    }
+
+    /// Sets the number of event loops. Defaults to the number of cores.
+    pub fn reactors(mut self, reactors: usize) -> ServerConfigBuilder {
+        self.config.reactors = reactors;
+        self
+    }
+
+    /// Sets the most connections served at once. Defaults to 1024.
+    pub fn max_connections(mut self, max: usize) -> ServerConfigBuilder {
+        self.config.max_connections = max;
+        self
+    }
+
+    /// Sets the largest request body accepted outside uploads. Defaults to 1 MiB.
+    pub fn max_body_size(mut self, bytes: u64) -> ServerConfigBuilder {
+        self.config.max_body_size = bytes;
+        self
+    }
+
+    /// Trusts the reverse proxies in `range` to say who their clients are (see
+    /// `HttpRequest::forwarded_client`).
+    pub fn trusted_proxy(mut self, range: Cidr) -> ServerConfigBuilder {
+        self.config.trusted_proxies.push(range);
+        self
+    }
+
+    /// Sets the header trusted proxies record their hops in. Defaults to
+    /// `X-Forwarded-*`.
+    pub fn forwarded_header(mut self, header: ForwardedHeader) -> ServerConfigBuilder {
+        self.config.forwarded_header = header;
+        self
+    }
+
+    /// Sets the most header fields a request may have. Defaults to 100.
+    pub fn max_header_fields(mut self, max: usize) -> ServerConfigBuilder {
+        self.config.max_header_fields = max;
+        self
+    }
+
+    /// Sets the longest request head, in bytes. Defaults to 16 KiB.
+    pub fn max_head_size(mut self, bytes: usize) -> ServerConfigBuilder {
+        self.config.max_head_size = bytes;
+        self
+    }
+
+    /// Sets how long an idle connection is kept open. Defaults to 5 seconds.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> ServerConfigBuilder {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a partly received request may stall. Defaults to 10 seconds.
+    pub fn header_read_timeout(mut self, timeout: Duration) -> ServerConfigBuilder {
+        self.config.header_read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a response may go unread. Defaults to 30 seconds.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerConfigBuilder {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Sets how long handling a request may take. Defaults to 60 seconds.
+    pub fn handler_timeout(mut self, timeout: Duration) -> ServerConfigBuilder {
+        self.config.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long shutdown waits for open responses. Defaults to 10 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> ServerConfigBuilder {
+        self.config.drain_timeout = timeout;
+        self
+    }
+
+    /// Sets the log format; see `ServerConfig::set_log_format`.
+    pub fn log_format(mut self, format: LogFormat) -> ServerConfigBuilder {
+        self.config.set_log_format(format);
+        self
+    }
+
+    /// Gives every request an ID (see `http::request_id`).
+    pub fn request_id(mut self, request_id: RequestIdConfig) -> ServerConfigBuilder {
+        self.config.request_id = Some(request_id);
+        self
+    }
+
+    /// Logs every response (see `io::access_log`).
+    pub fn access_log(mut self, access_log: AccessLogConfig) -> ServerConfigBuilder {
+        self.config.access_log = Some(access_log);
+        self
+    }
+
+    /// Serves Prometheus metrics (see `http::metrics`).
+    pub fn metrics(mut self, metrics: MetricsConfig) -> ServerConfigBuilder {
+        self.config.metrics = Some(metrics);
+        self
+    }
+
+    /// Redirects every request on some listeners to HTTPS (see
+    /// `HttpsRedirectConfig`). `build` checks they are among the addresses.
+    pub fn redirect_to_https(mut self, redirect: HttpsRedirectConfig) -> ServerConfigBuilder {
+        self.config.redirect_to_https = Some(redirect);
+        self
+    }
+
+    /// Sends `Strict-Transport-Security` over TLS (see `HstsConfig`).
+    pub fn hsts(mut self, hsts: HstsConfig) -> ServerConfigBuilder {
+        self.config.hsts = Some(hsts);
+        self
+    }
+
+    /// Answers the health probes (see `http::health`).
+    pub fn health(mut self, health: HealthConfig) -> ServerConfigBuilder {
+        self.config.health = Some(health);
+        self
+    }
+
+    /// Sets the limits of websocket connections. Defaults to
+    /// `WebSocketConfig::default()`.
+    pub fn websocket(mut self, websocket: WebSocketConfig) -> ServerConfigBuilder {
+        self.config.websocket = websocket;
+        self
+    }
+
+    /// Checks the settings and returns the config, with `document_root` and
+    /// the mount directories made absolute (see
+    /// `ServerConfig::resolve_document_root`).
+    ///
+    /// # Errors
+    /// - `ServerError::InvalidAddress` for the first address that doesn't parse.
+    /// - `ServerError::Config` for zero workers, reactors or connections, a
+    ///   mount or `spa_fallback` prefix that doesn't start with `/`, or a
+    ///   `redirect_to_https` address that isn't listened on or canonical host
+    ///   that isn't a host name.
+    /// - `ServerError::Io` if the document root (its own or a virtual host's)
+    ///   doesn't exist or isn't a directory, only checked when serving from
+    ///   the filesystem, or if a mount directory doesn't.
+    pub fn build(self) -> Result<ServerConfig, ServerError> {
+        let ServerConfigBuilder {
+            mut config,
+            addresses,
+        } = self;
+        if !addresses.is_empty() {
+            config.addresses = addresses;
+        }
+        for address in &config.addresses {
+            BindAddress::parse(address)?;
+        }
+        for (name, value) in [
+            ("workers", config.reactor.workers),
+            ("reactors", config.reactors),
+            ("max_connections", config.max_connections),
+            ("max_header_fields", config.max_header_fields),
+            ("max_head_size", config.max_head_size),
+        ] {
+            if value == 0 {
+                return Err(ServerError::Config(format!("{name} must be at least 1")));
+            }
+        }
+        if let Some(mount) = config.mounts.iter().find(|m| !m.prefix.starts_with('/')) {
+            return Err(ServerError::Config(format!(
+                "mount prefix {} must start with /",
+                mount.prefix
+            )));
+        }
+        if let Some(spa) = config
+            .spa_fallback
+            .as_ref()
+            .filter(|spa| !spa.prefix.starts_with('/'))
+        {
+            return Err(ServerError::Config(format!(
+                "spa_fallback prefix {} must start with /",
+                spa.prefix
+            )));
+        }
+        if let Some(redirect) = &config.redirect_to_https {
+            if let Some(address) = redirect
+                .addresses
+                .iter()
+                .find(|address| !config.addresses.contains(address))
+            {
+                return Err(ServerError::Config(format!(
+                    "redirect_to_https address {address} is not one of the addresses"
+                )));
+            }
+            if let Some(host) = redirect
+                .canonical_host
+                .as_ref()
+                .filter(|host| https::host_name(host).is_none())
+            {
+                return Err(ServerError::Config(format!(
+                    "redirect_to_https canonical_host {host} is not a host name"
+                )));
+            }
+        }
+        if let Some(request_id) = &config.request_id
+            && HeaderName::try_from(request_id.header.as_str()).is_err()
+        {
+            return Err(ServerError::Config(format!(
+                "request_id header {:?} is not a header name",
+                request_id.header
+            )));
+        }
+        if config.assets == Assets::Filesystem {
+            config.resolve_document_root()?;
+        }
+        config.resolve_mounts()?;
+        for vhost in &mut config.virtual_hosts {
+            let site = Arc::make_mut(&mut vhost.config);
+            if site.assets == Assets::Filesystem {
+                site.resolve_document_root()?;
+            }
+            site.resolve_mounts()?;
+        }
+        Ok(config)
+    }
+}
+
+/// A site served to requests for some host names (see
+/// `ServerConfig::virtual_hosts`).
+///
+/// # Fields
+/// - `hosts` (*Vec<String>*): Host names, compared with the `Host` header without
+///   its port, ignoring case. `*.example.com` stands for every subdomain of
+///   `example.com`, but not `example.com` itself.
+/// - `config` (*Arc<ServerConfig>*): How the site's requests are answered: its
+///   document root, router and middleware, error pages, file cache and so on.
+///   Whatever concerns the connection rather than the request (addresses,
+///   timeouts, limits, TLS, `stats`, health probes, metrics) comes from the
+///   server's own config and is ignored here, as are the site's own
+///   `virtual_hosts`.
+///
+/// # Example
+/// ```
+/// let blog = ServerConfig {
+///     document_root: PathBuf::from("/srv/blog"),
+///     ..ServerConfig::default()
+/// };
+/// let config = ServerConfig::builder()
+///     .virtual_host(["blog.example.com", "*.blog.example.com"], blog)
+///     .unknown_host(UnknownHost::Misdirected)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct VirtualHost {
+    pub hosts: Vec<String>,
+    pub config: Arc<ServerConfig>,
+}
+
+/// What a request gets when no virtual host serves its host.
+///
+/// Variants:
+/// - `Default`: The server's own site, from its `document_root` and `router`.
+/// - `Misdirected`: `421 Misdirected Request`.
+/// - `NotFound`: `404 Not Found`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownHost {
+    #[default]
+    Default,
+    Misdirected,
+    NotFound,
+}
+
+/// A directory served under a URL prefix instead of from the document root
+/// (see `ServerConfig::mounts`).
+///
+/// A request path is under the mount if it is the prefix or continues it
+/// with a `/`: `/docs` covers `/docs` and `/docs/guide.html`, but not
+/// `/docsets`. The rest of the path is looked up in `directory` with the
+/// same traversal and symlink checks, index files, listings, ETags and file
+/// cache as the document root.
+///
+/// # Fields
+/// - `prefix` (*String*): The URL prefix, starting with `/`; a trailing `/`
+///   makes no difference.
+/// - `directory` (*PathBuf*): The directory served, resolved at startup by
+///   `ServerConfig::resolve_mounts`.
+/// - `fall_through` (*bool*): When `true`, a path under the mount that doesn't
+///   exist in `directory` is looked up in the next shorter matching mount or
+///   the document root. When `false` (the default), it gets 404.
+///
+/// # Example
+/// ```
+/// let config = ServerConfig::builder()
+///     .mount("/assets", "/srv/assets")
+///     .mount("/docs", "/usr/share/doc/site")
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub prefix: String,
+    pub directory: PathBuf,
+    pub fall_through: bool,
+}
+
+impl Mount {
+    /// Returns a mount of `directory` at `prefix` that doesn't fall through.
+    ///
+    /// # Errors
+    /// The reason, if `prefix` doesn't start with `/`.
+    pub fn new(prefix: &str, directory: impl Into<PathBuf>) -> Result<Mount, String> {
+        if !prefix.starts_with('/') {
+            return Err(format!("mount prefix {prefix} must start with /"));
+        }
+        Ok(Mount {
+            prefix: prefix.to_owned(),
+            directory: directory.into(),
+            fall_through: false,
+        })
+    }
+
+    /// Returns the part of the normalized request `path` below the prefix,
+    /// starting with `/`, or `None` if `path` isn't under the mount.
+    pub fn strip<'p>(&self, path: &'p str) -> Option<&'p str> {
+        match path.strip_prefix(self.prefix.trim_end_matches('/'))? {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+/// A `Cache-Control` value for the static files whose path matches a pattern
+/// (see `ServerConfig::cache_control`).
+///
+/// The pattern is matched against the root-relative path of the file served,
+/// after index files and `clean_urls` are resolved, so `/` is matched as
+/// `/index.html`. Each `*` matches any run of characters, `/` included:
+/// `/assets/*` covers everything under `/assets`, and `/*.html` every page.
+///
+/// # Fields
+/// - `pattern` (*String*): The paths it applies to, starting with `/`.
+/// - `value` (*String*): The header value, e.g. `public, max-age=31536000, immutable`.
+///
+/// # Example
+/// ```
+/// let config = ServerConfig::builder()
+///     .cache_control(CacheRule::new("/assets/*", "public, max-age=31536000, immutable")?)
+///     .cache_control(CacheRule::new("/*.html", "no-cache")?)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheRule {
+    pub pattern: String,
+    pub value: String,
+}
+
+impl CacheRule {
+    /// Returns a rule sending `value` for the files matching `pattern`.
+    ///
+    /// # Errors
+    /// The reason, if `pattern` doesn't start with `/` or `value` can't be
+    /// sent as a header value.
+    pub fn new(pattern: &str, value: &str) -> Result<CacheRule, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("cache_control pattern {pattern} must start with /"));
+        }
+        if HeaderValue::try_from(value).is_err() {
+            return Err(format!("{value:?} is not a valid header value"));
+        }
+        Ok(CacheRule {
+            pattern: pattern.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+
+    /// Returns `true` if the rule applies to the file at `path`.
+    pub fn matches(&self, path: &str) -> bool {
+        util::wildcard_captures(&self.pattern, path).is_some()
+    }
+}
+
+/// Answers browser navigations to missing paths with a single-page app's
+/// index page, so deep links into an app that does its own routing
+/// (`/app/orders/42`) work (see `ServerConfig::spa_fallback`).
+///
+/// A `GET` or `HEAD` request that would get `404 Not Found` from the static
+/// files gets `index` with `200 OK` instead if its path is under `prefix`,
+/// its last segment has no extension, and it prefers HTML (see
+/// `HttpRequest::prefers_html`). A missing asset (`/app/main.js`) or a call
+/// asking for JSON still gets 404. Files that exist are served as ever,
+/// including the `.html` pages `clean_urls` finds: `/app/about` serves
+/// `app/about.html` if there is one and falls back only if there isn't.
+/// Responses the fallback decides on carry `Vary: Accept`.
+///
+/// # Fields
+/// - `prefix` (*String*): Where the app lives, starting with `/`: `/app`
+///   covers `/app` and everything under it, but not `/apps`. Defaults to
+///   `/`, the whole site.
+/// - `index` (*String*): The page served instead, as a request path.
+///   Defaults to `/index.html`.
+///
+/// # Example
+/// ```
+/// let config = ServerConfig::builder()
+///     .spa_fallback(SpaFallback {
+///         prefix: String::from("/app"),
+///         index: String::from("/app/index.html"),
+///     })
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaFallback {
+    pub prefix: String,
+    pub index: String,
+}
+
+impl Default for SpaFallback {
+    fn default() -> SpaFallback {
+        SpaFallback {
+            prefix: String::from("/"),
+            index: String::from("/index.html"),
+        }
+    }
+}
+
+impl SpaFallback {
+    /// Returns `true` if a miss on `path` may fall back to the index page,
+    /// whatever the request prefers: `path` is under `prefix` and its last
+    /// segment has no extension.
+    pub(crate) fn covers(&self, path: &str) -> bool {
+        let under = match path.strip_prefix(self.prefix.trim_end_matches('/')) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        under && Path::new(path).extension().is_none()
+    }
+}
+
+/// Returns the host name in a `Host` header value: without the port or a
+/// trailing dot, in lowercase. IPv6 literals keep their brackets.
+fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.rfind(':') {
+        // A colon inside brackets is part of an IPv6 address, not a port.
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns `true` if `host` (from `host_name`) is named by `pattern`, as an
+/// exact name or, if `wildcard`, as a `*.` pattern.
+fn host_matches(pattern: &str, host: &str, wildcard: bool) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) if wildcard => host
+            .strip_suffix(domain)
+            .and_then(|sub| sub.strip_suffix('.'))
+            .is_some_and(|sub| !sub.is_empty()),
+        Some(_) => false,
+        None => !wildcard && pattern.trim_end_matches('.').eq_ignore_ascii_case(host),
+    }
+}
+
+/// A handler for a `Router` endpoint.
+pub type Handler = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+/// Dynamic endpoints, by method and path pattern, and the middleware
+/// wrapped around every request.
+///
+/// `http::response::handle` asks the router before looking for a file: a
+/// path no route matches falls through to the static files, and a path that
+/// matches only under other methods gets `405 Method Not Allowed` listing
+/// them in `Allow`. `HEAD` is answered by the `GET` route, without the body.
+///
+/// Middleware (see `Middleware`) runs around all of it, routed and static
+/// requests alike, in the order it was added: the first is the outermost.
+///
+/// A pattern is either an exact path (`/healthz`) or a prefix ending in `/*`
+/// (`/api/*` matches `/api/` and everything under it, but not `/api`).
+/// Routes are tried in the order they were added, websocket routes (see
+/// `Router::websocket`) before the others.
+///
+/// Paths can also be given their own `ServerConfig::handler_timeout` with
+/// `Router::timeout`, whether a route, a proxy or a file answers them.
+///
+/// # Example
+/// ```
+/// let mut router = Router::new();
+/// router.get("/healthz", |_| HttpResponse::text(StatusCode::OK, "ok"));
+/// router.websocket("/echo", |socket: &WebSocket, message: Message| {
+///     let _ = socket.send(message);
+/// });
+/// router.get("/reports/*", build_report).timeout("/reports/*", Duration::from_secs(300));
+/// let config = ServerConfig::builder().router(router).build()?;
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    websockets: Vec<(String, Arc<dyn WebSocketHandler>)>,
+    middleware: Vec<Box<dyn Middleware>>,
+    timeouts: Vec<(String, Duration)>,
+}
+
+struct Route {
+    method: Method,
+    pattern: String,
+    handler: Handler,
+}
+
+/// What a `Router` has for a request.
+///
+/// Variants:
+/// - `Handler(&Handler)`: The handler to answer it with.
+/// - `WebSocket(&Arc<dyn WebSocketHandler>)`: A websocket route matches the path;
+///   the request is answered with the opening handshake.
+/// - `MethodNotAllowed(String)`: Routes match the path, but not the method;
+///   the `Allow` header value listing the methods that do.
+/// - `NoRoute`: No route matches the path.
+pub enum RouteMatch<'a> {
+    Handler(&'a Handler),
+    WebSocket(&'a Arc<dyn WebSocketHandler>),
+    MethodNotAllowed(String),
+    NoRoute,
+}
+
+impl Router {
+    /// Returns a router without routes.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a route for `method` requests matching `pattern`.
+    pub fn route(
+        &mut self,
+        method: Method,
+        pattern: impl Into<String>,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> &mut Router {
+        self.routes.push(Route {
+            method,
+            pattern: pattern.into(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Adds a `GET` (and `HEAD`) route.
+    pub fn get(
+        &mut self,
+        pattern: impl Into<String>,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> &mut Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    /// Adds a `POST` route.
+    pub fn post(
+        &mut self,
+        pattern: impl Into<String>,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> &mut Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Adds a `PUT` route.
+    pub fn put(
+        &mut self,
+        pattern: impl Into<String>,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> &mut Router {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    /// Adds a `DELETE` route.
+    pub fn delete(
+        &mut self,
+        pattern: impl Into<String>,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> &mut Router {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Adds a websocket route: a request matching `pattern` is answered with
+    /// the opening handshake, and the connection it upgrades is handed to
+    /// `handler` (see `http::websocket`).
+    pub fn websocket(
+        &mut self,
+        pattern: impl Into<String>,
+        handler: impl WebSocketHandler + 'static,
+    ) -> &mut Router {
+        self.websockets.push((pattern.into(), Arc::new(handler)));
+        self
+    }
+
+    /// Adds `middleware` inside the middleware added before it.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Gives requests for paths matching `pattern` `timeout` to be handled in,
+    /// instead of `ServerConfig::handler_timeout`. The first matching pattern
+    /// added wins.
+    pub fn timeout(&mut self, pattern: impl Into<String>, timeout: Duration) -> &mut Router {
+        self.timeouts.push((pattern.into(), timeout));
+        self
+    }
+
+    /// Returns the handling timeout `Router::timeout` set for `path`, if any.
+    pub fn timeout_for(&self, path: &str) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .find(|(pattern, _)| pattern_matches(pattern, path))
+            .map(|&(_, timeout)| timeout)
+    }
+
+    /// Returns `true` if no route and no middleware has been added.
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.websockets.is_empty() && self.middleware.is_empty()
+    }
+
+    /// Answers `request` with `endpoint`, through the middleware.
+    ///
+    /// Middleware may change the request, so it works on a copy; without
+    /// middleware `endpoint` gets `request` itself.
+    pub fn serve(
+        &self,
+        request: &HttpRequest,
+        endpoint: impl Fn(&HttpRequest) -> HttpResponse,
+    ) -> HttpResponse {
+        if self.middleware.is_empty() {
+            return endpoint(request);
+        }
+        run_chain(&self.middleware, &mut request.clone(), &|request| {
+            endpoint(request)
+        })
+    }
+
+    /// Finds the route for `request`.
+    pub fn lookup(&self, request: &HttpRequest) -> RouteMatch<'_> {
+        if let Some((_, handler)) = self
+            .websockets
+            .iter()
+            .find(|(pattern, _)| pattern_matches(pattern, &request.path))
+        {
+            return RouteMatch::WebSocket(handler);
+        }
+        let mut matching = self
+            .routes
+            .iter()
+            .filter(|route| pattern_matches(&route.pattern, &request.path))
+            .peekable();
+        if matching.peek().is_none() {
+            return RouteMatch::NoRoute;
+        }
+        let mut allow: Vec<&str> = Vec::new();
+        for route in matching {
+            if route.method == request.method
+                || (route.method == Method::Get && request.method == Method::Head)
+            {
+                return RouteMatch::Handler(&route.handler);
+            }
+            let methods = match route.method {
+                Method::Get => &["GET", "HEAD"][..],
+                ref method => &[method.as_str()][..],
+            };
+            for method in methods {
+                if !allow.contains(method) {
+                    allow.push(method);
+                }
+            }
+        }
+        RouteMatch::MethodNotAllowed(allow.join(", "))
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let routes: Vec<String> = self
+            .routes
+            .iter()
+            .map(|route| format!("{} {}", route.method.as_str(), route.pattern))
+            .collect();
+        let websockets: Vec<&str> = self
+            .websockets
+            .iter()
+            .map(|(pattern, _)| pattern.as_str())
+            .collect();
+        f.debug_struct("Router")
+            .field("routes", &routes)
+            .field("websockets", &websockets)
+            .field("middleware", &self.middleware.len())
+            .field("timeouts", &self.timeouts)
+            .finish()
+    }
+}
+
+/// Code that runs around the handling of every request, e.g. for logging,
+/// authentication or extra response headers (see `Router::wrap`).
+///
+/// `handle` gets the request, which it may change, and `next`, which runs the
+/// rest of the chain (the middleware added later, then the route or static
+/// file) and returns its response. It can return early without calling
+/// `next`, or change what `next` returned on the way out.
+///
+/// Functions and closures with the same signature are middleware too.
+///
+/// # Example
+/// ```
+/// router.wrap(|request: &mut HttpRequest, next: Next<'_>| {
+///     if request.header("Authorization").is_none() {
+///         return HttpResponse::text(StatusCode::UNAUTHORIZED, "who are you?");
+///     }
+///     next(request).with_header(
+///         HeaderName::from_static("X-Frame-Options"),
+///         HeaderValue::from_static("DENY"),
+///     )
+/// });
+/// ```
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// The rest of a middleware chain, as passed to `Middleware::handle`.
+pub type Next<'a> = &'a dyn Fn(&mut HttpRequest) -> HttpResponse;
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut HttpRequest, Next<'_>) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        self(request, next)
+    }
+}
+
+/// Runs `layers` around `endpoint`, outermost first.
+fn run_chain(
+    layers: &[Box<dyn Middleware>],
+    request: &mut HttpRequest,
+    endpoint: Next<'_>,
+) -> HttpResponse {
+    match layers.split_first() {
+        Some((layer, rest)) => layer.handle(request, &|request| run_chain(rest, request, endpoint)),
+        None => endpoint(request),
+    }
+}
+
+/// Middleware printing a line per request: client, method, target, status
+/// and how long the answer took to build, e.g.
+/// `127.0.0.1 GET /index.html 200 0.4ms`, tokens in the query blanked (see
+/// `http::auth::redact_query`). Printed at `LogLevel::Info`.
+///
+/// The user an authentication middleware wrapped after it let in follows
+/// the client, e.g. `127.0.0.1 (alice) GET /admin/ 200 0.4ms`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let started = Instant::now();
+        let (method, target) = (
+            request.method.clone(),
+            redact_query(&request.target).into_owned(),
+        );
+        let client = request
+            .peer
+            .map_or_else(|| String::from("-"), |ip| ip.to_string());
+        let response = next(request);
+        if log::enabled(LogLevel::Info) {
+            let client = match &request.user {
+                Some(user) => format!("{client} ({user})"),
+                None => client,
+            };
+            log::info(format_args!(
+                "{client} {} {target} {} {:.1}ms",
+                method.as_str(),
+                response.status().as_u16(),
+                started.elapsed().as_secs_f64() * 1000.0
+            ));
+        }
+        response
+    }
+}
+
+/// Returns `true` if the request `path` matches a route `pattern`.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) if prefix.ends_with('/') => path.starts_with(prefix),
+        _ => path == pattern,
+    }
+}
+
+/// Returns where the access log goes for a `--access-log` or `path` value:
+/// `-` is standard output, anything else a file.
+fn log_target(value: &str) -> LogTarget {
+    match value {
+        "-" => LogTarget::Stdout,
+        path => LogTarget::File(PathBuf::from(path)),
+    }
+}
+
+/// Returns the IP address `address` consists of, if it has no port: `0.0.0.0`,
+/// `::1` or `[::1]`.
+fn host_only(address: &str) -> Option<IpAddr> {
+    let unbracketed = address
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(address);
+    unbracketed.parse().ok()
+}
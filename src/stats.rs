@@ -0,0 +1,341 @@
+//! Connection and traffic counters.
+//!
+//! One `ServerStats` is shared (through `ServerConfig::stats`) by every
+//! reactor and worker of a server. The counters are plain atomics updated
+//! with relaxed ordering, so recording is cheap and a `snapshot` is a set of
+//! loads that may be a few events apart from each other, never a consistent
+//! cut. That is fine for metrics and for tests that wait for a known state.
+//!
+//! `write_prometheus` renders the counters, with those of the worker pools
+//! being watched, in the Prometheus text format (see `http::metrics`).
+use crate::http::status::StatusCode;
+use crate::thread_pool::PoolMonitor;
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The upper bounds of the request duration histogram's buckets, in
+/// microseconds, with their `le` labels (Prometheus' default buckets).
+const DURATION_BUCKETS: [(u64, &str); 11] = [
+    (5_000, "0.005"),
+    (10_000, "0.01"),
+    (25_000, "0.025"),
+    (50_000, "0.05"),
+    (100_000, "0.1"),
+    (250_000, "0.25"),
+    (500_000, "0.5"),
+    (1_000_000, "1"),
+    (2_500_000, "2.5"),
+    (5_000_000, "5"),
+    (10_000_000, "10"),
+];
+
+/// Live counters for a server.
+///
+/// # Example
+/// ```
+/// let stats = ServerStats::default();
+/// stats.record_response(StatusCode::NOT_FOUND);
+/// assert_eq!(stats.snapshot().responses_4xx, 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    connections_accepted: AtomicU64,
+    connections_active: AtomicU64,
+    requests_served: AtomicU64,
+    // Responses by status class, 1xx through 5xx.
+    responses: [AtomicU64; 5],
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    // Event loop timings, only recorded with `ReactorConfig::loop_metrics`.
+    loop_iterations: AtomicU64,
+    loop_events: AtomicU64,
+    loop_max_events: AtomicU64,
+    loop_busy_micros: AtomicU64,
+    loop_max_iteration_micros: AtomicU64,
+    // Time spent by phase, indexed by `LoopPhase`.
+    phase_micros: [AtomicU64; 3],
+    // Requests timed, by `DURATION_BUCKETS` bucket (not cumulative), the
+    // last one for those slower than every bound.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_micros: AtomicU64,
+    pools: Mutex<Vec<PoolMonitor>>,
+}
+
+/// The part of an event loop iteration a stretch of time was spent in.
+///
+/// Variants:
+/// - `Accept`: Accepting connections.
+/// - `Read`: Reading and parsing requests.
+/// - `Write`: Queuing and writing responses, including those arriving from the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopPhase {
+    Accept,
+    Read,
+    Write,
+}
+
+/// The values of a `ServerStats` at one moment.
+///
+/// # Fields
+/// - `connections_accepted` (*u64*): Connections accepted since startup, including
+///   ones turned away at the connection limit or by `ServerConfig::ip_filter`.
+/// - `connections_active` (*u64*): Connections open right now.
+/// - `requests_served` (*u64*): Responses queued for sending, error responses included.
+/// - `responses_1xx` .. `responses_5xx` (*u64*): `requests_served` by status class.
+/// - `bytes_read` (*u64*): Request bytes received (decrypted bytes, under TLS).
+/// - `bytes_written` (*u64*): Response bytes sent (before encryption, under TLS).
+/// - `loop_iterations` (*u64*): Event loop wakeups, across all reactors.
+/// - `loop_events` (*u64*): Events handled by those wakeups.
+/// - `loop_max_events` (*u64*): The most events a single wakeup handled.
+/// - `loop_busy_micros` (*u64*): Time spent handling wakeups, i.e. not waiting
+///   for events. Divided by `loop_iterations`, the average iteration time.
+/// - `loop_max_iteration_micros` (*u64*): The longest single iteration.
+/// - `accept_micros`, `read_micros`, `write_micros` (*u64*): `loop_busy_micros`
+///   by `LoopPhase`.
+///
+/// The `loop_` and phase fields stay zero unless `ReactorConfig::loop_metrics` is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatsSnapshot {
+    pub connections_accepted: u64,
+    pub connections_active: u64,
+    pub requests_served: u64,
+    pub responses_1xx: u64,
+    pub responses_2xx: u64,
+    pub responses_3xx: u64,
+    pub responses_4xx: u64,
+    pub responses_5xx: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub loop_iterations: u64,
+    pub loop_events: u64,
+    pub loop_max_events: u64,
+    pub loop_busy_micros: u64,
+    pub loop_max_iteration_micros: u64,
+    pub accept_micros: u64,
+    pub read_micros: u64,
+    pub write_micros: u64,
+}
+
+impl ServerStats {
+    /// Counts a newly accepted connection, which is now active.
+    pub fn connection_opened(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+        self.connections_active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection turned away without ever becoming active.
+    pub fn connection_refused(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an active connection that has closed.
+    pub fn connection_closed(&self) {
+        self.connections_active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of connections open right now.
+    pub fn connections_active(&self) -> u64 {
+        self.connections_active.load(Ordering::Relaxed)
+    }
+
+    /// Counts a response with `status`. Codes outside 100-599 only count
+    /// towards `requests_served`.
+    pub fn record_response(&self, status: StatusCode) {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = self
+            .responses
+            .get((status.as_u16() / 100).wrapping_sub(1) as usize)
+        {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts `n` bytes received from a client.
+    pub fn record_read(&self, n: usize) {
+        self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `n` bytes sent to a client.
+    pub fn record_written(&self, n: usize) {
+        self.bytes_written.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Counts one event loop iteration that handled `events` events in `time`.
+    pub fn record_iteration(&self, events: usize, time: Duration) {
+        let micros = time.as_micros() as u64;
+        self.loop_iterations.fetch_add(1, Ordering::Relaxed);
+        self.loop_events.fetch_add(events as u64, Ordering::Relaxed);
+        self.loop_max_events
+            .fetch_max(events as u64, Ordering::Relaxed);
+        self.loop_busy_micros.fetch_add(micros, Ordering::Relaxed);
+        self.loop_max_iteration_micros
+            .fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Adds `time` spent in `phase` of an event loop iteration.
+    pub fn record_phase(&self, phase: LoopPhase, time: Duration) {
+        self.phase_micros[phase as usize].fetch_add(time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a request that took `time` from being parsed to the last byte
+    /// of its response being sent.
+    pub fn record_duration(&self, time: Duration) {
+        let micros = time.as_micros() as u64;
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&(bound, _)| micros <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Adds a worker pool to those `write_prometheus` reports on.
+    pub fn watch_pool(&self, pool: PoolMonitor) {
+        self.pools
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(pool);
+    }
+
+    /// Writes the counters to `out` in the Prometheus text exposition
+    /// format: requests by status class, connections, bytes, the request
+    /// duration histogram and the load of the watched pools, summed.
+    ///
+    /// # Errors
+    /// Only those of `out`; writing to a `String` never fails.
+    pub fn write_prometheus(&self, out: &mut impl Write) -> fmt::Result {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+            )
+        };
+        metric(
+            "http_connections_accepted_total",
+            "counter",
+            "Connections accepted, including those turned away.",
+            load(&self.connections_accepted),
+        )?;
+        metric(
+            "http_connections_active",
+            "gauge",
+            "Connections open right now.",
+            load(&self.connections_active),
+        )?;
+        metric(
+            "http_bytes_read_total",
+            "counter",
+            "Request bytes received.",
+            load(&self.bytes_read),
+        )?;
+        metric(
+            "http_bytes_written_total",
+            "counter",
+            "Response bytes sent.",
+            load(&self.bytes_written),
+        )?;
+
+        let (size, busy, queued, completed) = self
+            .pools
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .fold((0, 0, 0, 0), |(size, busy, queued, completed), pool| {
+                (
+                    size + pool.current_size(),
+                    busy + pool.active_jobs(),
+                    queued + pool.queued_jobs(),
+                    completed + pool.completed_jobs(),
+                )
+            });
+        metric(
+            "threadpool_workers",
+            "gauge",
+            "Worker threads running.",
+            size as u64,
+        )?;
+        metric(
+            "threadpool_busy_workers",
+            "gauge",
+            "Workers running a job.",
+            busy as u64,
+        )?;
+        metric(
+            "threadpool_queue_depth",
+            "gauge",
+            "Jobs waiting for a free worker.",
+            queued as u64,
+        )?;
+        metric(
+            "threadpool_jobs_completed_total",
+            "counter",
+            "Jobs run by the workers.",
+            completed as u64,
+        )?;
+
+        out.write_str(
+            "# HELP http_requests_total Responses queued for sending, by status class.\n\
+             # TYPE http_requests_total counter\n",
+        )?;
+        for (class, count) in self.responses.iter().enumerate() {
+            writeln!(
+                out,
+                "http_requests_total{{status_class=\"{}xx\"}} {}",
+                class + 1,
+                load(count)
+            )?;
+        }
+
+        out.write_str(
+            "# HELP http_request_duration_seconds Time from a request being parsed \
+             to the last byte of its response being sent.\n\
+             # TYPE http_request_duration_seconds histogram\n",
+        )?;
+        let mut cumulative = 0;
+        for (&(_, le), count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += load(count);
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{le=\"{le}\"}} {cumulative}"
+            )?;
+        }
+        cumulative += load(&self.duration_buckets[DURATION_BUCKETS.len()]);
+        let micros = load(&self.duration_micros);
+        writeln!(
+            out,
+            "http_request_duration_seconds_bucket{{le=\"+Inf\"}} {cumulative}\n\
+             http_request_duration_seconds_sum {}.{:06}\n\
+             http_request_duration_seconds_count {cumulative}",
+            micros / 1_000_000,
+            micros % 1_000_000
+        )
+    }
+
+    /// Returns the current value of every counter.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        StatsSnapshot {
+            connections_accepted: load(&self.connections_accepted),
+            connections_active: load(&self.connections_active),
+            requests_served: load(&self.requests_served),
+            responses_1xx: load(&self.responses[0]),
+            responses_2xx: load(&self.responses[1]),
+            responses_3xx: load(&self.responses[2]),
+            responses_4xx: load(&self.responses[3]),
+            responses_5xx: load(&self.responses[4]),
+            bytes_read: load(&self.bytes_read),
+            bytes_written: load(&self.bytes_written),
+            loop_iterations: load(&self.loop_iterations),
+            loop_events: load(&self.loop_events),
+            loop_max_events: load(&self.loop_max_events),
+            loop_busy_micros: load(&self.loop_busy_micros),
+            loop_max_iteration_micros: load(&self.loop_max_iteration_micros),
+            accept_micros: load(&self.phase_micros[LoopPhase::Accept as usize]),
+            read_micros: load(&self.phase_micros[LoopPhase::Read as usize]),
+            write_micros: load(&self.phase_micros[LoopPhase::Write as usize]),
+        }
+    }
+}
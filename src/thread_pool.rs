@@ -1,71 +1,947 @@
-//! Implementation of a thread pool.
+//! The worker pool that runs request handlers off the reactor threads.
 //!
-//! Note that this implementation is based off The Rust Programming Language book.
-//! Almost all the code is similar or the same to the book.
+//! Jobs are queued in a shared queue with two priorities: `High` jobs run
+//! first, but a `Low` one is let through after every `HIGH_STREAK` of them
+//! so it can't starve. A worker taking a job from the shared queue also
+//! moves a share of the jobs behind it (up to `BATCH`) into its own local
+//! queue, which it drains without touching the shared lock. A worker with
+//! nothing to do steals half of another worker's local queue before it
+//! goes to sleep.
 //!
-//! When I learn more about Rust, I will come back and see if I can change things to make them better.
-//! Or completely rewrite this code.
-//!
-//! However, as the book is the only placed I've learned rust from,
-//! it is inevitable that this first version would basically be identical to the book... :(
+//! The queue is bounded: once `queue_depth` jobs wait, `execute` blocks and
+//! `try_execute` hands the job back as `PoolFull`. A job that panics is
+//! counted and logged, and its worker carries on. The pool can be grown or
+//! shrunk while it runs with `resize`, and stopped with `shutdown`, which
+//! finishes or discards the queued jobs according to a `DrainPolicy` and
+//! leaves behind workers still busy at its deadline.
 
+use crate::log;
 use std::{
-    sync::{Arc, Mutex, mpsc},
+    any::Any,
+    collections::VecDeque,
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    workers: Mutex<Workers>,
+    shared: Arc<Shared>,
+    name_prefix: String,
+    stack_size: Option<usize>,
+}
+
+/// A queued closure and when it was handed to the pool.
+struct Job {
+    run: Box<dyn FnOnce() + Send + 'static>,
+    queued_at: Instant,
+    // The job is skipped if this is cancelled before it starts.
+    token: Option<CancellationToken>,
+}
+
+impl Job {
+    fn new<F>(f: F, token: Option<CancellationToken>) -> Job
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        Job {
+            run: Box::new(f),
+            queued_at: Instant::now(),
+            token,
+        }
+    }
+}
+
+/// A flag that tells queued or running work its result is no longer wanted,
+/// e.g. because the client it was for has disconnected.
+///
+/// Clones share the flag. A job started with
+/// `ThreadPool::try_execute_cancellable` is skipped if its token is cancelled
+/// before a worker gets to it; once running, the job itself decides when to
+/// check `is_cancelled`.
+///
+/// # Example
+/// ```
+/// let token = CancellationToken::new();
+/// pool.try_execute_cancellable(token.clone(), || expensive_work())?;
+/// token.cancel();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Marks the work as unwanted. Cancelling twice is harmless.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How urgently a job should run, for `ThreadPool::execute_with_priority`.
+///
+/// Variants:
+/// - `High`: Work someone is waiting for, like a response. What `execute` uses.
+/// - `Low`: Background work (precompression, hashing) that may wait while
+///   `High` jobs are queued, though never indefinitely: a worker takes a `Low`
+///   job after `HIGH_STREAK` `High` ones in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+/// How many `High` jobs are taken in a row while `Low` ones wait.
+pub const HIGH_STREAK: usize = 8;
+
+/// The most jobs a worker moves from the shared queue to its own at once.
+const BATCH: usize = 32;
+
+/// How often a worker that found nothing to do yields before it sleeps.
+const SPINS: usize = 4;
+
+/// The shared queue jobs are submitted to (the injector), and the pool's
+/// bookkeeping, all behind one lock.
+#[derive(Default)]
+struct Queue {
+    high: VecDeque<Job>,
+    low: VecDeque<Job>,
+    // `High` jobs taken since the last `Low` one.
+    streak: usize,
+    // The own queues of the running workers. Only filled with this lock
+    // held, so a worker that finds them all empty here may safely sleep.
+    locals: Vec<Arc<Local>>,
+    // Workers waiting on `Shared::available`, and how many of them have
+    // been woken but not yet run.
+    idle: usize,
+    waking: usize,
+    // Workers `resize` has asked to exit, which the next idle ones do.
+    exits: usize,
+    // Set once the pool is shutting down: no more jobs will come.
+    closed: bool,
+}
+
+/// A worker's own queue. It takes `High` jobs from the shared queue in
+/// batches, so it only needs the shared lock every so often, and idle
+/// workers steal from it.
+#[derive(Default)]
+struct Local {
+    jobs: Mutex<VecDeque<Job>>,
+}
+
+impl Local {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Queue {
+    /// Returns whether a worker should be woken: one is sleeping that no
+    /// one has woken already.
+    fn wake_one(&mut self) -> bool {
+        if self.idle > self.waking {
+            self.waking += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn push(&mut self, priority: Priority, job: Job) {
+        match priority {
+            Priority::High => self.high.push_back(job),
+            Priority::Low => self.low.push_back(job),
+        }
+    }
+
+    /// Takes the next job: `High` first, but `Low` after `HIGH_STREAK` of
+    /// them. With a `High` job, `local` gets a fair share of those behind it.
+    fn take(&mut self, local: &Local) -> Option<Job> {
+        if !self.low.is_empty() && (self.high.is_empty() || self.streak >= HIGH_STREAK) {
+            self.streak = 0;
+            return self.low.pop_front();
+        }
+        let job = self.high.pop_front()?;
+        let share = (self.high.len() / self.locals.len().max(1)).min(BATCH);
+        local.lock().extend(self.high.drain(..share));
+        self.streak += 1 + share;
+        Some(job)
+    }
+
+    /// Moves half the jobs of another worker's queue to `local`, returning
+    /// the first of them.
+    fn steal(&self, local: &Arc<Local>) -> Option<Job> {
+        for victim in &self.locals {
+            if Arc::ptr_eq(victim, local) {
+                continue;
+            }
+            let mut jobs = victim.lock();
+            if jobs.is_empty() {
+                continue;
+            }
+            // The victim keeps the older half, which it runs next anyway.
+            let half = jobs.len() / 2;
+            let mut stolen = jobs.split_off(half);
+            drop(jobs);
+            let job = stolen.pop_front();
+            local.lock().extend(stolen);
+            return job;
+        }
+        None
+    }
+
+    /// Forgets the queue of a worker that is exiting, which is empty.
+    fn retire(&mut self, local: &Arc<Local>) {
+        self.locals.retain(|other| !Arc::ptr_eq(other, local));
+    }
+}
+
+/// The workers started so far, some of which may have exited after a
+/// `resize` and wait to be joined.
+struct Workers {
+    list: Vec<Worker>,
+    // The size asked for by the last `resize`.
+    size: usize,
+    next_id: usize,
+}
+
+/// The state every worker of a pool shares with it.
+struct Shared {
+    queue: Mutex<Queue>,
+    // Signalled when a job or an exit is queued, and when the pool closes.
+    available: Condvar,
+    // Signalled when a job leaves a full queue.
+    space: Condvar,
+    // Jobs submitted and not yet taken to run, wherever they are queued.
+    queued: AtomicUsize,
+    // Submitters waiting on `space`.
+    blocked: AtomicUsize,
+    // The most jobs `queued` may count before submitters wait.
+    queue_depth: usize,
+    panics: AtomicUsize,
+    // Workers running a job right now.
+    busy: AtomicUsize,
+    // Worker threads that haven't exited.
+    live: AtomicUsize,
+    // Jobs run to the end (or to a panic).
+    completed: AtomicUsize,
+    // Jobs skipped because their token was cancelled while they were queued.
+    cancelled: AtomicUsize,
+    // Summed over the completed jobs: time spent in the queue, and running.
+    wait_micros: AtomicU64,
+    run_micros: AtomicU64,
+}
+
+/// A pool's counters at one moment, from `ThreadPool::stats`.
+///
+/// # Fields
+/// - `size` (*usize*): Worker threads running, as `current_size`.
+/// - `busy_workers` (*usize*): Workers running a job, as `active_jobs`.
+/// - `queued_jobs` (*usize*): Jobs waiting for a free worker.
+/// - `completed_jobs` (*usize*): Jobs run to the end (or to a panic) since the pool started.
+/// - `panicked_jobs` (*usize*): The part of `completed_jobs` that panicked.
+/// - `cancelled_jobs` (*usize*): Jobs skipped because their `CancellationToken` was
+///   cancelled before they started.
+/// - `queue_wait` (*Duration*): Time the completed jobs spent queued, summed. Divided by
+///   `completed_jobs`, the average wait; one that keeps growing means too few workers.
+/// - `run_time` (*Duration*): Time the completed jobs spent running, summed.
+/// - `workers` (*Vec<WorkerStats>*): Jobs completed by each worker still in the pool.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    pub size: usize,
+    pub busy_workers: usize,
+    pub queued_jobs: usize,
+    pub completed_jobs: usize,
+    pub panicked_jobs: usize,
+    pub cancelled_jobs: usize,
+    pub queue_wait: Duration,
+    pub run_time: Duration,
+    pub workers: Vec<WorkerStats>,
+}
+
+/// One worker's share of `PoolStats`.
+///
+/// # Fields
+/// - `id` (*usize*): The worker's number, as in its thread name.
+/// - `jobs` (*u64*): Jobs it has completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub id: usize,
+    pub jobs: u64,
+}
+
+/// A read-only view of a pool's load, from `ThreadPool::monitor`, for
+/// reporting on a pool without owning it (see `stats::ServerStats::watch_pool`).
+/// It stays valid after the pool is gone, and then reads as idle.
+#[derive(Clone)]
+pub struct PoolMonitor(Arc<Shared>);
+
+impl PoolMonitor {
+    /// As `ThreadPool::current_size`.
+    pub fn current_size(&self) -> usize {
+        self.0.live.load(Ordering::Relaxed)
+    }
+
+    /// As `ThreadPool::active_jobs`.
+    pub fn active_jobs(&self) -> usize {
+        self.0.busy.load(Ordering::Relaxed)
+    }
+
+    /// As `ThreadPool::queued_jobs`.
+    pub fn queued_jobs(&self) -> usize {
+        self.0.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many jobs have run to the end (or to a panic) so far.
+    pub fn completed_jobs(&self) -> usize {
+        self.0.completed.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for PoolMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolMonitor")
+            .field("size", &self.current_size())
+            .field("busy", &self.active_jobs())
+            .field("queued", &self.queued_jobs())
+            .finish()
+    }
+}
+
+/// How long dropping a pool waits for its workers before leaving them behind.
+pub const DROP_DEADLINE: Duration = Duration::from_secs(2);
+
+/// What `ThreadPool::shutdown` does with jobs still waiting in the queue.
+///
+/// Variants:
+/// - `Finish`: Run them, as far as the deadline allows.
+/// - `Discard`: Drop them unrun. Their `JobHandle`s report `JobPanicked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainPolicy {
+    Finish,
+    Discard,
+}
+
+/// What became of a pool's work when it shut down.
+///
+/// # Fields
+/// - `completed` (*usize*): Jobs that ran to the end (or panicked) during the shutdown,
+///   including those already running when it began.
+/// - `discarded` (*usize*): Queued jobs dropped under `DrainPolicy::Discard`.
+/// - `abandoned` (*usize*): Workers still busy at the deadline. They are detached and
+///   left to finish (or hang) on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShutdownReport {
+    pub completed: usize,
+    pub discarded: usize,
+    pub abandoned: usize,
+}
+
+/// The most jobs a pool from `ThreadPool::new` queues before `execute` blocks.
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
+
+/// Settings for a `ThreadPool`, from `ThreadPool::builder`.
+///
+/// # Example
+/// ```
+/// let pool = ThreadPool::builder()
+///     .size(8)
+///     .name_prefix("http-worker")
+///     .stack_size(256 * 1024)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    queue_depth: usize,
+    name_prefix: String,
+    stack_size: Option<usize>,
+}
+
+/// Why `ThreadPoolBuilder::build` failed.
+///
+/// Variants:
+/// - `ZeroSize`: The pool was asked for no threads.
+/// - `Spawn(io::Error)`: The OS refused to start a worker thread.
+#[derive(Debug)]
+pub enum PoolBuildError {
+    ZeroSize,
+    Spawn(io::Error),
+}
+
+impl fmt::Display for PoolBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolBuildError::ZeroSize => f.write_str("a thread pool needs at least one thread"),
+            PoolBuildError::Spawn(e) => write!(f, "cannot start a worker thread: {e}"),
+        }
+    }
+}
+
+impl Error for PoolBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolBuildError::ZeroSize => None,
+            PoolBuildError::Spawn(e) => Some(e),
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Sets the number of threads. Defaults to 4.
+    pub fn size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.size = size;
+        self
+    }
+
+    /// Sets how many jobs (at least one) may wait for a free worker before
+    /// `execute` blocks and `try_execute` refuses. Defaults to `DEFAULT_QUEUE_DEPTH`.
+    pub fn queue_depth(mut self, queue_depth: usize) -> ThreadPoolBuilder {
+        self.queue_depth = queue_depth;
+        self
+    }
+
+    /// Sets the thread names: worker `i` is called `{prefix}-{i}`. Defaults
+    /// to `http-worker`.
+    pub fn name_prefix(mut self, prefix: impl Into<String>) -> ThreadPoolBuilder {
+        self.name_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the stack size of each worker, in bytes. Defaults to the
+    /// standard library's (2 MiB, or `RUST_MIN_STACK`).
+    pub fn stack_size(mut self, bytes: usize) -> ThreadPoolBuilder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Starts the workers.
+    ///
+    /// # Errors
+    /// `PoolBuildError::ZeroSize` for a size of zero, and
+    /// `PoolBuildError::Spawn` if a thread can't be started; the workers
+    /// started before it are shut down again.
+    pub fn build(self) -> Result<ThreadPool, PoolBuildError> {
+        if self.size == 0 {
+            return Err(PoolBuildError::ZeroSize);
+        }
+
+        let pool = ThreadPool {
+            workers: Mutex::new(Workers {
+                list: Vec::with_capacity(self.size),
+                size: 0,
+                next_id: 0,
+            }),
+            shared: Arc::new(Shared {
+                queue: Mutex::default(),
+                available: Condvar::new(),
+                space: Condvar::new(),
+                queued: AtomicUsize::new(0),
+                blocked: AtomicUsize::new(0),
+                queue_depth: self.queue_depth.max(1),
+                panics: AtomicUsize::new(0),
+                busy: AtomicUsize::new(0),
+                live: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                cancelled: AtomicUsize::new(0),
+                wait_micros: AtomicU64::new(0),
+                run_micros: AtomicU64::new(0),
+            }),
+            name_prefix: self.name_prefix,
+            stack_size: self.stack_size,
+        };
+
+        // On failure `pool` is dropped, which stops the workers already running.
+        pool.resize(self.size)?;
+        Ok(pool)
+    }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+/// A job `ThreadPool::try_execute` turned down because the queue was full,
+/// handed back to the caller.
+pub struct PoolFull<F>(pub F);
+
+/// The result of a job started with `ThreadPool::execute_with_result`.
+///
+/// # Example
+/// ```
+/// let pool = ThreadPool::new(2);
+/// let answer = pool.execute_with_result(|| 6 * 7);
+/// assert_eq!(answer.wait(), Ok(42));
+/// ```
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<T>,
+    // The result has already been handed out by `try_get` or `wait_timeout`.
+    taken: bool,
+}
+
+/// The job behind a `JobHandle` panicked (or the pool was shut down before
+/// it ran), so there is no result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobPanicked;
+
+impl fmt::Display for JobPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("job panicked before producing a result")
+    }
+}
+
+impl Error for JobPanicked {}
+
+impl<T> JobHandle<T> {
+    /// Blocks until the job has finished and returns its result.
+    ///
+    /// # Errors
+    /// `JobPanicked` if the job panicked, or if `try_get` or `wait_timeout`
+    /// already returned the result.
+    pub fn wait(self) -> Result<T, JobPanicked> {
+        if self.taken {
+            return Err(JobPanicked);
+        }
+        self.receiver.recv().map_err(|_| JobPanicked)
+    }
+
+    /// Returns the result if the job has finished, without blocking.
+    ///
+    /// # Returns
+    /// - `Ok(Some(value))`: The job's result, handed out only once.
+    /// - `Ok(None)`: The job is still queued or running, or its result was
+    ///   already returned.
+    /// - `Err(JobPanicked)`: The job panicked.
+    pub fn try_get(&mut self) -> Result<Option<T>, JobPanicked> {
+        if self.taken {
+            return Ok(None);
+        }
+        match self.receiver.try_recv() {
+            Ok(value) => {
+                self.taken = true;
+                Ok(Some(value))
+            }
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => Err(JobPanicked),
+        }
+    }
+
+    /// Like `try_get`, but waits up to `timeout` for the job to finish.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<T>, JobPanicked> {
+        if self.taken {
+            return Ok(None);
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(value) => {
+                self.taken = true;
+                Ok(Some(value))
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(JobPanicked),
+        }
+    }
+}
 
 impl ThreadPool {
     /// Creates a new ThreadPool.
     ///
-    /// The size is the number of threads in the pool.
+    /// The size is the number of threads in the pool. Up to
+    /// `DEFAULT_QUEUE_DEPTH` jobs wait for a free worker. Use `builder` for
+    /// anything else, or to handle failures.
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if the size is zero.
+    /// The `new` function will panic if the size is zero, or if a thread
+    /// can't be started.
     pub fn new(size: usize) -> ThreadPool {
-        // 0 is not a valid size
-        assert!(size > 0);
-
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        match ThreadPool::builder().size(size).build() {
+            Ok(pool) => pool,
+            Err(e) => panic!("{e}"),
         }
+    }
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+    /// Returns a builder for a pool with custom settings.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size: 4,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            name_prefix: String::from("http-worker"),
+            stack_size: None,
         }
     }
 
+    /// Runs `f` on one of the workers, waiting for room if the queue is full.
+    ///
+    /// If `f` panics, the panic is logged and counted (see `panicked_jobs`)
+    /// and the worker carries on with the next job, so the pool never shrinks.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.execute_with_priority(Priority::High, f);
+    }
+
+    /// Runs `f` on one of the workers like `execute`, ahead of or behind
+    /// other queued jobs according to `priority`.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Job::new(f, None);
+        let shared = &self.shared;
+        let mut queue = shared.lock();
+        while shared.queued.load(Ordering::SeqCst) >= shared.queue_depth {
+            // Announced before checking again, so a worker taking a job
+            // either sees us waiting or we see the room it made.
+            shared.blocked.fetch_add(1, Ordering::SeqCst);
+            if shared.queued.load(Ordering::SeqCst) < shared.queue_depth {
+                break;
+            }
+            queue = shared.space.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+        shared.push(queue, priority, job);
+    }
+
+    /// Runs `f` on one of the workers if the queue has room, like `execute`.
+    ///
+    /// # Errors
+    /// `PoolFull(f)` if `queue_depth` jobs are already waiting, so the caller
+    /// can turn the work away (e.g. with `503`) instead of letting it wait.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), PoolFull<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_submit(f, None)
+    }
+
+    /// Like `try_execute`, but `f` never runs if `token` is cancelled before
+    /// a worker picks it up. Such jobs are counted in `PoolStats::cancelled_jobs`.
+    pub fn try_execute_cancellable<F>(
+        &self,
+        token: CancellationToken,
+        f: F,
+    ) -> Result<(), PoolFull<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_submit(f, Some(token))
+    }
+
+    fn try_submit<F>(&self, f: F, token: Option<CancellationToken>) -> Result<(), PoolFull<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let queue = self.shared.lock();
+        if self.shared.queued.load(Ordering::SeqCst) >= self.shared.queue_depth {
+            return Err(PoolFull(f));
+        }
+        self.shared.push(queue, Priority::High, Job::new(f, token));
+        Ok(())
+    }
+
+    /// Runs `f` on one of the workers like `execute`, and returns a handle
+    /// through which its result can be collected.
+    pub fn execute_with_result<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // A job that panics drops `sender` without sending, which the
+        // handle reports as `JobPanicked`.
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.execute(move || {
+            let _ = sender.send(f());
+        });
+        JobHandle {
+            receiver,
+            taken: false,
+        }
+    }
+
+    /// Grows or shrinks the pool to `size` threads.
+    ///
+    /// New workers start right away. When shrinking, surplus workers exit
+    /// as soon as they are idle, i.e. busy ones finish their current job
+    /// first; `current_size` follows as they do, and they are joined by a
+    /// later `resize` or on drop. Concurrent calls are applied one after the
+    /// other.
+    ///
+    /// # Errors
+    /// `PoolBuildError::ZeroSize` for a size of zero, and
+    /// `PoolBuildError::Spawn` if a thread can't be started; the pool then
+    /// keeps the workers started before it.
+    pub fn resize(&self, size: usize) -> Result<(), PoolBuildError> {
+        if size == 0 {
+            return Err(PoolBuildError::ZeroSize);
+        }
+
+        let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        workers.reap();
+
+        if workers.size > size {
+            self.shared.lock().exits += workers.size - size;
+            self.shared.available.notify_all();
+            workers.size = size;
+        }
+        while workers.size < size {
+            let id = workers.next_id;
+            let worker = self.spawn_worker(id).map_err(PoolBuildError::Spawn)?;
+            workers.list.push(worker);
+            workers.next_id += 1;
+            workers.size += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Starts worker `id`.
+    fn spawn_worker(&self, id: usize) -> io::Result<Worker> {
+        let mut thread = thread::Builder::new().name(format!("{}-{id}", self.name_prefix));
+        if let Some(bytes) = self.stack_size {
+            thread = thread.stack_size(bytes);
+        }
+        // Counted up front, so a worker that exits at once can't take
+        // `live` below zero.
+        self.shared.live.fetch_add(1, Ordering::Relaxed);
+        Worker::spawn(id, thread, Arc::clone(&self.shared)).inspect_err(|_| {
+            self.shared.live.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Returns how many worker threads are running, including any a shrinking
+    /// `resize` has asked to stop that haven't got round to it yet.
+    pub fn current_size(&self) -> usize {
+        self.shared.live.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many jobs are running right now.
+    pub fn active_jobs(&self) -> usize {
+        self.shared.busy.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many jobs are waiting for a free worker, for metrics.
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many jobs have panicked so far.
+    pub fn panicked_jobs(&self) -> usize {
+        self.shared.panics.load(Ordering::Relaxed)
+    }
+
+    /// Returns a view of the pool's load that can be kept elsewhere.
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor(Arc::clone(&self.shared))
+    }
+
+    /// Returns the pool's counters, for sizing it.
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        let micros = |counter: &AtomicU64| Duration::from_micros(counter.load(Ordering::Relaxed));
+        let workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        PoolStats {
+            size: shared.live.load(Ordering::Relaxed),
+            busy_workers: shared.busy.load(Ordering::Relaxed),
+            queued_jobs: shared.queued.load(Ordering::Relaxed),
+            completed_jobs: shared.completed.load(Ordering::Relaxed),
+            panicked_jobs: shared.panics.load(Ordering::Relaxed),
+            cancelled_jobs: shared.cancelled.load(Ordering::Relaxed),
+            queue_wait: micros(&shared.wait_micros),
+            run_time: micros(&shared.run_micros),
+            workers: workers
+                .list
+                .iter()
+                .map(|worker| WorkerStats {
+                    id: worker.worker_id,
+                    jobs: worker.jobs.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Workers {
+    /// Joins and forgets the workers that have exited.
+    fn reap(&mut self) {
+        self.list.retain_mut(|worker| {
+            if !worker.thread.as_ref().is_some_and(|t| t.is_finished()) {
+                return true;
+            }
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+            false
+        });
+    }
+}
+
+impl ThreadPool {
+    /// Stops the pool, waiting up to `deadline` for the workers to finish.
+    ///
+    /// Jobs already running are always left to finish; `policy` decides
+    /// whether the queued ones run too. Workers still busy when the
+    /// deadline passes (say, on a job blocked on a dead socket) are detached
+    /// rather than joined, so a stuck job can't hang the process on exit.
+    ///
+    /// Dropping a pool does the same with `DROP_DEADLINE` and `DrainPolicy::Finish`.
+    pub fn shutdown(mut self, deadline: Duration, policy: DrainPolicy) -> ShutdownReport {
+        self.stop(deadline, policy)
+    }
+
+    /// Does the work of `shutdown`; a no-op once the pool has been stopped.
+    fn stop(&mut self, deadline: Duration, policy: DrainPolicy) -> ShutdownReport {
+        let completed = self.shared.completed.load(Ordering::Relaxed);
+        let mut queue = self.shared.lock();
+        if queue.closed {
+            return ShutdownReport::default();
+        }
+        // Once the queue is empty the workers see it closed and exit.
+        queue.closed = true;
+        let dropped: Vec<Job> = match policy {
+            DrainPolicy::Finish => Vec::new(),
+            DrainPolicy::Discard => {
+                let Queue {
+                    high, low, locals, ..
+                } = &mut *queue;
+                let mut jobs: Vec<Job> = high.drain(..).chain(low.drain(..)).collect();
+                for local in locals.iter() {
+                    jobs.extend(local.lock().drain(..));
+                }
+                jobs
+            }
+        };
+        self.shared
+            .queued
+            .fetch_sub(dropped.len(), Ordering::SeqCst);
+        drop(queue);
+        self.shared.available.notify_all();
+        // Dropped outside the lock, as a job's captures may do anything on drop.
+        let discarded = dropped.len();
+        drop(dropped);
+
+        let deadline = Instant::now() + deadline;
+        let workers = self.workers.get_mut().unwrap_or_else(|e| e.into_inner());
+        loop {
+            workers.reap();
+            let now = Instant::now();
+            if workers.list.is_empty() || now >= deadline {
+                break;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(5)));
+        }
+
+        let abandoned = workers.list.len();
+        // Dropping a `JoinHandle` detaches its thread.
+        for worker in workers.list.drain(..) {
+            log::warn(format_args!(
+                "Worker {} still busy at the shutdown deadline; leaving it behind.",
+                worker.worker_id
+            ));
+        }
+
+        ShutdownReport {
+            completed: self.shared.completed.load(Ordering::Relaxed) - completed,
+            discarded,
+            abandoned,
+        }
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        self.stop(DROP_DEADLINE, DrainPolicy::Finish);
+    }
+}
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.worker_id);
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+    /// Adds `job` to the shared queue, which `queue` locks, and wakes an
+    /// idle worker for it.
+    fn push(&self, mut queue: MutexGuard<'_, Queue>, priority: Priority, job: Job) {
+        queue.push(priority, job);
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let wake = queue.wake_one();
+        drop(queue);
+        if wake {
+            self.available.notify_one();
+        }
+    }
+
+    /// Counts a job taken to run, waking submitters waiting for room once
+    /// the queue is down to half its depth, so they don't wake (and block
+    /// again) for every job. Called without the lock held.
+    fn dequeued(&self) {
+        let queued = self.queued.fetch_sub(1, Ordering::SeqCst) - 1;
+        if queued <= self.queue_depth / 2 && self.blocked.swap(0, Ordering::SeqCst) > 0 {
+            // Taking the lock first makes sure they are already waiting.
+            drop(self.lock());
+            self.space.notify_all();
+        }
+    }
+
+    /// Waits for worker `id`'s next job, or returns `None` when it should exit.
+    ///
+    /// The worker's own queue comes first, and needs no shared lock; then
+    /// the shared queue, then the other workers' queues.
+    fn next_job(&self, id: usize, local: &Arc<Local>) -> Option<Job> {
+        let job = local.lock().pop_front();
+        if let Some(job) = job {
+            self.dequeued();
+            return Some(job);
+        }
+
+        let mut spins = 0;
+        let mut queue = self.lock();
+        loop {
+            if queue.exits > 0 {
+                queue.exits -= 1;
+                queue.retire(local);
+                log::debug(format_args!("Worker {id} no longer needed; shutting down."));
+                return None;
+            }
+            if let Some(job) = queue.take(local).or_else(|| queue.steal(local)) {
+                // Let an idle worker steal what is left over for us.
+                let wake = !local.lock().is_empty() && queue.wake_one();
+                drop(queue);
+                if wake {
+                    self.available.notify_one();
+                }
+                self.dequeued();
+                return Some(job);
             }
+            if queue.closed {
+                queue.retire(local);
+                log::debug(format_args!("Worker {id} disconnected; shutting down."));
+                return None;
+            }
+            if spins < SPINS {
+                // More jobs are often about to be queued: give the submitter
+                // the CPU before paying for a sleep and a wakeup.
+                spins += 1;
+                drop(queue);
+                thread::yield_now();
+                queue = self.lock();
+                continue;
+            }
+            queue.idle += 1;
+            queue = self
+                .available
+                .wait(queue)
+                .unwrap_or_else(|e| e.into_inner());
+            queue.idle -= 1;
+            queue.waking = queue.waking.saturating_sub(1);
         }
     }
 }
@@ -73,28 +949,64 @@ impl Drop for ThreadPool {
 struct Worker {
     worker_id: usize,
     thread: Option<thread::JoinHandle<()>>,
+    // Jobs this worker has completed.
+    jobs: Arc<AtomicU64>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || {
-            loop {
-                match receiver.lock().unwrap().recv() {
-                    Ok(job) => {
-                        println!("Worker {id} got a job; executing.");
-                        job();
-                    }
-                    Err(_) => {
-                        println!("Worker {id} disconnected; shutting down.");
-                        break;
-                    }
-                };
+    fn spawn(id: usize, thread: thread::Builder, shared: Arc<Shared>) -> io::Result<Worker> {
+        let jobs = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&jobs);
+        let thread = thread.spawn(move || {
+            let local = Arc::new(Local::default());
+            shared.lock().locals.push(Arc::clone(&local));
+            while let Some(job) = shared.next_job(id, &local) {
+                if job
+                    .token
+                    .as_ref()
+                    .is_some_and(CancellationToken::is_cancelled)
+                {
+                    shared.cancelled.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                shared.busy.fetch_add(1, Ordering::Relaxed);
+                let started = Instant::now();
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job.run)) {
+                    shared.panics.fetch_add(1, Ordering::Relaxed);
+                    log::error(format_args!(
+                        "Worker {id}: job panicked: {}",
+                        panic_message(&*payload)
+                    ));
+                }
+                let wait = started - job.queued_at;
+                shared
+                    .wait_micros
+                    .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+                shared
+                    .run_micros
+                    .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+                shared.busy.fetch_sub(1, Ordering::Relaxed);
+                shared.completed.fetch_add(1, Ordering::Relaxed);
+                counter.fetch_add(1, Ordering::Relaxed);
             }
-        });
+            shared.live.fetch_sub(1, Ordering::Relaxed);
+        })?;
 
-        Worker {
+        Ok(Worker {
             worker_id: id,
             thread: Some(thread),
-        }
+            jobs,
+        })
+    }
+}
+
+/// Returns the message a panic was raised with, for logs.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "(no message)"
     }
 }
@@ -0,0 +1,391 @@
+//! A minimal TOML reader for configuration files.
+//!
+//! Only the part of TOML a flat configuration needs is understood: `key =
+//! value` pairs, `[table]` headers one level deep, comments, and values that
+//! are strings (basic `"..."` with the usual escapes, or literal `'...'`),
+//! integers, booleans and arrays of those (which may span lines). Dotted
+//! keys, arrays of tables, inline tables, floats and dates are rejected with
+//! an error rather than misread.
+use std::error::Error;
+use std::fmt;
+
+/// A value on the right of `=`.
+///
+/// Variants:
+/// - `String(String)`: A basic or literal string.
+/// - `Integer(i64)`: A decimal integer, optionally signed, `_` separators allowed.
+/// - `Boolean(bool)`: `true` or `false`.
+/// - `Array(Vec<Value>)`: A bracketed, comma-separated list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Returns the string, if this is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer, if this is one.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean, if this is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements, if this is an array of strings only.
+    pub fn as_strings(&self) -> Option<Vec<String>> {
+        match self {
+            Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_owned))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+/// One `key = value` pair, in file order.
+///
+/// # Fields
+/// - `table` (*Option<String>*): The `[table]` the pair is under; `None` before the first header.
+/// - `key` (*String*): The key, unquoted.
+/// - `value` (*Value*): The value.
+/// - `line` (*usize*): The 1-based line the key is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub table: Option<String>,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+impl Entry {
+    /// Returns the key with its table in front, e.g. `error_pages.404`.
+    pub fn name(&self) -> String {
+        match &self.table {
+            Some(table) => format!("{table}.{}", self.key),
+            None => self.key.clone(),
+        }
+    }
+}
+
+/// Why `parse` failed: what was wrong, on which line, and under which key
+/// if the error is in a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlError {
+    pub line: usize,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "line {}: {key}: {}", self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl Error for TomlError {}
+
+/// Parses `text` into its entries, in file order.
+///
+/// # Errors
+/// A `TomlError` for the first thing that isn't (supported) TOML, or for a
+/// key given twice in the same table.
+///
+/// # Example
+/// ```
+/// let entries = parse("workers = 8\n[error_pages]\n404 = \"404.html\"\n")?;
+/// assert_eq!(entries[1].name(), "error_pages.404");
+/// ```
+pub fn parse(text: &str) -> Result<Vec<Entry>, TomlError> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+        line: 1,
+        key: None,
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut table = None;
+    loop {
+        parser.skip_blank();
+        match parser.peek() {
+            None => return Ok(entries),
+            Some(b'\n' | b'\r' | b'#') => parser.end_of_line()?,
+            Some(b'[') => {
+                parser.pos += 1;
+                if parser.peek() == Some(b'[') {
+                    return Err(parser.error("arrays of tables are not supported"));
+                }
+                parser.skip_blank();
+                let name = parser.key()?;
+                parser.skip_blank();
+                if !parser.eat(b']') {
+                    return Err(parser.error("expected `]` after the table name"));
+                }
+                parser.end_of_line()?;
+                table = Some(name);
+            }
+            Some(_) => {
+                let line = parser.line;
+                let key = parser.key()?;
+                if entries
+                    .iter()
+                    .any(|entry| entry.table == table && entry.key == key)
+                {
+                    parser.key = Some(key);
+                    return Err(parser.error("duplicate key"));
+                }
+                parser.key = Some(key.clone());
+                parser.skip_blank();
+                if !parser.eat(b'=') {
+                    return Err(parser.error("expected `=` after the key"));
+                }
+                parser.skip_blank();
+                let value = parser.value()?;
+                parser.end_of_line()?;
+                parser.key = None;
+                entries.push(Entry {
+                    table: table.clone(),
+                    key,
+                    value,
+                    line,
+                });
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+    // The key whose value is being parsed, for error messages.
+    key: Option<String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, message: impl Into<String>) -> TomlError {
+        TomlError {
+            line: self.line,
+            key: self.key.clone(),
+            message: message.into(),
+        }
+    }
+
+    /// Skips spaces and tabs.
+    fn skip_blank(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips whitespace, line breaks and comments, as allowed inside arrays.
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                Some(b'#') => self.skip_comment(),
+                Some(b'\r') => self.pos += 1,
+                Some(b'\n') => {
+                    self.pos += 1;
+                    self.line += 1;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some(b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Expects the rest of the line to be blank or a comment, and moves past it.
+    fn end_of_line(&mut self) -> Result<(), TomlError> {
+        self.skip_blank();
+        if self.peek() == Some(b'#') {
+            self.skip_comment();
+        }
+        self.eat(b'\r');
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.pos += 1;
+                self.line += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.error("unexpected text at the end of the line")),
+        }
+    }
+
+    /// Parses a bare (`[A-Za-z0-9_-]+`) or quoted key.
+    fn key(&mut self) -> Result<String, TomlError> {
+        let key = match self.peek() {
+            Some(b'"') => self.basic_string()?,
+            Some(b'\'') => self.literal_string()?,
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(b) if b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(self.error("expected a key"));
+                }
+                String::from_utf8_lossy(&self.text[start..self.pos]).into_owned()
+            }
+        };
+        if self.peek() == Some(b'.') {
+            return Err(self.error("dotted keys are not supported"));
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value, TomlError> {
+        match self.peek() {
+            Some(b'"') => Ok(Value::String(self.basic_string()?)),
+            Some(b'\'') => Ok(Value::String(self.literal_string()?)),
+            Some(b'[') => self.array(),
+            Some(b'{') => Err(self.error("inline tables are not supported")),
+            Some(b't' | b'f') => {
+                for (word, value) in [("true", true), ("false", false)] {
+                    if self.text[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(Value::Boolean(value));
+                    }
+                }
+                Err(self.error("expected a value"))
+            }
+            Some(b) if b.is_ascii_digit() || b == b'+' || b == b'-' => self.integer(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn integer(&mut self) -> Result<Value, TomlError> {
+        let start = self.pos;
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.pos += 1;
+        }
+        while matches!(self.peek(), Some(b) if b.is_ascii_alphanumeric() || b == b'_' || b == b'.')
+        {
+            self.pos += 1;
+        }
+        let digits: String = String::from_utf8_lossy(&self.text[start..self.pos])
+            .chars()
+            .filter(|&c| c != '_')
+            .collect();
+        digits
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| self.error(format!("{digits} is not an integer")))
+    }
+
+    fn array(&mut self) -> Result<Value, TomlError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_trivia();
+            if !self.eat(b',') {
+                self.skip_trivia();
+                if self.eat(b']') {
+                    return Ok(Value::Array(items));
+                }
+                return Err(self.error("expected `,` or `]` in the array"));
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, TomlError> {
+        self.pos += 1;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(b'\'') => break,
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(_) => self.pos += 1,
+            }
+        }
+        let s = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+        self.pos += 1;
+        Ok(s)
+    }
+
+    fn basic_string(&mut self) -> Result<String, TomlError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => {
+                            let hex = self.text.get(self.pos + 1..self.pos + 5).unwrap_or(&[]);
+                            let c = std::str::from_utf8(hex)
+                                .ok()
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            c
+                        }
+                        _ => return Err(self.error("invalid escape in string")),
+                    };
+                    self.pos += 1;
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) => {
+                    bytes.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+        self.pos += 1;
+        // The input is a `&str` and escapes are pushed as whole characters.
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
@@ -0,0 +1,473 @@
+//! Small helpers shared across the crate that don't belong to a single layer.
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Percent-encodes every byte of `input` for which `keep` returns `false`.
+///
+/// Bytes that are kept are copied through unchanged; everything else
+/// (including all non-ASCII bytes of multi-byte UTF-8 characters) becomes
+/// `%XX` with uppercase hex digits.
+///
+/// # Example
+/// ```
+/// let encoded = percent_encode("a b", |b| b.is_ascii_alphanumeric());
+/// assert_eq!(encoded, "a%20b");
+/// ```
+pub fn percent_encode(input: &str, keep: impl Fn(u8) -> bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for &byte in input.as_bytes() {
+        if keep(byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// Escapes the characters that are special in HTML text and attribute values.
+///
+/// # Example
+/// ```
+/// assert_eq!(html_escape("<script>"), "&lt;script&gt;");
+/// ```
+pub fn html_escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Splits a Unix timestamp into `(year, month, day, hour, minute, second)` in UTC.
+///
+/// Uses Howard Hinnant's `civil_from_days` algorithm so no date library is needed.
+pub fn civil_from_unix(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let (hour, minute, second) = (
+        (rem / 3600) as u32,
+        ((rem % 3600) / 60) as u32,
+        (rem % 60) as u32,
+    );
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, hour, minute, second)
+}
+
+/// Formats a `SystemTime` as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, mo, d, h, mi, s) = civil_from_unix(secs);
+    format!("{y:04}-{mo:02}-{d:02} {h:02}:{mi:02}:{s:02}")
+}
+
+/// Formats a `SystemTime` as RFC 3339 in UTC with milliseconds, e.g.
+/// `2000-10-10T13:55:36.042Z`.
+pub fn format_rfc3339(time: std::time::SystemTime) -> String {
+    let since = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let (y, mo, d, h, mi, s) = civil_from_unix(since.as_secs());
+    let millis = since.subsec_millis();
+    format!("{y:04}-{mo:02}-{d:02}T{h:02}:{mi:02}:{s:02}.{millis:03}Z")
+}
+
+/// Returns a new request ID, unique within the process and very likely
+/// across processes: the time in milliseconds, the process ID and a counter,
+/// in hex, e.g. `18b3c9e2a41-1f2c-2a`.
+pub fn request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{millis:x}-{:x}-{count:x}", std::process::id())
+}
+
+/// Escapes `input` for use inside a JSON string: `"`, `\` and control
+/// characters.
+///
+/// # Example
+/// ```
+/// assert_eq!(json_escape("a \"b\"\n"), "a \\\"b\\\"\\n");
+/// ```
+pub fn json_escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Replaces `{{name}}` placeholders in `template` with HTML-escaped values.
+///
+/// `lookup` is asked for each placeholder name (surrounding whitespace
+/// trimmed). Unknown names render as an empty string, and an opening `{{`
+/// without a matching `}}` is copied through literally.
+///
+/// # Example
+/// ```
+/// let out = fill_placeholders("<p>{{path}}{{nope}}</p>", |name| {
+///     (name == "path").then(|| String::from("/a<b"))
+/// });
+/// assert_eq!(out, "<p>/a&lt;b</p>");
+/// ```
+pub fn fill_placeholders(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                if let Some(value) = lookup(after[..end].trim()) {
+                    out.push_str(&html_escape(&value));
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decodes `%XX` escapes in a URL path.
+///
+/// # Returns
+/// - `Some(String)`: The decoded string.
+/// - `None`: An escape was malformed (e.g. `%G1`, a trailing `%`) or the
+///   decoded bytes are not valid UTF-8.
+///
+/// # Example
+/// ```
+/// assert_eq!(percent_decode("/a%20b").as_deref(), Some("/a b"));
+/// ```
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Lexically normalizes an absolute URL path.
+///
+/// Empty and `.` segments are dropped and `..` removes the previous segment.
+/// A trailing slash is preserved (it matters for directory requests).
+/// Nothing touches the filesystem here.
+///
+/// # Returns
+/// - `Some(String)`: The normalized path, always starting with `/`.
+/// - `None`: A `..` segment would climb above the root.
+///
+/// # Example
+/// ```
+/// assert_eq!(normalize_path("/a/./b/../c/").as_deref(), Some("/a/c/"));
+/// assert_eq!(normalize_path("/a/../../secret"), None);
+/// ```
+pub fn normalize_path(path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    let ends_in_dir = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if ends_in_dir && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// Matches `path` against `pattern`, in which each `*` stands for any run of
+/// characters, `/` included, and returns what each `*` matched, in order.
+/// A pattern without `*`s matches only itself.
+///
+/// # Example
+/// ```
+/// assert_eq!(wildcard_captures("/blog/*.html", "/blog/a/b.html"), Some(vec!["a/b"]));
+/// assert_eq!(wildcard_captures("/blog/*.html", "/blog/a.txt"), None);
+/// ```
+pub fn wildcard_captures<'p>(pattern: &str, path: &'p str) -> Option<Vec<&'p str>> {
+    let literals: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = literals.split_first()?;
+    let remaining = path.strip_prefix(first)?;
+    let Some((last, between)) = rest.split_last() else {
+        return remaining.is_empty().then(Vec::new);
+    };
+    let mut remaining = remaining.strip_suffix(last)?;
+    let mut captures = Vec::with_capacity(rest.len());
+    // Each literal between two `*`s is taken where it first occurs, which
+    // finds a match whenever there is one.
+    for literal in between {
+        let at = remaining.find(literal)?;
+        captures.push(&remaining[..at]);
+        remaining = &remaining[at + literal.len()..];
+    }
+    captures.push(remaining);
+    Some(captures)
+}
+
+/// Returns the SHA-1 digest of `data` (FIPS 180-4).
+///
+/// SHA-1 is broken for anything that needs collision resistance; it is here
+/// only because the WebSocket handshake (RFC 6455 §4.2.2) is defined with it.
+///
+/// # Example
+/// ```
+/// let digest = sha1(b"abc");
+/// assert_eq!(digest[..4], [0xa9, 0x99, 0x3e, 0x36]);
+/// ```
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..40 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, word) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Encodes `data` as standard base64 (RFC 4648 §4), with `=` padding.
+///
+/// # Example
+/// ```
+/// assert_eq!(base64_encode(b"hi!?"), "aGkhPw==");
+/// ```
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes standard base64 (RFC 4648 §4). Padding is optional; `None` for
+/// anything else that isn't base64.
+///
+/// # Example
+/// ```
+/// assert_eq!(base64_decode("aGkhPw==").unwrap(), b"hi!?");
+/// assert_eq!(base64_decode("aGkhPw").unwrap(), b"hi!?");
+/// ```
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len() / 4 * 3 + 2);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// An IPv4 or IPv6 address range in CIDR notation, such as `10.0.0.0/8` or
+/// `fd00::/8`.
+///
+/// # Example
+/// ```
+/// let range = Cidr::parse("192.168.0.0/16").unwrap();
+/// assert!(range.contains("192.168.4.20".parse().unwrap()));
+/// assert!(!range.contains("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    // Stored with the host bits cleared.
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parses `address/prefix`. A bare address is a range of one (`/32` or `/128`).
+    /// Host bits set in the address are ignored: `10.1.2.3/8` is `10.0.0.0/8`.
+    ///
+    /// # Returns
+    /// - `Some(Cidr)`: The range.
+    /// - `None`: The address doesn't parse, or the prefix is longer than the
+    ///   address (more than 32 bits for IPv4, 128 for IPv6).
+    pub fn parse(input: &str) -> Option<Cidr> {
+        let (address, prefix) = match input.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (input, None),
+        };
+        let address: IpAddr = address.parse().ok()?;
+        let bits = Cidr::bits(address);
+        let prefix = match prefix {
+            Some(prefix) if prefix.bytes().all(|b| b.is_ascii_digit()) => prefix.parse().ok()?,
+            Some(_) => return None,
+            None => bits,
+        };
+        if prefix > bits {
+            return None;
+        }
+        let network = match address {
+            IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & mask_v4(prefix)).into()),
+            IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & mask_v6(prefix)).into()),
+        };
+        Some(Cidr { network, prefix })
+    }
+
+    /// Returns `true` if `address` is in the range.
+    ///
+    /// IPv4 clients of a dual-stack IPv6 socket arrive as `::ffff:a.b.c.d`;
+    /// those are matched as the IPv4 address they are. Otherwise an address
+    /// never matches a range of the other family.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(address) & mask_v4(self.prefix) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & mask_v6(self.prefix) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+
+    fn bits(address: IpAddr) -> u8 {
+        match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Returns the netmask of an IPv4 `/prefix`, `prefix` at most 32.
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+/// Returns the netmask of an IPv6 `/prefix`, `prefix` at most 128.
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
@@ -0,0 +1,160 @@
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::io::access_log::{AccessLogConfig, AccessLogFormat, LogTarget};
+use custom_http::util::Cidr;
+use custom_http::{Router, Server, ServerConfig, ServerHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn start(format: AccessLogFormat) -> (SocketAddr, ServerHandle, mpsc::Receiver<String>) {
+    let mut router = Router::new();
+    router.get("/hello", |_: &HttpRequest| {
+        HttpResponse::text(StatusCode::OK, "hello")
+    });
+    let (lines, received) = mpsc::channel();
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .router(router)
+        .access_log(AccessLogConfig {
+            format,
+            target: LogTarget::Channel(lines),
+        })
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    (running.local_addr().unwrap(), running, received)
+}
+
+/// Sends `request` as-is on a new connection and returns the whole response.
+fn send(address: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn next_line(lines: &mpsc::Receiver<String>) -> String {
+    lines.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn combined_format_lines() {
+    let (address, running, lines) = start(AccessLogFormat::combined());
+
+    let response = send(
+        address,
+        "GET /hello?name=x HTTP/1.1\r\nReferer: http://example.com/\r\n\
+         User-Agent: test \"agent\"\r\nConnection: close\r\n\r\n",
+    );
+    let line = next_line(&lines);
+    let (client, rest) = line.split_once(" - - [").unwrap();
+    assert_eq!(client, "127.0.0.1");
+    let (time, rest) = rest.split_once("] ").unwrap();
+    assert!(time.ends_with(" +0000"), "{line}");
+    assert_eq!(time.matches('/').count(), 2, "{line}");
+    assert_eq!(
+        rest,
+        format!(
+            "\"GET /hello?name=x HTTP/1.1\" 200 {} \"http://example.com/\" \"test \\\"agent\\\"\"",
+            response.len()
+        )
+    );
+
+    send(
+        address,
+        "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    let line = next_line(&lines);
+    assert!(line.contains("\"GET /missing HTTP/1.1\" 404 "), "{line}");
+    assert!(line.ends_with(" \"-\" \"-\""), "{line}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn malformed_requests_are_logged_without_a_request_line() {
+    let (address, running, lines) = start(AccessLogFormat::common());
+    let response = send(address, "NONSENSE\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+    let line = next_line(&lines);
+    assert!(
+        line.ends_with(&format!(" \"-\" 400 {}", response.len())),
+        "{line}"
+    );
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn custom_formats() {
+    let format = AccessLogFormat::parse("%h %s %D 100%%").unwrap();
+    let (address, running, lines) = start(format);
+    send(address, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let line = next_line(&lines);
+    let fields: Vec<&str> = line.split(' ').collect();
+    assert_eq!(fields[..2], ["127.0.0.1", "200"], "{line}");
+    assert!(fields[2].parse::<u64>().is_ok(), "{line}");
+    assert_eq!(fields[3], "100%", "{line}");
+    running.shutdown();
+    running.join().unwrap();
+
+    assert!(AccessLogFormat::parse("%h %u").is_err());
+    assert!(AccessLogFormat::parse("%{Cookie}i").is_err());
+}
+
+#[test]
+fn access_log_table_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("access-log-{}.toml", std::process::id()));
+    std::fs::write(&path, "[access_log]\nformat = \"common\"\npath = \"-\"\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    let access_log = config.access_log.unwrap();
+    assert_eq!(access_log.format, AccessLogFormat::common());
+    assert!(matches!(access_log.target, LogTarget::Stdout));
+
+    std::fs::write(&path, "[access_log]\nformat = \"%q\"\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn forwarded_clients_are_logged_only_from_trusted_proxies() {
+    for (trusted, logged) in [(false, "127.0.0.1"), (true, "203.0.113.7")] {
+        let mut router = Router::new();
+        // Handlers see the address the connection came from, whoever it
+        // claims to forward for.
+        router.get("/peer", |request: &HttpRequest| {
+            HttpResponse::text(StatusCode::OK, format!("{:?}", request.peer))
+        });
+        let (lines, received) = mpsc::channel();
+        let mut builder = ServerConfig::builder()
+            .address("127.0.0.1:0")
+            .reactors(1)
+            .workers(1)
+            .router(router)
+            .access_log(AccessLogConfig {
+                format: AccessLogFormat::parse("%h").unwrap(),
+                target: LogTarget::Channel(lines),
+            });
+        if trusted {
+            builder = builder.trusted_proxy(Cidr::parse("127.0.0.0/8").unwrap());
+        }
+        let running = Server::start(builder.build().unwrap()).unwrap();
+
+        let response = send(
+            running.local_addr().unwrap(),
+            "GET /peer HTTP/1.1\r\nX-Forwarded-For: 203.0.113.7\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\nSome(127.0.0.1)"), "{response}");
+        assert_eq!(next_line(&received), logged, "trusted: {trusted}");
+
+        running.shutdown();
+        running.join().unwrap();
+    }
+}
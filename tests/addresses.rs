@@ -0,0 +1,150 @@
+mod common;
+
+use common::{read_response, site};
+use custom_http::io::listener::Peer;
+use custom_http::{Server, ServerConfig, ServerError};
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// Returns a loopback address with a port nothing listens on right now.
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Fetches `/page.html` from the server at `address`.
+fn fetch(address: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    read_response(&mut stream)
+}
+
+#[test]
+fn every_address_is_served() {
+    let (first, second) = (free_address(), free_address());
+    let running = Server::start(ServerConfig {
+        addresses: vec![first.to_string(), second.to_string()],
+        ..site("addresses", &[("page.html", "page")])
+    })
+    .unwrap();
+    assert_eq!(running.local_addr(), Some(first));
+    for address in [first, second] {
+        assert!(fetch(address).ends_with("\r\n\r\npage"), "{address}");
+    }
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn one_address_failing_fails_the_start() {
+    // Someone else's listener, held until the end of the test.
+    let holder = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = holder.local_addr().unwrap().to_string();
+    let result = Server::start(ServerConfig {
+        addresses: vec![free_address().to_string(), taken.clone()],
+        ..site("addresses", &[])
+    });
+    let Err(ServerError::Bind { address, .. }) = result else {
+        panic!("expected a bind error");
+    };
+    assert_eq!(address, taken);
+}
+
+#[test]
+fn ipv6_loopback_is_served() {
+    let running = Server::start(ServerConfig {
+        addresses: vec![String::from("[::1]:0")],
+        ..site("ipv6", &[("page.html", "page")])
+    })
+    .unwrap();
+    let address = running.local_addr().unwrap();
+    assert!(address.is_ipv6());
+    assert!(fetch(address).ends_with("\r\n\r\npage"));
+    running.shutdown();
+    running.join().unwrap();
+
+    // Peers print in brackets, so the port stays apart from the address.
+    let peer = Peer::Tcp("[::1]:50000".parse().unwrap());
+    assert_eq!(peer.to_string(), "[::1]:50000");
+}
+
+#[test]
+fn ipv6_wildcards_take_ipv4_clients_only_when_asked() {
+    for ipv6_only in [true, false] {
+        let mut config = ServerConfig {
+            addresses: vec![String::from("[::]:0")],
+            ..site("dual-stack", &[("page.html", "page")])
+        };
+        config.socket.ipv6_only = ipv6_only;
+        let running = Server::start(config).unwrap();
+        let port = running.local_addr().unwrap().port();
+        let v4 = SocketAddr::from(([127, 0, 0, 1], port));
+        if ipv6_only {
+            assert!(TcpStream::connect(v4).is_err());
+        } else {
+            assert!(fetch(v4).ends_with("\r\n\r\npage"));
+        }
+        running.shutdown();
+        running.join().unwrap();
+    }
+}
+
+#[test]
+fn malformed_addresses_are_an_error_not_a_panic() {
+    for address in ["::1:8080", "localhost", "127.0.0.1:99999"] {
+        let result = Server::start(ServerConfig {
+            addresses: vec![String::from(address)],
+            ..site("bad-address", &[])
+        });
+        let Err(error @ ServerError::InvalidAddress { .. }) = result else {
+            panic!("{address} was accepted");
+        };
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("invalid address {address}: "))
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_sockets_are_served() {
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let mut config = site("unix-socket", &[("page.html", "page")]);
+    let path = config.document_root.with_extension("sock");
+    // A socket file left behind by a run that is gone.
+    drop(UnixListener::bind(&path).unwrap());
+    config.addresses = vec![format!("unix:{}", path.display())];
+    config.socket.unix_mode = Some(0o660);
+    let running = Server::start(config.clone()).unwrap();
+    assert_eq!(running.local_addr(), None);
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\npage"), "{response}");
+
+    // A socket someone is listening on is left alone.
+    let Err(ServerError::Bind { address, .. }) = Server::start(config) else {
+        panic!("a live socket was taken over");
+    };
+    assert_eq!(address, format!("unix:{}", path.display()));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{respond, site};
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, ServerConfig};
+use std::sync::Arc;
+
+/// A config forcing downloads of `.zip` and `.PDF` files, serving `files`.
+fn downloads(files: &[(&str, &str)]) -> ServerConfig {
+    ServerConfig {
+        force_attachment_extensions: vec![String::from("zip"), String::from(".PDF")],
+        ..site("attachment", files)
+    }
+}
+
+#[test]
+fn ascii_names_are_quoted() {
+    let config = downloads(&[
+        ("report.zip", "zip"),
+        ("slides.pdf", "pdf"),
+        ("say \"hi\".zip", "quoted"),
+        ("notes.txt", "text"),
+    ]);
+    for (target, value) in [
+        ("/report.zip", Some(r#"attachment; filename="report.zip""#)),
+        // Extensions compare without the dot and ignoring case.
+        ("/slides.pdf", Some(r#"attachment; filename="slides.pdf""#)),
+        (
+            "/say%20%22hi%22.zip",
+            Some(r#"attachment; filename="say \"hi\".zip""#),
+        ),
+        ("/notes.txt", None),
+    ] {
+        let response = respond(&config, target, &[]);
+        assert_eq!(response.status().as_u16(), 200, "{target}");
+        assert_eq!(response.header("Content-Disposition"), value, "{target}");
+    }
+}
+
+#[test]
+fn utf8_names_also_get_an_rfc_5987_parameter() {
+    let config = downloads(&[("résumé.pdf", "pdf")]);
+    let response = respond(&config, "/r%C3%A9sum%C3%A9.pdf", &[]);
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.header("Content-Disposition"),
+        Some(r#"attachment; filename="r_sum_.pdf"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"#)
+    );
+}
+
+#[test]
+fn names_from_the_path_cannot_inject_header_lines() {
+    let config = downloads(&[("a\r\nSet-Cookie: x=1.zip", "zip")]);
+    let response = respond(&config, "/a%0D%0ASet-Cookie:%20x=1.zip", &[]);
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.header("Content-Disposition"),
+        Some(r#"attachment; filename="aSet-Cookie: x=1.zip""#)
+    );
+    assert_eq!(response.header("Set-Cookie"), None);
+}
+
+#[test]
+fn handlers_can_force_a_download() {
+    let mut router = Router::new();
+    router.get("/report", |_: &HttpRequest| {
+        HttpResponse::bytes(
+            StatusCode::OK,
+            HeaderValue::from_static("text/csv"),
+            b"a,b\n".to_vec(),
+        )
+        .attachment("résumé \"2024\".csv")
+    });
+    let config = ServerConfig {
+        router: Arc::new(router),
+        ..ServerConfig::default()
+    };
+    let response = respond(&config, "/report", &[]);
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.header("Content-Disposition"),
+        Some(
+            r#"attachment; filename="r_sum_ \"2024\".csv"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%222024%22.csv"#
+        )
+    );
+}
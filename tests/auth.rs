@@ -0,0 +1,237 @@
+mod common;
+
+use common::{respond, site};
+use custom_http::Router;
+use custom_http::ServerConfig;
+use custom_http::http::auth::{BasicAuth, Credentials, PasswordHash};
+use custom_http::http::headers::{HeaderName, HeaderValue};
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::util::base64_encode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// `htpasswd -nbs alice secret` and `htpasswd -nbB -C 5 bob hunter2`, the
+// latter checked against crypt(3).
+const HTPASSWD: &str = "\
+# admins
+alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=
+bob:$2y$05$abcdefghijklmnopqrstuuoXuKqgZXLiJqzfmMXDDhSFPIvxV7t8.
+";
+
+fn config() -> ServerConfig {
+    let mut router = Router::new();
+    router
+        .wrap(BasicAuth {
+            realm: String::from("Staff only"),
+            prefixes: vec![String::from("/admin/")],
+            credentials: Credentials::parse_htpasswd(HTPASSWD).unwrap(),
+        })
+        .get("/admin/*", |request: &HttpRequest| {
+            let user = request.user.as_deref().unwrap_or("nobody");
+            HttpResponse::text(StatusCode::OK, "hello").with_header(
+                HeaderName::from_static("X-User"),
+                HeaderValue::try_from(user).unwrap(),
+            )
+        })
+        .get("/public", |request: &HttpRequest| {
+            assert_eq!(request.user, None);
+            HttpResponse::text(StatusCode::OK, "hello")
+        });
+    ServerConfig {
+        router: Arc::new(router),
+        ..ServerConfig::default()
+    }
+}
+
+fn basic(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64_encode(format!("{user}:{password}").as_bytes())
+    )
+}
+
+fn assert_challenged(response: &HttpResponse) {
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.header("WWW-Authenticate"),
+        Some("Basic realm=\"Staff only\", charset=\"UTF-8\"")
+    );
+}
+
+#[test]
+fn missing_credentials_are_challenged() {
+    let config = config();
+    assert_challenged(&respond(&config, "/admin/", &[]));
+    assert_challenged(&respond(&config, "/admin", &[]));
+    assert_challenged(&respond(
+        &config,
+        "/admin/users",
+        &[("Authorization", "Bearer abc")],
+    ));
+    assert_challenged(&respond(
+        &config,
+        "/admin/users",
+        &[("Authorization", "Basic !!!")],
+    ));
+}
+
+#[test]
+fn wrong_passwords_and_unknown_users_are_challenged() {
+    let config = config();
+    assert_challenged(&respond(
+        &config,
+        "/admin/",
+        &[("Authorization", &basic("alice", "hunter2"))],
+    ));
+    assert_challenged(&respond(
+        &config,
+        "/admin/",
+        &[("Authorization", &basic("bob", "secret"))],
+    ));
+    assert_challenged(&respond(
+        &config,
+        "/admin/",
+        &[("Authorization", &basic("carol", "secret"))],
+    ));
+}
+
+#[test]
+fn correct_credentials_pass_with_the_user_name() {
+    let config = config();
+    let response = respond(
+        &config,
+        "/admin/",
+        &[("Authorization", &basic("alice", "secret"))],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-User"), Some("alice"));
+    let response = respond(
+        &config,
+        "/admin/x",
+        &[("Authorization", &basic("bob", "hunter2"))],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-User"), Some("bob"));
+    // The scheme is case-insensitive.
+    let lower = basic("alice", "secret").replacen("Basic", "basic", 1);
+    assert_eq!(
+        respond(&config, "/admin/", &[("Authorization", &lower)]).status(),
+        StatusCode::OK
+    );
+}
+
+#[test]
+fn unprotected_paths_need_no_credentials() {
+    let config = config();
+    assert_eq!(respond(&config, "/public", &[]).status(), StatusCode::OK);
+    // Only whole path segments are protected.
+    assert_ne!(
+        respond(&config, "/administrator", &[]).status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test]
+fn encoded_and_unnormalized_paths_are_protected_too() {
+    let mut router = Router::new();
+    router.wrap(BasicAuth {
+        realm: String::from("Staff only"),
+        prefixes: vec![String::from("/admin/")],
+        credentials: Credentials::parse_htpasswd(HTPASSWD).unwrap(),
+    });
+    let config = ServerConfig {
+        router: Arc::new(router),
+        ..site("auth-paths", &[("admin/secret.html", "secret")])
+    };
+    // Static files are looked up by the decoded, normalized path.
+    for target in [
+        "/admin/secret.html",
+        "/%61dmin/secret.html",
+        "/x/../admin/secret.html",
+        "//admin/secret.html",
+        "/admin%2fsecret.html",
+        "/./admin/secret.html",
+    ] {
+        assert_challenged(&respond(&config, target, &[]));
+    }
+    let response = respond(
+        &config,
+        "/%61dmin/secret.html",
+        &[("Authorization", &basic("alice", "secret"))],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    // Paths that can't be decoded or climb above the root are refused.
+    for target in ["/%zzadmin/secret.html", "/../admin/secret.html"] {
+        assert_eq!(
+            respond(&config, target, &[]).status(),
+            StatusCode::BAD_REQUEST,
+            "{target}"
+        );
+    }
+}
+
+#[test]
+fn bcrypt_hashes_match_crypt() {
+    // Checked against crypt(3), including the 72-byte cut.
+    for (password, hash) in [
+        (
+            "U*U",
+            "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+        ),
+        (
+            "",
+            "$2a$04$......................w74bL5gU7LSJClZClCa.Pkz14aTv/XO",
+        ),
+        (
+            &"x".repeat(80),
+            "$2b$04$abcdefghijklmnopqrstuubzadhGtS2zEF.gu0yd0opP6cVzb.e0i",
+        ),
+        (
+            &"x".repeat(71),
+            "$2b$04$abcdefghijklmnopqrstuu.gc7UY/21CSNJGJg21jJzx9QiOpJ9bO",
+        ),
+    ] {
+        let parsed = PasswordHash::parse(hash).unwrap();
+        assert!(parsed.verify(password), "{hash}");
+        assert!(!parsed.verify("U*U*"), "{hash}");
+    }
+    assert!(
+        PasswordHash::parse("$2b$04$abcdefghijklmnopqrstuubzadhGtS2zEF.gu0yd0opP6cVzb.e0i")
+            .unwrap()
+            .verify(&"x".repeat(72))
+    );
+}
+
+#[test]
+fn unknown_users_take_as_long_as_bcrypt_users() {
+    // Cost 8 against the cost 5 of the file's bcrypt entry: the costliest
+    // entry is the one an unknown user is checked against.
+    let text =
+        format!("{HTPASSWD}carol:$2y$08$abcdefghijklmnopqrstuuoXuKqgZXLiJqzfmMXDDhSFPIvxV7t8.\n");
+    let credentials = Credentials::parse_htpasswd(&text).unwrap();
+    let time = |user: &str| {
+        // The fastest of a few runs, to keep scheduling noise out.
+        (0..3)
+            .map(|_| {
+                let started = Instant::now();
+                assert!(!credentials.verify(user, "wrong"));
+                started.elapsed()
+            })
+            .min()
+            .unwrap_or(Duration::ZERO)
+    };
+    let known = time("carol");
+    let unknown = time("mallory");
+    assert!(unknown * 2 > known, "{unknown:?} vs {known:?}");
+}
+
+#[test]
+fn unsupported_htpasswd_lines_are_refused() {
+    assert!(Credentials::parse_htpasswd("alice:$apr1$salt$hash\n").is_err());
+    assert!(Credentials::parse_htpasswd("alice:plaintext\n").is_err());
+    assert!(Credentials::parse_htpasswd("no colon\n").is_err());
+    assert!(Credentials::parse_htpasswd("alice:{SHA}short\n").is_err());
+    assert!(Credentials::parse_htpasswd("\n# nobody yet\n").is_ok());
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use common::{respond, site};
+use custom_http::http::auth::{BearerAuth, TokenSet, TokenVerifier, redact_query};
+use custom_http::http::headers::{HeaderName, HeaderValue};
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::io::access_log::{AccessLogConfig, AccessLogFormat, LogTarget};
+use custom_http::{Router, Server, ServerConfig};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+fn router(verifier: TokenVerifier, forbid_invalid: bool) -> Router {
+    let mut router = Router::new();
+    router
+        .wrap(BearerAuth {
+            prefixes: vec![String::from("/api/"), String::from("/metrics")],
+            verifier,
+            forbid_invalid,
+        })
+        .get("/api/*", |request: &HttpRequest| {
+            let user = request.user.as_deref().unwrap_or("nobody");
+            HttpResponse::text(StatusCode::OK, "hello").with_header(
+                HeaderName::from_static("X-User"),
+                HeaderValue::try_from(user).unwrap(),
+            )
+        })
+        .get("/metrics/*", |_: &HttpRequest| {
+            HttpResponse::text(StatusCode::OK, "open")
+        })
+        .get("/public", |request: &HttpRequest| {
+            assert_eq!(request.user, None);
+            HttpResponse::text(StatusCode::OK, "hello")
+        });
+    router
+}
+
+fn tokens() -> TokenVerifier {
+    let mut tokens = TokenSet::new();
+    tokens
+        .insert("t0ken-for-billing", "billing")
+        .insert("t0ken-for-reports", "reports");
+    TokenVerifier::Tokens(tokens)
+}
+
+fn config(verifier: TokenVerifier, forbid_invalid: bool) -> ServerConfig {
+    ServerConfig {
+        router: Arc::new(router(verifier, forbid_invalid)),
+        ..ServerConfig::default()
+    }
+}
+
+#[test]
+fn missing_tokens_are_challenged() {
+    let config = config(tokens(), false);
+    for (path, authorization) in [
+        ("/api/", None),
+        ("/api", None),
+        ("/api/x", Some("Basic YWxpY2U6c2VjcmV0")),
+        ("/metrics", None),
+    ] {
+        let headers: Vec<_> = authorization
+            .map(|value| ("Authorization", value))
+            .into_iter()
+            .collect();
+        let response = respond(&config, path, &headers);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+        assert_eq!(
+            response.header("WWW-Authenticate"),
+            Some("Bearer"),
+            "{path}"
+        );
+    }
+}
+
+#[test]
+fn invalid_tokens_are_refused() {
+    let refusing = config(tokens(), false);
+    let response = respond(
+        &refusing,
+        "/api/x",
+        &[("Authorization", "Bearer t0ken-for-nobody")],
+    );
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.header("WWW-Authenticate"),
+        Some("Bearer error=\"invalid_token\"")
+    );
+
+    let forbidding = config(tokens(), true);
+    let response = respond(
+        &forbidding,
+        "/api/x",
+        &[("Authorization", "Bearer t0ken-for-nobody")],
+    );
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.header("WWW-Authenticate"), None);
+    // A missing token is still a 401 so the client knows to send one.
+    assert_eq!(
+        respond(&forbidding, "/api/x", &[]).status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test]
+fn valid_tokens_pass_with_their_identity() {
+    let config = config(tokens(), true);
+    let response = respond(
+        &config,
+        "/api/x",
+        &[("Authorization", "Bearer t0ken-for-billing")],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-User"), Some("billing"));
+    let response = respond(
+        &config,
+        "/api/",
+        &[("Authorization", "bearer t0ken-for-reports")],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-User"), Some("reports"));
+}
+
+#[test]
+fn custom_verifiers_decide_the_identity() {
+    let verifier = TokenVerifier::Custom(Arc::new(|token: &str| {
+        token
+            .strip_prefix("signed.")
+            .map(|client| format!("client:{client}"))
+    }));
+    let config = config(verifier, false);
+    let response = respond(
+        &config,
+        "/api/x",
+        &[("Authorization", "Bearer signed.acme")],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-User"), Some("client:acme"));
+    assert_eq!(
+        respond(&config, "/api/x", &[("Authorization", "Bearer forged")]).status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test]
+fn unprotected_paths_need_no_token() {
+    let config = config(tokens(), false);
+    assert_eq!(respond(&config, "/public", &[]).status(), StatusCode::OK);
+    // `/metrics` protects only itself, `/api/` only whole segments.
+    assert_eq!(
+        respond(&config, "/metrics/extra", &[]).status(),
+        StatusCode::OK
+    );
+    assert_ne!(
+        respond(&config, "/apiary", &[]).status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test]
+fn encoded_and_unnormalized_paths_are_protected_too() {
+    let mut router = Router::new();
+    router.wrap(BearerAuth {
+        prefixes: vec![String::from("/api/")],
+        verifier: tokens(),
+        forbid_invalid: false,
+    });
+    let config = ServerConfig {
+        router: Arc::new(router),
+        ..site("bearer-paths", &[("api/secret.txt", "secret")])
+    };
+    for target in [
+        "/api/secret.txt",
+        "/%61pi/secret.txt",
+        "/x/../api/secret.txt",
+        "//api/secret.txt",
+        "/api%2fsecret.txt",
+        "/./api/secret.txt",
+    ] {
+        let response = respond(&config, target, &[]);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{target}");
+    }
+    let response = respond(
+        &config,
+        "/%61pi/secret.txt",
+        &[("Authorization", "Bearer t0ken-for-billing")],
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        respond(&config, "/../api/secret.txt", &[]).status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[test]
+fn credentials_in_query_strings_are_redacted() {
+    assert_eq!(redact_query("/a?b=1"), "/a?b=1");
+    assert_eq!(redact_query("/a"), "/a");
+    assert_eq!(
+        redact_query("/a?access_token=xyz&b=1&API_KEY=k&token"),
+        "/a?access_token=REDACTED&b=1&API_KEY=REDACTED&token"
+    );
+
+    let (lines, received) = mpsc::channel();
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .router(router(tokens(), false))
+        .access_log(AccessLogConfig {
+            format: AccessLogFormat::combined(),
+            target: LogTarget::Channel(lines),
+        })
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(
+            b"GET /api/x?access_token=t0ken-for-billing&page=2 HTTP/1.1\r\n\
+              Authorization: Bearer t0ken-for-billing\r\n\
+              Referer: http://example.com/?token=t0ken-for-reports\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    drop(stream);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+
+    let line = received.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!line.contains("t0ken"), "{line}");
+    assert!(
+        line.contains("\"GET /api/x?access_token=REDACTED&page=2 HTTP/1.1\""),
+        "{line}"
+    );
+    assert!(
+        line.contains("\"http://example.com/?token=REDACTED\""),
+        "{line}"
+    );
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,54 @@
+//! `buffer_allocations` counts across the whole process, so this test crate
+//! holds a single test.
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::nonblocking::buffer_allocations;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn buffers_are_reused_across_requests_and_connections() {
+    let config = ServerConfig {
+        reactors: 1,
+        ..site("buffers", &[("page.html", "page")])
+    };
+    let stats = config.stats.clone();
+    let running = serve(config);
+    let address = running.local_addr().unwrap();
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+    let allocated = buffer_allocations();
+
+    // The next request on the connection reads into the same buffer.
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+    assert_eq!(buffer_allocations(), allocated);
+
+    // A closed connection's buffer goes to the next one accepted.
+    drop(stream);
+    let started = Instant::now();
+    while stats.connections_active() > 0 && started.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+    assert_eq!(buffer_allocations(), allocated);
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::respond;
+use custom_http::{CacheRule, ServerConfig};
+use std::fs;
+use std::path::PathBuf;
+
+/// Creates a fresh document root with an index page, a stylesheet and a text file,
+/// and a config serving it with `rules`.
+fn config(name: &str, rules: &[(&str, &str)]) -> ServerConfig {
+    let root: PathBuf = std::env::temp_dir().join(format!(
+        "custom_http-cache-control-{}-{name}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("assets")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("docs/index.html"), "<p>docs</p>").unwrap();
+    fs::write(root.join("assets/site.css"), "p {}").unwrap();
+    fs::write(root.join("notes.txt"), "notes").unwrap();
+    ServerConfig {
+        document_root: root,
+        cache_control: rules
+            .iter()
+            .map(|(pattern, value)| CacheRule::new(pattern, value).unwrap())
+            .collect(),
+        ..ServerConfig::default()
+    }
+}
+
+#[test]
+fn matching_files_get_the_rules_value() {
+    let config = config(
+        "rules",
+        &[
+            ("/assets/*", "public, max-age=31536000, immutable"),
+            ("/*.html", "no-cache"),
+        ],
+    );
+    for (target, value) in [
+        (
+            "/assets/site.css",
+            Some("public, max-age=31536000, immutable"),
+        ),
+        // Index files are matched by the file served.
+        ("/docs/", Some("no-cache")),
+        ("/docs/index.html", Some("no-cache")),
+        ("/notes.txt", None),
+    ] {
+        let response = respond(&config, target, &[]);
+        assert_eq!(response.status().as_u16(), 200, "{target}");
+        assert_eq!(response.header("Cache-Control"), value, "{target}");
+    }
+    // Error pages aren't static files.
+    let response = respond(&config, "/assets/missing.css", &[]);
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(response.header("Cache-Control"), None);
+}
+
+#[test]
+fn not_modified_responses_repeat_the_header() {
+    let config = config("not-modified", &[("/assets/*", "public, max-age=600")]);
+    let response = respond(&config, "/assets/site.css", &[]);
+    let etag = response.header("ETag").unwrap().to_owned();
+    let response = respond(&config, "/assets/site.css", &[("If-None-Match", &etag)]);
+    assert_eq!(response.status().as_u16(), 304);
+    assert_eq!(
+        response.header("Cache-Control"),
+        Some("public, max-age=600")
+    );
+}
+
+#[test]
+fn rules_need_a_path_pattern_and_a_header_value() {
+    assert!(CacheRule::new("*.css", "no-cache").is_err());
+    assert!(CacheRule::new("/*.css", "no-cache\r\nX-Injected: 1").is_err());
+    let rule = CacheRule::new("/docs/*/index.html", "no-cache").unwrap();
+    assert!(rule.matches("/docs/v1/index.html"));
+    assert!(rule.matches("/docs/v1/api/index.html"));
+    assert!(!rule.matches("/docs/index.html"));
+}
@@ -0,0 +1,195 @@
+#![cfg(unix)]
+
+use custom_http::ServerConfig;
+use custom_http::http::cgi::CgiConfig;
+use custom_http::http::request::parse_request;
+use custom_http::http::response::http_handler;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const ENV_SCRIPT: &str = r#"#!/bin/sh
+printf 'Content-Type: text/plain\r\nX-Script: env\r\n\r\n'
+echo "method=$REQUEST_METHOD"
+echo "query=$QUERY_STRING"
+echo "path_info=$PATH_INFO"
+echo "script_name=$SCRIPT_NAME"
+echo "protocol=$SERVER_PROTOCOL"
+echo "remote=$REMOTE_ADDR"
+echo "agent=$HTTP_USER_AGENT"
+echo "gateway=$GATEWAY_INTERFACE"
+echo "proxy=${HTTP_PROXY-unset}"
+"#;
+
+const ECHO_SCRIPT: &str = r#"#!/bin/sh
+printf 'Status: 201 Created\nContent-Type: %s\n\n' "$CONTENT_TYPE"
+echo "length=$CONTENT_LENGTH"
+head -c "$CONTENT_LENGTH"
+"#;
+
+const SLOW_SCRIPT: &str = r#"#!/bin/sh
+echo $$ > "$0.pid"
+exec sleep 10
+"#;
+
+const CRASH_SCRIPT: &str = "#!/bin/sh\nkill -SEGV $$\n";
+
+// A stray CR would end the header line early on the way to the client.
+const INJECT_SCRIPT: &str = r#"#!/bin/sh
+printf 'Content-Type: text/plain\r\nX-Note: a\rSet-Cookie: session=stolen\r\n\r\nbody'
+"#;
+
+/// A script directory holding the scripts above, and a file that isn't executable.
+struct Scripts(PathBuf);
+
+impl Scripts {
+    fn new(name: &str) -> Scripts {
+        let dir =
+            std::env::temp_dir().join(format!("custom_http-cgi-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, script, mode) in [
+            ("env.sh", ENV_SCRIPT, 0o755),
+            ("echo.sh", ECHO_SCRIPT, 0o755),
+            ("slow.sh", SLOW_SCRIPT, 0o755),
+            ("crash.sh", CRASH_SCRIPT, 0o755),
+            ("inject.sh", INJECT_SCRIPT, 0o755),
+            ("plain.sh", ENV_SCRIPT, 0o644),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        Scripts(dir)
+    }
+
+    fn config(&self, timeout: Duration) -> ServerConfig {
+        let mut cgi = CgiConfig {
+            root: self.0.clone(),
+            timeout,
+            ..CgiConfig::default()
+        };
+        cgi.resolve_root().unwrap();
+        ServerConfig {
+            cgi: Some(cgi),
+            ..ServerConfig::default()
+        }
+    }
+}
+
+impl Drop for Scripts {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Answers `request` (head and body) as if it came from 192.0.2.10.
+fn exchange(config: &ServerConfig, request: &str) -> String {
+    let (mut parsed, head_len) = parse_request(request.as_bytes()).unwrap();
+    parsed.body = request.as_bytes()[head_len..].to_vec();
+    parsed.peer = "192.0.2.10".parse().ok();
+    String::from_utf8_lossy(&http_handler(&parsed, config)).into_owned()
+}
+
+#[test]
+fn scripts_get_the_cgi_environment() {
+    let scripts = Scripts::new("env");
+    let config = scripts.config(Duration::from_secs(10));
+
+    let response = exchange(
+        &config,
+        "GET /cgi-bin/env.sh/extra/info?name=x&y=1 HTTP/1.1\r\nHost: site.test\r\n\
+         User-Agent: tester\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(
+        response.contains("\r\nContent-Type: text/plain\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("\r\nX-Script: env\r\n"), "{response}");
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    assert_eq!(
+        body,
+        "method=GET\nquery=name=x&y=1\npath_info=/extra/info\nscript_name=/cgi-bin/env.sh\n\
+         protocol=HTTP/1.1\nremote=192.0.2.10\nagent=tester\ngateway=CGI/1.1\nproxy=unset\n"
+    );
+}
+
+#[test]
+fn a_proxy_header_does_not_become_http_proxy() {
+    let scripts = Scripts::new("httpoxy");
+    let config = scripts.config(Duration::from_secs(10));
+
+    let response = exchange(
+        &config,
+        "GET /cgi-bin/env.sh HTTP/1.1\r\nHost: site.test\r\nProxy: http://evil\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\nproxy=unset\n"), "{response}");
+}
+
+#[test]
+fn post_bodies_reach_stdin() {
+    let scripts = Scripts::new("post");
+    let config = scripts.config(Duration::from_secs(10));
+
+    let response = exchange(
+        &config,
+        "POST /cgi-bin/echo.sh HTTP/1.1\r\nHost: site.test\r\nContent-Type: text/x-test\r\n\
+         Content-Length: 11\r\n\r\nhello world",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 201 Created\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.contains("\r\nContent-Type: text/x-test\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.ends_with("\r\n\r\nlength=11\nhello world"),
+        "{response}"
+    );
+}
+
+#[test]
+fn slow_scripts_are_killed() {
+    let scripts = Scripts::new("slow");
+    let config = scripts.config(Duration::from_millis(300));
+
+    let started = Instant::now();
+    let response = exchange(
+        &config,
+        "GET /cgi-bin/slow.sh HTTP/1.1\r\nHost: site.test\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    #[cfg(target_os = "linux")]
+    {
+        let pid = fs::read_to_string(scripts.0.join("slow.sh.pid")).unwrap();
+        let process = PathBuf::from(format!("/proc/{}", pid.trim()));
+        assert!(!process.exists(), "{} is still running", pid.trim());
+    }
+}
+
+#[test]
+fn failures_map_to_error_pages() {
+    let scripts = Scripts::new("failures");
+    let config = scripts.config(Duration::from_secs(10));
+
+    for (path, status) in [
+        ("/cgi-bin/missing.sh", 404),
+        ("/cgi-bin/plain.sh", 403),
+        ("/cgi-bin/crash.sh", 502),
+        ("/cgi-bin/inject.sh", 502),
+        ("/cgi-bin/../cgi-bin/env.sh", 200),
+        ("/cgi-bin/%2e%2e/%2e%2e/bin/sh", 403),
+    ] {
+        let response = exchange(
+            &config,
+            &format!("GET {path} HTTP/1.1\r\nHost: site.test\r\n\r\n"),
+        );
+        let expected = format!("HTTP/1.1 {status} ");
+        assert!(response.starts_with(&expected), "{path}: {response}");
+    }
+}
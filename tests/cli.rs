@@ -0,0 +1,99 @@
+use custom_http::ServerError;
+use custom_http::cli::{self, Command};
+use custom_http::log::LogLevel;
+use custom_http::server::ServerConfig;
+use std::path::Path;
+
+fn serve(args: &[&str], config_env: Option<&str>) -> Result<ServerConfig, ServerError> {
+    let args = args.iter().map(|arg| arg.to_string());
+    match cli::parse(args, config_env.map(Into::into))? {
+        Command::Serve(config) => Ok(*config),
+        command => panic!("expected a config, got {command:?}"),
+    }
+}
+
+#[test]
+fn defaults_without_arguments() {
+    let config = serve(&[], None).unwrap();
+    assert_eq!(config.addresses, ["127.0.0.1:8080"]);
+    assert_eq!(config.document_root, Path::new("public"));
+    assert_eq!(config.log_level, LogLevel::Info);
+}
+
+#[test]
+fn config_file_overrides_defaults() {
+    let config = serve(&["--config", "config/custom_http.toml"], None).unwrap();
+    assert_eq!(config.addresses, ["127.0.0.1:8080", "[::1]:8080"]);
+    assert_eq!(config.reactor.workers, 8);
+}
+
+#[test]
+fn flags_override_config_file_wherever_they_appear() {
+    let config = serve(
+        &[
+            "--workers",
+            "3",
+            "-c",
+            "config/custom_http.toml",
+            "-r",
+            "site",
+        ],
+        None,
+    )
+    .unwrap();
+    assert_eq!(config.reactor.workers, 3);
+    assert_eq!(config.document_root, Path::new("site"));
+    // Untouched by flags, so still from the file.
+    assert_eq!(config.reactors, 2);
+}
+
+#[test]
+fn config_flag_beats_environment() {
+    let config = serve(
+        &["--config", "config/custom_http.toml"],
+        Some("tests/fixtures/broken.toml"),
+    )
+    .unwrap();
+    assert_eq!(config.reactor.workers, 8);
+    let error = serve(&[], Some("tests/fixtures/broken.toml")).unwrap_err();
+    assert!(matches!(error, ServerError::Config(_)));
+}
+
+#[test]
+fn port_applies_to_configured_and_portless_addresses() {
+    let config = serve(&["-p", "9000"], None).unwrap();
+    assert_eq!(config.addresses, ["127.0.0.1:9000"]);
+    let config = serve(&["--port", "9000", "-a", "0.0.0.0", "-a", "[::1]"], None).unwrap();
+    assert_eq!(config.addresses, ["0.0.0.0:9000", "[::1]:9000"]);
+    let config = serve(&["-a", "::"], None).unwrap();
+    assert_eq!(config.addresses, ["[::]:8080"]);
+}
+
+#[test]
+fn address_with_port_conflicts_with_port_flag() {
+    let error = serve(&["-a", "0.0.0.0:80", "-p", "9000"], None).unwrap_err();
+    assert!(matches!(error, ServerError::Config(message) if message.contains("0.0.0.0:80")));
+}
+
+#[test]
+fn bad_values_are_errors() {
+    for args in [
+        &["--port", "http"][..],
+        &["--workers", "0"],
+        &["--log-level", "loud"],
+        &["--config"],
+        &["--frobnicate"],
+    ] {
+        assert!(
+            matches!(serve(args, None), Err(ServerError::Config(_))),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn help_and_version_win() {
+    let parse = |args: &[&str]| cli::parse(args.iter().map(|arg| arg.to_string()), None);
+    assert!(matches!(parse(&["-p", "80", "--help"]), Ok(Command::Help)));
+    assert!(matches!(parse(&["-V"]), Ok(Command::Version)));
+}
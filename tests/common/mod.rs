@@ -0,0 +1,91 @@
+//! Helpers shared by the integration tests. Each test crate uses a
+//! different subset, hence the `dead_code` allowance.
+#![allow(dead_code)]
+
+use custom_http::http::request::{HttpRequest, parse_request};
+use custom_http::http::response::{HttpResponse, handle, http_handler};
+use custom_http::{Server, ServerConfig, ServerHandle};
+use std::fs;
+use std::io::Read;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Parses a `GET` of `target` for `localhost`, with the extra header
+/// fields `headers` as (name, value) pairs.
+pub fn request(target: &str, headers: &[(&str, &str)]) -> HttpRequest {
+    let mut head = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    parse_request(head.as_bytes()).unwrap().0
+}
+
+/// Answers a `GET` of `target` and returns the response, unserialized.
+pub fn respond(config: &ServerConfig, target: &str, headers: &[(&str, &str)]) -> HttpResponse {
+    handle(&request(target, headers), config)
+}
+
+/// Answers a `GET` of `target` with the extra header fields `headers` and
+/// returns the serialized response.
+pub fn get_with(config: &ServerConfig, target: &str, headers: &[(&str, &str)]) -> String {
+    let bytes = http_handler(&request(target, headers), config);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Answers a plain `GET` of `target` and returns the serialized response.
+pub fn get(config: &ServerConfig, target: &str) -> String {
+    get_with(config, target, &[])
+}
+
+/// Creates a fresh document root for the test `name` holding `files`, as
+/// (relative path, contents) pairs, and a config serving it. Every call gets
+/// a directory of its own, so tests running at once don't share one.
+pub fn site(name: &str, files: &[(&str, &str)]) -> ServerConfig {
+    static SITES: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "custom_http-{}-{name}-{}",
+        std::process::id(),
+        SITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    for (path, contents) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    ServerConfig {
+        document_root: root,
+        ..ServerConfig::default()
+    }
+}
+
+/// Starts serving `config` on an ephemeral port of `127.0.0.1`.
+pub fn serve(config: ServerConfig) -> ServerHandle {
+    Server::start(ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        ..config
+    })
+    .unwrap()
+}
+
+/// Reads one `Content-Length` framed response from `stream`, leaving the
+/// connection ready for the next one.
+pub fn read_response(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&response).into_owned();
+    let length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).unwrap();
+    response.extend(body);
+    String::from_utf8_lossy(&response).into_owned()
+}
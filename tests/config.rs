@@ -0,0 +1,160 @@
+use custom_http::{CacheRule, ServerConfig, ServerError};
+use std::io;
+use std::time::Duration;
+
+#[test]
+fn builder_applies_settings() {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .address("[::1]:0")
+        .workers(3)
+        .keep_alive_timeout(Duration::from_secs(15))
+        .max_body_size(4096)
+        .build()
+        .unwrap();
+    assert_eq!(config.addresses, ["127.0.0.1:0", "[::1]:0"]);
+    assert_eq!(config.reactor.workers, 3);
+    assert_eq!(config.keep_alive_timeout, Duration::from_secs(15));
+    assert_eq!(config.max_body_size, 4096);
+    assert!(config.document_root.is_absolute());
+}
+
+#[test]
+fn builder_rejects_zero_workers() {
+    let error = ServerConfig::builder().workers(0).build().unwrap_err();
+    assert!(matches!(error, ServerError::Config(message) if message.contains("workers")));
+}
+
+#[test]
+fn builder_rejects_unparseable_address() {
+    let error = ServerConfig::builder()
+        .address("not an address")
+        .build()
+        .unwrap_err();
+    assert!(
+        matches!(error, ServerError::InvalidAddress { address, .. } if address == "not an address")
+    );
+}
+
+#[test]
+fn builder_rejects_missing_document_root() {
+    let error = ServerConfig::builder()
+        .document_root("does/not/exist")
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, ServerError::Io(e) if e.kind() == io::ErrorKind::NotFound));
+}
+
+#[test]
+fn example_config_file_parses() {
+    let config = ServerConfig::from_toml_path("config/custom_http.toml").unwrap();
+    assert_eq!(config.addresses, ["127.0.0.1:8080", "[::1]:8080"]);
+    assert_eq!(config.reactor.workers, 8);
+    assert_eq!(config.max_body_size, 1_048_576);
+    assert_eq!(config.header_deadline, Duration::from_secs(30));
+    assert_eq!(config.error_pages[&404], "public/404.html");
+    assert_eq!(config.mime_overrides["tar.gz"], "application/gzip");
+    assert_eq!(config.max_header_fields, 100);
+    assert_eq!(config.max_head_size, 16384);
+    assert_eq!(
+        config.cache_control,
+        [
+            CacheRule::new("/assets/*", "public, max-age=31536000, immutable").unwrap(),
+            CacheRule::new("/*.html", "no-cache").unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn broken_config_file_names_file_line_and_key() {
+    let error = ServerConfig::from_toml_path("tests/fixtures/broken.toml").unwrap_err();
+    let ServerError::Config(message) = error else {
+        panic!("expected a configuration error, got {error:?}");
+    };
+    assert_eq!(
+        message,
+        "tests/fixtures/broken.toml: line 4: keep_alive_timeout: expected a number of seconds"
+    );
+}
+
+/// Writes `contents` to a fresh config file named after `name`.
+fn config_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "custom_http-config-{}-{name}.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn header_limits_in_a_config_file() {
+    let path = config_file("limits", "max_header_fields = 20\nmax_head_size = 8192\n");
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.max_header_fields, 20);
+    assert_eq!(config.max_head_size, 8192);
+
+    for (contents, key) in [
+        ("max_header_fields = 0\n", "max_header_fields"),
+        ("max_head_size = -1\n", "max_head_size"),
+        ("max_head_size = \"16k\"\n", "max_head_size"),
+    ] {
+        let path = config_file("bad-limits", contents);
+        let error = ServerConfig::from_toml_path(&path).unwrap_err();
+        assert!(
+            matches!(&error, ServerError::Config(message)
+                if message.contains(&format!("line 1: {key}: expected a positive integer"))),
+            "{contents}: {error:?}"
+        );
+    }
+}
+
+#[test]
+fn cache_control_rules_in_a_config_file() {
+    let path = config_file(
+        "cache-control",
+        "[cache_control]\n\
+         \"/assets/*\" = \"public, max-age=31536000, immutable\"\n\
+         \"/*.html\" = \"no-cache\"\n\
+         \"/*\" = \"max-age=60\"\n",
+    );
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    // The first matching rule wins.
+    for (filename, value) in [
+        (
+            "/assets/app.css",
+            Some("public, max-age=31536000, immutable"),
+        ),
+        (
+            "/assets/docs/index.html",
+            Some("public, max-age=31536000, immutable"),
+        ),
+        ("/index.html", Some("no-cache")),
+        ("/blog/post.html", Some("no-cache")),
+        ("/robots.txt", Some("max-age=60")),
+    ] {
+        assert_eq!(config.cache_control_for(filename), value, "{filename}");
+    }
+    assert_eq!(
+        ServerConfig::default().cache_control_for("/index.html"),
+        None
+    );
+
+    for (contents, reason) in [
+        (
+            "[cache_control]\n\"*.css\" = \"no-cache\"\n",
+            "line 2: cache_control.*.css: cache_control pattern *.css must start with /",
+        ),
+        (
+            "[cache_control]\n\"/*.css\" = 3600\n",
+            "line 2: cache_control./*.css: expected a string",
+        ),
+    ] {
+        let path = config_file("bad-cache-control", contents);
+        let error = ServerConfig::from_toml_path(&path).unwrap_err();
+        assert!(
+            matches!(&error, ServerError::Config(message) if message.ends_with(reason)),
+            "{contents}: {error:?}"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, ServerConfig, ServerHandle};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A single-reactor config serving a small page.
+fn config() -> ServerConfig {
+    ServerConfig {
+        reactors: 1,
+        ..site("connections", &[("page.html", "page")])
+    }
+}
+
+fn start(config: &ServerConfig) -> (SocketAddr, ServerHandle) {
+    let running = serve(config.clone());
+    (running.local_addr().unwrap(), running)
+}
+
+/// Waits up to two seconds for `done` to hold.
+fn eventually(mut done: impl FnMut() -> bool) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(2) {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// The number of open file descriptors of this process.
+#[cfg(target_os = "linux")]
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+#[test]
+fn closed_connections_leave_nothing_behind() {
+    let config = config();
+    let (address, running) = start(&config);
+    #[cfg(target_os = "linux")]
+    let fds = open_fds();
+
+    for i in 0..2000 {
+        let mut stream = TcpStream::connect(address).unwrap();
+        match i % 4 {
+            // Served, then closed by the server.
+            0 => {
+                stream
+                    .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                read_response(&mut stream);
+            }
+            // Served, then closed by the client while kept alive.
+            1 => {
+                stream
+                    .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+                    .unwrap();
+                read_response(&mut stream);
+            }
+            // Gone in the middle of a request.
+            2 => stream.write_all(b"GET /page.html HTT").unwrap(),
+            // Never said a word.
+            _ => {}
+        }
+        drop(stream);
+    }
+
+    let stats = &config.stats;
+    assert!(
+        eventually(|| stats.connections_active() == 0),
+        "{} still open",
+        stats.connections_active()
+    );
+    assert_eq!(stats.snapshot().connections_accepted, 2000);
+    // Other tests come and go meanwhile; a leak would be thousands.
+    #[cfg(target_os = "linux")]
+    assert!(
+        eventually(|| open_fds() < fds + 50),
+        "{} fds, {fds} before",
+        open_fds()
+    );
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn a_reset_mid_response_is_cleaned_up() {
+    let config = config();
+    let (address, running) = start(&config);
+    let stream = TcpStream::connect(address).unwrap();
+    (&stream)
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    stream.shutdown(Shutdown::Both).unwrap();
+    drop(stream);
+
+    let stats = &config.stats;
+    assert!(eventually(|| stats.connections_active() == 0));
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn kept_alive_connections_serve_request_after_request() {
+    let config = ServerConfig {
+        reactors: 1,
+        ..site("keep-alive", &[("a.html", "first"), ("b.html", "second")])
+    };
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    stream.write_all(b"GET /a.html HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nfirst"));
+    stream.write_all(b"GET /b.html HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nsecond"));
+
+    // Pipelined: the second request is already buffered when the first
+    // response goes out, and is answered after it.
+    stream
+        .write_all(b"GET /b.html HTTP/1.1\r\n\r\nGET /a.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nsecond"));
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nfirst"));
+    assert_eq!(config.stats.snapshot().connections_accepted, 1);
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn connections_over_the_limit_get_a_503() {
+    let config = ServerConfig {
+        max_connections: 3,
+        ..config()
+    };
+    let (address, running) = start(&config);
+    let idle: Vec<TcpStream> = (0..3)
+        .map(|_| TcpStream::connect(address).unwrap())
+        .collect();
+    let stats = &config.stats;
+    assert!(eventually(|| stats.connections_active() == 3));
+
+    // Answered and hung up on without reading a request.
+    let mut over = TcpStream::connect(address).unwrap();
+    over.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut response = String::new();
+    over.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("\r\nConnection: close\r\n"), "{response}");
+    assert_eq!(stats.connections_active(), 3);
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.connections_accepted, 4);
+    assert_eq!(snapshot.responses_5xx, 1);
+
+    // A freed place is taken by the next client.
+    drop(idle);
+    assert!(eventually(|| stats.connections_active() == 0));
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn requests_are_read_while_a_response_is_being_written() {
+    let big = "x".repeat(8 * 1024 * 1024);
+    let config = ServerConfig {
+        reactors: 1,
+        ..site("interest", &[("big.txt", &big), ("small.txt", "small")])
+    };
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET /big.txt HTTP/1.1\r\n\r\n").unwrap();
+    // Nothing is read yet, so most of the body is still queued when the
+    // next request comes in.
+    thread::sleep(Duration::from_millis(100));
+    stream
+        .write_all(b"GET /small.txt HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let first = read_response(&mut stream);
+    assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "{first:.100}");
+    assert!(first.ends_with(&big));
+    let second = read_response(&mut stream);
+    assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{second}");
+    assert!(second.ends_with("\r\n\r\nsmall"), "{second}");
+    // Closed once the last response is out.
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn half_closed_clients_still_get_their_response() {
+    let config = config();
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    // Done sending; still reading.
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut response = String::new();
+    // The connection closes after the response, since no more can come.
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\npage"), "{response}");
+
+    // Pipelined requests sent before the half-close are all answered.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\n\r\n")
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(read_response(&mut stream).starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    drop(stream);
+    let stats = &config.stats;
+    assert!(eventually(|| stats.connections_active() == 0));
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn requests_arriving_in_pieces_are_put_back_together() {
+    let mut router = Router::new();
+    router.post("/echo", |request: &HttpRequest| {
+        HttpResponse::bytes(
+            StatusCode::OK,
+            HeaderValue::from_static("text/plain"),
+            request.body.clone(),
+        )
+    });
+    let config = ServerConfig {
+        router: Arc::new(router),
+        ..config()
+    };
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+
+    // Head and body each split over several reads, the head mid-line.
+    for piece in [
+        "POST /ec",
+        "ho HTTP/1.1\r\nContent-Le",
+        "ngth: 11\r\n\r",
+        "\nhello",
+        " wor",
+        "ld",
+    ] {
+        stream.write_all(piece.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nhello world"), "{response}");
+
+    // Back to reading a head: the next request on the connection is served.
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nagain")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nagain"));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn chunked_bodies_are_decoded_as_they_trickle_in() {
+    let mut router = Router::new();
+    router.post("/echo", |request: &HttpRequest| {
+        HttpResponse::bytes(
+            StatusCode::OK,
+            HeaderValue::from_static("text/plain"),
+            request.body.clone(),
+        )
+    });
+    let config = ServerConfig {
+        router: Arc::new(router),
+        ..config()
+    };
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+
+    // One byte at a time, so decoding stops and resumes inside every part
+    // of the encoding: sizes, extensions, data, CRLFs and trailers.
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+        .unwrap();
+    for byte in b"4;ext=1\r\nwiki\r\n5\r\npedia\r\n0\r\nTrailer: x\r\n\r\n" {
+        stream.write_all(&[*byte]).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nwikipedia"), "{response}");
+
+    // Many one-byte chunks on the same connection: the decoder starts over
+    // for the new request, and gets through them all.
+    let chunks = 100_000;
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+        .unwrap();
+    let body = b"1\r\nx\r\n".repeat(chunks);
+    for piece in body.chunks(600) {
+        stream.write_all(piece).unwrap();
+    }
+    stream.write_all(b"0\r\n\r\n").unwrap();
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains(&format!("\r\nContent-Length: {chunks}\r\n")));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn a_shutdown_sent_from_another_thread_stops_the_event_loop() {
+    let config = config();
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    let started = Instant::now();
+    // `shutdown` hands `ReactorMsg::Shutdown` to the reactor's thread and
+    // wakes it.
+    let running = thread::spawn(move || {
+        running.shutdown();
+        running
+    })
+    .join()
+    .unwrap();
+    running.join().unwrap();
+    // Woken at once, not at the end of a poll timeout.
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    // The idle connection was closed and the listener is gone.
+    assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn a_flood_of_connections_never_grows_past_the_cap() {
+    let mut config = ServerConfig {
+        max_connections: 20,
+        ..config()
+    };
+    config.reactor.slab_capacity = 4;
+    let (address, running) = start(&config);
+
+    let flood: Vec<TcpStream> = (0..100)
+        .map(|_| TcpStream::connect(address).unwrap())
+        .collect();
+    let stats = &config.stats;
+    assert!(eventually(|| stats.snapshot().connections_accepted == 100));
+    assert_eq!(stats.connections_active(), 20);
+    assert_eq!(stats.snapshot().responses_5xx, 80);
+
+    // The 20 held ones are still served.
+    let mut served = 0;
+    for mut stream in flood {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        if stream
+            .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .is_err()
+        {
+            continue;
+        }
+        let mut response = String::new();
+        if stream.read_to_string(&mut response).is_ok()
+            && response.starts_with("HTTP/1.1 200 OK\r\n")
+        {
+            served += 1;
+        }
+    }
+    assert_eq!(served, 20);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn one_wait_returns_at_most_event_capacity_events() {
+    let mut config = config();
+    config.reactor.event_capacity = 1;
+    config.reactor.loop_metrics = true;
+    let (address, running) = start(&config);
+
+    let mut streams: Vec<TcpStream> = (0..20)
+        .map(|_| TcpStream::connect(address).unwrap())
+        .collect();
+    for stream in &mut streams {
+        stream
+            .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+            .unwrap();
+    }
+    for stream in &mut streams {
+        assert!(read_response(stream).ends_with("\r\n\r\npage"));
+    }
+    let snapshot = config.stats.snapshot();
+    assert_eq!(snapshot.loop_max_events, 1);
+    // Every event took a wakeup of its own.
+    assert!(snapshot.loop_events > 1, "{snapshot:?}");
+    assert!(
+        snapshot.loop_iterations >= snapshot.loop_events,
+        "{snapshot:?}"
+    );
+
+    drop(streams);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn responses_go_out_without_waiting_for_the_poll_timeout() {
+    let mut router = Router::new();
+    router.get("/routed", |_: &HttpRequest| {
+        HttpResponse::text(StatusCode::OK, "routed")
+    });
+    let mut config = ServerConfig {
+        router: Arc::new(router),
+        ..config()
+    };
+    config.reactor.poll_timeout = Duration::from_secs(10);
+    let (address, running) = start(&config);
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    // Nothing else happens meanwhile, so any response held back for a
+    // later event would take the whole poll timeout.
+    for target in ["/page.html", "/routed", "/page.html", "/routed"] {
+        let started = Instant::now();
+        stream
+            .write_all(format!("GET {target} HTTP/1.1\r\n\r\n").as_bytes())
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 OK\r\n"));
+        let took = started.elapsed();
+        assert!(took < Duration::from_millis(250), "{target} took {took:?}");
+    }
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
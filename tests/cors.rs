@@ -0,0 +1,152 @@
+mod common;
+
+use common::site;
+use custom_http::ServerConfig;
+use custom_http::http::cors::{AllowedOrigins, CorsConfig};
+use custom_http::http::request::{Method, parse_request};
+use custom_http::http::response::{HttpResponse, handle};
+
+/// A config serving an index page, with CORS allowed for `origins`.
+fn with_cors(origins: AllowedOrigins) -> ServerConfig {
+    ServerConfig {
+        cors: Some(CorsConfig {
+            allowed_origins: origins,
+            allowed_methods: vec![Method::Get, Method::Post],
+            allowed_headers: vec![String::from("Content-Type")],
+            max_age: Some(600),
+        }),
+        ..site("cors", &[("index.html", "<p>home</p>")])
+    }
+}
+
+/// Answers `method target` with the header fields `headers`.
+fn send(
+    config: &ServerConfig,
+    method: &str,
+    target: &str,
+    headers: &[(&str, &str)],
+) -> HttpResponse {
+    let mut head = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    handle(&parse_request(head.as_bytes()).unwrap().0, config)
+}
+
+fn app() -> AllowedOrigins {
+    AllowedOrigins::List(vec![String::from("https://app.example.com")])
+}
+
+#[test]
+fn simple_requests_from_allowed_origins_get_the_origin() {
+    let config = with_cors(app());
+    let response = send(
+        &config,
+        "GET",
+        "/index.html",
+        &[("Origin", "https://app.example.com")],
+    );
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(response.header("Vary"), Some("Origin"));
+
+    let any = with_cors(AllowedOrigins::Any);
+    let response = send(
+        &any,
+        "GET",
+        "/index.html",
+        &[("Origin", "https://elsewhere.example")],
+    );
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+
+    // Same-origin requests carry no `Origin` and get no CORS headers.
+    let response = send(&config, "GET", "/index.html", &[]);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+}
+
+#[test]
+fn allowed_preflights_get_every_header_without_a_file() {
+    let config = with_cors(app());
+    // No such file: a preflight never reaches the filesystem.
+    let response = send(
+        &config,
+        "OPTIONS",
+        "/api/missing",
+        &[
+            ("Origin", "https://app.example.com"),
+            ("Access-Control-Request-Method", "POST"),
+            ("Access-Control-Request-Headers", "Content-Type"),
+        ],
+    );
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Methods"),
+        Some("GET, POST")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Headers"),
+        Some("Content-Type")
+    );
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+}
+
+#[test]
+fn disallowed_origins_get_no_cors_headers() {
+    let config = with_cors(app());
+    for origin in [
+        "https://evil.example",
+        "https://app.example.com.evil.org",
+        "https://evil-app.example.com",
+        "http://app.example.com",
+    ] {
+        let response = send(
+            &config,
+            "OPTIONS",
+            "/api/missing",
+            &[
+                ("Origin", origin),
+                ("Access-Control-Request-Method", "POST"),
+            ],
+        );
+        assert_eq!(response.status().as_u16(), 204, "{origin}");
+        for name in [
+            "Access-Control-Allow-Origin",
+            "Access-Control-Allow-Methods",
+            "Access-Control-Allow-Headers",
+            "Access-Control-Max-Age",
+        ] {
+            assert_eq!(response.header(name), None, "{origin}: {name}");
+        }
+
+        let response = send(&config, "GET", "/index.html", &[("Origin", origin)]);
+        assert_eq!(response.status().as_u16(), 200, "{origin}");
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            None,
+            "{origin}"
+        );
+    }
+}
+
+#[test]
+fn options_without_a_requested_method_is_not_a_preflight() {
+    let config = with_cors(app());
+    let response = send(
+        &config,
+        "OPTIONS",
+        "/api/missing",
+        &[("Origin", "https://app.example.com")],
+    );
+    // An ordinary `OPTIONS`, answered with what the static files allow.
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{get, site};
+use custom_http::ServerConfig;
+use custom_http::io::file::read_dir_entries;
+
+/// A listing-enabled config serving a `files/` directory without an index.
+fn listing() -> ServerConfig {
+    ServerConfig {
+        directory_listing: true,
+        ..site(
+            "listing",
+            &[
+                ("files/<script>.txt", "xss"),
+                ("files/b.txt", "12345"),
+                ("files/A.txt", "a"),
+                ("files/.secret", "hidden"),
+                ("files/zeta/inner.txt", "inner"),
+                ("files/alpha/inner.txt", "inner"),
+            ],
+        )
+    }
+}
+
+/// The position of `needle` in `haystack`, failing the test if it's absent.
+fn at(haystack: &str, needle: &str) -> usize {
+    haystack
+        .find(needle)
+        .unwrap_or_else(|| panic!("no {needle:?} in {haystack}"))
+}
+
+#[test]
+fn listings_escape_names_and_sort_directories_first() {
+    let response = get(&listing(), "/files/");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.contains("Content-Type: text/html"), "{response}");
+    assert!(response.contains("<title>Index of /files/</title>"));
+
+    assert!(!response.contains("<script>"), "{response}");
+    at(
+        &response,
+        "<a href=\"%3Cscript%3E.txt\">&lt;script&gt;.txt</a>",
+    );
+    at(&response, "<a href=\"b.txt\">b.txt</a></td><td>5</td>");
+    at(&response, "<a href=\"alpha/\">alpha/</a></td><td>-</td>");
+    at(&response, "<a href=\"../\">../</a>");
+
+    // Directories first, then files, each alphabetical ignoring case.
+    let order = ["alpha/", "zeta/", "&lt;script&gt;.txt", "A.txt", "b.txt"]
+        .map(|name| at(&response, &format!(">{name}</a>")));
+    assert!(order.is_sorted(), "{order:?}: {response}");
+}
+
+#[test]
+fn hidden_entries_are_listed_only_when_enabled() {
+    let config = listing();
+    assert!(!get(&config, "/files/").contains(".secret"));
+
+    let shown = ServerConfig {
+        listing_show_hidden: true,
+        ..config.clone()
+    };
+    at(&get(&shown, "/files/"), "<a href=\".secret\">.secret</a>");
+
+    // Off by default: a directory without an index is simply missing.
+    let off = ServerConfig {
+        directory_listing: false,
+        ..config
+    };
+    assert!(get(&off, "/files/").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn directory_entries_carry_kind_and_size() {
+    let config = listing();
+    let files = config.document_root.join("files");
+    let mut entries = read_dir_entries(&files.to_string_lossy()).unwrap();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.is_dir, entry.size))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (".secret", false, 6),
+            ("<script>.txt", false, 3),
+            ("A.txt", false, 1),
+            ("alpha", true, 0),
+            ("b.txt", false, 5),
+            ("zeta", true, 0),
+        ]
+    );
+    assert!(entries.iter().all(|entry| entry.modified.is_some()));
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{get, site};
+use custom_http::{Server, ServerConfig};
+use std::fs;
+use std::path::{Component, PathBuf};
+
+#[test]
+fn roots_resolve_to_absolute_canonical_paths() {
+    let config = site("root", &[("site/index.html", "home")]);
+    let canonical = fs::canonicalize(config.document_root.join("site")).unwrap();
+
+    // The same directory, relative to where the tests run.
+    let cwd = std::env::current_dir().unwrap();
+    let mut relative: PathBuf = cwd
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .map(|_| "..")
+        .collect();
+    relative.push(canonical.strip_prefix("/").unwrap());
+
+    for root in [
+        config.document_root.join("site"),
+        config.document_root.join("site/../site/."),
+        relative,
+    ] {
+        let mut resolved = ServerConfig {
+            document_root: root.clone(),
+            ..config.clone()
+        };
+        resolved.resolve_document_root().unwrap();
+        assert_eq!(resolved.document_root, canonical, "{}", root.display());
+    }
+}
+
+#[test]
+fn missing_roots_are_startup_errors_naming_the_path() {
+    let config = site("missing-root", &[("file.txt", "not a directory")]);
+    for root in [
+        config.document_root.join("nowhere"),
+        config.document_root.join("file.txt"),
+    ] {
+        let mut broken = ServerConfig {
+            document_root: root.clone(),
+            ..config.clone()
+        };
+        let error = broken.resolve_document_root().unwrap_err();
+        assert!(
+            error.to_string().contains(&root.display().to_string()),
+            "{error}"
+        );
+
+        let error = Server::start(ServerConfig {
+            addresses: vec![String::from("127.0.0.1:0")],
+            ..broken
+        })
+        .err()
+        .unwrap();
+        assert!(
+            error.to_string().contains(&root.display().to_string()),
+            "{error}"
+        );
+    }
+}
+
+#[test]
+fn files_and_error_pages_come_from_the_root() {
+    let config = site(
+        "root-pages",
+        &[
+            ("page.html", "from the root"),
+            ("404.html", "<p>our 404</p>"),
+        ],
+    );
+    let response = get(&config, "/page.html");
+    assert!(response.ends_with("\r\n\r\nfrom the root"), "{response}");
+    let response = get(&config, "/missing.html");
+    assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+    assert!(response.ends_with("\r\n\r\n<p>our 404</p>"), "{response}");
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "embed")]
+
+mod common;
+
+use common::{get, get_with};
+use custom_http::ServerConfig;
+use custom_http::server::Assets;
+use std::path::PathBuf;
+
+/// `public/` from disk, and the copy of it compiled into the binary.
+fn sources() -> [(&'static str, ServerConfig); 2] {
+    let mut disk = ServerConfig {
+        document_root: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("public"),
+        ..ServerConfig::default()
+    };
+    disk.resolve_document_root().unwrap();
+    let embedded = ServerConfig {
+        assets: Assets::Embedded,
+        ..ServerConfig::default()
+    };
+    [("disk", disk), ("embedded", embedded)]
+}
+
+/// The response without the fields only a file on disk has.
+fn comparable(response: &str) -> String {
+    response
+        .split("\r\n")
+        .filter(|line| !line.starts_with("Last-Modified: "))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+#[test]
+fn both_sources_answer_the_same_requests_alike() {
+    let [(_, disk), (_, embedded)] = sources();
+    for target in [
+        "/",
+        "/index.html",
+        "/about",
+        "/about.html",
+        "/welcome.html",
+        "/missing",
+        "/missing.css",
+        "/nested/missing.html",
+        "/index.html/",
+        "/.env",
+        "/..%2fCargo.toml",
+        "/a/../../secret",
+        "/%zz",
+    ] {
+        let on_disk = get(&disk, target);
+        assert_eq!(
+            comparable(&get(&embedded, target)),
+            comparable(&on_disk),
+            "{target}"
+        );
+    }
+}
+
+#[test]
+fn embedded_files_have_the_same_etags() {
+    for (name, config) in sources() {
+        let response = get(&config, "/index.html");
+        let etag = response
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap_or_else(|| panic!("{name}: no ETag in {response}"));
+        assert!(!etag.starts_with("W/"), "{name}: {etag}");
+        let response = get_with(&config, "/index.html", &[("If-None-Match", etag)]);
+        assert!(response.starts_with("HTTP/1.1 304 "), "{name}: {response}");
+    }
+}
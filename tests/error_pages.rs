@@ -0,0 +1,129 @@
+mod common;
+
+use common::{request, site};
+use custom_http::ServerConfig;
+use custom_http::http::response::{ErrorPage, error_handler, http_handler};
+use custom_http::http::rewrite::RewriteRule;
+use std::fs;
+use std::path::PathBuf;
+
+/// Creates a fresh document root whose 404 page is the template `template`,
+/// and a config serving it.
+fn config(name: &str, template: &str) -> (ServerConfig, PathBuf) {
+    let root = std::env::temp_dir().join(format!(
+        "custom_http-error-pages-{}-{name}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let page = root.join("404.tpl.html");
+    fs::write(&page, template).unwrap();
+    let mut config = ServerConfig {
+        document_root: root.clone(),
+        ..ServerConfig::default()
+    };
+    config
+        .error_pages
+        .insert(404, page.to_string_lossy().into_owned());
+    (config, root)
+}
+
+#[test]
+fn templates_get_the_request_id() {
+    let (config, _) = config("request-id", "<p>id={{request_id}}</p>");
+    let mut missing = request("/missing", &[]);
+    missing.id = Some(String::from("req-<42>"));
+    let response = String::from_utf8_lossy(&http_handler(&missing, &config)).into_owned();
+    assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+    assert!(response.ends_with("<p>id=req-&lt;42&gt;</p>"), "{response}");
+
+    // Without an ID the placeholder renders empty.
+    missing.id = None;
+    let response = String::from_utf8_lossy(&http_handler(&missing, &config)).into_owned();
+    assert!(response.ends_with("<p>id=</p>"), "{response}");
+}
+
+/// Splits a serialized response into its head and body.
+fn split(response: &[u8]) -> (String, &[u8]) {
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    (
+        String::from_utf8_lossy(&response[..at]).into_owned(),
+        &response[at + 4..],
+    )
+}
+
+#[test]
+fn missing_error_pages_fall_back_to_the_built_in_ones() {
+    // A root without any error pages.
+    let config = site("no-error-pages", &[("index.html", "home")]);
+
+    let response = error_handler(ErrorPage::InternalServerError, &config);
+    let (head, body) = split(&response);
+    assert!(
+        head.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("\r\nContent-Type: text/html\r\n"), "{head}");
+    assert!(
+        head.contains(&format!("\r\nContent-Length: {}\r\n", body.len())),
+        "{head}"
+    );
+    assert_eq!(body, include_bytes!("../src/http/fallback/500.html"));
+
+    // A rewrite loop ends in the 500 page too, over the whole handler.
+    let looping = ServerConfig {
+        rewrites: vec![RewriteRule::parse("/*", "rewrite /again/$1").unwrap()],
+        ..config.clone()
+    };
+    let response = http_handler(&request("/loop", &[]), &looping);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 500 "), "{head}");
+    assert_eq!(body, include_bytes!("../src/http/fallback/500.html"));
+
+    let response = http_handler(&request("/missing", &[]), &config);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"), "{head}");
+    assert_eq!(body, include_bytes!("../src/http/fallback/404.html"));
+}
+
+#[test]
+fn error_pages_on_disk_win_over_the_built_in_ones() {
+    let on_disk = site("error-pages", &[("500.html", "<p>our fault</p>")]);
+    let response = error_handler(ErrorPage::InternalServerError, &on_disk);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 500 "), "{head}");
+    assert_eq!(body, b"<p>our fault</p>");
+
+    // A configured template that has gone missing falls back as well.
+    let (config, root) = config("missing-template", "unused");
+    fs::remove_file(root.join("404.tpl.html")).unwrap();
+    let response = http_handler(&request("/missing", &[]), &config);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 404 "), "{head}");
+    assert_eq!(body, include_bytes!("../src/http/fallback/404.html"));
+}
+
+#[test]
+fn templates_fill_every_placeholder_and_blank_unknown_ones() {
+    let (config, root) = config(
+        "placeholders",
+        "{{path}}|{{status}}|{{reason}}|{{client}}|{{request_id}}|{{nope}}|",
+    );
+    let mut missing = request("/a<b>&c", &[]);
+    missing.peer = Some("192.0.2.7".parse().unwrap());
+    missing.id = Some(String::from("abc"));
+    let response = http_handler(&missing, &config);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 404 "), "{head}");
+    assert_eq!(
+        String::from_utf8_lossy(body),
+        "/a&lt;b&gt;&amp;c|404|Not Found|192.0.2.7|abc||"
+    );
+
+    // Regular static files are sent as they are, placeholders and all.
+    fs::write(root.join("raw.html"), "{{path}}").unwrap();
+    let response = http_handler(&request("/raw.html", &[]), &config);
+    let (head, body) = split(&response);
+    assert!(head.starts_with("HTTP/1.1 200 "), "{head}");
+    assert_eq!(body, b"{{path}}");
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::site;
+use custom_http::{Server, ServerConfig, ServerError};
+use std::error::Error;
+use std::net::TcpListener;
+use std::process::Command;
+
+/// Starts a server on `address` and returns why it failed.
+fn start_error(address: &str) -> ServerError {
+    let result = Server::start(ServerConfig {
+        addresses: vec![String::from(address)],
+        ..site("errors", &[])
+    });
+    match result {
+        Ok(_) => panic!("{address} was served"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn an_occupied_port_names_the_address() {
+    let holder = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = holder.local_addr().unwrap().to_string();
+    let error = start_error(&taken);
+    assert!(matches!(error, ServerError::Bind { .. }), "{error:?}");
+    let message = error.to_string();
+    assert!(
+        message.starts_with(&format!("cannot bind {taken}: ")),
+        "{message}"
+    );
+    // The OS error is kept as the source, for callers that look deeper.
+    let source = error.source().unwrap().downcast_ref::<std::io::Error>();
+    assert_eq!(
+        source.map(std::io::Error::kind),
+        Some(std::io::ErrorKind::AddrInUse)
+    );
+}
+
+#[test]
+fn garbage_addresses_say_what_is_wrong() {
+    let error = start_error("not an address");
+    assert!(
+        matches!(error, ServerError::InvalidAddress { .. }),
+        "{error:?}"
+    );
+    assert_eq!(
+        error.to_string(),
+        "invalid address not an address: invalid socket address syntax"
+    );
+    assert!(error.source().is_none());
+}
+
+#[test]
+fn config_errors_read_as_configuration_problems() {
+    let error = ServerError::Config(String::from("max_connections must be at least 1"));
+    assert_eq!(
+        error.to_string(),
+        "invalid configuration: max_connections must be at least 1"
+    );
+    assert!(error.source().is_none());
+}
+
+#[test]
+fn the_binary_prints_the_error_and_exits_nonzero() {
+    let holder = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = holder.local_addr().unwrap().to_string();
+    let root = site("errors-binary", &[]).document_root;
+    let output = Command::new(env!("CARGO_BIN_EXE_custom_http"))
+        .args(["--address", &taken, "--root"])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!("error: cannot bind {taken}: ")),
+        "{stderr}"
+    );
+
+    // A command line that can't be used at all exits with 2.
+    let output = Command::new(env!("CARGO_BIN_EXE_custom_http"))
+        .args(["--address", "localhost"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: invalid address localhost: "),
+        "{stderr}"
+    );
+}
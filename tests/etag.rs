@@ -0,0 +1,87 @@
+mod common;
+
+use common::{get_with, respond, site};
+use custom_http::ServerConfig;
+use custom_http::io::cache::HashCache;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io;
+use std::time::{Duration, SystemTime};
+
+fn etag(config: &ServerConfig, target: &str) -> String {
+    let response = respond(config, target, &[]);
+    assert_eq!(response.status().as_u16(), 200, "{target}");
+    response.header("ETag").unwrap().to_owned()
+}
+
+#[test]
+fn hashes_are_computed_once_per_size_and_mtime() {
+    let hashes = HashCache::new();
+    let computed = Cell::new(0);
+    let compute = || {
+        computed.set(computed.get() + 1);
+        Ok(42)
+    };
+    let then = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    for _ in 0..3 {
+        assert_eq!(hashes.get_or_compute("/a", 10, then, compute).unwrap(), 42);
+    }
+    assert_eq!(computed.get(), 1);
+
+    // A new size or mtime means new contents.
+    hashes.get_or_compute("/a", 11, then, compute).unwrap();
+    hashes
+        .get_or_compute("/a", 11, then + Duration::from_secs(1), compute)
+        .unwrap();
+    assert_eq!(computed.get(), 3);
+    assert_eq!(hashes.len(), 1);
+
+    // Failures aren't cached.
+    let failed = hashes.get_or_compute("/b", 1, then, || Err(io::Error::other("gone")));
+    assert!(failed.is_err());
+    assert_eq!(hashes.len(), 1);
+}
+
+#[test]
+fn etags_follow_the_contents() {
+    let config = site("etag", &[("page.html", "first")]);
+    let first = etag(&config, "/page.html");
+    assert!(first.starts_with('"'), "{first}");
+    assert_eq!(etag(&config, "/page.html"), first);
+    assert_eq!(config.hash_cache.len(), 1);
+
+    let response = get_with(&config, "/page.html", &[("If-None-Match", &first)]);
+    assert!(response.starts_with("HTTP/1.1 304 "), "{response}");
+
+    // Rewritten, with an mtime that is certainly different.
+    let page = config.document_root.join("page.html");
+    fs::write(&page, "second").unwrap();
+    File::options()
+        .write(true)
+        .open(&page)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    let second = etag(&config, "/page.html");
+    assert_ne!(second, first);
+    let response = get_with(&config, "/page.html", &[("If-None-Match", &first)]);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+}
+
+#[test]
+fn files_over_the_limit_get_a_weak_etag_without_hashing() {
+    let config = ServerConfig {
+        etag_hash_limit: 4,
+        ..site(
+            "etag-limit",
+            &[("small.txt", "tiny"), ("large.txt", "too large")],
+        )
+    };
+    assert!(etag(&config, "/small.txt").starts_with('"'));
+    let weak = etag(&config, "/large.txt");
+    assert!(weak.starts_with("W/\""), "{weak}");
+    assert_eq!(config.hash_cache.len(), 1);
+
+    let response = get_with(&config, "/large.txt", &[("If-None-Match", &weak)]);
+    assert!(response.starts_with("HTTP/1.1 304 "), "{response}");
+}
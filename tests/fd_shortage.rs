@@ -0,0 +1,110 @@
+//! The file descriptor limit is per process, so this test crate holds a
+//! single test.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use std::fs::File;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::Duration;
+
+/// Sets the soft limit on open file descriptors, returning the one before.
+fn set_fd_limit(soft: libc::rlim_t) -> libc::rlim_t {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid `rlimit` for both calls.
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit), 0);
+        let old = limit.rlim_cur;
+        limit.rlim_cur = soft;
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &limit), 0);
+        old
+    }
+}
+
+/// Creates a TCP socket now, to be connected once no new descriptor can be had.
+fn unconnected() -> TcpStream {
+    // SAFETY: a plain socket call; the descriptor is owned right away.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+    assert!(fd >= 0);
+    unsafe { TcpStream::from_raw_fd(fd) }
+}
+
+/// Connects `stream`, made by `unconnected`, to the IPv4 `address`.
+fn connect(stream: &TcpStream, address: SocketAddr) {
+    let SocketAddr::V4(address) = address else {
+        unreachable!()
+    };
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: address.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*address.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: `sockaddr` is a valid `sockaddr_in` of the length given.
+    let connected = unsafe {
+        libc::connect(
+            stream.as_raw_fd(),
+            (&raw const sockaddr).cast(),
+            size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(connected, 0, "{}", std::io::Error::last_os_error());
+}
+
+#[test]
+fn accepts_pause_while_descriptors_run_out_and_resume_after() {
+    let mut config = ServerConfig {
+        reactors: 1,
+        ..site("fd-shortage", &[("page.html", "page")])
+    };
+    config.reactor.loop_metrics = true;
+    let stats = config.stats.clone();
+    let running = serve(config);
+    let address = running.local_addr().unwrap();
+
+    let mut clients: Vec<TcpStream> = (0..3).map(|_| unconnected()).collect();
+    let before = stats.snapshot();
+    // The lowest free descriptor is the next one handed out; a limit at it
+    // leaves none.
+    let lowest = File::open("/dev/null").unwrap().as_raw_fd();
+    let limit = set_fd_limit(lowest as libc::rlim_t);
+    for client in &clients {
+        connect(client, address);
+    }
+    thread::sleep(Duration::from_millis(600));
+    let during = stats.snapshot();
+    set_fd_limit(limit);
+
+    // Nothing could be accepted, and the loop didn't spin on the listener
+    // that stayed readable all along.
+    assert_eq!(during.connections_accepted, before.connections_accepted);
+    let iterations = during.loop_iterations - before.loop_iterations;
+    assert!(iterations < 50, "{iterations} iterations");
+
+    // Once descriptors are back, the queued connections are taken without
+    // any new event.
+    for client in &mut clients {
+        client
+            .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(read_response(client).ends_with("\r\n\r\npage"));
+    }
+    assert_eq!(
+        stats.snapshot().connections_accepted,
+        before.connections_accepted + 3
+    );
+
+    drop(clients);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{get, site};
+use std::fs;
+
+#[test]
+fn missing_files_are_404() {
+    let config = site("file-errors-missing", &[("page.html", "page")]);
+    for target in ["/missing.html", "/nowhere/page.html", "/page.html/"] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 404 "),
+            "{target}: {response}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_files_are_403() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let config = site(
+        "file-errors-unreadable",
+        &[("locked.html", "locked"), ("closed/page.html", "page")],
+    );
+    let locked = config.document_root.join("locked.html");
+    let closed = config.document_root.join("closed");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    fs::set_permissions(&closed, fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions don't stop root, so there's nothing to see when run as root.
+    if fs::read(&locked).is_ok() {
+        return;
+    }
+    for target in ["/locked.html", "/closed/page.html"] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 403 "),
+            "{target}: {response}"
+        );
+    }
+    fs::set_permissions(&closed, fs::Permissions::from_mode(0o755)).unwrap();
+}
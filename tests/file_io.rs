@@ -0,0 +1,92 @@
+use custom_http::io::file::{ChunkedReader, FileError, metadata, read_file_range};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Creates a fresh directory for the test `name` holding `data.bin`, the
+/// bytes `0..100`.
+fn fixture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("custom_http-file-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("data.bin"), (0..100u8).collect::<Vec<_>>()).unwrap();
+    dir
+}
+
+fn path(dir: &Path, name: &str) -> String {
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+#[test]
+fn metadata_describes_files_and_directories() {
+    let dir = fixture("metadata");
+    let file = metadata(&path(&dir, "data.bin")).unwrap();
+    assert_eq!(file.size, 100);
+    assert!(file.is_file && !file.is_dir);
+    assert!(file.modified.is_some());
+
+    let sub = metadata(&path(&dir, "sub")).unwrap();
+    assert!(sub.is_dir && !sub.is_file);
+
+    assert!(matches!(
+        metadata(&path(&dir, "missing")),
+        Err(FileError::NotFound)
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn metadata_follows_symlinks() {
+    let dir = fixture("symlinks");
+    std::os::unix::fs::symlink(dir.join("data.bin"), dir.join("link")).unwrap();
+    let linked = metadata(&path(&dir, "link")).unwrap();
+    assert_eq!(linked.size, 100);
+    assert!(linked.is_file);
+
+    std::os::unix::fs::symlink(dir.join("gone"), dir.join("dangling")).unwrap();
+    assert!(matches!(
+        metadata(&path(&dir, "dangling")),
+        Err(FileError::NotFound)
+    ));
+}
+
+#[test]
+fn ranges_are_cut_short_at_the_end_of_the_file() {
+    let dir = fixture("ranges");
+    let data = path(&dir, "data.bin");
+    assert_eq!(read_file_range(&data, 40, 5).unwrap(), [40, 41, 42, 43, 44]);
+    assert_eq!(
+        read_file_range(&data, 95, 10).unwrap(),
+        [95, 96, 97, 98, 99]
+    );
+    assert!(read_file_range(&data, 100, 10).unwrap().is_empty());
+    assert!(read_file_range(&data, 500, 10).unwrap().is_empty());
+    assert_eq!(
+        read_file_range(&path(&dir, "missing"), 0, 1)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn streamed_ranges_must_lie_within_the_file() {
+    let dir = fixture("streamed-ranges");
+    let data = path(&dir, "data.bin");
+    let mut reader = ChunkedReader::open_range(&data, 10, 25, 10).unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = reader.next_chunk().unwrap() {
+        chunks.push(chunk);
+    }
+    assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [10, 10, 5]);
+    assert_eq!(chunks.concat(), (10..35u8).collect::<Vec<_>>());
+
+    for (offset, len) in [(95, 10), (100, 1), (500, 1), (u64::MAX, 2)] {
+        let error = ChunkedReader::open_range(&data, offset, len, 10).err();
+        assert_eq!(
+            error.map(|e| e.kind()),
+            Some(ErrorKind::UnexpectedEof),
+            "{offset}+{len}"
+        );
+    }
+}
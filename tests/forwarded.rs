@@ -0,0 +1,279 @@
+use custom_http::ServerConfig;
+use custom_http::http::headers::{Forwarded, ForwardedHeader, ForwardedHop};
+use custom_http::http::request::{ForwardedClient, HttpRequest, parse_request};
+use custom_http::util::Cidr;
+
+/// A request with the header lines `headers`, arriving from `peer`.
+fn forwarded(peer: &str, headers: &str) -> HttpRequest {
+    let head = format!("GET / HTTP/1.1\r\nHost: internal:8080\r\n{headers}\r\n");
+    let mut request = parse_request(head.as_bytes()).unwrap().0;
+    request.peer = peer.parse().ok();
+    request
+}
+
+const X_FORWARDED: ForwardedHeader = ForwardedHeader::XForwarded;
+const FORWARDED: ForwardedHeader = ForwardedHeader::Forwarded;
+
+fn ranges(ranges: &[&str]) -> Vec<Cidr> {
+    ranges
+        .iter()
+        .map(|range| Cidr::parse(range).unwrap())
+        .collect()
+}
+
+fn client(address: &str, proto: Option<&str>, host: Option<&str>) -> Option<ForwardedClient> {
+    Some(ForwardedClient {
+        address: address.parse().unwrap(),
+        proto: proto.map(String::from),
+        host: host.map(String::from),
+    })
+}
+
+fn hop(node: Option<&str>, proto: Option<&str>, host: Option<&str>) -> ForwardedHop {
+    ForwardedHop {
+        node: node.map(String::from),
+        proto: proto.map(String::from),
+        host: host.map(String::from),
+    }
+}
+
+#[test]
+fn a_trusted_proxy_names_the_client() {
+    let trusted = ranges(&["127.0.0.1/32"]);
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: https\r\n\
+         X-Forwarded-Host: example.com\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, X_FORWARDED),
+        client("203.0.113.7", Some("https"), Some("example.com"))
+    );
+    assert_eq!(
+        request.client_address(&trusted, X_FORWARDED),
+        "203.0.113.7".parse().ok()
+    );
+    // Trusting no one, the peer is the client.
+    assert_eq!(
+        request.forwarded_client(&[], X_FORWARDED),
+        client("127.0.0.1", None, None)
+    );
+}
+
+#[test]
+fn chains_are_walked_back_past_trusted_proxies() {
+    let trusted = ranges(&["10.0.0.0/8", "127.0.0.1/32"]);
+    // The client wrote the first entry itself; the proxies added the rest.
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 198.51.100.1, 203.0.113.7, 10.0.0.5\r\n\
+         X-Forwarded-Proto: https, http\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, X_FORWARDED),
+        client("203.0.113.7", Some("https"), None)
+    );
+    // A proxy that sets X-Forwarded-Proto without passing the one it got on.
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 203.0.113.7, 10.0.0.5\r\nX-Forwarded-Proto: http\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, X_FORWARDED),
+        client("203.0.113.7", Some("http"), None)
+    );
+    // Every hop trusted: the first one is as far back as it goes.
+    let request = forwarded("127.0.0.1", "X-Forwarded-For: 10.0.0.9, 10.0.0.5\r\n");
+    assert_eq!(
+        request.client_address(&trusted, X_FORWARDED),
+        "10.0.0.9".parse().ok()
+    );
+    // A hop that isn't an address stops the walk at the proxy reporting it.
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 203.0.113.7, unknown, 10.0.0.5\r\n",
+    );
+    assert_eq!(
+        request.client_address(&trusted, X_FORWARDED),
+        "10.0.0.5".parse().ok()
+    );
+}
+
+#[test]
+fn untrusted_peers_cant_spoof_forwarded_headers() {
+    let trusted = ranges(&["10.0.0.0/8"]);
+    for (headers, header) in [
+        (
+            "X-Forwarded-For: 10.0.0.1\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: bank.example\r\n",
+            X_FORWARDED,
+        ),
+        (
+            "Forwarded: for=10.0.0.1;proto=https;host=bank.example\r\n",
+            FORWARDED,
+        ),
+    ] {
+        let request = forwarded("198.51.100.9", headers);
+        assert_eq!(
+            request.forwarded_client(&trusted, header),
+            client("198.51.100.9", None, None),
+            "{headers}"
+        );
+    }
+    // Without a peer there is nothing to go on.
+    let mut request = forwarded("127.0.0.1", "X-Forwarded-For: 203.0.113.7\r\n");
+    request.peer = None;
+    assert_eq!(
+        request.forwarded_client(&ranges(&["0.0.0.0/0"]), X_FORWARDED),
+        None
+    );
+}
+
+#[test]
+fn only_the_configured_header_is_read() {
+    let trusted = ranges(&["127.0.0.1/32"]);
+    // nginx appended the address it saw to X-Forwarded-For and passed the
+    // client's own Forwarded header on untouched.
+    let request = forwarded(
+        "127.0.0.1",
+        "Forwarded: for=1.2.3.4;proto=https;host=bank.example\r\n\
+         X-Forwarded-For: 203.0.113.7\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, X_FORWARDED),
+        client("203.0.113.7", None, None)
+    );
+    // And the other way around.
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 1.2.3.4\r\nX-Forwarded-Proto: https\r\n\
+         Forwarded: for=203.0.113.7\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, FORWARDED),
+        client("203.0.113.7", None, None)
+    );
+    // Without the configured header, the peer is the client.
+    let request = forwarded("127.0.0.1", "Forwarded: for=1.2.3.4\r\n");
+    assert_eq!(
+        request.client_address(&trusted, X_FORWARDED),
+        "127.0.0.1".parse().ok()
+    );
+}
+
+#[test]
+fn forwarded_chains_stop_at_the_first_untrusted_hop() {
+    let trusted = ranges(&["127.0.0.1/32", "10.0.0.0/8"]);
+    // The client sent the first two elements itself.
+    let request = forwarded(
+        "127.0.0.1",
+        "Forwarded: for=10.9.9.9, for=192.0.2.1;proto=https, for=203.0.113.7;proto=http\r\n\
+         Forwarded: for=10.0.0.5\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, FORWARDED),
+        client("203.0.113.7", Some("http"), None)
+    );
+}
+
+#[test]
+fn forwarded_handles_quoting() {
+    let trusted = ranges(&["127.0.0.1/32", "10.0.0.0/8"]);
+    let request = forwarded(
+        "127.0.0.1",
+        "Forwarded: For=\"[2001:db8:cafe::17]:4711\";Proto=https;host=\"example.com\", \
+         for=10.0.0.5;proto=http\r\n",
+    );
+    assert_eq!(
+        request.forwarded_client(&trusted, FORWARDED),
+        client("2001:db8:cafe::17", Some("https"), Some("example.com"))
+    );
+
+    let request = forwarded(
+        "127.0.0.1",
+        "Forwarded: for=\"_hidden\";by=\"a,b;c\";host=\"we\\\"ird\", for=192.0.2.43\r\n\
+         Forwarded: for=unknown\r\n",
+    );
+    assert_eq!(
+        Forwarded::from_headers(&request.headers, FORWARDED).hops,
+        [
+            hop(Some("_hidden"), None, Some("we\"ird")),
+            hop(Some("192.0.2.43"), None, None),
+            hop(Some("unknown"), None, None),
+        ]
+    );
+    // `unknown` isn't an address, so the walk stops at the peer.
+    assert_eq!(
+        request.client_address(&trusted, FORWARDED),
+        "127.0.0.1".parse().ok()
+    );
+}
+
+#[test]
+fn hop_addresses_drop_ports_and_brackets() {
+    for (node, address) in [
+        ("192.0.2.1", Some("192.0.2.1")),
+        ("192.0.2.1:4711", Some("192.0.2.1")),
+        ("2001:db8::1", Some("2001:db8::1")),
+        ("[2001:db8::1]", Some("2001:db8::1")),
+        ("[2001:db8::1]:4711", Some("2001:db8::1")),
+        ("unknown", None),
+        ("_obfuscated", None),
+        ("example.com", None),
+    ] {
+        assert_eq!(
+            hop(Some(node), None, None).address(),
+            address.map(|address| address.parse().unwrap()),
+            "{node}"
+        );
+    }
+}
+
+#[test]
+fn x_forwarded_lists_line_up_from_the_end() {
+    let request = forwarded(
+        "127.0.0.1",
+        "X-Forwarded-For: 203.0.113.7\r\nX-Forwarded-For: 10.0.0.5\r\n\
+         X-Forwarded-Proto: https\r\n",
+    );
+    assert_eq!(
+        Forwarded::from_headers(&request.headers, X_FORWARDED).hops,
+        [
+            hop(Some("203.0.113.7"), None, None),
+            hop(Some("10.0.0.5"), Some("https"), None),
+        ]
+    );
+    let request = forwarded("127.0.0.1", "X-Forwarded-Proto: https\r\n");
+    assert_eq!(
+        Forwarded::from_headers(&request.headers, X_FORWARDED).hops,
+        [hop(None, Some("https"), None)]
+    );
+    assert_eq!(
+        Forwarded::from_headers(&forwarded("127.0.0.1", "").headers, X_FORWARDED),
+        Forwarded::default()
+    );
+}
+
+#[test]
+fn trusted_proxies_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("trusted-proxies-{}.toml", std::process::id()));
+    std::fs::write(&path, "trusted_proxies = [\"127.0.0.1/32\", \"::1/128\"]\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.trusted_proxies, ranges(&["127.0.0.1/32", "::1/128"]));
+    assert_eq!(config.forwarded_header, X_FORWARDED);
+    std::fs::write(&path, "trusted_proxies = [\"nginx\"]\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::write(&path, "forwarded_header = \"Forwarded\"\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.forwarded_header, FORWARDED);
+    std::fs::write(&path, "forwarded_header = \"x-real-ip\"\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    let config = ServerConfig::builder()
+        .trusted_proxy(Cidr::parse("10.0.0.0/8").unwrap())
+        .forwarded_header(FORWARDED)
+        .build()
+        .unwrap();
+    assert_eq!(config.trusted_proxies, ranges(&["10.0.0.0/8"]));
+    assert_eq!(config.forwarded_header, FORWARDED);
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::read_response;
+use custom_http::http::proxy::ProxyConfig;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, Server, ServerConfig, ServerConfigBuilder, ServerHandle};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A handler that takes `millis` to answer.
+fn sleeping(millis: u64) -> impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static {
+    move |_: &HttpRequest| {
+        thread::sleep(Duration::from_millis(millis));
+        HttpResponse::text(StatusCode::OK, "done")
+    }
+}
+
+fn start(builder: ServerConfigBuilder) -> (SocketAddr, ServerHandle) {
+    let mut router = Router::new();
+    router
+        .get("/fast", sleeping(100))
+        .get("/slow", sleeping(2500))
+        .post("/slow", sleeping(2500))
+        .get("/patient", sleeping(1500))
+        .timeout("/patient", Duration::from_secs(5));
+    let config = builder
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(4)
+        .handler_timeout(Duration::from_secs(1))
+        .router(router)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    (running.local_addr().unwrap(), running)
+}
+
+/// Sends `request` on a new connection and returns the whole response, read
+/// until the server closes the connection, and how long that took.
+fn send(address: SocketAddr, request: &str) -> (String, Duration) {
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    (response, started.elapsed())
+}
+
+#[test]
+fn slow_handlers_get_503_and_the_connection_closes() {
+    let (address, running) = start(ServerConfig::builder());
+
+    let (response, _) = send(address, "GET /fast HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+
+    // Kept alive, but closed after the 503 all the same, and nothing of the
+    // handler's late answer follows it.
+    for method in ["GET", "POST"] {
+        let (response, took) = send(
+            address,
+            &format!("{method} /slow HTTP/1.1\r\nContent-Length: 0\r\n\r\n"),
+        );
+        assert!(response.starts_with("HTTP/1.1 503 "), "{response}");
+        assert!(response.contains("Connection: close"), "{response}");
+        assert_eq!(response.matches("HTTP/1.1").count(), 1, "{response}");
+        assert!(took < Duration::from_millis(2400), "{method} took {took:?}");
+    }
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn routes_can_have_a_timeout_of_their_own() {
+    let (address, running) = start(ServerConfig::builder());
+    let (response, took) = send(
+        address,
+        "GET /patient HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with("done"), "{response}");
+    assert!(took >= Duration::from_millis(1500), "{took:?}");
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn stalled_upstreams_get_504() {
+    // Accepts the proxied request and never answers it.
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream_address = upstream.local_addr().unwrap();
+    thread::spawn(move || {
+        let held: Vec<TcpStream> = upstream.incoming().map_while(Result::ok).collect();
+        drop(held);
+    });
+    let proxy = ProxyConfig::new("/api", &format!("http://{upstream_address}")).unwrap();
+    let (address, running) = start(ServerConfig::builder().proxy(proxy));
+
+    let (response, took) = send(address, "GET /api/report HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+    assert!(took < Duration::from_secs(3), "{took:?}");
+    // Anything else that times out is the server's own doing.
+    let (response, _) = send(address, "GET /slow HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn handler_timeout_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("handler-timeout-{}.toml", std::process::id()));
+    assert_eq!(
+        ServerConfig::default().handler_timeout,
+        Some(Duration::from_secs(60))
+    );
+    std::fs::write(&path, "handler_timeout = 5\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.handler_timeout, Some(Duration::from_secs(5)));
+    std::fs::write(&path, "handler_timeout = 0\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.handler_timeout, None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn late_results_never_reach_the_next_connection_in_the_slot() {
+    let (address, running) = start(ServerConfig::builder());
+    let started = Instant::now();
+    let (response, _) = send(address, "GET /slow HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 "), "{response}");
+
+    // The only connection around, so it takes the slot the first one left.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET /fast HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 "));
+
+    // The `/slow` handler finishes meanwhile; its answer is dropped.
+    thread::sleep(Duration::from_millis(2800).saturating_sub(started.elapsed()));
+    stream
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let error = stream.read(&mut [0u8; 1]).unwrap_err();
+    assert!(
+        matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        "{error}"
+    );
+
+    // And the connection carries on with answers of its own.
+    stream.write_all(b"GET /fast HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\ndone"));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
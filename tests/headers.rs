@@ -0,0 +1,218 @@
+mod common;
+
+use common::get;
+use custom_http::ServerConfig;
+use custom_http::http::headers::{self, HeaderMap, HeaderName, HeaderValue, InvalidHeader};
+use custom_http::http::request::{ParseError, parse_request};
+use custom_http::http::response::http_handler;
+use std::fs;
+use std::path::PathBuf;
+
+// What an attacker would like a header line to end with.
+const INJECTIONS: [&str; 5] = [
+    "x\r\nSet-Cookie: session=stolen",
+    "x\nSet-Cookie: session=stolen",
+    "x\rSet-Cookie: session=stolen",
+    "x\r\n\r\n<script>alert(1)</script>",
+    "x\0y",
+];
+
+/// A document root of its own for the test `name`.
+fn root(name: &str) -> PathBuf {
+    let root =
+        std::env::temp_dir().join(format!("custom_http-headers-{}-{name}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+#[test]
+fn names_must_be_tokens() {
+    for name in ["Content-Type", "x-custom_1", "!#$%&'*+-.^_`|~", "ETag"] {
+        assert_eq!(HeaderName::try_from(name).unwrap().as_str(), name);
+    }
+    for name in [
+        "",
+        "Bad Name",
+        "Bad:Name",
+        "Name\t",
+        " Leading",
+        "(comment)",
+        "é",
+        "X-Injected\r\nSet-Cookie",
+    ] {
+        assert_eq!(
+            HeaderName::try_from(name),
+            Err(InvalidHeader::Name),
+            "{name:?}"
+        );
+        assert_eq!(
+            HeaderName::try_from(String::from(name)),
+            Err(InvalidHeader::Name)
+        );
+    }
+    assert_eq!(
+        headers::CONTENT_TYPE,
+        HeaderName::try_from("content-type").unwrap()
+    );
+    assert_ne!(headers::CONTENT_TYPE, headers::CONTENT_LENGTH);
+}
+
+#[test]
+fn values_must_not_break_the_line() {
+    for value in [
+        "",
+        "text/html; charset=utf-8",
+        "a \t b",
+        "é ✓",
+        "\"quoted\"",
+    ] {
+        assert_eq!(HeaderValue::try_from(value).unwrap().as_str(), value);
+    }
+    for value in INJECTIONS {
+        assert_eq!(
+            HeaderValue::try_from(value),
+            Err(InvalidHeader::Value),
+            "{value:?}"
+        );
+        assert_eq!(
+            HeaderValue::try_from(String::from(value)),
+            Err(InvalidHeader::Value)
+        );
+    }
+    assert_eq!(HeaderValue::from(3600).as_str(), "3600");
+    assert_eq!(HeaderValue::from(headers::ORIGIN).as_str(), "Origin");
+}
+
+#[test]
+#[should_panic(expected = "invalid header value")]
+fn static_values_are_checked_too() {
+    HeaderValue::from_static("x\r\nSet-Cookie: a=1");
+}
+
+#[test]
+#[should_panic(expected = "invalid header name")]
+fn static_names_are_checked_too() {
+    HeaderName::from_static("X Bad");
+}
+
+#[test]
+fn header_maps_keep_typed_fields() {
+    let mut map = HeaderMap::new();
+    map.append(headers::VARY, HeaderValue::from(headers::ORIGIN));
+    map.append(headers::VARY, HeaderValue::from(headers::ACCEPT));
+    map.insert(headers::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    assert_eq!(
+        map.get_all("vary").collect::<Vec<_>>(),
+        ["Origin", "Accept"]
+    );
+    map.insert(headers::VARY, HeaderValue::from_static("*"));
+    assert_eq!(map.get_all("Vary").collect::<Vec<_>>(), ["*"]);
+    map.retain(|name, _| name != "Cache-Control");
+    assert_eq!(map.iter().collect::<Vec<_>>(), [("Vary", "*")]);
+}
+
+#[test]
+fn request_headers_with_bad_bytes_are_malformed() {
+    for (head, reason) in [
+        (
+            "GET / HTTP/1.1\r\nBad Name: x\r\n\r\n",
+            "invalid header name",
+        ),
+        ("GET / HTTP/1.1\r\nName : x\r\n\r\n", "invalid header name"),
+        ("GET / HTTP/1.1\r\n: x\r\n\r\n", "invalid header name"),
+        (
+            "GET / HTTP/1.1\r\nX-A: a\rb\r\n\r\n",
+            "invalid header value",
+        ),
+        (
+            "GET / HTTP/1.1\r\nX-A: a\0b\r\n\r\n",
+            "invalid header value",
+        ),
+    ] {
+        assert!(
+            matches!(parse_request(head.as_bytes()), Err(ParseError::Malformed(r)) if r == reason),
+            "{head:?}"
+        );
+    }
+}
+
+#[test]
+fn rewrite_redirects_cant_inject_headers() {
+    let root = root("rewrite");
+    let rules = root.join("rules.toml");
+    fs::write(&rules, "[rewrites]\n\"/go/*\" = \"302 /to/$1\"\n").unwrap();
+    let config = ServerConfig::from_toml_path(&rules).unwrap();
+
+    for injection in INJECTIONS {
+        let head = "GET /go/x HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut request = parse_request(head.as_bytes()).unwrap().0;
+        request.path = format!("/go/{injection}");
+        let response = String::from_utf8_lossy(&http_handler(&request, &config)).into_owned();
+        assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+        assert!(!response.contains("Set-Cookie"), "{response}");
+    }
+    assert!(get(&config, "/go/x").contains("\r\nLocation: /to/x\r\n"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn attachment_names_cant_inject_headers() {
+    let root = root("attachment");
+    fs::write(root.join("a\r\nSet-Cookie: session=stolen.bin"), "data").unwrap();
+    let config = ServerConfig {
+        document_root: root.clone(),
+        force_attachment_extensions: vec![String::from("bin")],
+        ..ServerConfig::default()
+    };
+
+    let response = get(&config, "/a%0D%0ASet-Cookie:%20session=stolen.bin");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(!response.contains("\r\nSet-Cookie"), "{response}");
+    assert!(
+        response.contains(
+            "\r\nContent-Disposition: attachment; filename=\"aSet-Cookie: session=stolen.bin\"\r\n"
+        ),
+        "{response}"
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn configured_content_types_cant_inject_headers() {
+    let root = root("mime");
+    fs::write(root.join("page.evil"), "data").unwrap();
+    fs::write(root.join("page.fine"), "data").unwrap();
+    let mut config = ServerConfig {
+        document_root: root.clone(),
+        ..ServerConfig::default()
+    };
+    config.mime_overrides.insert(
+        String::from("evil"),
+        String::from("text/plain\r\nSet-Cookie: session=stolen"),
+    );
+    config
+        .mime_overrides
+        .insert(String::from("fine"), String::from("text/x-fine"));
+
+    let response = get(&config, "/page.evil");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(!response.contains("Set-Cookie"), "{response}");
+    assert!(
+        response.contains("\r\nContent-Type: application/octet-stream\r\n"),
+        "{response}"
+    );
+    assert!(get(&config, "/page.fine").contains("\r\nContent-Type: text/x-fine\r\n"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn request_id_headers_must_be_names() {
+    let built = ServerConfig::builder()
+        .request_id(custom_http::http::request_id::RequestIdConfig {
+            header: String::from("X-Id\r\nSet-Cookie"),
+            trust_incoming: true,
+        })
+        .build();
+    assert!(built.is_err());
+}
@@ -0,0 +1,146 @@
+use custom_http::http::health::HealthConfig;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::log::LogFormat;
+use custom_http::{Router, Server, ServerConfig, ServerHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::Command;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+fn start(router: Router, health: HealthConfig) -> (SocketAddr, ServerHandle) {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .router(router)
+        .health(health)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    (running.local_addr().unwrap(), running)
+}
+
+/// Sends `GET path` on a new connection and returns the whole response.
+fn get(address: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn probes_are_answered_while_every_worker_is_busy() {
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let release_rx = Arc::new(Mutex::new(release_rx));
+    let mut router = Router::new();
+    router.get("/slow", move |_: &HttpRequest| {
+        let _ = release_rx.lock().unwrap().recv();
+        HttpResponse::text(StatusCode::OK, "done")
+    });
+    let (address, running) = start(router, HealthConfig::default());
+
+    // Occupies the only worker until released.
+    let mut slow = TcpStream::connect(address).unwrap();
+    slow.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let response = get(address, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(
+        response.contains("Cache-Control: no-store\r\n"),
+        "{response}"
+    );
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+    let response = get(address, "/readyz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nready"), "{response}");
+
+    release_tx.send(()).unwrap();
+    let mut response = String::new();
+    slow.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\ndone"), "{response}");
+    drop(slow);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn readiness_fails_once_shutdown_begins() {
+    let health = HealthConfig {
+        healthz: String::from("/live"),
+        readyz: String::from("/ready"),
+        drain_delay: Duration::from_millis(500),
+        ..HealthConfig::default()
+    };
+    let (address, running) = start(Router::new(), health);
+    assert!(get(address, "/ready").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let started = Instant::now();
+    running.shutdown();
+    // Still accepting during the delay, but no longer ready.
+    let response = get(address, "/ready");
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{response}"
+    );
+    assert!(response.ends_with("\r\n\r\ndraining"), "{response}");
+    assert!(get(address, "/live").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    running.join().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(500));
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn probe_lines_go_through_the_logger() {
+    // The line goes to stdout, so the server runs in a copy of this test
+    // binary whose output is captured here.
+    if std::env::var_os("CUSTOM_HTTP_PROBE_LOG").is_some() {
+        let config = ServerConfig::builder()
+            .address("127.0.0.1:0")
+            .reactors(1)
+            .health(HealthConfig {
+                log: true,
+                ..HealthConfig::default()
+            })
+            .log_format(LogFormat::Json)
+            .build()
+            .unwrap();
+        let running = Server::start(config).unwrap();
+        get(running.local_addr().unwrap(), "/healthz");
+        running.shutdown();
+        running.join().unwrap();
+        return;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "probe_lines_go_through_the_logger",
+            "--nocapture",
+        ])
+        .env("CUSTOM_HTTP_PROBE_LOG", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The test harness's own output may share the line.
+    let line = stdout
+        .lines()
+        .find_map(|line| line.find("{\"ts\"").map(|at| &line[at..]))
+        .unwrap_or_else(|| panic!("{stdout}"));
+    assert!(line.contains("\"level\":\"info\""), "{line}");
+    let message = line
+        .split_once("\"message\":\"127.0.0.1 GET /healthz 200 ")
+        .and_then(|(_, rest)| rest.strip_suffix("ms\"}"))
+        .unwrap_or_else(|| panic!("{line}"));
+    assert!(message.parse::<f64>().is_ok(), "{line}");
+}
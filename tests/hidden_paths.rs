@@ -0,0 +1,58 @@
+mod common;
+
+use common::{get, site};
+use custom_http::ServerConfig;
+
+fn dotfiles() -> ServerConfig {
+    site(
+        "hidden",
+        &[
+            (".env", "SECRET=1"),
+            (".git/config", "[core]"),
+            ("sub/.hidden/file", "hidden"),
+            (".well-known/acme-challenge/token", "challenge"),
+            ("visible.txt", "visible"),
+        ],
+    )
+}
+
+#[test]
+fn dot_components_are_forbidden() {
+    let config = dotfiles();
+    for target in [
+        "/.env",
+        "/.git/config",
+        "/sub/.hidden/file",
+        // Decoded and normalized before the check.
+        "/%2eenv",
+        "/sub/%2Ehidden/file",
+        "/sub/./.hidden/file",
+        "/visible/../.env",
+        // Missing files too, so probing can't tell what exists.
+        "/.htpasswd",
+    ] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 403 "),
+            "{target}: {response}"
+        );
+        assert!(!response.contains("SECRET"), "{target}: {response}");
+    }
+    assert!(get(&config, "/visible.txt").starts_with("HTTP/1.1 200 "));
+}
+
+#[test]
+fn allowlisted_prefixes_stay_reachable() {
+    let config = dotfiles();
+    let response = get(&config, "/.well-known/acme-challenge/token");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with("\r\n\r\nchallenge"), "{response}");
+
+    let configured = ServerConfig {
+        hidden_allowlist: vec![String::from("/sub/.hidden/")],
+        ..config
+    };
+    assert!(get(&configured, "/sub/.hidden/file").starts_with("HTTP/1.1 200 "));
+    assert!(get(&configured, "/.well-known/acme-challenge/token").starts_with("HTTP/1.1 403 "));
+    assert!(get(&configured, "/.env").starts_with("HTTP/1.1 403 "));
+}
@@ -0,0 +1,233 @@
+use custom_http::http::headers::{self, HeaderValue};
+use custom_http::http::https::{HstsConfig, HttpsRedirectConfig};
+use custom_http::http::request::{HttpRequest, parse_request};
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{ServerConfig, ServerError};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+fn request(target: &str, host: Option<&str>) -> HttpRequest {
+    let host = host.map_or_else(String::new, |host| format!("Host: {host}\r\n"));
+    let head = format!("GET {target} HTTP/1.1\r\n{host}\r\n");
+    parse_request(head.as_bytes()).unwrap().0
+}
+
+#[test]
+fn location_keeps_host_path_and_query() {
+    let redirect = HttpsRedirectConfig::default();
+    for (target, host, location) in [
+        ("/", "example.com", "https://example.com/"),
+        (
+            "/a/b?x=1&y=%20",
+            "example.com",
+            "https://example.com/a/b?x=1&y=%20",
+        ),
+        ("/a%2Fb?", "Example.COM.", "https://example.com/a%2Fb?"),
+        // The plain port isn't the HTTPS one.
+        ("/p?q", "example.com:8080", "https://example.com/p?q"),
+        ("/", "10.0.0.1:80", "https://10.0.0.1/"),
+        ("/v6", "[::1]:8080", "https://[::1]/v6"),
+    ] {
+        assert_eq!(
+            redirect.location(&request(target, Some(host))).as_deref(),
+            Some(location),
+            "{host} {target}"
+        );
+    }
+}
+
+#[test]
+fn location_names_the_https_port_unless_it_is_443() {
+    let mut redirect = HttpsRedirectConfig {
+        https_port: Some(8443),
+        ..HttpsRedirectConfig::default()
+    };
+    assert_eq!(
+        redirect.location(&request("/x?y=1", Some("example.com:8080"))),
+        Some(String::from("https://example.com:8443/x?y=1"))
+    );
+    assert_eq!(
+        redirect.location(&request("/", Some("[2001:db8::1]"))),
+        Some(String::from("https://[2001:db8::1]:8443/"))
+    );
+    redirect.https_port = Some(443);
+    assert_eq!(
+        redirect.location(&request("/x", Some("example.com:8080"))),
+        Some(String::from("https://example.com/x"))
+    );
+}
+
+#[test]
+fn bad_hosts_fall_back_to_the_canonical_host() {
+    let mut redirect = HttpsRedirectConfig::default();
+    let bad_hosts = [
+        None,
+        Some(""),
+        Some("evil.com/path"),
+        Some("evil.com@example.com"),
+        Some("example.com:80:80"),
+        Some("example.com:+80"),
+        Some("example.com:99999"),
+        Some("-example.com"),
+        Some("exa mple.com"),
+        Some("[not-v6]"),
+        Some("[::1]x"),
+    ];
+    for host in bad_hosts {
+        assert_eq!(redirect.location(&request("/", host)), None, "{host:?}");
+    }
+    redirect.canonical_host = Some(String::from("example.com"));
+    for host in bad_hosts {
+        assert_eq!(
+            redirect.location(&request("/a?b", host)).as_deref(),
+            Some("https://example.com/a?b"),
+            "{host:?}"
+        );
+    }
+}
+
+#[test]
+fn hsts_is_only_sent_over_tls() {
+    let hsts = HstsConfig {
+        max_age: Duration::from_secs(600),
+        include_subdomains: true,
+    };
+    let mut plain = request("/", Some("example.com"));
+    let mut response = HttpResponse::text(StatusCode::OK, "hi");
+    hsts.apply(&plain, &mut response);
+    assert_eq!(response.header("Strict-Transport-Security"), None);
+
+    plain.secure = true;
+    hsts.apply(&plain, &mut response);
+    assert_eq!(
+        response.header("Strict-Transport-Security"),
+        Some("max-age=600; includeSubDomains")
+    );
+
+    // A handler's own header is left alone.
+    let mut response = HttpResponse::text(StatusCode::OK, "hi").with_header(
+        headers::STRICT_TRANSPORT_SECURITY,
+        HeaderValue::from_static("max-age=0"),
+    );
+    HstsConfig::default().apply(&plain, &mut response);
+    assert_eq!(
+        response.header("Strict-Transport-Security"),
+        Some("max-age=0")
+    );
+}
+
+#[test]
+fn redirect_listeners_answer_every_request_with_a_redirect() {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .redirect_to_https(HttpsRedirectConfig::default())
+        .build()
+        .unwrap();
+    let running = custom_http::Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    // Kept alive between requests without a body.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    for target in ["/one?a=1", "/two"] {
+        write!(
+            stream,
+            "GET {target} HTTP/1.1\r\nHost: example.com:8080\r\n\r\n"
+        )
+        .unwrap();
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 301 "), "{response}");
+        assert!(
+            response.contains(&format!("\r\nLocation: https://example.com{target}\r\n")),
+            "{response}"
+        );
+        assert!(!response.contains("Connection: close"), "{response}");
+    }
+    drop(stream);
+
+    // A body is never read: the connection closes after the redirect, and
+    // what looks like a request in the body is never answered.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(
+            b"POST /form HTTP/1.1\r\nHost: example.com\r\nContent-Length: 39\r\n\r\n\
+              GET /smuggled HTTP/1.1\r\nHost: x\r\n\r\n",
+        )
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 301 "), "{response}");
+    assert!(
+        response.contains("\r\nLocation: https://example.com/form\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("Connection: close"), "{response}");
+    assert_eq!(response.matches("HTTP/1.1").count(), 1, "{response}");
+
+    // Without a usable host there is nowhere to send the client.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+    drop(stream);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn redirect_and_hsts_tables_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("https-redirect-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "addresses = [\"0.0.0.0:80\", \"0.0.0.0:443\"]\n\
+         [redirect_to_https]\n\
+         addresses = [\"0.0.0.0:80\"]\n\
+         canonical_host = \"example.com\"\n\
+         https_port = 8443\n\
+         [hsts]\n\
+         max_age = 600\n",
+    )
+    .unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(
+        config.redirect_to_https,
+        Some(HttpsRedirectConfig {
+            addresses: vec![String::from("0.0.0.0:80")],
+            canonical_host: Some(String::from("example.com")),
+            https_port: Some(8443),
+        })
+    );
+    assert_eq!(
+        config.hsts,
+        Some(HstsConfig {
+            max_age: Duration::from_secs(600),
+            include_subdomains: false,
+        })
+    );
+    std::fs::write(&path, "[redirect_to_https]\ncanonical_host = \"a b\"\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::write(&path, "[redirect_to_https]\nhttps_port = 0\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    // Only addresses listened on can redirect.
+    let built = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .redirect_to_https(HttpsRedirectConfig {
+            addresses: vec![String::from("127.0.0.1:80")],
+            ..HttpsRedirectConfig::default()
+        })
+        .build();
+    assert!(matches!(built, Err(ServerError::Config(_))), "{built:?}");
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::get;
+use custom_http::ServerConfig;
+use std::fs;
+use std::path::PathBuf;
+
+/// Creates a fresh document root holding `files`, as (relative path,
+/// contents) pairs, and a config serving it.
+fn config(name: &str, files: &[(&str, &str)]) -> ServerConfig {
+    let root: PathBuf =
+        std::env::temp_dir().join(format!("custom_http-index-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    for (path, contents) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    ServerConfig {
+        document_root: root,
+        ..ServerConfig::default()
+    }
+}
+
+fn assert_serves(response: &str, body: &str) {
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with(&format!("\r\n\r\n{body}")), "{response}");
+}
+
+#[test]
+fn directories_serve_their_index_file() {
+    let config = config(
+        "index",
+        &[
+            ("docs/index.html", "docs"),
+            ("docs/api/v1/index.html", "v1"),
+            ("legacy/index.htm", "legacy"),
+            ("both/index.htm", "htm"),
+            ("both/index.html", "html"),
+            ("empty/readme.txt", "no index"),
+        ],
+    );
+    assert_serves(&get(&config, "/docs/"), "docs");
+    assert_serves(&get(&config, "/docs/api/v1/"), "v1");
+    assert_serves(&get(&config, "/legacy/"), "legacy");
+    // The first configured name that exists wins.
+    assert_serves(&get(&config, "/both/"), "html");
+    assert!(get(&config, "/empty/").starts_with("HTTP/1.1 404 "));
+
+    let listing = ServerConfig {
+        directory_listing: true,
+        ..config.clone()
+    };
+    let response = get(&listing, "/empty/");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.contains("readme.txt"), "{response}");
+
+    let htm_first = ServerConfig {
+        index_files: vec![String::from("index.htm")],
+        ..config
+    };
+    assert_serves(&get(&htm_first, "/both/"), "htm");
+}
+
+#[test]
+fn directories_without_a_slash_are_redirected() {
+    let config = config("slash", &[("docs/api/index.html", "api")]);
+    for (target, location) in [
+        ("/docs", "/docs/"),
+        ("/docs/api", "/docs/api/"),
+        ("/docs/api?lang=en", "/docs/api/?lang=en"),
+        ("/docs/./api", "/docs/api/"),
+        ("/docs/api/.", "/docs/api/"),
+    ] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 301 "),
+            "{target}: {response}"
+        );
+        assert!(
+            response.contains(&format!("\r\nLocation: {location}\r\n")),
+            "{target}: {response}"
+        );
+    }
+}
+
+#[test]
+fn slash_redirects_stay_on_this_site() {
+    let config = config(
+        "open-redirect",
+        &[
+            ("evil.example/index.html", "local"),
+            ("a b/index.html", "spaced"),
+        ],
+    );
+    for (target, location) in [
+        ("//evil.example", "/evil.example/"),
+        ("///evil.example", "/evil.example/"),
+        ("/.//evil.example", "/evil.example/"),
+        ("/a%20b", "/a%20b/"),
+    ] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 301 "),
+            "{target}: {response}"
+        );
+        assert!(
+            response.contains(&format!("\r\nLocation: {location}\r\n")),
+            "{target}: {response}"
+        );
+    }
+}
+
+#[test]
+fn the_landing_page_and_clean_urls_are_configurable() {
+    let config = config(
+        "clean-urls",
+        &[
+            ("welcome.html", "welcome"),
+            ("home.html", "home"),
+            ("about.html", "about"),
+            ("api", "no extension"),
+        ],
+    );
+    assert_serves(&get(&config, "/"), "welcome");
+    // On by default, for extensionless paths that don't exist as they are.
+    assert_serves(&get(&config, "/about"), "about");
+    assert_serves(&get(&config, "/api"), "no extension");
+
+    let home = ServerConfig {
+        index: String::from("home.html"),
+        clean_urls: false,
+        ..config
+    };
+    assert_serves(&get(&home, "/"), "home");
+    assert!(get(&home, "/about").starts_with("HTTP/1.1 404 "));
+    assert_serves(&get(&home, "/about.html"), "about");
+    assert_serves(&get(&home, "/api"), "no extension");
+}
@@ -0,0 +1,110 @@
+use custom_http::{Server, ServerConfig};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+#[test]
+fn serves_a_request_on_an_ephemeral_port() {
+    let config = ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        reactors: 1,
+        ..ServerConfig::default()
+    };
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    let running = server.spawn().unwrap();
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    drop(stream);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn shutdown_drains_within_the_deadline() {
+    let config = ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        reactors: 1,
+        drain_timeout: Duration::from_secs(2),
+        ..ServerConfig::default()
+    };
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    // A kept-alive connection, left idle after its first response.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0u8; 17];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"HTTP/1.1 200 OK\r\n");
+
+    let started = Instant::now();
+    running.shutdown();
+    running.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn dropping_the_handle_shuts_the_server_down() {
+    let config = ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        reactors: 1,
+        ..ServerConfig::default()
+    };
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+    assert!(TcpStream::connect(address).is_ok());
+
+    drop(running);
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn files_in_public_are_served_byte_for_byte() {
+    let config = ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        reactors: 1,
+        ..ServerConfig::default()
+    };
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    for (target, file, status) in [
+        ("/about.html", "public/about.html", "200 OK"),
+        ("/no/such/page", "public/404.html", "404 Not Found"),
+    ] {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(
+                format!("GET {target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..at]);
+        assert!(
+            head.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+            "{head}"
+        );
+        assert!(head.contains("\r\nContent-Type: text/html\r\n"), "{head}");
+        assert!(
+            response[at + 4..] == std::fs::read(file).unwrap(),
+            "{target}"
+        );
+    }
+
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,53 @@
+//! `reregistrations` counts across the whole process, so this test crate
+//! holds a single test.
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::nonblocking::reregistrations;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn connections_are_reregistered_only_when_their_interest_changes() {
+    let big = "x".repeat(8 << 20);
+    let running = serve(ServerConfig {
+        reactors: 1,
+        ..site("interest", &[("small.html", "small"), ("big.html", &big)])
+    });
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+
+    // A response that goes out in one write leaves the connection reading,
+    // as it was: nothing to reregister, however many requests come.
+    let before = reregistrations();
+    for _ in 0..5 {
+        stream
+            .write_all(b"GET /small.html HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nsmall"));
+    }
+    assert_eq!(reregistrations(), before);
+
+    // One that doesn't fit the socket buffer adds writable interest until it
+    // is out, then drops it again: two reregistrations for the whole cycle,
+    // not one per write.
+    stream.write_all(b"GET /big.html HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).ends_with(&big));
+    // The last write and the reregistration after it may trail the bytes.
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(reregistrations(), before + 2);
+
+    // And the connection is back to reading with nothing to reregister.
+    stream
+        .write_all(b"GET /small.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nsmall"));
+    assert_eq!(reregistrations(), before + 2);
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,69 @@
+//! Signals go to the whole process, so this test crate holds a single test.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+extern "C" fn ignore(_: libc::c_int) {}
+
+/// Returns the kernel thread ID of this process' thread named `name`.
+fn thread_id(name: &str) -> libc::pid_t {
+    fs::read_dir("/proc/self/task")
+        .unwrap()
+        .map(|task| task.unwrap().path())
+        .find(|task| fs::read_to_string(task.join("comm")).unwrap().trim() == name)
+        .and_then(|task| task.file_name()?.to_str()?.parse().ok())
+        .unwrap()
+}
+
+#[test]
+fn interrupted_waits_are_retried() {
+    // A handler without `SA_RESTART`: every signal the reactor thread takes
+    // while waiting for events makes the wait fail with `EINTR`.
+    // SAFETY: a zeroed `sigaction` with a handler that does nothing.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        assert_eq!(
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()),
+            0
+        );
+    }
+    let running = serve(ServerConfig {
+        reactors: 1,
+        ..site("interrupts", &[("page.html", "page")])
+    });
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+
+    let reactor = thread_id("reactor-0");
+    for _ in 0..20 {
+        // SAFETY: signals a thread of this process, which handles SIGUSR1.
+        let sent =
+            unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), reactor, libc::SIGUSR1) };
+        assert_eq!(sent, 0);
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // Still serving, on the connection accepted before the signals and on
+    // a new one.
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
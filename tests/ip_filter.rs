@@ -0,0 +1,141 @@
+mod common;
+
+use common::{serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::listener::IpFilter;
+use custom_http::util::Cidr;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn cidr(range: &str) -> Cidr {
+    Cidr::parse(range).unwrap()
+}
+
+#[test]
+fn zero_prefixes_match_their_whole_family() {
+    let v4 = cidr("0.0.0.0/0");
+    assert!(v4.contains(ip("0.0.0.0")));
+    assert!(v4.contains(ip("255.255.255.255")));
+    assert!(!v4.contains(ip("::1")));
+    let v6 = cidr("::/0");
+    assert!(v6.contains(ip("::")));
+    assert!(v6.contains(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+    assert!(!v6.contains(ip("10.0.0.1")));
+    // Host bits under a zero prefix are all ignored.
+    assert_eq!(cidr("203.0.113.9/0"), v4);
+}
+
+#[test]
+fn full_prefixes_match_one_address() {
+    let one = cidr("192.0.2.1/32");
+    assert_eq!(cidr("192.0.2.1"), one);
+    assert!(one.contains(ip("192.0.2.1")));
+    assert!(!one.contains(ip("192.0.2.0")));
+    assert!(!one.contains(ip("192.0.2.2")));
+
+    let one = cidr("2001:db8::1/128");
+    assert_eq!(cidr("2001:db8::1"), one);
+    assert!(one.contains(ip("2001:db8::1")));
+    assert!(!one.contains(ip("2001:db8::")));
+    assert!(!one.contains(ip("2001:db8::2")));
+}
+
+#[test]
+fn prefixes_cut_at_any_bit() {
+    let range = cidr("10.1.2.3/15");
+    assert_eq!(range.to_string(), "10.0.0.0/15");
+    assert!(range.contains(ip("10.1.255.255")));
+    assert!(!range.contains(ip("10.2.0.0")));
+
+    let range = cidr("fd00:1234::/31");
+    assert_eq!(range.to_string(), "fd00:1234::/31");
+    assert!(range.contains(ip("fd00:1235::1")));
+    assert!(!range.contains(ip("fd00:1236::1")));
+}
+
+#[test]
+fn families_never_mix_except_for_mapped_ipv4() {
+    let v4 = cidr("127.0.0.0/8");
+    // A dual-stack socket's IPv4 client.
+    assert!(v4.contains(ip("::ffff:127.0.0.1")));
+    assert!(!v4.contains(ip("::1")));
+    assert!(!v4.contains(ip("::7f00:1")));
+    let v6 = cidr("::1/128");
+    assert!(!v6.contains(ip("127.0.0.1")));
+}
+
+#[test]
+fn malformed_ranges_are_refused() {
+    for range in [
+        "",
+        "10.0.0.0/",
+        "10.0.0.0/33",
+        "::/129",
+        "10.0.0.0/+8",
+        "10.0.0.0/-1",
+        "10.0.0.0/8/8",
+        "10.0.0/8",
+        "example.com/8",
+    ] {
+        assert_eq!(Cidr::parse(range), None, "{range}");
+    }
+}
+
+#[test]
+fn deny_wins_and_an_allow_list_refuses_the_rest() {
+    let filter = IpFilter {
+        allow: vec![cidr("10.0.0.0/8"), cidr("fd00::/8")],
+        deny: vec![cidr("10.6.6.0/24")],
+        ..IpFilter::default()
+    };
+    assert!(filter.permits(ip("10.1.2.3")));
+    assert!(filter.permits(ip("fd12::1")));
+    assert!(filter.permits(ip("::ffff:10.1.2.3")));
+    assert!(!filter.permits(ip("10.6.6.6")));
+    assert!(!filter.permits(ip("192.0.2.1")));
+    assert!(!filter.permits(ip("2001:db8::1")));
+
+    // No lists: everyone.
+    assert!(IpFilter::default().permits(ip("192.0.2.1")));
+}
+
+/// Requests `/page.html` from a server refusing loopback clients, and
+/// returns whatever comes back before the connection closes.
+fn refused(respond_forbidden: bool) -> String {
+    let running = serve(ServerConfig {
+        ip_filter: IpFilter {
+            deny: vec![cidr("127.0.0.0/8")],
+            respond_forbidden,
+            ..IpFilter::default()
+        },
+        ..site("ip-filter", &[("page.html", "page")])
+    });
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // Refused before the request is read; writing it may already fail.
+    let _ = stream.write_all(b"GET /page.html HTTP/1.1\r\n\r\n");
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[test]
+fn refused_clients_are_hung_up_on_at_accept() {
+    assert_eq!(refused(false), "");
+    let response = refused(true);
+    assert!(
+        response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+        "{response}"
+    );
+    assert!(!response.ends_with("page"), "{response}");
+}
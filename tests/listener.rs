@@ -0,0 +1,87 @@
+#![cfg(target_os = "linux")]
+
+use custom_http::io::listener::{SocketOptions, Stream, bind, configure_stream};
+use std::io;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// Reads the integer socket option `name` at `level` of `fd`.
+fn option(fd: &impl AsRawFd, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+    let mut value: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` are valid for the call and sized to match.
+    let got = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            (&raw mut value).cast(),
+            &mut len,
+        )
+    };
+    assert_eq!(got, 0, "{}", io::Error::last_os_error());
+    value
+}
+
+/// Binds a listener with `options`, connects to it and returns the accepted
+/// stream with `options` applied, as the reactor would.
+fn accepted(options: &SocketOptions) -> (Stream, TcpStream) {
+    let listener = bind("127.0.0.1:0".parse().unwrap(), options, false).unwrap();
+    assert_eq!(
+        option(&listener, libc::SOL_SOCKET, libc::SO_REUSEADDR),
+        i32::from(options.reuseaddr)
+    );
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let stream = Stream::Tcp(stream);
+                configure_stream(&stream, options).unwrap();
+                return (stream, client);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+#[test]
+fn accepted_streams_get_the_configured_options() {
+    let options = SocketOptions {
+        keepalive: Some(Duration::from_secs(45)),
+        ..SocketOptions::default()
+    };
+    let (Stream::Tcp(stream), _client) = accepted(&options) else {
+        unreachable!()
+    };
+    assert_eq!(option(&stream, libc::IPPROTO_TCP, libc::TCP_NODELAY), 1);
+    assert_eq!(option(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+    assert_eq!(option(&stream, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 45);
+}
+
+#[test]
+fn the_defaults_are_nodelay_without_keepalive() {
+    let options = SocketOptions::default();
+    assert!(options.nodelay);
+    assert_eq!(options.keepalive, None);
+    assert_eq!(options.backlog, 1024);
+    let (Stream::Tcp(stream), _client) = accepted(&options) else {
+        unreachable!()
+    };
+    assert_eq!(option(&stream, libc::IPPROTO_TCP, libc::TCP_NODELAY), 1);
+    assert_eq!(option(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+
+    let options = SocketOptions {
+        nodelay: false,
+        reuseaddr: false,
+        ..SocketOptions::default()
+    };
+    let (Stream::Tcp(stream), _client) = accepted(&options) else {
+        unreachable!()
+    };
+    assert_eq!(option(&stream, libc::IPPROTO_TCP, libc::TCP_NODELAY), 0);
+}
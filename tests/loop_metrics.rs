@@ -0,0 +1,139 @@
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::http::body::{BodySource, StreamBody};
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, ServerConfig};
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A body whose one chunk takes 200 ms, which blocks the reactor thread.
+struct Sluggish {
+    done: bool,
+}
+
+impl BodySource for Sluggish {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(200));
+        self.done = true;
+        Ok(Some(b"late".to_vec()))
+    }
+}
+
+/// A single-reactor config serving `page.html` and a `/sluggish` route.
+fn config(loop_metrics: bool) -> ServerConfig {
+    let mut router = Router::new();
+    router.get("/sluggish", |_: &HttpRequest| {
+        HttpResponse::stream(
+            StatusCode::OK,
+            HeaderValue::from_static("text/plain"),
+            StreamBody::sized(Sluggish { done: false }, 4),
+        )
+    });
+    let mut config = ServerConfig {
+        reactors: 1,
+        router: Arc::new(router),
+        ..site("loop-metrics", &[("page.html", "page")])
+    };
+    config.reactor.loop_metrics = loop_metrics;
+    config
+}
+
+/// Sends a `GET` of each of `targets` on one connection, reading every response.
+fn fetch(config: ServerConfig, targets: &[&str]) {
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    for target in targets {
+        stream
+            .write_all(format!("GET {target} HTTP/1.1\r\n\r\n").as_bytes())
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn loop_counters_move_with_traffic() {
+    let config = config(true);
+    let stats = config.stats.clone();
+    fetch(config, &["/page.html", "/page.html", "/sluggish"]);
+    let snapshot = stats.snapshot();
+    assert!(snapshot.loop_iterations > 0, "{snapshot:?}");
+    assert!(
+        snapshot.loop_events >= snapshot.loop_max_events,
+        "{snapshot:?}"
+    );
+    assert!(snapshot.loop_max_events > 0, "{snapshot:?}");
+    assert!(snapshot.accept_micros > 0, "{snapshot:?}");
+    assert!(snapshot.read_micros > 0, "{snapshot:?}");
+    assert!(snapshot.write_micros > 0, "{snapshot:?}");
+    // The blocking body shows up as one long iteration.
+    assert!(
+        snapshot.loop_max_iteration_micros >= 200_000,
+        "{snapshot:?}"
+    );
+    assert!(
+        snapshot.loop_busy_micros >= snapshot.loop_max_iteration_micros,
+        "{snapshot:?}"
+    );
+}
+
+#[test]
+fn loop_counters_stay_zero_when_off() {
+    let config = config(false);
+    let stats = config.stats.clone();
+    fetch(config, &["/page.html"]);
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.requests_served, 1);
+    assert_eq!(
+        [
+            snapshot.loop_iterations,
+            snapshot.loop_events,
+            snapshot.loop_busy_micros,
+            snapshot.accept_micros,
+            snapshot.read_micros,
+            snapshot.write_micros,
+        ],
+        [0; 6]
+    );
+}
+
+#[test]
+fn slow_iterations_are_warned_about() {
+    // The warning goes to stderr, so the server runs in a copy of this
+    // test binary whose output is captured here, at the log level given.
+    if let Some(level) = std::env::var_os("CUSTOM_HTTP_SLOW_ITERATION") {
+        let config = ServerConfig {
+            log_level: level.to_str().unwrap().parse().unwrap(),
+            ..config(false)
+        };
+        fetch(config, &["/page.html", "/sluggish"]);
+        return;
+    }
+    let warned = |level: &str| {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "slow_iterations_are_warned_about", "--nocapture"])
+            .env("CUSTOM_HTTP_SLOW_ITERATION", level)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .any(|line| line.starts_with("event loop iteration took "))
+    };
+    assert!(warned("warn"));
+    // A warning, so not at the error level.
+    assert!(!warned("error"));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{read_response, serve, site};
+use std::io::Write;
+use std::net::TcpStream;
+
+#[test]
+fn unknown_methods_get_501_and_known_ones_405() {
+    let config = site("methods", &[("index.html", "<p>home</p>")]);
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+
+    // One kept-alive connection throughout: neither error closes it.
+    stream
+        .write_all(b"BREW /coffee HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert!(
+        response.starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.contains("\r\nContent-Type: text/html"),
+        "{response}"
+    );
+    assert!(!response.contains("Connection: close"), "{response}");
+
+    stream
+        .write_all(b"DELETE /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert!(
+        response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+        "{response}"
+    );
+    assert!(!response.contains("Connection: close"), "{response}");
+
+    stream
+        .write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\n<p>home</p>"), "{response}");
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
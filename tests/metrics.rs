@@ -0,0 +1,182 @@
+use custom_http::http::metrics::MetricsConfig;
+use custom_http::util::Cidr;
+use custom_http::{Server, ServerConfig, ServerHandle};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+fn start(metrics: MetricsConfig) -> (SocketAddr, ServerHandle) {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(2)
+        .metrics(metrics)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    (running.local_addr().unwrap(), running)
+}
+
+/// Sends `GET path` on a new connection and returns the head and the body.
+fn get(address: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_owned(), body.to_owned())
+}
+
+/// Parses an exposition into its samples, keyed by name and labels as
+/// written, checking the syntax of every line on the way.
+fn parse(exposition: &str) -> HashMap<String, u64> {
+    let mut samples = HashMap::new();
+    for line in exposition.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            assert!(
+                comment.starts_with("HELP ") || comment.starts_with("TYPE "),
+                "{line}"
+            );
+            continue;
+        }
+        let (series, value) = line.rsplit_once(' ').unwrap();
+        let name_end = series.find('{').unwrap_or(series.len());
+        let name = &series[..name_end];
+        assert!(
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            "{line}"
+        );
+        if name_end < series.len() {
+            let labels = series[name_end..]
+                .strip_prefix('{')
+                .and_then(|labels| labels.strip_suffix('}'))
+                .unwrap();
+            for label in labels.split(',') {
+                let (key, value) = label.split_once('=').unwrap();
+                assert!(
+                    key.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'),
+                    "{line}"
+                );
+                assert!(value.starts_with('"') && value.ends_with('"'), "{line}");
+            }
+        }
+        // The duration sum is the only sample with a fraction.
+        let value = value.split('.').next().unwrap().parse().unwrap();
+        samples.insert(series.to_owned(), value);
+    }
+    samples
+}
+
+#[test]
+fn counters_increase_with_traffic() {
+    let (address, running) = start(MetricsConfig::default());
+
+    let (head, body) = get(address, "/metrics");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(
+        head.contains("Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n"),
+        "{head}"
+    );
+    assert!(
+        body.contains("# TYPE http_requests_total counter\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("# TYPE http_request_duration_seconds histogram\n"),
+        "{body}"
+    );
+    let before = parse(&body);
+    for name in [
+        "http_requests_total{status_class=\"2xx\"}",
+        "http_requests_total{status_class=\"4xx\"}",
+        "http_connections_accepted_total",
+        "http_connections_active",
+        "http_bytes_read_total",
+        "http_bytes_written_total",
+        "threadpool_workers",
+        "threadpool_busy_workers",
+        "threadpool_queue_depth",
+        "threadpool_jobs_completed_total",
+        "http_request_duration_seconds_bucket{le=\"0.005\"}",
+        "http_request_duration_seconds_bucket{le=\"+Inf\"}",
+        "http_request_duration_seconds_sum",
+        "http_request_duration_seconds_count",
+    ] {
+        assert!(before.contains_key(name), "{name} missing from\n{body}");
+    }
+    assert_eq!(before["threadpool_workers"], 2);
+
+    for _ in 0..3 {
+        let (head, _) = get(address, "/no-such-page");
+        assert!(head.starts_with("HTTP/1.1 404 "), "{head}");
+    }
+    let (_, body) = get(address, "/metrics");
+    let after = parse(&body);
+    let increase = |name: &str| after[name] - before[name];
+    assert_eq!(increase("http_requests_total{status_class=\"4xx\"}"), 3);
+    // The first scrape counts once its response is queued.
+    assert_eq!(increase("http_requests_total{status_class=\"2xx\"}"), 1);
+    assert_eq!(increase("http_connections_accepted_total"), 4);
+    assert!(increase("http_bytes_read_total") > 0);
+    assert!(increase("http_bytes_written_total") > 0);
+    assert_eq!(increase("http_request_duration_seconds_count"), 4);
+    assert_eq!(
+        after["http_request_duration_seconds_bucket{le=\"+Inf\"}"],
+        after["http_request_duration_seconds_count"]
+    );
+    assert!(
+        after["http_request_duration_seconds_bucket{le=\"0.005\"}"]
+            <= after["http_request_duration_seconds_bucket{le=\"10\"}"]
+    );
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn scrapes_are_limited_to_the_allowlist() {
+    let (address, running) = start(MetricsConfig {
+        path: String::from("/internal/metrics"),
+        allow: vec![Cidr::parse("10.0.0.0/8").unwrap()],
+    });
+    let (head, _) = get(address, "/internal/metrics");
+    assert!(head.starts_with("HTTP/1.1 403 "), "{head}");
+    let (head, _) = get(address, "/metrics");
+    assert!(head.starts_with("HTTP/1.1 404 "), "{head}");
+    running.shutdown();
+    running.join().unwrap();
+
+    let (address, running) = start(MetricsConfig {
+        path: String::from("/internal/metrics"),
+        allow: vec![Cidr::parse("127.0.0.0/8").unwrap()],
+    });
+    let (head, _) = get(address, "/internal/metrics");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn metrics_table_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("metrics-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "[metrics]\npath = \"/stats\"\nallow = [\"127.0.0.1\", \"10.0.0.0/8\"]\n",
+    )
+    .unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    let metrics = config.metrics.unwrap();
+    assert_eq!(metrics.path, "/stats");
+    assert_eq!(metrics.allow.len(), 2);
+
+    std::fs::write(&path, "[metrics]\nenabled = false\n").unwrap();
+    assert!(
+        ServerConfig::from_toml_path(&path)
+            .unwrap()
+            .metrics
+            .is_none()
+    );
+    std::fs::write(&path, "[metrics]\nallow = [\"nonsense\"]\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
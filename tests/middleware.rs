@@ -0,0 +1,117 @@
+use custom_http::http::headers::{HeaderName, HeaderValue};
+use custom_http::http::request::{HttpRequest, parse_request};
+use custom_http::http::response::{HttpResponse, handle};
+use custom_http::http::status::StatusCode;
+use custom_http::server::{Middleware, Next, RequestLog};
+use custom_http::{Router, ServerConfig};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+fn request(head: &str) -> HttpRequest {
+    parse_request(head.as_bytes()).unwrap().0
+}
+
+fn config(router: Router) -> ServerConfig {
+    ServerConfig {
+        router: Arc::new(router),
+        ..ServerConfig::default()
+    }
+}
+
+/// Records when it is entered and left, and tags the response on the way out.
+struct Trace {
+    name: &'static str,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Trace {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{} in", self.name));
+        let response = next(request);
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{} out", self.name));
+        response.with_header(
+            HeaderName::from_static("X-Last-Out"),
+            HeaderValue::from_static(self.name),
+        )
+    }
+}
+
+#[test]
+fn middleware_runs_in_order_around_routes() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut router = Router::new();
+    let handler_events = Arc::clone(&events);
+    router
+        .wrap(Trace {
+            name: "outer",
+            events: Arc::clone(&events),
+        })
+        .wrap(Trace {
+            name: "inner",
+            events: Arc::clone(&events),
+        })
+        .get("/hello", move |_| {
+            handler_events.lock().unwrap().push(String::from("handler"));
+            HttpResponse::text(StatusCode::OK, "hi")
+        });
+
+    let response = handle(&request("GET /hello HTTP/1.1\r\n\r\n"), &config(router));
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        *events.lock().unwrap(),
+        ["outer in", "inner in", "handler", "inner out", "outer out"]
+    );
+    assert_eq!(response.header("X-Last-Out"), Some("outer"));
+}
+
+#[test]
+fn middleware_wraps_static_files() {
+    let mut router = Router::new();
+    router.wrap(|request: &mut HttpRequest, next: Next<'_>| {
+        next(request).with_header(
+            HeaderName::from_static("X-Content-Type-Options"),
+            HeaderValue::from_static("nosniff"),
+        )
+    });
+    let response = handle(&request("GET / HTTP/1.1\r\n\r\n"), &config(router));
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+}
+
+#[test]
+fn middleware_can_short_circuit_and_rewrite_requests() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls = Arc::clone(&calls);
+    let mut router = Router::new();
+    router
+        .wrap(RequestLog)
+        .wrap(|request: &mut HttpRequest, next: Next<'_>| {
+            if request.header("Authorization").is_none() {
+                return HttpResponse::text(StatusCode::UNAUTHORIZED, "no");
+            }
+            request.path = String::from("/private");
+            next(request)
+        })
+        .get("/private", move |_| {
+            handler_calls.fetch_add(1, Ordering::Relaxed);
+            HttpResponse::text(StatusCode::OK, "secret")
+        });
+    let config = config(router);
+
+    let response = handle(&request("GET /anything HTTP/1.1\r\n\r\n"), &config);
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+    let response = handle(
+        &request("GET /anything HTTP/1.1\r\nAuthorization: yes\r\n\r\n"),
+        &config,
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+}
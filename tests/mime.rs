@@ -0,0 +1,124 @@
+mod common;
+
+use common::{get, site};
+use custom_http::ServerConfig;
+use std::collections::HashMap;
+
+/// Serves one small file for each of `names`, with `overrides` and `default`.
+fn config(names: &[&str], overrides: &[(&str, &str)], default: &str) -> ServerConfig {
+    let files: Vec<(&str, &str)> = names.iter().map(|name| (*name, "x")).collect();
+    ServerConfig {
+        mime_overrides: overrides
+            .iter()
+            .map(|(ext, content_type)| (ext.to_string(), content_type.to_string()))
+            .collect::<HashMap<_, _>>(),
+        default_mime: String::from(default),
+        ..site("mime", &files)
+    }
+}
+
+/// Asserts that each (file, type) pair is served with that `Content-Type`.
+fn assert_types(config: &ServerConfig, expected: &[(&str, &str)]) {
+    for (name, content_type) in expected {
+        let response = get(config, &format!("/{name}"));
+        assert!(response.starts_with("HTTP/1.1 200 "), "{name}: {response}");
+        assert!(
+            response.contains(&format!("\r\nContent-Type: {content_type}\r\n")),
+            "{name}: {response}"
+        );
+    }
+}
+
+#[test]
+fn overrides_win_and_everything_else_passes_through() {
+    let config = config(
+        &[
+            "app.map",
+            "level.GDAT",
+            "README.md",
+            "style.css",
+            "blob.zzq",
+        ],
+        &[
+            ("map", "application/json"),
+            (".gdat", "application/x-game-data"),
+            ("MD", "text/plain"),
+        ],
+        "application/x-unknown",
+    );
+    assert_types(
+        &config,
+        &[
+            ("app.map", "application/json"),
+            // Extensions compare ignoring case, with or without the dot.
+            ("level.GDAT", "application/x-game-data"),
+            ("README.md", "text/plain"),
+            ("style.css", "text/css"),
+            // Unknown to `mime_guess` and the table: the configured default.
+            ("blob.zzq", "application/x-unknown"),
+        ],
+    );
+}
+
+#[test]
+fn the_default_for_unknown_types_is_octet_stream() {
+    let config = ServerConfig {
+        default_mime: ServerConfig::default().default_mime,
+        ..config(&["blob.zzq"], &[], "unused")
+    };
+    assert_types(&config, &[("blob.zzq", "application/octet-stream")]);
+}
+
+#[test]
+fn compound_extensions_use_both_parts() {
+    let config = config(
+        &[
+            "backup.tar.gz",
+            "archive.tar.bz2",
+            "logs.tar.xz",
+            "app.js.map",
+            "site.css.map",
+            "tool.user.js",
+            "jquery.min.js",
+            "photo.final.PNG",
+            "plain.gz",
+        ],
+        &[],
+        "application/octet-stream",
+    );
+    assert_types(
+        &config,
+        &[
+            ("backup.tar.gz", "application/gzip"),
+            ("archive.tar.bz2", "application/x-bzip2"),
+            ("logs.tar.xz", "application/x-xz"),
+            ("app.js.map", "application/json"),
+            ("site.css.map", "application/json"),
+            ("tool.user.js", "text/javascript"),
+            // Dots in the name alone don't make a compound extension.
+            ("jquery.min.js", "text/javascript"),
+            ("photo.final.PNG", "image/png"),
+            ("plain.gz", "application/gzip"),
+        ],
+    );
+}
+
+#[test]
+fn configured_compound_types_merge_with_the_built_in_ones() {
+    let config = config(
+        &["backup.tar.gz", "archive.tar.bz2", "bundle.min.js"],
+        &[
+            ("tar.gz", "application/x-tar+gzip"),
+            ("MIN.JS", "application/x-minified"),
+        ],
+        "application/octet-stream",
+    );
+    assert_types(
+        &config,
+        &[
+            ("backup.tar.gz", "application/x-tar+gzip"),
+            ("archive.tar.bz2", "application/x-bzip2"),
+            ("bundle.min.js", "application/x-minified"),
+        ],
+    );
+}
@@ -0,0 +1,112 @@
+#![cfg(any(unix, windows))]
+
+mod common;
+
+use common::{request, site};
+use custom_http::ServerConfig;
+use custom_http::http::response::{Body, handle};
+use custom_http::io::file::MappedReader;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, keeping track of the bytes in use and their peak.
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grew(size: usize) {
+    let now = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grew(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        grew(new_size);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const SIZE: usize = 100 * 1024 * 1024;
+const CHUNK: usize = 64 * 1024;
+
+/// Answers `GET /big.bin`, drains the whole body, and returns how many bytes
+/// it was and the most memory it had allocated at once.
+fn serve_and_measure(config: &ServerConfig) -> (usize, usize) {
+    let baseline = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+
+    let mut queue: Vec<Vec<u8>> = Vec::new();
+    let rest = handle(&request("/big.bin", &[]), config).start_writing(&mut queue, false);
+    let mut sent: usize = queue.drain(1..).map(|piece| piece.len()).sum();
+    match rest {
+        Some(Body::Stream(mut stream)) => {
+            while let Some(piece) = stream.next_wire_chunk().unwrap() {
+                sent += piece.len();
+            }
+        }
+        None => {}
+        Some(_) => panic!("unexpected body"),
+    }
+    (sent, PEAK.load(Ordering::Relaxed) - baseline)
+}
+
+#[test]
+fn mapped_files_use_a_fraction_of_the_memory_of_reading_them() {
+    let base = ServerConfig {
+        stream_chunk_size: CHUNK,
+        sendfile: false,
+        ..site("mmap", &[])
+    };
+    let big = base.document_root.join("big.bin");
+    fs::write(&big, vec![7u8; SIZE]).unwrap();
+
+    let read = ServerConfig {
+        max_in_memory_file_size: u64::MAX,
+        ..base.clone()
+    };
+    let (sent, read_peak) = serve_and_measure(&read);
+    assert_eq!(sent, SIZE);
+    assert!(read_peak >= SIZE, "read peak {read_peak}");
+
+    let mapped = ServerConfig {
+        mmap_threshold: Some(1024 * 1024),
+        ..base
+    };
+    let (sent, mapped_peak) = serve_and_measure(&mapped);
+    assert_eq!(sent, SIZE);
+    // A chunk in flight plus the head and bookkeeping, whatever the file size.
+    assert!(mapped_peak < 16 * CHUNK, "mapped peak {mapped_peak}");
+    fs::remove_file(big).unwrap();
+}
+
+#[test]
+fn truncation_while_serving_is_an_error() {
+    let config = site("mmap-truncated", &[]);
+    let path = config.document_root.join("shrinking.bin");
+    fs::write(&path, vec![1u8; 4 * CHUNK]).unwrap();
+
+    let (mut reader, length) = MappedReader::open(&path.to_string_lossy(), CHUNK).unwrap();
+    assert_eq!(length, 4 * CHUNK as u64);
+    assert_eq!(reader.next_chunk().unwrap().map(|c| c.len()), Some(CHUNK));
+
+    fs::write(&path, b"short").unwrap();
+    let error = reader.next_chunk().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::get;
+use custom_http::ServerConfig;
+use custom_http::server::Mount;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Creates a fresh directory holding `files`, as (relative path, contents) pairs.
+fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("custom_http-mounts-{}-{name}", std::process::id()));
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+fn assert_serves(config: &ServerConfig, path: &str, body: &str) {
+    let response = get(config, path);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{path}: {response}");
+    assert!(
+        response.ends_with(&format!("\r\n\r\n{body}")),
+        "{path}: {response}"
+    );
+}
+
+fn assert_status(config: &ServerConfig, path: &str, status: u16) {
+    let response = get(config, path);
+    assert!(
+        response.starts_with(&format!("HTTP/1.1 {status} ")),
+        "{path}: {response}"
+    );
+}
+
+/// The document root plus `/docs` and `/docs/api`, which shadows part of `/docs`.
+struct Sites {
+    root: PathBuf,
+    docs: PathBuf,
+    api: PathBuf,
+}
+
+impl Sites {
+    fn new(name: &str) -> Sites {
+        Sites {
+            root: dir(
+                &format!("{name}-root"),
+                &[
+                    ("welcome.html", "root"),
+                    ("docs/only-in-root.html", "root docs"),
+                ],
+            ),
+            docs: dir(
+                &format!("{name}-docs"),
+                &[
+                    ("guide.html", "docs guide"),
+                    ("api-v2.html", "docs api v2"),
+                    ("api/index.html", "shadowed"),
+                    ("api/extra.html", "docs api extra"),
+                    ("index.html", "docs index"),
+                ],
+            ),
+            api: dir(&format!("{name}-api"), &[("index.html", "api index")]),
+        }
+    }
+
+    fn config(&self) -> ServerConfig {
+        ServerConfig::builder()
+            .document_root(&self.root)
+            .mount("/docs", &self.docs)
+            .mount("/docs/api/", &self.api)
+            .build()
+            .unwrap()
+    }
+}
+
+impl Drop for Sites {
+    fn drop(&mut self) {
+        for dir in [&self.root, &self.docs, &self.api] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[test]
+fn serves_each_prefix_from_its_longest_mount() {
+    let sites = Sites::new("longest");
+    let config = sites.config();
+
+    assert_serves(&config, "/", "root");
+    assert_serves(&config, "/docs/guide.html", "docs guide");
+    assert_serves(&config, "/docs/", "docs index");
+    // `/docs/api` takes everything under it, but only on a segment boundary.
+    assert_serves(&config, "/docs/api/", "api index");
+    assert_serves(&config, "/docs/api-v2.html", "docs api v2");
+    assert!(get(&config, "/docs/api").contains("\r\nLocation: /docs/api/\r\n"));
+    // Clean URLs, ETags and the rest work as they do under the root.
+    assert_serves(&config, "/docs/guide", "docs guide");
+    assert!(get(&config, "/docs/guide.html").contains("\r\nETag: "));
+}
+
+#[test]
+fn missing_files_under_a_mount_are_not_found() {
+    let sites = Sites::new("missing");
+    let config = sites.config();
+
+    assert_status(&config, "/docs/only-in-root.html", 404);
+    assert_status(&config, "/docs/api/extra.html", 404);
+}
+
+#[test]
+fn mounts_can_fall_through() {
+    let sites = Sites::new("fall-through");
+    let mut config = sites.config();
+    for mount in &mut config.mounts {
+        mount.fall_through = true;
+    }
+
+    assert_serves(&config, "/docs/only-in-root.html", "root docs");
+    assert_serves(&config, "/docs/api/extra.html", "docs api extra");
+    assert_serves(&config, "/docs/api/", "api index");
+}
+
+#[test]
+fn mounts_confine_requests_to_their_directory() {
+    let sites = Sites::new("confine");
+    let config = sites.config();
+
+    // `..` can't climb out of a mount, only back into the root's URL space.
+    assert_serves(&config, "/docs/api/../guide.html", "docs guide");
+    assert_status(&config, "/docs/%2e%2e/%2e%2e/etc/passwd", 403);
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(sites.root.join("welcome.html"), sites.api.join("out.html"))
+            .unwrap();
+        assert_status(&config, "/docs/api/out.html", 403);
+    }
+}
+
+#[test]
+fn mount_prefixes_must_be_absolute() {
+    assert!(Mount::new("assets", "public").is_err());
+    let mut config = ServerConfig::default();
+    let args = ["--mount", "/assets=public"].map(String::from);
+    config.apply_args(args).unwrap();
+    assert_eq!(config.mounts, [Mount::new("/assets", "public").unwrap()]);
+    let args = ["--mount", "assets=public"].map(String::from);
+    assert!(config.apply_args(args).is_err());
+    let missing = Path::new("/nonexistent/custom_http-mount");
+    let error = ServerConfig::builder().mount("/x", missing).build();
+    assert!(error.is_err());
+}
+
+#[test]
+fn mounts_load_from_toml() {
+    let dir = dir(
+        "toml",
+        &[("site.toml", "[mounts]\n\"/assets\" = \"/srv/assets\"\n")],
+    );
+    let config = ServerConfig::from_toml_path(dir.join("site.toml")).unwrap();
+    assert_eq!(
+        config.mounts,
+        [Mount::new("/assets", "/srv/assets").unwrap()]
+    );
+    fs::write(dir.join("bad.toml"), "[mounts]\nassets = \"/srv/assets\"\n").unwrap();
+    let error = ServerConfig::from_toml_path(dir.join("bad.toml")).unwrap_err();
+    assert!(
+        error.to_string().contains("line 2: mounts.assets: "),
+        "{error}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,88 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::TcpStream;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn a_stalled_file_read_holds_up_no_other_connection() {
+    let config = ServerConfig {
+        reactors: 1,
+        ..site("offload", &[("fast.txt", "fast")])
+    };
+    // Reading a FIFO blocks until something writes into it: a disk that
+    // takes as long as the test wants.
+    let fifo = config.document_root.join("slow.txt");
+    assert!(
+        Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success()
+    );
+    let running = serve(config);
+    let address = running.local_addr().unwrap();
+
+    let mut slow = TcpStream::connect(address).unwrap();
+    slow.write_all(b"GET /slow.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    for _ in 0..5 {
+        let mut fast = TcpStream::connect(address).unwrap();
+        fast.write_all(b"GET /fast.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut fast);
+        assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+        assert!(response.ends_with("\r\n\r\nfast"), "{response}");
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // Once the "disk" delivers, the stalled request completes as well. The
+    // server may open the file more than once (e.g. to hash it for its ETag),
+    // so it gets the same contents every time it reads until it's done. A
+    // reader still open when the "disk" comes round again reads them more than once.
+    let done = Arc::new(AtomicBool::new(false));
+    let disk = thread::spawn({
+        let done = Arc::clone(&done);
+        move || {
+            while !done.load(Ordering::Relaxed) {
+                // Without a reader, a nonblocking open fails instead of waiting.
+                match OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&fifo)
+                {
+                    Ok(mut reader) => {
+                        let _ = reader.write_all(b"finally");
+                    }
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        }
+    });
+    let response = read_response(&mut slow);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    assert!(
+        !body.is_empty() && body.replace("finally", "").is_empty(),
+        "{response}"
+    );
+    done.store(true, Ordering::Relaxed);
+    disk.join().unwrap();
+
+    drop(slow);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::site;
+use custom_http::io::precompress::{PrecompressConfig, Summary, precompress};
+use custom_http::thread_pool::ThreadPool;
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Siblings written per file: `.gz`, and `.br` with the `brotli` feature.
+const PER_FILE: usize = if cfg!(feature = "brotli") { 2 } else { 1 };
+
+/// Bytes that don't compress, from a fixed xorshift sequence.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// A tree with two compressible pages, a file too small to bother with, an
+/// image, and a script that doesn't shrink.
+fn tree() -> (PathBuf, String) {
+    let page = "<p>the same words, over and over</p>\n".repeat(200);
+    let root = site(
+        "precompress",
+        &[
+            ("index.html", &page),
+            ("docs/guide.html", &page),
+            ("tiny.css", "p {}"),
+        ],
+    )
+    .document_root;
+    fs::write(root.join("photo.png"), noise(4096)).unwrap();
+    fs::write(root.join("random.js"), noise(4096)).unwrap();
+    (root, page)
+}
+
+fn gunzip(path: &Path) -> String {
+    let mut text = String::new();
+    GzDecoder::new(File::open(path).unwrap())
+        .read_to_string(&mut text)
+        .unwrap();
+    text
+}
+
+#[test]
+fn compressible_files_get_smaller_siblings() {
+    let (root, page) = tree();
+    let summary = precompress(&root, &PrecompressConfig::default(), &ThreadPool::new(2));
+    assert_eq!(
+        summary,
+        Summary {
+            written: 2 * PER_FILE,
+            up_to_date: 0,
+            not_smaller: PER_FILE,
+            failed: 0,
+        }
+    );
+
+    for name in ["index.html", "docs/guide.html"] {
+        let sibling = root.join(format!("{name}.gz"));
+        assert!(fs::metadata(&sibling).unwrap().len() < page.len() as u64 / 4);
+        assert_eq!(gunzip(&sibling), page, "{name}");
+        if cfg!(feature = "brotli") {
+            assert!(root.join(format!("{name}.br")).exists(), "{name}");
+        }
+    }
+    for name in ["tiny.css", "photo.png", "random.js"] {
+        assert!(!root.join(format!("{name}.gz")).exists(), "{name}");
+        assert!(!root.join(format!("{name}.br")).exists(), "{name}");
+    }
+}
+
+#[test]
+fn only_changed_files_are_compressed_again() {
+    let (root, _) = tree();
+    let pool = ThreadPool::new(2);
+    precompress(&root, &PrecompressConfig::default(), &pool);
+
+    let summary = precompress(&root, &PrecompressConfig::default(), &pool);
+    assert_eq!(summary.written, 0);
+    assert_eq!(summary.up_to_date, 2 * PER_FILE);
+
+    let changed = "<p>new words, over and over</p>\n".repeat(200);
+    let index = root.join("index.html");
+    fs::write(&index, &changed).unwrap();
+    File::options()
+        .write(true)
+        .open(&index)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    let summary = precompress(&root, &PrecompressConfig::default(), &pool);
+    assert_eq!(summary.written, PER_FILE);
+    assert_eq!(summary.up_to_date, PER_FILE);
+    assert_eq!(gunzip(&root.join("index.html.gz")), changed);
+}
@@ -0,0 +1,216 @@
+use custom_http::http::proxy::ProxyConfig;
+use custom_http::{Server, ServerConfig};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Sends one request on a fresh connection and returns the whole response.
+fn exchange(address: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn header<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    let head = message.split("\r\n\r\n").next()?;
+    head.lines().find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Starts an upstream that answers one request with `response` and returns
+/// its address and the request it received, head and body.
+fn upstream(response: &'static str) -> (SocketAddr, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let received = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0; 1024];
+        let head_end = loop {
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let read = stream.read(&mut chunk).unwrap();
+            request.extend_from_slice(&chunk[..read]);
+        };
+        let head = String::from_utf8_lossy(&request[..head_end]).into_owned();
+        let length: usize = header(&head, "Content-Length").map_or(0, |n| n.parse().unwrap());
+        while request.len() < head_end + length {
+            let read = stream.read(&mut chunk).unwrap();
+            request.extend_from_slice(&chunk[..read]);
+        }
+        stream.write_all(response.as_bytes()).unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (address, received)
+}
+
+fn serve(proxy: ProxyConfig) -> (SocketAddr, custom_http::ServerHandle) {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .proxy(proxy)
+        .build()
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    (address, server.spawn().unwrap())
+}
+
+#[test]
+fn forwards_requests_and_relays_responses() {
+    let (upstream_address, received) = upstream(
+        "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nX-Upstream: yes\r\n\
+         X-Internal: secret\r\nConnection: close, X-Internal\r\n\
+         Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+    );
+    let mut proxy = ProxyConfig::new("/api", &format!("http://{upstream_address}/v1")).unwrap();
+    proxy.strip_prefix = true;
+    let (address, running) = serve(proxy);
+
+    let response = exchange(
+        address,
+        "POST /api/echo?x=1 HTTP/1.1\r\nHost: site.test\r\nKeep-Alive: timeout=5\r\n\
+         Connection: close, X-Hop\r\nX-Hop: 1\r\nX-Custom: kept\r\n\
+         X-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: https\r\n\
+         Content-Length: 4\r\n\r\nping",
+    );
+    let request = received.join().unwrap();
+
+    assert!(
+        request.starts_with("POST /v1/echo?x=1 HTTP/1.1\r\n"),
+        "{request}"
+    );
+    assert_eq!(
+        header(&request, "Host"),
+        Some(&*upstream_address.to_string())
+    );
+    assert_eq!(header(&request, "X-Custom"), Some("kept"));
+    assert_eq!(
+        header(&request, "X-Forwarded-For"),
+        Some("203.0.113.7, 127.0.0.1")
+    );
+    assert_eq!(header(&request, "X-Forwarded-Proto"), Some("http"));
+    assert_eq!(header(&request, "X-Forwarded-Host"), Some("site.test"));
+    assert_eq!(header(&request, "Keep-Alive"), None);
+    assert_eq!(header(&request, "X-Hop"), None);
+    assert_eq!(header(&request, "Connection"), Some("close"));
+    assert!(request.ends_with("\r\n\r\nping"), "{request}");
+
+    assert!(
+        response.starts_with("HTTP/1.1 201 Created\r\n"),
+        "{response}"
+    );
+    assert_eq!(header(&response, "Content-Type"), Some("text/plain"));
+    assert_eq!(header(&response, "X-Upstream"), Some("yes"));
+    assert_eq!(header(&response, "X-Internal"), None);
+    assert_eq!(header(&response, "Transfer-Encoding"), None);
+    assert!(response.ends_with("\r\n\r\nhello world"), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn keeps_the_prefix_unless_told_otherwise() {
+    let (upstream_address, received) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+    let proxy = ProxyConfig::new("/api/", &format!("http://{upstream_address}")).unwrap();
+    let (address, running) = serve(proxy);
+
+    let response = exchange(
+        address,
+        "GET /api/users HTTP/1.1\r\nHost: site.test\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+    assert!(
+        received
+            .join()
+            .unwrap()
+            .starts_with("GET /api/users HTTP/1.1\r\n")
+    );
+    // Only whole path segments match.
+    let response = exchange(
+        address,
+        "GET /apis HTTP/1.1\r\nHost: site.test\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn unreachable_upstreams_are_bad_gateways() {
+    // A port that was just free is almost certainly still closed.
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let proxy = ProxyConfig::new("/api", &format!("http://{closed}")).unwrap();
+    let (address, running) = serve(proxy);
+
+    let response = exchange(
+        address,
+        "GET /api/users HTTP/1.1\r\nHost: site.test\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 502 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn upstream_headers_with_line_breaks_are_bad_gateways() {
+    let (upstream_address, _) = upstream(
+        "HTTP/1.1 200 OK\r\nX-Note: a\rSet-Cookie: session=stolen\r\nContent-Length: 2\r\n\r\nok",
+    );
+    let proxy = ProxyConfig::new("/api", &format!("http://{upstream_address}")).unwrap();
+    let (address, running) = serve(proxy);
+
+    let response = exchange(
+        address,
+        "GET /api/users HTTP/1.1\r\nHost: site.test\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 502 "), "{response}");
+    assert!(!response.contains("Set-Cookie"), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn slow_upstreams_time_out() {
+    let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut proxy =
+        ProxyConfig::new("/api", &format!("http://{}", silent.local_addr().unwrap())).unwrap();
+    proxy.timeout = Duration::from_millis(200);
+    let (address, running) = serve(proxy);
+
+    let response = exchange(
+        address,
+        "GET /api/users HTTP/1.1\r\nHost: site.test\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+    drop(silent);
+}
+
+#[test]
+fn proxies_must_name_an_http_upstream() {
+    assert!(ProxyConfig::new("api", "http://127.0.0.1:3000").is_err());
+    assert!(ProxyConfig::new("/api", "https://127.0.0.1:3000").is_err());
+    assert!(ProxyConfig::new("/api", "http://").is_err());
+    let mut config = ServerConfig::default();
+    let args = ["--proxy", "/api=http://127.0.0.1:3000"].map(String::from);
+    config.apply_args(args).unwrap();
+    assert_eq!(config.proxies[0].upstream, "http://127.0.0.1:3000");
+    assert!(config.proxy_for("/api/users").is_some());
+    assert!(config.proxy_for("/apis").is_none());
+}
@@ -0,0 +1,44 @@
+//! `accepted_per_reactor` lists every reactor started in the process, so this
+//! test crate holds a single test. Only Linux spreads `SO_REUSEPORT`
+//! connections evenly; other systems may hand them all to one listener.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::nonblocking::accepted_per_reactor;
+use std::io::Write;
+use std::net::TcpStream;
+
+#[test]
+fn connections_are_spread_across_reactors() {
+    let config = ServerConfig {
+        reactors: 4,
+        ..site("reactors", &[("page.html", "page")])
+    };
+    let stats = config.stats.clone();
+    let running = serve(config);
+    let address = running.local_addr().unwrap();
+
+    for _ in 0..400 {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("\r\n\r\npage"));
+    }
+
+    let accepted = accepted_per_reactor();
+    assert_eq!(accepted.len(), 4);
+    assert_eq!(accepted.iter().sum::<usize>(), 400);
+    // The kernel hashes each connection to a listener; every reactor gets a
+    // fair share, far from all or nothing.
+    for count in &accepted {
+        assert!(*count > 40, "{accepted:?}");
+    }
+    assert_eq!(stats.snapshot().connections_accepted, 400);
+
+    running.shutdown();
+    running.join().unwrap();
+}
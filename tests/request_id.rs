@@ -0,0 +1,269 @@
+use custom_http::http::request::HttpRequest;
+use custom_http::http::request_id::RequestIdConfig;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::io::access_log::{AccessLogConfig, LogTarget};
+use custom_http::log::LogFormat;
+use custom_http::server::RequestLog;
+use custom_http::{Router, Server, ServerConfig, ServerConfigBuilder, ServerHandle};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn start(builder: ServerConfigBuilder) -> (SocketAddr, ServerHandle, mpsc::Receiver<String>) {
+    let mut router = Router::new();
+    router.get("/id", |request: &HttpRequest| {
+        HttpResponse::text(StatusCode::OK, request.id.as_deref().unwrap_or("none"))
+    });
+    let (lines, received) = mpsc::channel();
+    let config = builder
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .router(router)
+        .access_log(AccessLogConfig {
+            target: LogTarget::Channel(lines),
+            ..AccessLogConfig::default()
+        })
+        .log_format(LogFormat::Json)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    (running.local_addr().unwrap(), running, received)
+}
+
+/// Sends `GET path` with `headers` on a new connection and returns the
+/// head and the body.
+fn get(address: SocketAddr, path: &str, headers: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\n{headers}Connection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_owned(), body.to_owned())
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .filter_map(|line| line.split_once(": "))
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// Parses a flat JSON object of strings, numbers and nulls, the values as
+/// written (strings unescaped), failing on anything else.
+fn parse_json(line: &str) -> HashMap<String, String> {
+    fn string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+        assert_eq!(chars.next(), Some('"'));
+        let mut out = String::new();
+        loop {
+            match chars.next().unwrap() {
+                '"' => return out,
+                '\\' => match chars.next().unwrap() {
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        out.push(char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                    }
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    c @ ('"' | '\\' | '/') => out.push(c),
+                    c => panic!("bad escape \\{c}"),
+                },
+                c => {
+                    assert!(!c.is_control(), "raw control character");
+                    out.push(c);
+                }
+            }
+        }
+    }
+    let mut fields = HashMap::new();
+    let mut chars = line.chars().peekable();
+    assert_eq!(chars.next(), Some('{'), "{line}");
+    loop {
+        let key = string(&mut chars);
+        assert_eq!(chars.next(), Some(':'), "{line}");
+        let value = if chars.peek() == Some(&'"') {
+            string(&mut chars)
+        } else {
+            let mut raw = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == '}' {
+                    break;
+                }
+                raw.push(c);
+                chars.next();
+            }
+            assert!(
+                raw == "null" || raw.parse::<f64>().is_ok(),
+                "{raw} in {line}"
+            );
+            raw
+        };
+        fields.insert(key, value);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            other => panic!("unexpected {other:?} in {line}"),
+        }
+    }
+    assert_eq!(chars.next(), None, "{line}");
+    fields
+}
+
+fn next_line(lines: &mpsc::Receiver<String>) -> HashMap<String, String> {
+    parse_json(&lines.recv_timeout(Duration::from_secs(5)).unwrap())
+}
+
+#[test]
+fn ids_go_from_request_to_response_to_log() {
+    let (address, running, lines) = start(ServerConfig::builder());
+
+    let (head, body) = get(address, "/id", "X-Request-Id: upstream-42\r\n");
+    assert_eq!(header(&head, "X-Request-Id"), Some("upstream-42"), "{head}");
+    assert_eq!(body, "upstream-42");
+    let line = next_line(&lines);
+    assert_eq!(line["request_id"], "upstream-42");
+    assert_eq!(line["level"], "info");
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], "/id");
+    assert_eq!(line["status"], "200");
+    assert_eq!(line["peer"], "127.0.0.1");
+    assert!(line["bytes"].parse::<u64>().unwrap() > 0);
+    assert!(line["duration_ms"].parse::<f64>().unwrap() >= 0.0);
+    assert!(line["ts"].ends_with('Z'), "{}", line["ts"]);
+
+    // Without one (or with one not fit to keep), the server makes one up.
+    let (head, body) = get(address, "/missing", "");
+    let id = header(&head, "X-Request-Id").unwrap().to_owned();
+    assert!(head.starts_with("HTTP/1.1 404 "), "{head}");
+    assert!(!body.is_empty());
+    let line = next_line(&lines);
+    assert_eq!(line["request_id"], id);
+    assert_eq!(line["status"], "404");
+
+    let long = "x".repeat(200);
+    let (head, _) = get(address, "/id", &format!("X-Request-Id: {long}\r\n"));
+    let id = header(&head, "X-Request-Id").unwrap();
+    assert_ne!(id, long);
+    assert_eq!(next_line(&lines)["request_id"], id);
+
+    // A malformed request has no ID, and nothing to name in the line.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"NONSENSE\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    drop(stream);
+    let line = next_line(&lines);
+    assert_eq!(line["status"], "400");
+    for field in ["request_id", "method", "path"] {
+        assert_eq!(line[field], "null", "{field}");
+    }
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn server_messages_are_json_too() {
+    // The messages go to stdout and stderr, so the server runs in a copy of
+    // this test binary whose output is captured here.
+    if std::env::var_os("CUSTOM_HTTP_JSON_MESSAGES").is_some() {
+        let mut router = Router::new();
+        router.wrap(RequestLog).get("/id", |_: &HttpRequest| {
+            HttpResponse::text(StatusCode::OK, "id")
+        });
+        let config = ServerConfig::builder()
+            .address("127.0.0.1:0")
+            .reactors(1)
+            .router(router)
+            .log_format(LogFormat::Json)
+            .build()
+            .unwrap();
+        let running = Server::start(config).unwrap();
+        get(running.local_addr().unwrap(), "/id", "");
+        running.shutdown();
+        running.join().unwrap();
+        return;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "server_messages_are_json_too", "--nocapture"])
+        .env("CUSTOM_HTTP_JSON_MESSAGES", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let mut messages = Vec::new();
+    for stream in [&output.stdout, &output.stderr] {
+        for line in String::from_utf8_lossy(stream).lines() {
+            // The test harness's own output goes around the messages,
+            // sometimes on the same line.
+            match line.find('{') {
+                Some(at) => messages.push(parse_json(&line[at..])),
+                None => assert!(
+                    !line.contains("/id") && !line.contains("Shutting"),
+                    "{line}"
+                ),
+            }
+        }
+    }
+    // Access lines, which have no `message`, are among them.
+    let logged = |start: &str| {
+        messages.iter().any(|message| {
+            message["level"] == "info"
+                && message
+                    .get("message")
+                    .is_some_and(|message| message.starts_with(start))
+        })
+    };
+    assert!(logged("127.0.0.1 GET /id 200 "), "{messages:?}");
+    assert!(logged("Shutting down: draining "), "{messages:?}");
+}
+
+#[test]
+fn incoming_ids_can_be_ignored() {
+    let builder = ServerConfig::builder().request_id(RequestIdConfig {
+        header: String::from("X-Trace"),
+        trust_incoming: false,
+    });
+    let (address, running, lines) = start(builder);
+
+    let (head, body) = get(address, "/id", "X-Trace: chosen-by-client\r\n");
+    let id = header(&head, "X-Trace").unwrap();
+    assert_ne!(id, "chosen-by-client");
+    assert_eq!(body, id);
+    assert_eq!(header(&head, "X-Request-Id"), None);
+    assert_eq!(next_line(&lines)["request_id"], id);
+
+    let (head, _) = get(address, "/id", "");
+    assert_ne!(header(&head, "X-Trace").unwrap(), id);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn log_format_and_request_id_settings_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("request-id-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "log_format = \"json\"\n[request_id]\nheader = \"X-Correlation-Id\"\ntrust_incoming = false\n",
+    )
+    .unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert!(config.access_log.is_some());
+    let request_id = config.request_id.unwrap();
+    assert_eq!(request_id.header, "X-Correlation-Id");
+    assert!(!request_id.trust_incoming);
+
+    std::fs::write(&path, "log_format = \"yaml\"\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,319 @@
+use custom_http::http::request::{
+    MAX_HEAD_SIZE, MAX_HEADER_FIELDS, ParseError, find_head_end, parse_request,
+    parse_request_limited,
+};
+use custom_http::{Server, ServerConfig};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+const VALID: &[u8] = b"POST /upload/a.txt?x=1 HTTP/1.1\r\nHost: example.com\r\n\
+    User-Agent: fuzz/1.0\r\nAccept: */*\r\nContent-Type: text/plain\r\n\
+    Content-Length: 5\r\n\r\nhello";
+
+/// A head with `count` header fields.
+fn head_with_fields(count: usize) -> String {
+    let mut head = String::from("GET / HTTP/1.1\r\n");
+    for i in 0..count {
+        head.push_str(&format!("X-Field-{i}: {i}\r\n"));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// A small xorshift generator, so every run mutates the same way.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Flips, inserts, deletes or overwrites a few bytes of `VALID`, favouring
+/// the bytes that matter to the syntax.
+fn mutate(rng: &mut Rng) -> Vec<u8> {
+    const INTERESTING: &[u8] = b"\r\n\0: \t\x7f\x80\xff\"(,;=";
+    let mut bytes = VALID.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(bytes.len());
+        let byte = if rng.below(2) == 0 {
+            INTERESTING[rng.below(INTERESTING.len())]
+        } else {
+            rng.next() as u8
+        };
+        match rng.below(4) {
+            0 => bytes[at] ^= 1 << rng.below(8),
+            1 => bytes.insert(at, byte),
+            2 => {
+                bytes.remove(at);
+            }
+            _ => bytes[at] = byte,
+        }
+    }
+    bytes
+}
+
+#[test]
+fn mutated_requests_never_panic() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut outcomes = [0; 4];
+    for _ in 0..20_000 {
+        let bytes = mutate(&mut rng);
+        let outcome = match parse_request_limited(&bytes, 4, MAX_HEAD_SIZE) {
+            Ok((request, head_len)) => {
+                assert!(head_len <= bytes.len());
+                for (name, value) in request.headers.iter() {
+                    assert!(!name.is_empty());
+                    assert!(!name.bytes().any(|b| b > 0x7f || b" \t:\r\n\0".contains(&b)));
+                    assert!(!value.bytes().any(|b| b"\r\n\0".contains(&b)));
+                }
+                assert!(!request.target.chars().any(char::is_control));
+                0
+            }
+            Err(ParseError::Malformed(_)) => 1,
+            Err(ParseError::TooManyHeaders) => 2,
+            // Only a mutation of the blank line can leave the head unfinished.
+            Err(ParseError::Incomplete) => 3,
+            Err(ParseError::TooLarge) => panic!("the body isn't read"),
+            Err(ParseError::HeadTooLarge) => panic!("the head is far below the limit"),
+        };
+        outcomes[outcome] += 1;
+    }
+    // Every kind of outcome came up.
+    assert!(outcomes.iter().all(|&n| n > 0), "{outcomes:?}");
+}
+
+#[test]
+fn bad_bytes_anywhere_in_the_head_are_malformed() {
+    for (head, reason) in [
+        ("GE\0T / HTTP/1.1\r\n\r\n", "invalid method"),
+        ("G(T / HTTP/1.1\r\n\r\n", "invalid method"),
+        ("GET /a\0b HTTP/1.1\r\n\r\n", "invalid request target"),
+        ("GET /a\rb HTTP/1.1\r\n\r\n", "invalid request target"),
+        ("GET /a\x7fb HTTP/1.1\r\n\r\n", "invalid request target"),
+        (
+            "GET / HTTP/1.1\r\nX-Fiéld: 1\r\n\r\n",
+            "invalid header name",
+        ),
+        ("GET / HTTP/1.1\r\nX-A\0: 1\r\n\r\n", "invalid header name"),
+        (
+            "GET / HTTP/1.1\r\nX-A: 1\nX-B: 2\r\n\r\n",
+            "invalid header value",
+        ),
+        (
+            "GET / HTTP/1.1\r\nX-A: 1\rX-B: 2\r\n\r\n",
+            "invalid header value",
+        ),
+        (
+            "GET / HTTP/1.1\r\n folded: 1\r\n\r\n",
+            "invalid header name",
+        ),
+    ] {
+        assert_eq!(
+            parse_request(head.as_bytes()).map(|_| ()),
+            Err(ParseError::Malformed(reason)),
+            "{head:?}"
+        );
+    }
+    // Non-ASCII values are still fine.
+    let head = "GET /caf%C3%A9 HTTP/1.1\r\nX-Name: café\r\n\r\n";
+    let (request, _) = parse_request(head.as_bytes()).unwrap();
+    assert_eq!(request.header("x-name"), Some("café"));
+}
+
+#[test]
+fn header_fields_are_limited() {
+    assert!(parse_request(head_with_fields(MAX_HEADER_FIELDS).as_bytes()).is_ok());
+    assert_eq!(
+        parse_request(head_with_fields(MAX_HEADER_FIELDS + 1).as_bytes()).map(|_| ()),
+        Err(ParseError::TooManyHeaders)
+    );
+    assert!(parse_request_limited(head_with_fields(3).as_bytes(), 3, MAX_HEAD_SIZE).is_ok());
+    assert_eq!(
+        parse_request_limited(head_with_fields(4).as_bytes(), 3, MAX_HEAD_SIZE).map(|_| ()),
+        Err(ParseError::TooManyHeaders)
+    );
+}
+
+#[test]
+fn head_size_is_limited() {
+    // 16 bytes of request line, 9 per header field and 2 for the blank line.
+    let head = "GET / HTTP/1.1\r\nX-A: 12\r\nX-B: 34\r\n\r\n";
+    assert_eq!(head.len(), 36);
+    assert!(parse_request_limited(head.as_bytes(), 10, 36).is_ok());
+    assert_eq!(
+        parse_request_limited(head.as_bytes(), 10, 35).map(|_| ()),
+        Err(ParseError::HeadTooLarge)
+    );
+    // An unfinished head is refused as soon as it can't fit.
+    assert_eq!(
+        parse_request_limited(&head.as_bytes()[..30], 10, 35).map(|_| ()),
+        Err(ParseError::Incomplete)
+    );
+    assert_eq!(
+        parse_request_limited(&head.as_bytes()[..36], 10, 30).map(|_| ()),
+        Err(ParseError::HeadTooLarge)
+    );
+    let long_line = format!("GET /{} HTTP/1.1", "a".repeat(MAX_HEAD_SIZE));
+    assert_eq!(
+        parse_request(long_line.as_bytes()).map(|_| ()),
+        Err(ParseError::Malformed("request line too long"))
+    );
+    let long_field = format!("GET / HTTP/1.1\r\nX-Big: {}", "a".repeat(MAX_HEAD_SIZE));
+    assert_eq!(
+        parse_request(long_field.as_bytes()).map(|_| ()),
+        Err(ParseError::HeadTooLarge)
+    );
+}
+
+#[test]
+fn head_ends_are_found_across_reads() {
+    let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r\nbody";
+    // However the head is split up, the end is found once it has arrived,
+    // searching on from where the last search stopped.
+    for step in 1..=head.len() {
+        let mut scanned = 0;
+        let mut end = Err(ParseError::Incomplete);
+        for len in (step..head.len()).step_by(step).chain([head.len()]) {
+            end = find_head_end(&head[..len], scanned, MAX_HEAD_SIZE);
+            if end != Err(ParseError::Incomplete) {
+                break;
+            }
+            scanned = len;
+        }
+        assert_eq!(end, Ok(27), "{step}");
+    }
+    assert_eq!(
+        find_head_end(b"GET / HTTP/1.1\r\n\r", 0, MAX_HEAD_SIZE),
+        Err(ParseError::Incomplete)
+    );
+}
+
+#[test]
+fn the_server_answers_400_or_431_and_closes() {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .max_header_fields(10)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    let exchange = |request: &[u8]| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // A valid request follows each bad one; it must never be answered.
+    let follow_up = "GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+    for (request, status) in [
+        (
+            format!("GET / HTTP/1.1\r\nX-A: a\rb\r\n\r\n{follow_up}"),
+            400,
+        ),
+        (
+            format!("GET / HTTP/1.1\r\nBad Name: 1\r\n\r\n{follow_up}"),
+            400,
+        ),
+        (format!("GET /\0 HTTP/1.1\r\n\r\n{follow_up}"), 400),
+        (format!("{}{follow_up}", head_with_fields(11)), 431),
+    ] {
+        let response = exchange(request.as_bytes());
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {status} ")),
+            "{response}"
+        );
+        assert!(response.contains("\r\nConnection: close\r\n"), "{response}");
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1, "{response}");
+    }
+    let response = exchange(head_with_fields(10).as_bytes());
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn unterminated_heads_are_cut_off() {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .max_head_size(1024)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    // The head never ends and the client never stops sending it; the
+    // server answers once it has more than it accepts, without waiting.
+    for (start, status) in [("GET / HTTP/1.1\r\nX-Filler: ", 431), ("GET /", 400)] {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(start.as_bytes()).unwrap();
+        for _ in 0..4 {
+            // Writes fail once the server has hung up.
+            let _ = stream.write_all(&[b'a'; 512]);
+        }
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {status} ")),
+            "{start}: {response}"
+        );
+        assert!(response.contains("\r\nConnection: close\r\n"), "{response}");
+        drop(stream);
+    }
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn max_header_fields_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("max-header-fields-{}.toml", std::process::id()));
+    std::fs::write(&path, "max_header_fields = 20\n").unwrap();
+    assert_eq!(
+        ServerConfig::from_toml_path(&path)
+            .unwrap()
+            .max_header_fields,
+        20
+    );
+    std::fs::write(&path, "max_header_fields = 0\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::write(&path, "max_head_size = 8192\n").unwrap();
+    assert_eq!(
+        ServerConfig::from_toml_path(&path).unwrap().max_head_size,
+        8192
+    );
+    std::fs::write(&path, "max_head_size = 0\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(ServerConfig::default().max_header_fields, MAX_HEADER_FIELDS);
+    assert_eq!(ServerConfig::default().max_head_size, MAX_HEAD_SIZE);
+    assert!(
+        ServerConfig::builder()
+            .max_header_fields(0)
+            .build()
+            .is_err()
+    );
+}
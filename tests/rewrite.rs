@@ -0,0 +1,139 @@
+mod common;
+
+use common::get;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::rewrite::{RewriteAction, RewriteRule};
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, ServerConfig};
+use std::fs;
+use std::path::PathBuf;
+
+const RULES: &str = "\
+[rewrites]
+\"/blog/*.html\" = \"301 /posts/$1/\"
+\"/about-us\" = \"308 /about\"
+\"/docs/*/*.htm\" = \"302 https://docs.example.com/$2/$1\"
+\"/img/*\" = \"rewrite /static/images/$1\"
+\"/pics/*\" = \"rewrite /img/$1\"
+\"/search/*\" = \"rewrite /find?q=$1\"
+\"/lookup\" = \"rewrite /find\"
+\"/loop/*\" = \"rewrite /loop/again/$1\"
+";
+
+/// A site with an image under `/static/images` and a `/find` route echoing
+/// its query, configured with `RULES`.
+fn config(name: &str) -> ServerConfig {
+    let root =
+        std::env::temp_dir().join(format!("custom_http-rewrite-{}-{name}", std::process::id()));
+    fs::create_dir_all(root.join("static/images")).unwrap();
+    fs::write(root.join("static/images/logo.png"), "png bytes").unwrap();
+    let rules = root.join("rules.toml");
+    fs::write(&rules, RULES).unwrap();
+
+    let mut config = ServerConfig::from_toml_path(&rules).unwrap();
+    let mut router = Router::new();
+    router.get("/find", |request: &HttpRequest| {
+        HttpResponse::text(StatusCode::OK, request.query.as_deref().unwrap_or("-"))
+    });
+    config.router = router.into();
+    config.document_root = PathBuf::from(&root);
+    config
+}
+
+fn assert_redirects(response: &str, status: u16, location: &str) {
+    assert!(
+        response.starts_with(&format!("HTTP/1.1 {status} ")),
+        "{response}"
+    );
+    assert!(
+        response.contains(&format!("\r\nLocation: {location}\r\n")),
+        "{response}"
+    );
+}
+
+fn assert_serves(response: &str, body: &str) {
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with(&format!("\r\n\r\n{body}")), "{response}");
+}
+
+#[test]
+fn redirects_keep_the_query_string() {
+    let config = config("redirects");
+    assert_redirects(
+        &get(&config, "/blog/old-post.html"),
+        301,
+        "/posts/old-post/",
+    );
+    assert_redirects(
+        &get(&config, "/blog/old-post.html?ref=feed&x=1"),
+        301,
+        "/posts/old-post/?ref=feed&x=1",
+    );
+    assert_redirects(&get(&config, "/about-us"), 308, "/about");
+    assert_redirects(
+        &get(&config, "/docs/v1/intro.htm"),
+        302,
+        "https://docs.example.com/intro/v1",
+    );
+    // An exact path is only that path.
+    assert!(get(&config, "/about-us/team").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn rewrites_serve_the_target_in_place() {
+    let config = config("rewrites");
+    assert_serves(&get(&config, "/img/logo.png"), "png bytes");
+    // Rewrites chain.
+    assert_serves(&get(&config, "/pics/logo.png"), "png bytes");
+    assert!(get(&config, "/img/missing.png").starts_with("HTTP/1.1 404 "));
+    // The query comes along, unless the target brings its own.
+    assert_serves(&get(&config, "/lookup?q=rust"), "q=rust");
+    assert_serves(&get(&config, "/search/rust?page=2"), "q=rust");
+}
+
+#[test]
+fn rewrite_loops_are_cut_short() {
+    let config = config("loop");
+    let response = get(&config, "/loop/x");
+    assert!(response.starts_with("HTTP/1.1 500 "), "{response}");
+}
+
+#[test]
+fn the_first_matching_rule_wins() {
+    let mut config = config("first");
+    config.rewrites.insert(
+        0,
+        RewriteRule::new(
+            "/img/*.png",
+            RewriteAction::Redirect(StatusCode::FOUND, String::from("/png/$1")),
+        )
+        .unwrap(),
+    );
+    assert_redirects(&get(&config, "/img/logo.png"), 302, "/png/logo");
+    assert!(get(&config, "/img/logo.gif").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn bad_rules_are_refused() {
+    for (pattern, action) in [
+        ("/a", "rewrite"),
+        ("/a", "rewrite b"),
+        ("/a", "200 /b"),
+        ("/a", "move /b"),
+        ("a", "301 /b"),
+        ("/a/*", "301 /b/$2"),
+    ] {
+        assert!(
+            RewriteRule::parse(pattern, action).is_err(),
+            "{pattern} = {action}"
+        );
+    }
+    assert_eq!(
+        RewriteRule::parse("/a/*", "307  /b/$1 "),
+        Ok(RewriteRule {
+            pattern: String::from("/a/*"),
+            action: RewriteAction::Redirect(StatusCode::TEMPORARY_REDIRECT, String::from("/b/$1")),
+        })
+    );
+}
@@ -0,0 +1,143 @@
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, Server, ServerConfig};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sends one request on a fresh connection and returns the whole response.
+fn exchange(address: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    let head = response.split("\r\n\r\n").next()?;
+    head.lines().find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+#[test]
+fn routes_match_fall_through_and_refuse_other_methods() {
+    let mut router = Router::new();
+    router
+        .get("/healthz", |_| HttpResponse::text(StatusCode::OK, "ok"))
+        .post("/echo", |request: &HttpRequest| {
+            HttpResponse::bytes(
+                StatusCode::OK,
+                HeaderValue::from_static("text/plain"),
+                request.body.clone(),
+            )
+        })
+        .get("/api/*", |request: &HttpRequest| {
+            HttpResponse::text(StatusCode::OK, request.path.clone())
+        });
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .router(router)
+        .build()
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    let running = server.spawn().unwrap();
+
+    let response = exchange(
+        address,
+        "GET /healthz HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+
+    let response = exchange(
+        address,
+        "POST /echo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    );
+    assert!(response.ends_with("\r\n\r\nhello"), "{response}");
+
+    let response = exchange(
+        address,
+        "GET /api/users/7 HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\n/api/users/7"), "{response}");
+
+    // No route for `/`: the static welcome page.
+    let response = exchange(address, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert_eq!(header(&response, "Content-Type"), Some("text/html"));
+
+    let response = exchange(
+        address,
+        "DELETE /healthz HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 405 "), "{response}");
+    assert_eq!(header(&response, "Allow"), Some("GET, HEAD"));
+
+    let response = exchange(address, "GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 405 "), "{response}");
+    assert_eq!(header(&response, "Allow"), Some("POST"));
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn slow_handlers_run_side_by_side_off_the_event_loop() {
+    let mut router = Router::new();
+    router.get("/slow", |_: &HttpRequest| {
+        thread::sleep(Duration::from_millis(500));
+        HttpResponse::text(StatusCode::OK, "slow")
+    });
+    router.get("/fast", |_: &HttpRequest| {
+        HttpResponse::text(StatusCode::OK, "fast")
+    });
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(4)
+        .router(router)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    let started = Instant::now();
+    let slow: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || {
+                exchange(address, "GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+            })
+        })
+        .collect();
+    // While every worker sleeps, new connections are still accepted and
+    // read. Their requests wait for a free worker, like any other.
+    thread::sleep(Duration::from_millis(100));
+    let connected = Instant::now();
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /fast HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nfast"), "{response}");
+    assert!(connected.elapsed() < Duration::from_millis(900));
+
+    for slow in slow {
+        let response = slow.join().unwrap();
+        assert!(response.ends_with("\r\n\r\nslow"), "{response}");
+    }
+    // Side by side: one after another would take two seconds.
+    assert!(started.elapsed() < Duration::from_millis(1500));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
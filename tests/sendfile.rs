@@ -0,0 +1,84 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{request, serve, site};
+use custom_http::ServerConfig;
+use custom_http::http::response::{Body, handle};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+/// A config sending files over 64 KiB with `sendfile`, serving an 8 MiB
+/// `big.bin` whose bytes all depend on their offset.
+fn config() -> (ServerConfig, Vec<u8>) {
+    let config = ServerConfig {
+        max_in_memory_file_size: 64 * 1024,
+        sendfile: true,
+        ..site("sendfile", &[])
+    };
+    let big: Vec<u8> = (0..8 * 1024 * 1024 + 17)
+        .map(|i: u32| (i % 253) as u8 ^ (i >> 16) as u8)
+        .collect();
+    fs::write(config.document_root.join("big.bin"), &big).unwrap();
+    (config, big)
+}
+
+/// Requests `/big.bin` and returns the body, reading `pace` bytes at a time
+/// with a pause in between, so the socket keeps filling up.
+fn fetch(config: ServerConfig, pace: Option<usize>) -> Vec<u8> {
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(b"GET /big.bin HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    match pace {
+        None => {
+            stream.read_to_end(&mut response).unwrap();
+        }
+        Some(pace) => {
+            let mut buf = vec![0u8; pace];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                response.extend_from_slice(&buf[..n]);
+                thread::sleep(Duration::from_micros(200));
+            }
+        }
+    }
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    response.split_off(at + 4)
+}
+
+#[test]
+fn large_files_become_file_bodies() {
+    let (config, big) = config();
+    let mut queue: Vec<Vec<u8>> = Vec::new();
+    let rest = handle(&request("/big.bin", &[]), &config).start_writing(&mut queue, false);
+    let Some(Body::File(file)) = rest else {
+        panic!("big.bin isn't a file body");
+    };
+    assert!(file.uses_sendfile());
+    assert_eq!(file.remaining(), big.len() as u64);
+}
+
+#[test]
+fn sendfile_delivers_every_byte() {
+    let (config, big) = config();
+    assert!(fetch(config, None) == big);
+}
+
+#[test]
+fn sendfile_resumes_where_a_full_socket_left_off() {
+    let (config, big) = config();
+    assert!(fetch(config, Some(64 * 1024)) == big);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{request, serve, site};
+use custom_http::ServerConfig;
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::response::{HttpResponse, handle, http_handler};
+use custom_http::http::status::StatusCode;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn the_body_is_moved_into_the_write_queue_not_copied() {
+    let body = vec![b'x'; 64 * 1024];
+    let at = body.as_ptr();
+    let response = HttpResponse::bytes(
+        StatusCode::OK,
+        HeaderValue::from_static("application/octet-stream"),
+        body,
+    );
+
+    let mut out: Vec<Vec<u8>> = Vec::new();
+    assert!(response.start_writing(&mut out, false).is_none());
+    assert_eq!(out.len(), 2);
+    assert!(out[0].starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(out[0].ends_with(b"\r\n\r\n"));
+    // Same allocation: the only copy of the body is the one made by the caller.
+    assert_eq!(out[1].as_ptr(), at);
+    assert_eq!(out[1].len(), 64 * 1024);
+}
+
+#[test]
+fn head_requests_queue_only_the_head() {
+    let response = HttpResponse::text(StatusCode::OK, "hello");
+    let mut out: Vec<Vec<u8>> = Vec::new();
+    assert!(response.start_writing(&mut out, true).is_none());
+    assert_eq!(out.len(), 1);
+    let head = String::from_utf8_lossy(&out[0]).into_owned();
+    // The framing still describes the body a `GET` would get.
+    assert!(head.contains("\r\nContent-Length: 5\r\n"), "{head}");
+}
+
+#[test]
+fn the_write_queue_and_the_serialized_response_agree() {
+    let config = site("serialization", &[("index.html", "<p>home</p>")]);
+    for target in ["/index.html", "/missing", "/.env"] {
+        let request = request(target, &[]);
+        let mut out: Vec<Vec<u8>> = Vec::new();
+        assert!(
+            handle(&request, &config)
+                .start_writing(&mut out, false)
+                .is_none()
+        );
+        // Only the `Date` header can differ between the two answers.
+        let strip = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .lines()
+                .filter(|line| !line.starts_with("Date: "))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        assert_eq!(
+            strip(&out.concat()),
+            strip(&http_handler(&request, &config)),
+            "{target}"
+        );
+    }
+}
+
+#[test]
+fn partial_writes_resume_where_they_stopped() {
+    let config = ServerConfig {
+        max_in_memory_file_size: 4 * 1024 * 1024,
+        ..site("partial-writes", &[("small.txt", "small")])
+    };
+    let big: Vec<u8> = (0..2 * 1024 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+    fs::write(config.document_root.join("big.bin"), &big).unwrap();
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    // Two responses, so the write queue holds head, body, head, body and a
+    // partial write can stop inside or between any of them.
+    stream
+        .write_all(
+            b"GET /big.bin HTTP/1.1\r\n\r\nGET /small.txt HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+
+    // A slow reader: the socket buffers fill and the server's writes come
+    // back short, or would block, many times over.
+    let mut response = Vec::new();
+    let mut piece = [0u8; 1000];
+    for reads in 1.. {
+        let read = stream.read(&mut piece).unwrap();
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&piece[..read]);
+        if reads % 16 == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&response[..at]);
+    assert!(
+        head.contains(&format!("\r\nContent-Length: {}\r\n", big.len())),
+        "{head}"
+    );
+    assert!(response[at..at + big.len()] == big[..]);
+    let second = String::from_utf8_lossy(&response[at + big.len()..]);
+    assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{second}");
+    assert!(second.ends_with("\r\n\r\nsmall"), "{second}");
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
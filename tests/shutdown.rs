@@ -0,0 +1,48 @@
+//! SIGTERM reaches every server in the process, so this test crate holds a
+//! single test.
+#![cfg(unix)]
+
+mod common;
+
+use common::{serve, site};
+use custom_http::ServerConfig;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn sigterm_lets_responses_in_flight_finish() {
+    let page = "x".repeat(16 * 1024 * 1024);
+    let running = serve(ServerConfig {
+        reactors: 1,
+        ..site("shutdown", &[("big.txt", &page)])
+    });
+    let address = running.local_addr().unwrap();
+
+    let mut busy = TcpStream::connect(address).unwrap();
+    busy.write_all(b"GET /big.txt HTTP/1.1\r\n\r\n").unwrap();
+    // Read a little, leaving most of the body unsent in the server's queue.
+    let mut start = [0u8; 4096];
+    busy.read_exact(&mut start).unwrap();
+    let mut idle = TcpStream::connect(address).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    // SAFETY: the reactor has replaced the default action of SIGTERM with its
+    // own handler, so this doesn't end the test process.
+    assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+
+    // Connections with nothing in flight are closed at once.
+    idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(idle.read(&mut [0u8; 1]).unwrap(), 0);
+
+    let mut response = start.to_vec();
+    busy.read_to_end(&mut response).unwrap();
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..at]);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(response[at + 4..] == *page.as_bytes());
+
+    drop((busy, idle));
+    running.join().unwrap();
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::get_with;
+use custom_http::{ServerConfig, SpaFallback};
+use std::fs;
+use std::path::PathBuf;
+
+const BROWSER: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+/// Creates a fresh document root with a site at `/` and an app under `/app`.
+fn root(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("custom_http-spa-{}-{name}", std::process::id()));
+    for (path, contents) in [
+        ("index.html", "site"),
+        ("welcome.html", "welcome"),
+        ("app/index.html", "app shell"),
+        ("app/about.html", "about page"),
+        ("app/main.js", "console.log(1)"),
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+fn config(name: &str, spa: SpaFallback) -> ServerConfig {
+    ServerConfig::builder()
+        .document_root(root(name))
+        .spa_fallback(spa)
+        .build()
+        .unwrap()
+}
+
+fn app() -> SpaFallback {
+    SpaFallback {
+        prefix: String::from("/app"),
+        index: String::from("/app/index.html"),
+    }
+}
+
+fn assert_serves(response: &str, body: &str) {
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with(&format!("\r\n\r\n{body}")), "{response}");
+}
+
+fn assert_not_found(response: &str) {
+    assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+}
+
+#[test]
+fn deep_links_get_the_index_page() {
+    let config = config("deep", app());
+    for path in ["/app/orders/42", "/app/settings/", "/app/v1.2/users"] {
+        let response = get_with(&config, path, &[("Accept", BROWSER)]);
+        assert_serves(&response, "app shell");
+        assert!(response.contains("Vary: Accept\r\n"), "{path}: {response}");
+    }
+    // Outside the prefix, misses are misses.
+    assert_not_found(&get_with(&config, "/elsewhere", &[("Accept", BROWSER)]));
+    assert_not_found(&get_with(&config, "/apps/x", &[("Accept", BROWSER)]));
+}
+
+#[test]
+fn missing_assets_still_get_404() {
+    let config = config("assets", app());
+    assert_not_found(&get_with(
+        &config,
+        "/app/missing.js",
+        &[("Accept", BROWSER)],
+    ));
+    assert_not_found(&get_with(
+        &config,
+        "/app/img/logo.png",
+        &[("Accept", BROWSER)],
+    ));
+    assert_serves(
+        &get_with(&config, "/app/main.js", &[("Accept", BROWSER)]),
+        "console.log(1)",
+    );
+}
+
+#[test]
+fn clients_not_asking_for_html_get_404() {
+    let config = config("api", app());
+    for accept in [
+        Some("application/json"),
+        Some("*/*"),
+        Some("application/json, text/html;q=0.5"),
+        Some("text/html;q=0"),
+        None,
+    ] {
+        let headers: Vec<_> = accept
+            .map(|accept| ("Accept", accept))
+            .into_iter()
+            .collect();
+        let response = get_with(&config, "/app/api/orders", &headers);
+        assert_not_found(&response);
+        assert!(
+            response.contains("Vary: Accept\r\n"),
+            "{accept:?}: {response}"
+        );
+    }
+}
+
+#[test]
+fn existing_clean_url_pages_win_over_the_fallback() {
+    let clean = config("clean", app());
+    assert_serves(
+        &get_with(&clean, "/app/about", &[("Accept", BROWSER)]),
+        "about page",
+    );
+
+    let mut unclean = config("unclean", app());
+    unclean.clean_urls = false;
+    assert_serves(
+        &get_with(&unclean, "/app/about", &[("Accept", BROWSER)]),
+        "app shell",
+    );
+}
+
+#[test]
+fn the_default_covers_the_whole_site() {
+    let config = config("whole", SpaFallback::default());
+    assert_serves(
+        &get_with(&config, "/some/route", &[("Accept", BROWSER)]),
+        "site",
+    );
+    assert_serves(&get_with(&config, "/", &[("Accept", BROWSER)]), "welcome");
+}
+
+#[test]
+fn spa_fallback_table_in_a_config_file() {
+    let path = std::env::temp_dir().join(format!("spa-{}.toml", std::process::id()));
+    fs::write(&path, "[spa_fallback]\nprefix = \"/app\"\n").unwrap();
+    let config = ServerConfig::from_toml_path(&path).unwrap();
+    assert_eq!(
+        config.spa_fallback,
+        Some(SpaFallback {
+            prefix: String::from("/app"),
+            index: String::from("/index.html"),
+        })
+    );
+    fs::write(&path, "[spa_fallback]\nprefix = \"app\"\n").unwrap();
+    assert!(ServerConfig::from_toml_path(&path).is_err());
+    fs::write(&path, "[spa_fallback]\nenabled = false\n").unwrap();
+    assert_eq!(
+        ServerConfig::from_toml_path(&path).unwrap().spa_fallback,
+        None
+    );
+    fs::remove_file(&path).unwrap();
+}
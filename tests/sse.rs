@@ -0,0 +1,184 @@
+use custom_http::http::request::HttpRequest;
+use custom_http::http::sse::{self, SseConfig, SseEvent};
+use custom_http::{Router, Server, ServerConfig, ServerHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn start(router: Router) -> (SocketAddr, ServerHandle) {
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .router(router)
+        .build()
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    (address, server.spawn().unwrap())
+}
+
+/// Serves `/events`: three events sent from another thread, a little apart,
+/// then the end of the stream.
+fn three_events() -> Router {
+    let mut router = Router::new();
+    router.get("/events", |request: &HttpRequest| {
+        let (response, events) = sse::event_stream(request, &SseConfig::default());
+        thread::spawn(move || {
+            events.send(&SseEvent::data("one")).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            events
+                .send(&SseEvent::data("two\nlines").event("update").id("2"))
+                .unwrap();
+            thread::sleep(Duration::from_millis(50));
+            events.send(&SseEvent::data("three")).unwrap();
+        });
+        response
+    });
+    router
+}
+
+const EVENTS: &str = "data: one\n\nevent: update\nid: 2\ndata: two\ndata: lines\n\ndata: three\n\n";
+
+/// Decodes a chunked body, checking it ends with the last chunk.
+fn dechunk(mut body: &str) -> String {
+    let mut out = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        if size == 0 {
+            assert_eq!(rest, "\r\n");
+            return out;
+        }
+        out.push_str(&rest[..size]);
+        body = rest[size..].strip_prefix("\r\n").unwrap();
+    }
+}
+
+#[test]
+fn three_events_reach_a_raw_socket_client() {
+    let (address, running) = start(three_events());
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(
+        head.contains("Content-Type: text/event-stream\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{head}");
+    assert!(head.contains("Cache-Control: no-cache\r\n"), "{head}");
+    assert!(!head.contains("Content-Length"), "{head}");
+    assert_eq!(dechunk(body), EVENTS);
+
+    // HTTP/1.0 clients get the events unframed, ended by the close.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(!head.contains("Transfer-Encoding"), "{head}");
+    assert!(!head.contains("keep-alive"), "{head}");
+    assert_eq!(body, EVENTS);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn quiet_streams_get_keep_alives_and_notice_the_client_leaving() {
+    let (closed_tx, closed_rx) = mpsc::channel();
+    let mut router = Router::new();
+    router.get("/events", move |request: &HttpRequest| {
+        let config = SseConfig {
+            keep_alive: Duration::from_millis(500),
+            ..SseConfig::default()
+        };
+        let (response, events) = sse::event_stream(request, &config);
+        let closed_tx = closed_tx.clone();
+        thread::spawn(move || {
+            while !events.is_closed() {
+                thread::sleep(Duration::from_millis(20));
+            }
+            closed_tx.send(()).unwrap();
+        });
+        response
+    });
+    let (address, running) = start(router);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while !String::from_utf8_lossy(&received).contains(": keep-alive\n\n") {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "the stream ended");
+        received.extend_from_slice(&buf[..n]);
+    }
+
+    drop(stream);
+    closed_rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("the sender never saw the client leave");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn a_client_that_stops_reading_is_dropped() {
+    let (result_tx, result_rx) = mpsc::channel();
+    let mut router = Router::new();
+    router.get("/events", move |request: &HttpRequest| {
+        let config = SseConfig {
+            max_buffered: 4096,
+            ..SseConfig::default()
+        };
+        let (response, events) = sse::event_stream(request, &config);
+        let result_tx = result_tx.clone();
+        thread::spawn(move || {
+            let event = SseEvent::data("x".repeat(1000));
+            let started = Instant::now();
+            let result = loop {
+                if let Err(e) = events.send(&event) {
+                    break Ok(e.kind());
+                }
+                if started.elapsed() > Duration::from_secs(20) {
+                    break Err(());
+                }
+            };
+            result_tx.send(result).unwrap();
+        });
+        response
+    });
+    let (address, running) = start(router);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+    // Never read: the socket buffers fill, then the server's queue, then
+    // the stream's buffer.
+    let result = result_rx.recv_timeout(Duration::from_secs(30)).unwrap();
+    assert_eq!(result, Ok(std::io::ErrorKind::BrokenPipe));
+
+    // The connection was closed: what's left to read ends without the last chunk.
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest);
+    assert!(!rest.ends_with(b"0\r\n\r\n"));
+
+    running.shutdown();
+    running.join().unwrap();
+}
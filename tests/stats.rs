@@ -0,0 +1,95 @@
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use custom_http::http::status::StatusCode;
+use custom_http::stats::{ServerStats, StatsSnapshot};
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn counters_follow_a_known_sequence_of_requests() {
+    let config = ServerConfig {
+        reactors: 1,
+        ..site("stats", &[("page.html", "page")])
+    };
+    let stats = config.stats.clone();
+    let running = serve(config);
+    let address = running.local_addr().unwrap();
+    let requests: [&[u8]; 3] = [
+        b"GET /page.html HTTP/1.1\r\n\r\n",
+        b"GET /missing HTTP/1.1\r\n\r\n",
+        b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n",
+    ];
+
+    // Two requests on one connection, the third on another.
+    let mut written = 0;
+    let mut stream = TcpStream::connect(address).unwrap();
+    for request in &requests[..2] {
+        stream.write_all(request).unwrap();
+        written += read_response(&mut stream).len();
+    }
+    drop(stream);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(requests[2]).unwrap();
+    written += read_response(&mut stream).len();
+    drop(stream);
+
+    let started = Instant::now();
+    while stats.connections_active() > 0 && started.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+        stats.snapshot(),
+        StatsSnapshot {
+            connections_accepted: 2,
+            connections_active: 0,
+            requests_served: 3,
+            responses_2xx: 2,
+            responses_4xx: 1,
+            bytes_read: requests.iter().map(|r| r.len() as u64).sum(),
+            bytes_written: written as u64,
+            ..StatsSnapshot::default()
+        }
+    );
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn responses_are_counted_by_status_class() {
+    let stats = ServerStats::default();
+    for status in [
+        StatusCode::SWITCHING_PROTOCOLS,
+        StatusCode::OK,
+        StatusCode::NO_CONTENT,
+        StatusCode::NOT_MODIFIED,
+        StatusCode::NOT_FOUND,
+        StatusCode::SERVICE_UNAVAILABLE,
+    ] {
+        stats.record_response(status);
+    }
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.requests_served, 6);
+    assert_eq!(
+        [
+            snapshot.responses_1xx,
+            snapshot.responses_2xx,
+            snapshot.responses_3xx,
+            snapshot.responses_4xx,
+            snapshot.responses_5xx,
+        ],
+        [1, 2, 1, 1, 1]
+    );
+
+    stats.connection_opened();
+    stats.connection_opened();
+    stats.connection_closed();
+    stats.connection_refused();
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.connections_accepted, 3);
+    assert_eq!(snapshot.connections_active, 1);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{get, site};
+use custom_http::http::status::StatusCode;
+
+#[test]
+fn status_lines_use_the_canonical_reason_phrases() {
+    for (status, line) in [
+        (StatusCode::OK, "HTTP/1.1 200 OK"),
+        (
+            StatusCode::MOVED_PERMANENTLY,
+            "HTTP/1.1 301 Moved Permanently",
+        ),
+        (StatusCode::NOT_MODIFIED, "HTTP/1.1 304 Not Modified"),
+        (StatusCode::BAD_REQUEST, "HTTP/1.1 400 Bad Request"),
+        (StatusCode::FORBIDDEN, "HTTP/1.1 403 Forbidden"),
+        (StatusCode::NOT_FOUND, "HTTP/1.1 404 Not Found"),
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "HTTP/1.1 405 Method Not Allowed",
+        ),
+        (
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            "HTTP/1.1 431 Request Header Fields Too Large",
+        ),
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "HTTP/1.1 500 Internal Server Error",
+        ),
+        (StatusCode::NOT_IMPLEMENTED, "HTTP/1.1 501 Not Implemented"),
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "HTTP/1.1 503 Service Unavailable",
+        ),
+    ] {
+        assert_eq!(status.status_line(), line);
+    }
+    // Unregistered codes keep the space before their (empty) phrase.
+    assert_eq!(
+        StatusCode::from_u16(299).unwrap().status_line(),
+        "HTTP/1.1 299 "
+    );
+    assert_eq!(StatusCode::from_u16(99), None);
+    assert_eq!(StatusCode::from_u16(600), None);
+}
+
+#[test]
+fn serialized_responses_start_with_the_exact_status_line() {
+    let config = site(
+        "status-lines",
+        &[("index.html", "home"), (".env", "SECRET=1")],
+    );
+    for (target, line) in [
+        ("/index.html", "HTTP/1.1 200 OK\r\n"),
+        ("/missing.html", "HTTP/1.1 404 Not Found\r\n"),
+        ("/.env", "HTTP/1.1 403 Forbidden\r\n"),
+        ("/../secret", "HTTP/1.1 403 Forbidden\r\n"),
+    ] {
+        let response = get(&config, target);
+        assert!(response.starts_with(line), "{target}: {response}");
+    }
+}
@@ -0,0 +1,251 @@
+mod common;
+
+use common::{request, serve, site};
+use custom_http::http::body::{BodySource, StreamBody};
+use custom_http::http::headers::HeaderValue;
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::{Body, HttpResponse, handle};
+use custom_http::http::status::StatusCode;
+use custom_http::io::cache::FileCache;
+use custom_http::{Router, ServerConfig};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHUNK: usize = 16 * 1024;
+
+/// A config streaming anything over 64 KiB in 16 KiB chunks, serving
+/// `big.bin` (1 MiB plus a bit, so the last chunk is short) and `small.txt`.
+fn config() -> (ServerConfig, Vec<u8>) {
+    let config = ServerConfig {
+        max_in_memory_file_size: 64 * 1024,
+        stream_chunk_size: CHUNK,
+        sendfile: false,
+        ..site("streaming", &[("small.txt", "small")])
+    };
+    let big: Vec<u8> = (0..1024 * 1024 + 123).map(|i| (i % 251) as u8).collect();
+    fs::write(config.document_root.join("big.bin"), &big).unwrap();
+    (config, big)
+}
+
+#[test]
+fn large_files_leave_only_the_head_in_the_write_queue() {
+    let (config, big) = config();
+    let mut queue: Vec<Vec<u8>> = Vec::new();
+    let rest = handle(&request("/big.bin", &[]), &config).start_writing(&mut queue, false);
+
+    // The length comes from the file's metadata, not from reading it.
+    assert_eq!(queue.len(), 1);
+    let head = String::from_utf8_lossy(&queue[0]).into_owned();
+    assert!(head.starts_with("HTTP/1.1 200 "), "{head}");
+    assert!(
+        head.contains(&format!("\r\nContent-Length: {}\r\n", big.len())),
+        "{head}"
+    );
+
+    let Some(Body::Stream(mut stream)) = rest else {
+        panic!("big.bin wasn't streamed");
+    };
+    let mut body = Vec::new();
+    while let Some(piece) = stream.next_wire_chunk().unwrap() {
+        assert!(piece.len() <= CHUNK, "a {} byte piece", piece.len());
+        body.extend(piece);
+    }
+    assert!(body == big);
+}
+
+#[test]
+fn small_files_keep_the_in_memory_path() {
+    let (config, _) = config();
+    let mut queue: Vec<Vec<u8>> = Vec::new();
+    let rest = handle(&request("/small.txt", &[]), &config).start_writing(&mut queue, false);
+    assert!(rest.is_none());
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue[1], b"small");
+}
+
+#[test]
+fn streamed_files_arrive_intact() {
+    let (config, big) = config();
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(b"GET /big.bin HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    assert!(response[at + 4..] == big[..]);
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn the_in_memory_limit_decides_the_path_and_what_is_cached() {
+    let cache = Arc::new(FileCache::new());
+    let limit = 4096;
+    let config = ServerConfig {
+        max_in_memory_file_size: limit,
+        file_cache: Some(Arc::clone(&cache)),
+        ..site("memory-limit", &[])
+    };
+    for (name, size) in [("at.bin", limit), ("over.bin", limit + 1)] {
+        fs::write(config.document_root.join(name), vec![b'x'; size as usize]).unwrap();
+    }
+
+    let mut queue: Vec<Vec<u8>> = Vec::new();
+    let at = handle(&request("/at.bin", &[]), &config).start_writing(&mut queue, false);
+    assert!(at.is_none());
+    assert_eq!(queue[1].len(), 4096);
+    assert_eq!(cache.len(), 1);
+
+    let over = handle(&request("/over.bin", &[]), &config).start_writing(&mut queue, false);
+    assert!(matches!(over, Some(Body::File(_) | Body::Stream(_))));
+    assert_eq!(cache.len(), 1);
+}
+
+/// An endless body, counting the bytes handed out.
+struct Endless {
+    pulled: Arc<AtomicUsize>,
+}
+
+impl BodySource for Endless {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.pulled.fetch_add(CHUNK, Ordering::Relaxed);
+        Ok(Some(vec![b'x'; CHUNK]))
+    }
+}
+
+#[test]
+fn slow_readers_hold_back_the_body_source() {
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&pulled);
+    let mut router = Router::new();
+    router.get("/endless", move |_: &HttpRequest| {
+        let source = Endless {
+            pulled: Arc::clone(&counter),
+        };
+        HttpResponse::stream(
+            StatusCode::OK,
+            HeaderValue::from_static("text/plain"),
+            StreamBody::chunked(source),
+        )
+    });
+    let config = ServerConfig {
+        reactors: 1,
+        router: Arc::new(router),
+        ..site("backpressure", &[])
+    };
+    let stats = Arc::clone(&config.stats);
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream.write_all(b"GET /endless HTTP/1.1\r\n\r\n").unwrap();
+
+    // A trickle of reads, then none: once the socket buffers and the write
+    // queue are full, nothing more is pulled from the source.
+    let mut piece = [0u8; 1024];
+    for _ in 0..5 {
+        stream.read_exact(&mut piece).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    thread::sleep(Duration::from_millis(500));
+    let stalled = pulled.load(Ordering::Relaxed);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(pulled.load(Ordering::Relaxed), stalled);
+    // Kernel buffers plus a write queue of at most 256 KiB, not the lot.
+    assert!(stalled < 16 * 1024 * 1024, "{stalled} bytes pulled");
+
+    // Reading again lets the source go on.
+    let mut drained = vec![0u8; stalled];
+    stream.read_exact(&mut drained).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert!(pulled.load(Ordering::Relaxed) > stalled);
+
+    drop(stream);
+    let started = Instant::now();
+    while stats.connections_active() > 0 && started.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    running.shutdown();
+    running.join().unwrap();
+}
+
+/// `total` bytes of a repeating pattern, handed out in `CHUNK` pieces.
+struct Pattern {
+    sent: usize,
+    total: usize,
+}
+
+impl BodySource for Pattern {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.sent == self.total {
+            return Ok(None);
+        }
+        let end = (self.sent + CHUNK).min(self.total);
+        let piece = (self.sent..end).map(|i| (i % 251) as u8).collect();
+        self.sent = end;
+        Ok(Some(piece))
+    }
+}
+
+#[test]
+fn close_delimited_bodies_reach_slow_readers_whole() {
+    const TOTAL: usize = 4 * 1024 * 1024;
+    let mut router = Router::new();
+    router.get("/tail", |_: &HttpRequest| {
+        let source = Pattern {
+            sent: 0,
+            total: TOTAL,
+        };
+        HttpResponse::stream(
+            StatusCode::OK,
+            HeaderValue::from_static("application/octet-stream"),
+            StreamBody::until_close(source),
+        )
+    });
+    let config = ServerConfig {
+        reactors: 1,
+        router: Arc::new(router),
+        ..site("close-delimited", &[])
+    };
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream.write_all(b"GET /tail HTTP/1.1\r\n\r\n").unwrap();
+
+    let mut response = Vec::new();
+    let mut piece = [0u8; 4096];
+    for reads in 1.. {
+        // Ends with an orderly EOF, never a reset, even though the server
+        // closes with input of ours it never read.
+        let read = stream.read(&mut piece).unwrap();
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&piece[..read]);
+        if reads == 10 {
+            stream
+                .write_all(b"more bytes the server won't read")
+                .unwrap();
+        }
+        if reads % 32 == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    let at = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..at]);
+    assert!(head.contains("\r\nConnection: close"), "{head}");
+    assert!(!head.contains("Content-Length"), "{head}");
+    let body = &response[at + 4..];
+    assert_eq!(body.len(), TOTAL);
+    assert!(body.iter().enumerate().all(|(i, b)| *b == (i % 251) as u8));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{get, site};
+use custom_http::ServerConfig;
+use std::os::unix::fs::symlink;
+
+/// A root with links to a file and a directory inside it, and to a secret
+/// file and directory outside it.
+fn linked() -> ServerConfig {
+    let outside = site(
+        "symlinks-outside",
+        &[("secret.txt", "SECRET"), ("assets/app.js", "app")],
+    );
+    let mut config = site(
+        "symlinks",
+        &[("real/page.html", "real"), ("docs/index.html", "docs")],
+    );
+    let root = config.document_root.clone();
+    symlink(root.join("real/page.html"), root.join("alias.html")).unwrap();
+    symlink(root.join("docs"), root.join("manual")).unwrap();
+    symlink(
+        outside.document_root.join("secret.txt"),
+        root.join("secret.txt"),
+    )
+    .unwrap();
+    symlink(outside.document_root.join("assets"), root.join("assets")).unwrap();
+    symlink(root.join("gone.html"), root.join("dangling.html")).unwrap();
+    config.resolve_document_root().unwrap();
+    config
+}
+
+fn assert_serves(config: &ServerConfig, target: &str, body: &str) {
+    let response = get(config, target);
+    assert!(
+        response.starts_with("HTTP/1.1 200 "),
+        "{target}: {response}"
+    );
+    assert!(response.ends_with(body), "{target}: {response}");
+}
+
+fn assert_status(config: &ServerConfig, target: &str, status: u16) {
+    let response = get(config, target);
+    assert!(
+        response.starts_with(&format!("HTTP/1.1 {status} ")),
+        "{target}: {response}"
+    );
+    assert!(!response.contains("SECRET"), "{target}: {response}");
+}
+
+#[test]
+fn links_within_the_root_are_served() {
+    let config = linked();
+    assert_serves(&config, "/alias.html", "real");
+    assert_serves(&config, "/manual/", "docs");
+    assert_serves(&config, "/manual/index.html", "docs");
+}
+
+#[test]
+fn links_out_of_the_root_are_forbidden_by_default() {
+    let config = linked();
+    assert_status(&config, "/secret.txt", 403);
+    assert_status(&config, "/assets/app.js", 403);
+    // Missing files below an escaping link too; missing files elsewhere are 404.
+    assert_status(&config, "/assets/missing.js", 403);
+    assert_status(&config, "/dangling.html", 404);
+    assert_status(&config, "/missing.html", 404);
+}
+
+#[test]
+fn following_symlinks_permits_links_out_of_the_root() {
+    let config = ServerConfig {
+        follow_symlinks: true,
+        ..linked()
+    };
+    assert_serves(&config, "/assets/app.js", "app");
+    assert_serves(&config, "/secret.txt", "SECRET");
+    assert_serves(&config, "/alias.html", "real");
+    assert_status(&config, "/assets/missing.js", 404);
+}
@@ -0,0 +1,194 @@
+use custom_http::ServerConfig;
+use custom_http::http::request::{HttpRequest, parse_request};
+use custom_http::http::response::http_handler;
+use custom_http::http::template::{Context, TemplateConfig, render};
+use std::collections::HashMap;
+use std::fs;
+
+fn entry(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn context() -> Context {
+    let mut context = Context::new();
+    context
+        .set("title", "Uploads & more")
+        .set("markup", "<em>hi</em>")
+        .set("name", "outer")
+        .list(
+            "files",
+            vec![
+                entry(&[("name", "a.txt"), ("size", "12")]),
+                entry(&[("name", "<b>.txt"), ("size", "0")]),
+            ],
+        )
+        .list("tags", vec![entry(&[("tag", "x")]), entry(&[("tag", "y")])]);
+    context
+}
+
+#[test]
+fn variables_are_escaped_unless_triple_braced() {
+    let context = context();
+    assert_eq!(
+        render("<h1>{{title}}</h1>", &context).unwrap(),
+        "<h1>Uploads &amp; more</h1>"
+    );
+    assert_eq!(
+        render("{{markup}}|{{{markup}}}", &context).unwrap(),
+        "&lt;em&gt;hi&lt;/em&gt;|<em>hi</em>"
+    );
+    // Whitespace inside the braces doesn't matter.
+    assert_eq!(
+        render("{{ title }}", &context).unwrap(),
+        "Uploads &amp; more"
+    );
+    assert_eq!(render("{{{ markup }}}", &context).unwrap(), "<em>hi</em>");
+}
+
+#[test]
+fn text_without_tags_is_copied() {
+    let context = Context::new();
+    for text in ["", "plain", "a } b { c", "{ {x} }", "é ✓\n"] {
+        assert_eq!(render(text, &context).unwrap(), text);
+    }
+}
+
+#[test]
+fn unknown_variables_and_lists_render_empty() {
+    let context = context();
+    assert_eq!(
+        render("[{{missing}}][{{{missing}}}]", &context).unwrap(),
+        "[][]"
+    );
+    assert_eq!(
+        render("[{{#each missing}}x{{/each}}]", &context).unwrap(),
+        "[]"
+    );
+    assert_eq!(
+        render("[{{#each files}}{{nope}}{{/each}}]", &context).unwrap(),
+        "[]"
+    );
+}
+
+#[test]
+fn each_repeats_its_body_per_entry() {
+    let context = context();
+    assert_eq!(
+        render(
+            "<ul>{{#each files}}<li>{{name}} ({{size}})</li>{{/each}}</ul>",
+            &context
+        )
+        .unwrap(),
+        "<ul><li>a.txt (12)</li><li>&lt;b&gt;.txt (0)</li></ul>"
+    );
+    // Entries hide outer variables of the same name, but not the others.
+    assert_eq!(
+        render(
+            "{{name}}:{{#each files}}{{name}}/{{title}};{{/each}}{{name}}",
+            &context
+        )
+        .unwrap(),
+        "outer:a.txt/Uploads &amp; more;&lt;b&gt;.txt/Uploads &amp; more;outer"
+    );
+    let mut empty = context.clone();
+    empty.list("files", Vec::new());
+    assert_eq!(render("[{{#each files}}x{{/each}}]", &empty).unwrap(), "[]");
+}
+
+#[test]
+fn each_blocks_nest() {
+    let context = context();
+    assert_eq!(
+        render(
+            "{{#each files}}{{name}}[{{#each tags}}{{name}}-{{tag}} {{/each}}]{{/each}}",
+            &context
+        )
+        .unwrap(),
+        "a.txt[a.txt-x a.txt-y ]&lt;b&gt;.txt[&lt;b&gt;.txt-x &lt;b&gt;.txt-y ]"
+    );
+}
+
+#[test]
+fn malformed_templates_are_errors() {
+    let context = context();
+    for (template, reason) in [
+        (
+            "{{#each files}}x",
+            "line 1: {{#each files}} is never closed",
+        ),
+        (
+            "a\n{{#each files}}{{#each tags}}{{/each}}\n",
+            "line 2: {{#each files}} is never closed",
+        ),
+        ("x{{/each}}", "line 1: {{/each}} without {{#each}}"),
+        ("\n\n{{title", "line 3: {{ is never closed"),
+        ("{{{markup}}", "line 1: {{{ is never closed"),
+        (
+            "{{#if title}}{{/if}}",
+            "line 1: unknown block {{#if title}}",
+        ),
+        ("{{#each}}{{/each}}", "line 1: unknown block {{#each}}"),
+        ("{{/if}}", "line 1: unknown block end {{/if}}"),
+        ("{{{#each files}}}", "line 1: blocks take two braces"),
+        ("{{ }}", "line 1: empty tag"),
+    ] {
+        assert_eq!(
+            render(template, &context),
+            Err(String::from(reason)),
+            "{template:?}"
+        );
+    }
+}
+
+#[test]
+fn template_files_are_rendered_with_the_provider_context() {
+    let root = std::env::temp_dir().join(format!("custom_http-template-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(
+        root.join("status.tpl"),
+        "<p>{{path}} for {{agent}}</p>{{#each checks}}<i>{{check}}</i>{{/each}}",
+    )
+    .unwrap();
+    fs::write(root.join("broken.TPL"), "{{#each checks}}").unwrap();
+    fs::write(root.join("plain.html"), "{{path}}").unwrap();
+
+    let templates = TemplateConfig::new(|request: &HttpRequest| {
+        let mut context = Context::new();
+        context
+            .set("path", request.path.clone())
+            .set("agent", request.header("User-Agent").unwrap_or("-"))
+            .list(
+                "checks",
+                vec![entry(&[("check", "disk")]), entry(&[("check", "db")])],
+            );
+        context
+    });
+    let config = ServerConfig::builder()
+        .document_root(&root)
+        .templates(templates)
+        .build()
+        .unwrap();
+    let get = |path: &str| {
+        let head = format!("GET {path} HTTP/1.1\r\nUser-Agent: <probe>\r\n\r\n");
+        let request = parse_request(head.as_bytes()).unwrap().0;
+        String::from_utf8_lossy(&http_handler(&request, &config)).into_owned()
+    };
+
+    let response = get("/status.tpl");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(
+        response.contains("Content-Type: text/html; charset=utf-8\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.ends_with("\r\n\r\n<p>/status.tpl for &lt;probe&gt;</p><i>disk</i><i>db</i>"),
+        "{response}"
+    );
+    assert!(get("/broken.TPL").starts_with("HTTP/1.1 500 "));
+    // Other files are served as they are.
+    assert!(get("/plain.html").ends_with("\r\n\r\n{{path}}"));
+    assert!(get("/missing.tpl").starts_with("HTTP/1.1 404 "));
+}
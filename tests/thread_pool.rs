@@ -0,0 +1,309 @@
+use custom_http::thread_pool::{
+    CancellationToken, DrainPolicy, JobPanicked, PoolBuildError, PoolFull, Priority,
+    ShutdownReport, ThreadPool,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Polls `condition` until it holds, failing the test after five seconds.
+fn wait_until(what: &str, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// A job that holds its worker until the returned sender is sent to or
+/// dropped, and a receiver that hears when it has started.
+fn holder() -> (
+    impl FnOnce() + Send + 'static,
+    mpsc::Receiver<()>,
+    mpsc::Sender<()>,
+) {
+    let (started, has_started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let job = move || {
+        started.send(()).unwrap();
+        let _ = released.recv();
+    };
+    (job, has_started, release)
+}
+
+/// Occupies one worker of `pool` until the returned sender is dropped.
+fn hold(pool: &ThreadPool) -> mpsc::Sender<()> {
+    let (job, started, release) = holder();
+    pool.execute(job);
+    started.recv().unwrap();
+    release
+}
+
+#[test]
+fn panicking_jobs_leave_the_pool_its_workers() {
+    let pool = ThreadPool::new(2);
+    for _ in 0..3 {
+        pool.execute(|| panic!("job failed"));
+    }
+    wait_until("the panics", || pool.panicked_jobs() == 3);
+    assert_eq!(pool.current_size(), 2);
+
+    // Both workers still take jobs.
+    let (done, finished) = mpsc::channel();
+    for _ in 0..4 {
+        let done = done.clone();
+        pool.execute(move || done.send(()).unwrap());
+    }
+    for _ in 0..4 {
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    assert_eq!(pool.current_size(), 2);
+    assert_eq!(pool.panicked_jobs(), 3);
+}
+
+#[test]
+fn try_execute_refuses_jobs_beyond_the_queue_depth() {
+    let pool = ThreadPool::builder()
+        .size(1)
+        .queue_depth(2)
+        .build()
+        .unwrap();
+    let release = hold(&pool);
+    let ran = Arc::new(AtomicUsize::new(0));
+    let job = || {
+        let ran = Arc::clone(&ran);
+        move || {
+            ran.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+
+    assert!(pool.try_execute(job()).is_ok());
+    assert!(pool.try_execute(job()).is_ok());
+    assert_eq!(pool.queued_jobs(), 2);
+    // The refused job comes back to the caller, who may still run it.
+    match pool.try_execute(job()) {
+        Err(PoolFull(refused)) => refused(),
+        Ok(()) => panic!("a third job was queued"),
+    }
+    assert_eq!(ran.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.queued_jobs(), 2);
+
+    drop(release);
+    wait_until("the queued jobs", || ran.load(Ordering::SeqCst) == 3);
+    assert!(pool.try_execute(job()).is_ok());
+    wait_until("the last job", || ran.load(Ordering::SeqCst) == 4);
+}
+
+#[test]
+fn job_handles_return_results_once() {
+    let pool = ThreadPool::new(2);
+    assert_eq!(pool.execute_with_result(|| 6 * 7).wait(), Ok(42));
+
+    let mut answer = pool.execute_with_result(|| String::from("done"));
+    assert_eq!(
+        answer.wait_timeout(Duration::from_secs(5)),
+        Ok(Some(String::from("done")))
+    );
+    assert_eq!(answer.try_get(), Ok(None));
+    assert_eq!(answer.wait(), Err(JobPanicked));
+
+    let failed = pool.execute_with_result(|| -> u32 { panic!("no result") });
+    assert_eq!(failed.wait(), Err(JobPanicked));
+    let mut failed = pool.execute_with_result(|| -> u32 { panic!("no result") });
+    wait_until("the panic", || pool.panicked_jobs() == 2);
+    assert_eq!(failed.try_get(), Err(JobPanicked));
+}
+
+#[test]
+fn builders_name_workers_and_refuse_zero_threads() {
+    let pool = ThreadPool::builder()
+        .size(1)
+        .name_prefix("test-worker")
+        .stack_size(256 * 1024)
+        .build()
+        .unwrap();
+    let name = pool.execute_with_result(|| thread::current().name().map(String::from));
+    assert_eq!(name.wait(), Ok(Some(String::from("test-worker-0"))));
+
+    assert!(matches!(
+        ThreadPool::builder().size(0).build(),
+        Err(PoolBuildError::ZeroSize)
+    ));
+}
+
+#[test]
+fn resize_grows_and_shrinks_the_pool() {
+    let pool = ThreadPool::new(2);
+    assert_eq!(pool.current_size(), 2);
+
+    pool.resize(4).unwrap();
+    assert_eq!(pool.current_size(), 4);
+    // The new workers take jobs: four held at once need all of them.
+    let releases: Vec<_> = (0..4).map(|_| hold(&pool)).collect();
+    assert_eq!(pool.active_jobs(), 4);
+    drop(releases);
+    wait_until("the held jobs", || pool.active_jobs() == 0);
+
+    pool.resize(1).unwrap();
+    wait_until("the surplus workers to exit", || pool.current_size() == 1);
+    assert_eq!(pool.execute_with_result(|| 1 + 1).wait(), Ok(2));
+
+    assert!(matches!(pool.resize(0), Err(PoolBuildError::ZeroSize)));
+    assert_eq!(pool.current_size(), 1);
+
+    pool.resize(3).unwrap();
+    assert_eq!(pool.current_size(), 3);
+}
+
+#[test]
+fn shutdown_can_discard_queued_jobs() {
+    let pool = ThreadPool::new(1);
+    let release = hold(&pool);
+    let ran = Arc::new(AtomicUsize::new(0));
+    for _ in 0..3 {
+        let ran = Arc::clone(&ran);
+        pool.execute(move || {
+            ran.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    let result = pool.execute_with_result(|| 42);
+
+    // The held worker outlives the deadline and is left behind.
+    let report = pool.shutdown(Duration::from_millis(50), DrainPolicy::Discard);
+    assert_eq!(
+        report,
+        ShutdownReport {
+            completed: 0,
+            discarded: 4,
+            abandoned: 1,
+        }
+    );
+    assert_eq!(result.wait(), Err(JobPanicked));
+    drop(release);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn shutdown_can_finish_queued_jobs() {
+    let pool = ThreadPool::new(2);
+    let ran = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let ran = Arc::clone(&ran);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(5));
+            ran.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    let report = pool.shutdown(Duration::from_secs(5), DrainPolicy::Finish);
+    assert_eq!(ran.load(Ordering::SeqCst), 10);
+    assert_eq!(report.discarded, 0);
+    assert_eq!(report.abandoned, 0);
+    assert!(report.completed <= 10, "{report:?}");
+}
+
+#[test]
+fn stats_count_jobs_per_worker() {
+    let pool = ThreadPool::new(2);
+    for _ in 0..5 {
+        pool.execute(|| thread::sleep(Duration::from_millis(10)));
+    }
+    pool.execute(|| panic!("counted too"));
+    wait_until("the jobs", || pool.stats().completed_jobs == 6);
+
+    let stats = pool.stats();
+    assert_eq!(stats.size, 2);
+    assert_eq!(stats.busy_workers, 0);
+    assert_eq!(stats.queued_jobs, 0);
+    assert_eq!(stats.panicked_jobs, 1);
+    assert_eq!(stats.cancelled_jobs, 0);
+    assert!(stats.run_time >= Duration::from_millis(50), "{stats:?}");
+    assert_eq!(stats.workers.len(), 2);
+    assert_eq!(stats.workers.iter().map(|w| w.jobs).sum::<u64>(), 6);
+    let mut ids: Vec<usize> = stats.workers.iter().map(|w| w.id).collect();
+    ids.sort_unstable();
+    assert_eq!(ids, [0, 1]);
+
+    let monitor = pool.monitor();
+    assert_eq!(monitor.completed_jobs(), 6);
+    drop(pool);
+    assert_eq!(monitor.current_size(), 0);
+}
+
+#[test]
+fn high_priority_jobs_run_first() {
+    let pool = ThreadPool::new(1);
+    let release = hold(&pool);
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for (priority, name) in [
+        (Priority::Low, "low-1"),
+        (Priority::Low, "low-2"),
+        (Priority::High, "high-1"),
+        (Priority::High, "high-2"),
+        (Priority::High, "high-3"),
+    ] {
+        let order = Arc::clone(&order);
+        pool.execute_with_priority(priority, move || order.lock().unwrap().push(name));
+    }
+
+    drop(release);
+    wait_until("the jobs", || order.lock().unwrap().len() == 5);
+    assert_eq!(
+        *order.lock().unwrap(),
+        ["high-1", "high-2", "high-3", "low-1", "low-2"]
+    );
+}
+
+#[test]
+fn idle_workers_steal_queued_jobs() {
+    let pool = ThreadPool::new(2);
+    let first = hold(&pool);
+    let second = hold(&pool);
+    let (stuck, stuck_started, unstick) = holder();
+    pool.execute(stuck);
+    let ran = Arc::new(AtomicUsize::new(0));
+    for _ in 0..9 {
+        let ran = Arc::clone(&ran);
+        pool.execute(move || {
+            ran.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    // The first worker takes the stuck job, and a share of those behind
+    // it into its own queue.
+    drop(first);
+    stuck_started.recv().unwrap();
+    // The other worker runs the rest, taking the stuck worker's share.
+    drop(second);
+    wait_until("the stolen jobs", || ran.load(Ordering::SeqCst) == 9);
+    assert_eq!(pool.active_jobs(), 1);
+    drop(unstick);
+}
+
+#[test]
+fn cancelled_jobs_are_skipped() {
+    let pool = ThreadPool::new(1);
+    let release = hold(&pool);
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let wanted = CancellationToken::new();
+    let unwanted = CancellationToken::new();
+    for (token, name) in [(&wanted, "wanted"), (&unwanted, "unwanted")] {
+        let ran = Arc::clone(&ran);
+        let submitted =
+            pool.try_execute_cancellable(token.clone(), move || ran.lock().unwrap().push(name));
+        assert!(submitted.is_ok());
+    }
+    unwanted.cancel();
+    unwanted.cancel();
+    assert!(unwanted.is_cancelled());
+    assert!(!wanted.is_cancelled());
+
+    drop(release);
+    // Jobs run in order, so this one runs after both.
+    assert_eq!(pool.execute_with_result(|| ()).wait(), Ok(()));
+    assert_eq!(*ran.lock().unwrap(), ["wanted"]);
+    wait_until("the counters", || pool.stats().completed_jobs == 3);
+    assert_eq!(pool.stats().cancelled_jobs, 1);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::{serve, site};
+use custom_http::ServerConfig;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// A single-reactor config with one-second idle and header timeouts.
+fn config() -> ServerConfig {
+    ServerConfig {
+        reactors: 1,
+        keep_alive_timeout: Duration::from_secs(1),
+        header_read_timeout: Duration::from_secs(1),
+        ..site("timeouts", &[("page.html", "page")])
+    }
+}
+
+/// Reads from `stream` until the server closes it, returning what was read
+/// and how long that took.
+fn read_until_closed(stream: &mut TcpStream) -> (String, Duration) {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let started = Instant::now();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    (
+        String::from_utf8_lossy(&received).into_owned(),
+        started.elapsed(),
+    )
+}
+
+#[test]
+fn silent_connections_are_closed_after_the_keep_alive_timeout() {
+    let running = serve(config());
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    let (received, waited) = read_until_closed(&mut stream);
+    // Nothing was asked, so nothing is answered.
+    assert_eq!(received, "");
+    // Timeouts are swept once a second.
+    assert!(waited >= Duration::from_millis(900), "{waited:?}");
+    assert!(waited < Duration::from_secs(3), "{waited:?}");
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn partial_requests_get_a_408_before_the_close() {
+    let running = serve(config());
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\nHost: loc")
+        .unwrap();
+    let (received, waited) = read_until_closed(&mut stream);
+    assert!(received.starts_with("HTTP/1.1 408 "), "{received}");
+    assert!(waited < Duration::from_secs(3), "{waited:?}");
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn trickled_headers_are_cut_off_at_the_header_deadline() {
+    let running = serve(ServerConfig {
+        header_read_timeout: Duration::from_secs(10),
+        header_deadline: Duration::from_secs(1),
+        ..config()
+    });
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let started = Instant::now();
+    let mut received = Vec::new();
+    // One byte every 100 ms is always active, so only the deadline can stop it.
+    for byte in b"GET /page.html HTTP/1.1\r\nX-Padding: "
+        .iter()
+        .chain([b'a'].iter().cycle())
+    {
+        if started.elapsed() > Duration::from_secs(5) || stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        let mut buffer = [0u8; 1024];
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => received.extend_from_slice(&buffer[..read]),
+            // The read timeout paces the writes.
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+    let waited = started.elapsed();
+    let received = String::from_utf8_lossy(&received);
+    assert!(received.starts_with("HTTP/1.1 408 "), "{received}");
+    assert!(waited >= Duration::from_millis(900), "{waited:?}");
+    assert!(waited < Duration::from_secs(3), "{waited:?}");
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn a_long_poll_timeout_does_not_delay_the_sweep() {
+    let mut config = config();
+    config.reactor.poll_timeout = Duration::from_secs(60);
+    let running = serve(config);
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    // The wait is cut short for the next sweep, so this still takes about
+    // the keep-alive timeout, not a minute.
+    let (_, waited) = read_until_closed(&mut stream);
+    assert!(waited < Duration::from_secs(3), "{waited:?}");
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
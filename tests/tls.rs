@@ -0,0 +1,97 @@
+#![cfg(feature = "tls")]
+
+mod common;
+
+use common::{serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::tls::TlsConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A config serving a small page over TLS, with the certificate for
+/// `localhost` in `tests/fixtures/tls`.
+fn config() -> ServerConfig {
+    let fixtures = PathBuf::from("tests/fixtures/tls");
+    let mut tls = TlsConfig::default();
+    tls.cert_chain = fixtures.join("cert.pem");
+    tls.private_key = fixtures.join("key.pem");
+    ServerConfig {
+        reactors: 1,
+        tls: Some(tls),
+        ..site("tls", &[("page.html", "page")])
+    }
+}
+
+/// A client trusting only the test CA that signed the fixture certificate.
+fn client() -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter("tests/fixtures/tls/ca.pem").unwrap() {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+#[test]
+fn requests_over_tls_are_served() {
+    let running = serve(config());
+    let name = ServerName::try_from("localhost").unwrap();
+    let session = ClientConnection::new(client(), name).unwrap();
+    let socket = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    let mut stream = StreamOwned::new(session, socket);
+    stream
+        .write_all(b"GET /page.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    // Ends cleanly: the server sends `close_notify` before closing.
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\npage"), "{response}");
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]), "ALPN");
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn failed_handshakes_are_cleaned_up() {
+    let config = config();
+    let stats = Arc::clone(&config.stats);
+    let running = serve(config);
+    // Plain HTTP to a TLS port: the handshake fails at the first record.
+    let mut socket = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    socket
+        .write_all(b"GET /page.html HTTP/1.1\r\n\r\n")
+        .unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut alert = Vec::new();
+    let _ = socket.read_to_end(&mut alert);
+    // Nothing of the page: at most a TLS alert record (content type 21).
+    assert!(alert.is_empty() || alert[0] == 21, "{alert:?}");
+
+    let started = Instant::now();
+    while stats.connections_active() > 0 && started.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(stats.connections_active(), 0);
+
+    drop(socket);
+    running.shutdown();
+    running.join().unwrap();
+}
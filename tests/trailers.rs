@@ -0,0 +1,118 @@
+mod common;
+
+use common::serve;
+use custom_http::http::body::{BodySource, StreamBody};
+use custom_http::http::headers::{HeaderMap, HeaderName, HeaderValue};
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::{Router, ServerConfig};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// Streams `pieces`, summing their bytes into an `X-Checksum` trailer. It
+/// also offers an undeclared `X-Secret` trailer, which must never be sent.
+struct Checksummed {
+    pieces: Vec<&'static str>,
+    sum: u32,
+}
+
+impl BodySource for Checksummed {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.pieces.is_empty() {
+            return Ok(None);
+        }
+        let piece = self.pieces.remove(0);
+        self.sum += piece.bytes().map(u32::from).sum::<u32>();
+        Ok(Some(piece.as_bytes().to_vec()))
+    }
+
+    fn trailers(&mut self) -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert(
+            HeaderName::from_static("X-Checksum"),
+            HeaderValue::from(u64::from(self.sum)),
+        );
+        trailers.insert(
+            HeaderName::from_static("X-Secret"),
+            HeaderValue::from_static("leaked"),
+        );
+        trailers
+    }
+}
+
+fn checksummed() -> Checksummed {
+    Checksummed {
+        pieces: vec!["hello, ", "trailer ", "world"],
+        sum: 0,
+    }
+}
+
+fn with_trailer(mut body: StreamBody) -> HttpResponse {
+    body.declare_trailer("X-Checksum");
+    HttpResponse::stream(StatusCode::OK, HeaderValue::from_static("text/plain"), body)
+}
+
+/// Sends `GET target` and returns the response, read until the server closes.
+fn fetch(target: &str) -> String {
+    let mut router = Router::new();
+    router
+        .get("/chunked", |_: &HttpRequest| {
+            with_trailer(StreamBody::chunked(checksummed()))
+        })
+        .get("/sized", |_: &HttpRequest| {
+            with_trailer(StreamBody::sized(checksummed(), 20))
+        });
+    let running = serve(ServerConfig::builder().router(router).build().unwrap());
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    stream
+        .write_all(format!("GET {target} HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes())
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+    response
+}
+
+/// Decodes a chunked body, returning its data and the trailer section.
+fn dechunk(mut body: &str) -> (String, String) {
+    let mut data = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        if size == 0 {
+            return (data, rest.to_owned());
+        }
+        data.push_str(&rest[..size]);
+        assert_eq!(&rest[size..size + 2], "\r\n");
+        body = &rest[size + 2..];
+    }
+}
+
+#[test]
+fn chunked_bodies_end_with_the_declared_trailers() {
+    let response = fetch("/chunked");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 "), "{head}");
+    assert!(
+        head.contains("\r\nTransfer-Encoding: chunked\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("\r\nTrailer: X-Checksum\r\n"), "{head}");
+
+    let (data, trailers) = dechunk(body);
+    assert_eq!(data, "hello, trailer world");
+    let expected: u32 = data.bytes().map(u32::from).sum();
+    assert_eq!(trailers, format!("X-Checksum: {expected}\r\n\r\n"));
+}
+
+#[test]
+fn sized_bodies_send_no_trailers() {
+    let response = fetch("/sized");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("\r\nContent-Length: 20\r\n"), "{head}");
+    assert!(!head.contains("Trailer"), "{head}");
+    assert_eq!(body, "hello, trailer world");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{get, site};
+use custom_http::ServerConfig;
+use custom_http::util::normalize_path;
+use std::fs;
+
+#[test]
+fn traversal_out_of_the_root_is_forbidden() {
+    let config = site("traversal", &[("public/index.html", "home")]);
+    // A secret right next to the document root.
+    fs::write(config.document_root.join("secret"), "SECRET").unwrap();
+    let config = ServerConfig {
+        document_root: config.document_root.join("public"),
+        ..config
+    };
+    for target in [
+        "/..%2f..%2fetc/passwd",
+        "/%2e%2e/secret",
+        "/%2E%2E%2Fsecret",
+        "/a/../../secret",
+        "/../secret",
+        // Missing paths are checked the same way.
+        "/missing/../../../nowhere",
+    ] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 403 "),
+            "{target}: {response}"
+        );
+        assert!(!response.contains("SECRET"), "{target}: {response}");
+    }
+    // Climbing back down inside the root is fine.
+    assert!(get(&config, "/a/../index.html").starts_with("HTTP/1.1 200 "));
+}
+
+#[test]
+fn double_dots_inside_a_name_are_not_traversal() {
+    let config = site(
+        "double-dots",
+        &[
+            ("release..notes.txt", "notes"),
+            ("v1..2/readme.txt", "readme"),
+        ],
+    );
+    for (target, body) in [
+        ("/release..notes.txt", "notes"),
+        ("/v1..2/readme.txt", "readme"),
+    ] {
+        let response = get(&config, target);
+        assert!(
+            response.starts_with("HTTP/1.1 200 "),
+            "{target}: {response}"
+        );
+        assert!(response.ends_with(body), "{target}: {response}");
+    }
+}
+
+#[test]
+fn paths_normalize_lexically() {
+    assert_eq!(normalize_path("/a/./b/../c/").as_deref(), Some("/a/c/"));
+    assert_eq!(normalize_path("//a//b").as_deref(), Some("/a/b"));
+    assert_eq!(normalize_path("/a/..").as_deref(), Some("/"));
+    assert_eq!(
+        normalize_path("/release..notes.txt").as_deref(),
+        Some("/release..notes.txt")
+    );
+    assert_eq!(normalize_path("/a/../../secret"), None);
+    assert_eq!(normalize_path("/.."), None);
+}
@@ -0,0 +1,172 @@
+use custom_http::http::request::{BodyFraming, HttpRequest, ParseError, parse_request, read_body};
+use custom_http::http::response::http_handler;
+use custom_http::http::upload::UploadConfig;
+use custom_http::{Server, ServerConfig};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A config accepting uploads of up to `max_size` bytes into a fresh root
+/// (which already has an `artifacts/` directory), and that root.
+fn config(name: &str, max_size: u64) -> (ServerConfig, PathBuf) {
+    let dir =
+        std::env::temp_dir().join(format!("custom_http-upload-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let root = dir.join("uploads");
+    fs::create_dir_all(root.join("artifacts")).unwrap();
+    let mut upload = UploadConfig {
+        root,
+        max_size,
+        ..UploadConfig::default()
+    };
+    upload.resolve_root().unwrap();
+    let root = upload.root.clone();
+    let config = ServerConfig {
+        document_root: dir,
+        upload: Some(upload),
+        ..ServerConfig::default()
+    };
+    (config, root)
+}
+
+fn put(config: &ServerConfig, target: &str, body: &str) -> String {
+    let head = format!("PUT {target} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let mut request: HttpRequest = parse_request(head.as_bytes()).unwrap().0;
+    request.body = body.as_bytes().to_vec();
+    String::from_utf8_lossy(&http_handler(&request, config)).into_owned()
+}
+
+#[test]
+fn new_files_are_created_and_old_ones_overwritten() {
+    let (config, root) = config("create", 1024);
+
+    let response = put(&config, "/uploads/artifacts/build.tar.gz", "first");
+    assert!(response.starts_with("HTTP/1.1 201 "), "{response}");
+    assert!(
+        response.contains("\r\nLocation: /uploads/artifacts/build.tar.gz\r\n"),
+        "{response}"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("artifacts/build.tar.gz")).unwrap(),
+        "first"
+    );
+
+    let response = put(&config, "/uploads/artifacts/build.tar.gz", "second");
+    assert!(response.starts_with("HTTP/1.1 204 "), "{response}");
+    assert_eq!(
+        fs::read_to_string(root.join("artifacts/build.tar.gz")).unwrap(),
+        "second"
+    );
+    // No temporary files are left behind.
+    assert_eq!(fs::read_dir(root.join("artifacts")).unwrap().count(), 1);
+
+    // Parent directories aren't created on the fly.
+    let response = put(&config, "/uploads/nightly/build.tar.gz", "x");
+    assert!(response.starts_with("HTTP/1.1 409 "), "{response}");
+    assert!(!root.join("nightly").exists());
+}
+
+#[test]
+fn uploads_cant_leave_the_root() {
+    let (config, root) = config("traversal", 1024);
+    let outside = root.parent().unwrap().to_path_buf();
+
+    for target in [
+        "/uploads/../escape.txt",
+        "/uploads/%2e%2e/escape.txt",
+        "/uploads/..%2fescape.txt",
+        "/uploads/artifacts/../../escape.txt",
+        "/uploads/.hidden",
+        "/uploads/a%00b",
+    ] {
+        let response = put(&config, target, "x");
+        assert!(
+            response.starts_with("HTTP/1.1 403 ") || response.starts_with("HTTP/1.1 400 "),
+            "{target}: {response}"
+        );
+    }
+    assert!(!outside.join("escape.txt").exists());
+    assert!(!root.join(".hidden").exists());
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+}
+
+#[test]
+fn oversized_uploads_are_refused() {
+    let (mut config, root) = config("oversize", 10);
+    config.addresses = vec![String::from("127.0.0.1:0")];
+    config.reactors = 1;
+    config.reactor.workers = 1;
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    let exchange = |request: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = exchange(
+        "PUT /uploads/artifacts/big.bin HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    let response = exchange(
+        "PUT /uploads/artifacts/big.bin HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+         6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    // A chunk size that would overflow the running total.
+    let response = exchange(
+        "PUT /uploads/artifacts/big.bin HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+         1\r\na\r\nffffffffffffffff\r\nxx\r\n0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    assert!(!root.join("artifacts/big.bin").exists());
+
+    let response = exchange(
+        "PUT /uploads/artifacts/small.bin HTTP/1.1\r\nContent-Length: 10\r\n\r\nhelloworld",
+    );
+    assert!(response.starts_with("HTTP/1.1 201 "), "{response}");
+    assert_eq!(
+        fs::read_to_string(root.join("artifacts/small.bin")).unwrap(),
+        "helloworld"
+    );
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn huge_chunk_sizes_are_too_large_not_overflows() {
+    for (body, limit) in [
+        (
+            &b"1\r\na\r\nffffffffffffffff\r\nxx\r\n0\r\n\r\n"[..],
+            u64::MAX,
+        ),
+        (b"1\r\na\r\nffffffffffffffff\r\nxx\r\n0\r\n\r\n", 1024),
+        (b"ffffffffffffffff\r\nxx\r\n0\r\n\r\n", u64::MAX),
+        (b"fffffffffffffffe\r\nxx\r\n0\r\n\r\n", u64::MAX),
+    ] {
+        assert_eq!(
+            read_body(body, BodyFraming::Chunked, limit).map(|_| ()),
+            Err(ParseError::TooLarge),
+            "{limit}: {:?}",
+            String::from_utf8_lossy(body)
+        );
+    }
+    assert_eq!(
+        read_body(b"1\r\na\r\n2\r\nbc\r\n0\r\n\r\n", BodyFraming::Chunked, 3),
+        Ok((b"abc".to_vec(), 18))
+    );
+    assert_eq!(
+        read_body(b"1\r\na\r\n3\r\nbcd\r\n0\r\n\r\n", BodyFraming::Chunked, 3).map(|_| ()),
+        Err(ParseError::TooLarge)
+    );
+}
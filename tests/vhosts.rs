@@ -0,0 +1,77 @@
+use custom_http::{Server, ServerConfig, UnknownHost};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+
+/// Creates a fresh document root whose index page says `text`.
+fn site(name: &str, text: &str) -> ServerConfig {
+    let root =
+        std::env::temp_dir().join(format!("custom_http-vhosts-{}-{name}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("welcome.html"), text).unwrap();
+    ServerConfig {
+        document_root: root,
+        ..ServerConfig::default()
+    }
+}
+
+fn get(address: SocketAddr, host: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_each_host_from_its_own_root() {
+    let a = site("a", "site a");
+    let b = site("b", "site b");
+    let roots: Vec<PathBuf> = vec![a.document_root.clone(), b.document_root.clone()];
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .virtual_host(["a.test"], a)
+        .virtual_host(["b.test", "*.b.test"], b)
+        .unknown_host(UnknownHost::Misdirected)
+        .build()
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    let running = server.spawn().unwrap();
+
+    assert!(get(address, "a.test").ends_with("\r\n\r\nsite a"));
+    // The port and case don't matter.
+    assert!(get(address, "A.Test:8080").ends_with("\r\n\r\nsite a"));
+    assert!(get(address, "b.test").ends_with("\r\n\r\nsite b"));
+    assert!(get(address, "www.b.test").ends_with("\r\n\r\nsite b"));
+    let response = get(address, "c.test");
+    assert!(response.starts_with("HTTP/1.1 421 "), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+    for root in roots {
+        fs::remove_dir_all(root).unwrap();
+    }
+}
+
+#[test]
+fn unknown_hosts_can_get_the_default_site() {
+    let config = ServerConfig::builder()
+        .virtual_host(["a.test"], site("default", "site a"))
+        .build()
+        .unwrap();
+    let request = custom_http::http::request::parse_request(
+        b"GET / HTTP/1.1\r\nHost: elsewhere.test\r\n\r\n",
+    )
+    .unwrap()
+    .0;
+    let site = config.site_for(&request).unwrap();
+    assert_eq!(site.document_root, config.document_root);
+    fs::remove_dir_all(&config.virtual_hosts[0].config.document_root).unwrap();
+}
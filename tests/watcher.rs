@@ -0,0 +1,94 @@
+mod common;
+
+use common::{read_response, serve, site};
+use custom_http::ServerConfig;
+use custom_http::io::cache::FileCache;
+use custom_http::io::watcher::Watcher;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_millis(50);
+
+/// Waits up to two seconds for `done` to hold.
+fn eventually(mut done: impl FnMut() -> bool) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(2) {
+        if done() {
+            return true;
+        }
+        thread::sleep(INTERVAL / 2);
+    }
+    false
+}
+
+#[test]
+fn replaced_files_are_served_fresh_without_an_mtime_change() {
+    let cache = Arc::new(FileCache::new());
+    let config = ServerConfig {
+        file_cache: Some(Arc::clone(&cache)),
+        watch_interval: Some(INTERVAL),
+        ..site("watcher", &[("page.html", "old")])
+    };
+    let page = config.document_root.join("page.html");
+    let running = serve(config.clone());
+    let mut stream = TcpStream::connect(running.local_addr().unwrap()).unwrap();
+    let mut fetch = || {
+        stream
+            .write_all(b"GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        read_response(&mut stream)
+    };
+    assert!(fetch().ends_with("\r\n\r\nold"));
+    assert_eq!(cache.len(), 1);
+
+    // An editor's save: a new file renamed over the old one, here with the
+    // same size and mtime, so only the watcher can tell.
+    let modified = fs::metadata(&page).unwrap().modified().unwrap();
+    let temp = config.document_root.join(".page.html.swp");
+    fs::write(&temp, "new").unwrap();
+    File::options()
+        .write(true)
+        .open(&temp)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    fs::rename(&temp, &page).unwrap();
+
+    assert!(eventually(|| fetch().ends_with("\r\n\r\nnew")));
+
+    drop(stream);
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn removed_directories_drop_everything_below_them() {
+    let config = site(
+        "watcher-dirs",
+        &[
+            ("docs/a.html", "a"),
+            ("docs/api/b.html", "b"),
+            ("keep.html", "k"),
+        ],
+    );
+    let root = config.document_root.to_string_lossy().into_owned();
+    let cache = Arc::new(FileCache::new());
+    for name in ["docs/a.html", "docs/api/b.html", "keep.html"] {
+        let path = config.document_root.join(name);
+        let modified = fs::metadata(&path).unwrap().modified().ok();
+        cache.insert(&path.to_string_lossy(), modified, b"cached".to_vec());
+    }
+
+    let watcher = Watcher::spawn(&root, INTERVAL, Arc::clone(&cache));
+    fs::remove_dir_all(config.document_root.join("docs")).unwrap();
+    assert!(eventually(|| cache.len() == 1));
+
+    // Dropping the watcher stops its thread without waiting out a scan.
+    let started = Instant::now();
+    drop(watcher);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
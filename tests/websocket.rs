@@ -0,0 +1,300 @@
+use custom_http::http::websocket::{Message, WebSocket, WebSocketConfig, accept_key};
+use custom_http::util::{base64_encode, sha1};
+use custom_http::{Router, Server, ServerConfig, ServerHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// Serves an echo websocket at `/echo` (text messages upper-cased, so the
+/// echo is told apart from the input) with messages limited to 64 bytes.
+fn start() -> (SocketAddr, ServerHandle) {
+    let mut router = Router::new();
+    router.websocket("/echo", |socket: &WebSocket, message: Message| {
+        let reply = match message {
+            Message::Text(text) => Message::Text(text.to_uppercase()),
+            binary => binary,
+        };
+        socket.send(reply).unwrap();
+    });
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .router(router)
+        .websocket(WebSocketConfig {
+            max_message_size: 64,
+            ..WebSocketConfig::default()
+        })
+        .build()
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let address = server.local_addr().unwrap();
+    (address, server.spawn().unwrap())
+}
+
+/// Opens a websocket at `/echo` and returns the stream, the `101` read off it.
+fn open(address: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                 Connection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: {KEY}\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+    assert!(
+        head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("Upgrade: websocket\r\n"), "{head}");
+    stream
+}
+
+/// Builds a masked client frame.
+fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&MASK);
+    frame.extend(payload.iter().zip(MASK.iter().cycle()).map(|(b, m)| b ^ m));
+    frame
+}
+
+/// Reads one server frame: `(opcode, payload)`. Server frames are never masked.
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[0] & 0x80, 0x80, "server frames are never fragmented");
+    assert_eq!(head[1] & 0x80, 0, "server frames are never masked");
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            usize::from(u16::from_be_bytes(len))
+        }
+        len => usize::from(len),
+    };
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+#[test]
+fn sha1_and_base64_match_known_vectors() {
+    let hex: String = sha1(b"abc").iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    let hex: String = sha1(b"").iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(hex, "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    // Longer than one block.
+    let hex: String = sha1(&[b'a'; 1000])
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    assert_eq!(hex, "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+
+    assert_eq!(base64_encode(b""), "");
+    assert_eq!(base64_encode(b"f"), "Zg==");
+    assert_eq!(base64_encode(b"fo"), "Zm8=");
+    assert_eq!(base64_encode(b"foo"), "Zm9v");
+    assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+
+    assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn handshake_then_echo_including_a_fragmented_message() {
+    let (address, running) = start();
+    let mut stream = open(address);
+
+    stream.write_all(&frame(true, 0x1, b"hello")).unwrap();
+    assert_eq!(read_frame(&mut stream), (0x1, b"HELLO".to_vec()));
+
+    stream
+        .write_all(&frame(true, 0x2, &[0, 1, 2, 255]))
+        .unwrap();
+    assert_eq!(read_frame(&mut stream), (0x2, vec![0, 1, 2, 255]));
+
+    // A text message in three fragments, with a ping between two of them,
+    // sent in one write: the pong comes first, then the whole message.
+    let mut bytes = frame(false, 0x1, b"frag");
+    bytes.extend(frame(false, 0x0, b"men"));
+    bytes.extend(frame(true, 0x9, b"are you there?"));
+    bytes.extend(frame(true, 0x0, "ted ✓".as_bytes()));
+    stream.write_all(&bytes).unwrap();
+    assert_eq!(read_frame(&mut stream), (0xA, b"are you there?".to_vec()));
+    assert_eq!(
+        read_frame(&mut stream),
+        (0x1, "FRAGMENTED ✓".as_bytes().to_vec())
+    );
+
+    // A close frame is echoed, then the server closes.
+    stream
+        .write_all(&frame(true, 0x8, &1000u16.to_be_bytes()))
+        .unwrap();
+    assert_eq!(
+        read_frame(&mut stream),
+        (0x8, 1000u16.to_be_bytes().to_vec())
+    );
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    drop(stream);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn oversized_and_unmasked_frames_close_the_connection() {
+    let (address, running) = start();
+
+    let mut stream = open(address);
+    stream.write_all(&frame(true, 0x1, &[b'a'; 65])).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1009u16.to_be_bytes());
+
+    // Fragments count together against the limit.
+    let mut stream = open(address);
+    let mut bytes = frame(false, 0x2, &[0; 40]);
+    bytes.extend(frame(true, 0x0, &[0; 40]));
+    stream.write_all(&bytes).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1009u16.to_be_bytes());
+
+    let mut stream = open(address);
+    stream.write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1002u16.to_be_bytes());
+
+    let mut stream = open(address);
+    stream.write_all(&frame(true, 0x1, &[0xff, 0xfe])).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1007u16.to_be_bytes());
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+/// Builds a masked continuation frame header claiming a 64-bit `len`, with
+/// no payload.
+fn huge_continuation(len: u64) -> Vec<u8> {
+    let mut frame = vec![0x80, 0x80 | 127];
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&MASK);
+    frame
+}
+
+#[test]
+fn lengths_that_would_overflow_close_the_connection() {
+    let (address, running) = start();
+
+    // The length's top bit must be clear; this one would also wrap around
+    // when added to the fragment before it.
+    let mut stream = open(address);
+    let mut bytes = frame(false, 0x1, &[b'a'; 10]);
+    bytes.extend(huge_continuation(u64::MAX - 9));
+    stream.write_all(&bytes).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1002u16.to_be_bytes());
+
+    let mut stream = open(address);
+    let mut bytes = frame(false, 0x1, &[b'a'; 10]);
+    bytes.extend(huge_continuation(u64::MAX >> 1));
+    stream.write_all(&bytes).unwrap();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1009u16.to_be_bytes());
+    drop(stream);
+
+    // The reactor is still serving.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 426 "), "{response}");
+    drop(stream);
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn requests_that_are_not_handshakes_are_refused() {
+    let (address, running) = start();
+    let exchange = |request: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = exchange("GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(
+        response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.contains("Sec-WebSocket-Version: 13\r\n"),
+        "{response}"
+    );
+
+    let response = exchange(
+        "GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade, close\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+
+    let response = exchange("POST /echo HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 405 "), "{response}");
+    assert!(response.contains("Allow: GET\r\n"), "{response}");
+
+    running.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn shutdown_sends_going_away() {
+    let (address, running) = start();
+    let mut stream = open(address);
+    // The connection is upgraded once an echo comes back.
+    stream.write_all(&frame(true, 0x1, b"hi")).unwrap();
+    assert_eq!(read_frame(&mut stream), (0x1, b"HI".to_vec()));
+
+    running.shutdown();
+    let (opcode, payload) = read_frame(&mut stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1001u16.to_be_bytes());
+    running.join().unwrap();
+}
@@ -0,0 +1,72 @@
+use custom_http::http::request::HttpRequest;
+use custom_http::http::response::HttpResponse;
+use custom_http::http::status::StatusCode;
+use custom_http::io::nonblocking::{ReactorConfig, default_workers};
+use custom_http::{Router, Server, ServerConfig, ServerError};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+
+#[test]
+fn the_default_follows_the_cpus_within_bounds() {
+    let expected = thread::available_parallelism().map_or(4, |n| n.get().clamp(2, 16));
+    assert_eq!(default_workers(), expected);
+    assert!((2..=16).contains(&default_workers()));
+    assert_eq!(ReactorConfig::default().workers, default_workers());
+}
+
+#[test]
+fn zero_workers_fail_the_start_instead_of_panicking() {
+    let mut config = ServerConfig {
+        addresses: vec![String::from("127.0.0.1:0")],
+        ..ServerConfig::default()
+    };
+    config.reactor.workers = 0;
+    let Err(error) = Server::bind(config) else {
+        panic!("a server without workers started");
+    };
+    assert!(matches!(error, ServerError::Config(_)), "{error:?}");
+    assert_eq!(
+        error.to_string(),
+        "invalid configuration: reactor.workers must be at least 1"
+    );
+}
+
+#[test]
+fn a_single_worker_runs_every_handler() {
+    let mut router = Router::new();
+    router.get("/worker", |_: &HttpRequest| {
+        let name = thread::current().name().unwrap_or("").to_string();
+        HttpResponse::text(StatusCode::OK, name)
+    });
+    let config = ServerConfig::builder()
+        .address("127.0.0.1:0")
+        .reactors(1)
+        .workers(1)
+        .router(router)
+        .build()
+        .unwrap();
+    let running = Server::start(config).unwrap();
+    let address = running.local_addr().unwrap();
+
+    let names: HashSet<String> = (0..8)
+        .map(|_| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /worker HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response.split("\r\n\r\n").nth(1).unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(names.len(), 1, "{names:?}");
+    assert!(
+        names.iter().all(|name| name.starts_with("http-worker")),
+        "{names:?}"
+    );
+
+    running.shutdown();
+    running.join().unwrap();
+}